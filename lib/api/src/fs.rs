@@ -0,0 +1,39 @@
+use crate::{download_result_bytes, flush_host_calls, upload_bytes};
+
+extern "C" {
+    fn host_fs_read() -> u32;
+    fn host_fs_write() -> u32;
+    fn host_fs_remove() -> u32;
+}
+
+/**
+ * Reads a file of the private directory of this module,
+ * or of the shared paths the host allows to read.
+ * Paths are always relative.
+ */
+pub fn read_file(path: &str) -> Option<Vec<u8>> {
+    flush_host_calls();
+    upload_bytes(0, path.as_bytes());
+    if unsafe { host_fs_read() } != 0 {
+        Some(download_result_bytes())
+    } else {
+        None
+    }
+}
+
+/**
+ * Writes a file to the private directory of this module.
+ * Returns false if the path is invalid or the quota of the module is exceeded.
+ */
+pub fn write_file(path: &str, data: &[u8]) -> bool {
+    flush_host_calls();
+    upload_bytes(0, path.as_bytes());
+    upload_bytes(1, data);
+    unsafe { host_fs_write() != 0 }
+}
+
+pub fn remove_file(path: &str) -> bool {
+    flush_host_calls();
+    upload_bytes(0, path.as_bytes());
+    unsafe { host_fs_remove() != 0 }
+}
@@ -4,11 +4,13 @@ use graphics_types::{
     rendering::{SVertex, State},
     types::DrawModes,
 };
+use wasm_runtime_types::host_calls::HostCall;
 
-use crate::upload_bytes;
+use crate::queue_host_call;
 
 extern "C" {
-    fn flush_vertices(vertices_offset: u64);
+    fn host_canvas_width() -> u32;
+    fn host_canvas_height() -> u32;
 }
 
 struct BackendBuffer {
@@ -63,29 +65,12 @@ impl GraphicsStreamHandler for BackendHandle {
     }
 
     fn flush_vertices(&mut self, state: &State, vertices_offset: usize, draw_mode: DrawModes) {
-        upload_bytes(
-            0,
-            bincode::encode_to_vec(
-                self.buffer.vertices[vertices_offset..self.buffer.vertices_count].to_vec(),
-                bincode::config::standard(),
-            )
-            .unwrap()
-            .as_slice(),
-        ); // TODO: vertices
-        upload_bytes(
-            1,
-            bincode::encode_to_vec(state, bincode::config::standard())
-                .unwrap()
-                .as_slice(),
-        ); // state
-        upload_bytes(
-            2,
-            bincode::encode_to_vec(draw_mode, bincode::config::standard())
-                .unwrap()
-                .as_slice(),
-        ); // draw_mode
-
-        unsafe { flush_vertices(vertices_offset as u64) }
+        queue_host_call(HostCall::FlushVertices {
+            vertices: self.buffer.vertices[vertices_offset..self.buffer.vertices_count].to_vec(),
+            state: *state,
+            vertices_offset: vertices_offset as u64,
+            draw_mode,
+        });
 
         self.buffer.vertices_count = 0;
     }
@@ -113,6 +98,21 @@ impl Graphics {
         }
     }
 
+    /**
+     * The size of the canvas the host currently renders to
+     */
+    pub fn canvas_width(&self) -> u32 {
+        unsafe { host_canvas_width() }
+    }
+
+    pub fn canvas_height(&self) -> u32 {
+        unsafe { host_canvas_height() }
+    }
+
+    pub fn canvas_aspect(&self) -> f32 {
+        self.canvas_width() as f32 / self.canvas_height().max(1) as f32
+    }
+
     pub fn quads_begin(&mut self) -> DrawQuads {
         let vertices_offset = self.backend_handle.buffer.vertices.len();
         DrawQuads::new(&mut self.backend_handle, vertices_offset)
@@ -1,13 +1,25 @@
 use graphics::graphics::Graphics;
+use wasm_runtime_types::host_calls::HostCall;
 
+pub mod fs;
 pub mod graphics;
+pub mod state;
+pub mod storage;
+
+/**
+ * The api version this module is built against,
+ * the host refuses to load modules with an incompatible version
+ */
+pub const API_VERSION: u32 = 6;
 
 extern "C" {
     fn host_raw_bytes_add_u64(byte_stream: u64, byte_count: u8);
     fn host_raw_bytes_add_u64_2(byte_stream: u64, byte_count: u8);
     fn host_raw_bytes_add_u64_3(byte_stream: u64, byte_count: u8);
     fn host_raw_bytes_add_u64_4(byte_stream: u64, byte_count: u8);
-    fn host_println();
+    fn host_run_batch();
+    fn host_result_bytes_len() -> u64;
+    fn host_result_bytes_get_u64(offset: u64) -> u64;
 }
 
 extern "Rust" {
@@ -33,15 +45,74 @@ pub fn upload_bytes(index: usize, bytes: &[u8]) {
     });
 }
 
+/**
+ * Reads the bytes the host wrote as result of the last call
+ */
+pub fn download_result_bytes() -> Vec<u8> {
+    let len = unsafe { host_result_bytes_len() } as usize;
+    let mut res: Vec<u8> = Vec::with_capacity(len);
+    while res.len() < len {
+        let stream_el = unsafe { host_result_bytes_get_u64(res.len() as u64) };
+        let byte_count = (len - res.len()).min(std::mem::size_of::<u64>());
+        res.extend_from_slice(&stream_el.to_le_bytes()[0..byte_count]);
+    }
+    res
+}
+
+// flushed at the latest after the module's main function returned
+static mut HOST_CALL_BATCH: Vec<HostCall> = Vec::new();
+const HOST_CALL_BATCH_MAX_LEN: usize = 1024;
+
+/**
+ * Queues a call to the host, the host runs all queued calls in order
+ * in a single call across the wasm boundary.
+ */
+pub fn queue_host_call(call: HostCall) {
+    let batch = unsafe { &mut HOST_CALL_BATCH };
+    batch.push(call);
+    if batch.len() >= HOST_CALL_BATCH_MAX_LEN {
+        flush_host_calls();
+    }
+}
+
+/**
+ * Runs all queued host calls. Calls that return a result from the host
+ * must flush first, so the host sees all calls in the order the module did them.
+ */
+pub fn flush_host_calls() {
+    let batch = unsafe { &mut HOST_CALL_BATCH };
+    if batch.is_empty() {
+        return;
+    }
+    upload_bytes(
+        3,
+        bincode::encode_to_vec(&*batch, bincode::config::standard())
+            .unwrap()
+            .as_slice(),
+    );
+    batch.clear();
+    unsafe { host_run_batch() };
+}
+
+/**
+ * Prints in order with the queued calls, it's flushed right away,
+ * so the text is not lost if the module traps afterwards
+ */
 pub fn println(text: &str) {
-    upload_bytes(0, text.as_bytes());
-    unsafe { host_println() };
+    queue_host_call(HostCall::Println(text.to_string()));
+    flush_host_calls();
 }
 
 static mut GRAPHICS: once_cell::unsync::Lazy<Graphics> =
     once_cell::unsync::Lazy::new(|| Graphics::new());
 
+#[no_mangle]
+pub fn api_version() -> u32 {
+    API_VERSION
+}
+
 #[no_mangle]
 pub fn api_run() {
     unsafe { mod_main(&mut GRAPHICS) };
+    flush_host_calls();
 }
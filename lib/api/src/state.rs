@@ -0,0 +1,34 @@
+use crate::flush_host_calls;
+
+extern "C" {
+    fn host_state_pause();
+    fn host_state_resume();
+    fn host_state_step(ticks: u64);
+    fn host_state_is_paused() -> u32;
+}
+
+/**
+ * Asks the host to freeze the simulation of the game, e.g. for the editor's test play.
+ * The server decides if it allows it, so the game might still continue.
+ */
+pub fn state_pause() {
+    flush_host_calls();
+    unsafe { host_state_pause() };
+}
+
+pub fn state_resume() {
+    flush_host_calls();
+    unsafe { host_state_resume() };
+}
+
+/**
+ * Simulates the next `ticks` ticks while paused
+ */
+pub fn state_step(ticks: u64) {
+    flush_host_calls();
+    unsafe { host_state_step(ticks) };
+}
+
+pub fn state_is_paused() -> bool {
+    unsafe { host_state_is_paused() != 0 }
+}
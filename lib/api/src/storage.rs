@@ -0,0 +1,38 @@
+use crate::{download_result_bytes, flush_host_calls, upload_bytes};
+
+extern "C" {
+    fn host_storage_get() -> u32;
+    fn host_storage_set() -> u32;
+    fn host_storage_remove();
+}
+
+/**
+ * Persistent key-value storage of this module, e.g. for the last opened tab.
+ * Keys are limited to 256 bytes, values to 4096 bytes and
+ * a module can store at most 256 entries.
+ */
+pub fn storage_get(key: &str) -> Option<String> {
+    flush_host_calls();
+    upload_bytes(0, key.as_bytes());
+    if unsafe { host_storage_get() } != 0 {
+        String::from_utf8(download_result_bytes()).ok()
+    } else {
+        None
+    }
+}
+
+/**
+ * Returns false if the value could not be stored (e.g. because of the limits)
+ */
+pub fn storage_set(key: &str, value: &str) -> bool {
+    flush_host_calls();
+    upload_bytes(0, key.as_bytes());
+    upload_bytes(1, value.as_bytes());
+    unsafe { host_storage_set() != 0 }
+}
+
+pub fn storage_remove(key: &str) {
+    flush_host_calls();
+    upload_bytes(0, key.as_bytes());
+    unsafe { host_storage_remove() };
+}
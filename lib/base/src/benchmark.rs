@@ -10,7 +10,7 @@ macro_rules! benchmark {
     ($e1:expr, $e2:expr, $e3:expr, $e4:expr, $e5:expr) => {
         if $e1 {
             let diff = $e2.time_get_nanoseconds() - $e4;
-            println!(
+            $crate::log_println!(
                 "{} took {:.2}s / {:.2}ms",
                 $e3,
                 diff.as_secs_f32(),
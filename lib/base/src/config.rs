@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::anyhow;
+
+use crate::input_actions::{InputActions, CLIENT_ACTIONS_NAMESPACE};
 
 use num_derive::FromPrimitive;
 use serde::{Deserialize, Serialize};
@@ -26,11 +30,53 @@ impl ConfigPath {
     }
 }
 
+/**
+ * Prediction settings for a single server, unset values use the normal config
+ */
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfigPredictionOverride {
+    pub prediction: Option<bool>,
+    pub margin_ms: Option<u32>,
+    pub input_delay_ticks: Option<u32>,
+    pub smoothing_ticks: Option<u32>,
+}
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct Config {
     // client
     pub cl_background_show_tile_layers: bool,
     pub cl_overlay_entities: i32,
+    // receive and save the ghosts of race mods
+    pub cl_race_ghost: bool,
+    // join servers as spectator instead of spawning a character
+    pub cl_join_as_spectator: bool,
+    // the file name in data/languages/ without extension, english is built in
+    pub cl_language: String,
+    // skins that were not used for this time are unloaded
+    // when all skins together need more memory than the budget
+    pub cl_skins_memory_budget_kib: u32,
+    pub cl_skins_unused_secs: u32,
+
+    // how often an unchanged menu checks for input, 0 means as fast as possible
+    pub cl_menu_idle_fps: u32,
+
+    // predict the own character, else it's rendered like the server sees it
+    pub cl_prediction: bool,
+    // predicted further than the ping, to cover ping jitter
+    pub cl_prediction_margin_ms: u32,
+    // the own input is simulated this many ticks later, trades responsiveness for fewer corrections
+    pub cl_prediction_input_delay_ticks: u32,
+    // corrections of the predicted position are spread over these ticks, 0 disables it
+    pub cl_prediction_smoothing_ticks: u32,
+    // by server address, e.g. for servers with unusual physics
+    pub cl_prediction_server_overrides: BTreeMap<String, ConfigPredictionOverride>,
+
+    // the interval in which the editor saves unsaved changes for crash recovery, 0 disables it
+    pub ed_autosave_secs: u32,
+
+    // the actions of the client, of mods and the console binds, bound to keys/mouse/gamepad
+    pub cl_input_actions: InputActions,
 
     // sound
 
@@ -48,13 +94,41 @@ pub struct Config {
     pub gfx_window_fullscreen_mode: u32,
     pub gfx_thread_count: usize,
     // server
+    pub sv_name: String,
+    // the address the game server listens on, servers that only listen on localhost
+    // are not announced in the local network
+    pub sv_bind_addr: String,
+    // the limits of every client connection in bytes per second, 0 means unlimited
+    pub sv_upload_limit: u64,
+    pub sv_download_limit: u64,
+    // maximum of concurrent connections from the same ip, 0 means unlimited
+    pub sv_max_connections_per_ip: u32,
+    // kick votes need at least this many voters to pass
+    pub sv_vote_kick_min_voters: u32,
+    // clients can pause and single step the simulation, only for local practice or debugging
+    pub sv_allow_simulation_control: bool,
+    // the tick statistics in the prometheus text format are written to this file every second,
+    // empty disables it
+    pub sv_tick_stats_file: String,
+
+    // console
+    // name -> commands, the binds are part of `cl_input_actions`
+    pub console_aliases: BTreeMap<String, String>,
 
     // network
+    // in bytes per second, 0 means unlimited
+    pub net_upload_limit: u64,
+    pub net_download_limit: u64,
 
     // debug
     pub dbg_gfx: EDebugGFXModes,
     // show various "benchmarks" (e.g. loading of components etc.)
     pub dbg_bench: bool,
+    // artificial network conditions for testing, applied to sent and received packets
+    pub dbg_net_latency_ms: u64,
+    pub dbg_net_jitter_ms: u64,
+    // 0 - 100
+    pub dbg_net_loss_percent: u32,
 }
 
 impl Config {
@@ -63,6 +137,27 @@ impl Config {
             gfx_no_clip: false,
             cl_background_show_tile_layers: true,
             cl_overlay_entities: 0,
+            cl_race_ghost: true,
+            cl_join_as_spectator: false,
+            cl_language: "en".to_string(),
+            cl_skins_memory_budget_kib: 64 * 1024,
+            cl_skins_unused_secs: 30,
+            cl_menu_idle_fps: 30,
+            cl_prediction: true,
+            cl_prediction_margin_ms: 10,
+            cl_prediction_input_delay_ticks: 0,
+            cl_prediction_smoothing_ticks: 5,
+            ed_autosave_secs: 60,
+            sv_name: "unnamed server".to_string(),
+            sv_bind_addr: "127.0.0.1:8305".to_string(),
+            sv_max_connections_per_ip: 4,
+            sv_vote_kick_min_voters: 2,
+            sv_allow_simulation_control: false,
+            cl_input_actions: {
+                let mut actions = InputActions::default();
+                Self::register_default_input_actions(&mut actions);
+                actions
+            },
             gfx_high_detail: true,
 
             gfx_window_width: 800,
@@ -73,13 +168,62 @@ impl Config {
         }
     }
 
+    /**
+     * Also called for loaded configs, so new actions get their default binds
+     */
+    fn register_default_input_actions(actions: &mut InputActions) {
+        actions.register(CLIENT_ACTIONS_NAMESPACE, "left", &["a"]);
+        actions.register(CLIENT_ACTIONS_NAMESPACE, "right", &["d"]);
+        actions.register(CLIENT_ACTIONS_NAMESPACE, "jump", &["space", "gamepad_a"]);
+    }
+
+    /**
+     * The value of a variable as the console shows it
+     */
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        let json = serde_json::to_value(self).ok()?;
+        match json.get(name)? {
+            serde_json::Value::String(val) => Some(val.clone()),
+            serde_json::Value::Bool(val) => Some((*val as u8).to_string()),
+            serde_json::Value::Number(val) => Some(val.to_string()),
+            // e.g. the ui path can't be shown as a single value
+            _ => None,
+        }
+    }
+
+    /**
+     * Sets a variable by its name, the value is parsed as the type of the variable
+     */
+    pub fn set_var(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let mut json = serde_json::to_value(&*self)?;
+        let var = json
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("unknown variable"))?;
+        *var = match var {
+            serde_json::Value::String(_) => serde_json::Value::String(value.to_string()),
+            serde_json::Value::Bool(_) => serde_json::Value::Bool(match value {
+                "1" | "true" | "on" => true,
+                "0" | "false" | "off" => false,
+                _ => return Err(anyhow!("expected 0 or 1, found \"{}\"", value)),
+            }),
+            serde_json::Value::Number(_) => serde_json::from_str::<serde_json::Number>(value)
+                .map(serde_json::Value::Number)
+                .map_err(|_| anyhow!("expected a number, found \"{}\"", value))?,
+            _ => return Err(anyhow!("the variable can't be set from the console")),
+        };
+        // e.g. a negative value for an unsigned variable fails here
+        *self = serde_json::from_value(json)?;
+        Ok(())
+    }
+
     pub fn to_json_string(&self) -> anyhow::Result<String> {
         let res = serde_json::to_string_pretty(self)?;
         Ok(res)
     }
 
     pub fn from_json_string(json_str: &str) -> anyhow::Result<Self> {
-        let res = serde_json::from_str(json_str)?;
+        let mut res: Self = serde_json::from_str(json_str)?;
+        Self::register_default_input_actions(&mut res.cl_input_actions);
         Ok(res)
     }
 
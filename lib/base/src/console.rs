@@ -0,0 +1,477 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use arrayvec::ArrayString;
+
+use crate::{
+    config::Config,
+    filesys::FileSystem,
+    input_actions::{CLIENT_ACTIONS_NAMESPACE, CONSOLE_ACTIONS_NAMESPACE},
+    io_batcher::{IOBatcher, IOBatcherTask},
+};
+
+// aliases that execute themselves are stopped at this depth
+const MAX_COMMAND_DEPTH: usize = 16;
+// aliases that execute themselves more than once (e.g. `alias a "a;a"`) stay below
+// the depth for a very long time, so a single `run` executes at most this many commands
+const MAX_COMMANDS_PER_RUN: usize = 4096;
+
+/**
+ * Quotes the argument if it would otherwise be split into more arguments or commands
+ */
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == ';')
+    {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/**
+ * Splits a command into its arguments like the console does,
+ * arguments with spaces are quoted and `\"` is an escaped quote.
+ */
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut chars = command.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(c) = chars.next() else {
+            break;
+        };
+        let mut arg = String::new();
+        if c == '"' {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            arg.push(escaped);
+                        }
+                    }
+                    c => arg.push(c),
+                }
+            }
+        } else {
+            arg.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        res.push(arg);
+    }
+    res
+}
+
+/**
+ * Splits a line at the `;` that are not inside of quotes
+ */
+pub fn split_commands(line: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    let mut cur = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' if in_quotes => {
+                cur.push(c);
+                if let Some(escaped) = chars.next() {
+                    cur.push(escaped);
+                }
+                continue;
+            }
+            ';' if !in_quotes => {
+                res.push(std::mem::take(&mut cur));
+                continue;
+            }
+            _ => {}
+        }
+        cur.push(c);
+    }
+    res.push(cur);
+    res.retain(|command| !command.trim().is_empty());
+    res
+}
+
+/**
+ * Replaces `$1`..`$9` with the arguments and `$*` with all arguments.
+ * It's a single pass, so a `$1` inside of an argument stays as is.
+ */
+fn substitute_args(command: &str, args: &[String]) -> String {
+    let mut res = String::with_capacity(command.len());
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            if chars.next_if_eq(&'*').is_some() {
+                res.push_str(
+                    &args
+                        .iter()
+                        .map(|arg| quote_arg(arg))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+                continue;
+            }
+            if let Some(index) = chars.next_if(|c| ('1'..='9').contains(c)) {
+                // missing arguments are empty
+                if let Some(arg) = args.get(index as usize - '1' as usize) {
+                    res.push_str(&quote_arg(arg));
+                }
+                continue;
+            }
+        }
+        res.push(c);
+    }
+    res
+}
+
+/**
+ * Commands that are not part of the console itself, e.g. the game commands of the client
+ */
+pub trait ConsoleCommandHandler {
+    /**
+     * Returns `None` if the command is unknown
+     */
+    fn handle_command(
+        &mut self,
+        name: &str,
+        args: &[String],
+        output: &mut Vec<String>,
+    ) -> Option<Result<(), String>>;
+}
+
+/**
+ * For commands that run before there is something to handle game commands, e.g. the autoexec
+ */
+pub struct NoConsoleCommands;
+
+impl ConsoleCommandHandler for NoConsoleCommands {
+    fn handle_command(
+        &mut self,
+        _name: &str,
+        _args: &[String],
+        _output: &mut Vec<String>,
+    ) -> Option<Result<(), String>> {
+        None
+    }
+}
+
+struct QueuedCommand {
+    line: String,
+    depth: usize,
+}
+
+/**
+ * A file of `exec` that is still read, the commands queued after it wait for it
+ */
+struct PendingExec {
+    task: IOBatcherTask<Vec<u8>>,
+    path: String,
+    depth: usize,
+}
+
+/**
+ * The command system on top of the config: config variables, aliases,
+ * key binds and exec files. Aliases and binds are part of the config,
+ * so they are saved with it.
+ * Commands are queued and only executed in `run`, so a command can
+ * safely queue more commands (e.g. `exec` or an alias).
+ */
+#[derive(Default)]
+pub struct Console {
+    queue: VecDeque<QueuedCommand>,
+    pending_exec: Option<PendingExec>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Queues a line of commands separated by `;`
+     */
+    pub fn queue(&mut self, line: &str) {
+        self.queue_with_depth(line, 0);
+    }
+
+    fn queue_with_depth(&mut self, line: &str, depth: usize) {
+        self.queue.extend(
+            split_commands(line)
+                .into_iter()
+                .map(|line| QueuedCommand { line, depth }),
+        );
+    }
+
+    /**
+     * Queues the command bound to the input (key, mouse or gamepad button),
+     * returns false if the input is not bound
+     */
+    pub fn input_pressed(&mut self, config: &Config, input: &str) -> bool {
+        let commands = config
+            .cl_input_actions
+            .actions(CONSOLE_ACTIONS_NAMESPACE, input);
+        for command in &commands {
+            self.queue(command);
+        }
+        !commands.is_empty()
+    }
+
+    /**
+     * Executes all queued commands, returns the output lines.
+     * `exec` reads its files over the file system, so they are searched
+     * in the config directory first, like all other user files.
+     * The file is read in the background, the commands after `exec`
+     * run in a later call once the file was read
+     */
+    pub fn run(
+        &mut self,
+        config: &mut Config,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+        commands: &mut dyn ConsoleCommandHandler,
+    ) -> Vec<String> {
+        let mut output: Vec<String> = Vec::new();
+        let mut executed_commands = 0;
+        loop {
+            if let Some(pending) = &self.pending_exec {
+                if !pending.task.is_finished() {
+                    break;
+                }
+                let pending = self.pending_exec.take().unwrap();
+                if let Err(err) = self.queue_exec_file(pending) {
+                    output.push(err);
+                }
+            }
+            let Some(command) = self.queue.pop_front() else {
+                break;
+            };
+            executed_commands += 1;
+            if executed_commands > MAX_COMMANDS_PER_RUN {
+                output.push(format!(
+                    "more than {} commands at once, the remaining {} commands are skipped",
+                    MAX_COMMANDS_PER_RUN,
+                    self.queue.len() + 1
+                ));
+                self.queue.clear();
+                break;
+            }
+            if let Err(err) =
+                self.exec_command(config, fs, io_batcher, commands, &command, &mut output)
+            {
+                output.push(err);
+            }
+        }
+        output
+    }
+
+    /**
+     * Like `run`, but waits for the files of `exec`,
+     * for the commands before the first frame (e.g. the autoexec)
+     */
+    pub fn run_blocking(
+        &mut self,
+        config: &mut Config,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+        commands: &mut dyn ConsoleCommandHandler,
+    ) -> Vec<String> {
+        let mut output = self.run(config, fs, io_batcher, commands);
+        while let Some(pending) = &mut self.pending_exec {
+            io_batcher.lock().unwrap().wait_finished(&mut pending.task);
+            output.append(&mut self.run(config, fs, io_batcher, commands));
+        }
+        output
+    }
+
+    /**
+     * The commands of the file run before the commands queued after `exec`
+     */
+    fn queue_exec_file(&mut self, mut pending: PendingExec) -> Result<(), String> {
+        let file = pending.task.get_storage().map_err(|err| err.to_string())?;
+        let file = String::from_utf8(file)
+            .map_err(|_| format!("exec: {} is not valid utf-8", pending.path))?;
+        let mut queued_after = std::mem::take(&mut self.queue);
+        for line in file
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+        {
+            self.queue_with_depth(line, pending.depth);
+        }
+        self.queue.append(&mut queued_after);
+        Ok(())
+    }
+
+    fn exec_command(
+        &mut self,
+        config: &mut Config,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+        commands: &mut dyn ConsoleCommandHandler,
+        command: &QueuedCommand,
+        output: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let args = split_command(&command.line);
+        let Some((name, args)) = args.split_first() else {
+            return Ok(());
+        };
+        let arg = |index: usize| {
+            args.get(index)
+                .ok_or_else(|| format!("{}: expected at least {} argument(s)", name, index + 1))
+        };
+        match name.as_str() {
+            "echo" => output.push(args.join(" ")),
+            "alias" => {
+                config
+                    .console_aliases
+                    .insert(arg(0)?.clone(), arg(1)?.clone());
+            }
+            "unalias" => {
+                config.console_aliases.remove(arg(0)?);
+            }
+            "bind" => {
+                let (input, bind_command) = (arg(0)?.to_lowercase(), arg(1)?);
+                let actions = &mut config.cl_input_actions;
+                // an input executes only one command
+                actions.unbind_input(CONSOLE_ACTIONS_NAMESPACE, &input);
+                actions.bind(CONSOLE_ACTIONS_NAMESPACE, bind_command, &input);
+                // the console binds and the actions of the client are active at the same time
+                for conflict in actions
+                    .conflicts(&[CLIENT_ACTIONS_NAMESPACE, CONSOLE_ACTIONS_NAMESPACE])
+                    .into_iter()
+                    .filter(|conflict| conflict.input == input)
+                {
+                    output.push(format!(
+                        "bind: {} is bound more than once: {}",
+                        input,
+                        conflict
+                            .actions
+                            .iter()
+                            .map(|(namespace, action)| format!("{}/{}", namespace, action))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+            }
+            "unbind" => {
+                config
+                    .cl_input_actions
+                    .unbind_input(CONSOLE_ACTIONS_NAMESPACE, arg(0)?);
+            }
+            "exec" => {
+                let depth = command.depth + 1;
+                if depth > MAX_COMMAND_DEPTH {
+                    return Err("exec: files execute each other too deep".to_string());
+                }
+                let fs = fs.clone();
+                let path = arg(0)?.clone();
+                let task_path = path.clone();
+                let task = io_batcher.lock().unwrap().spawn::<Vec<u8>, _>(async move {
+                    fs.open_file(&task_path).await.map_err(|err| {
+                        ArrayString::from(&format!("exec: could not read {}: {}", task_path, err))
+                            .unwrap_or_default()
+                    })
+                });
+                self.pending_exec = Some(PendingExec { task, path, depth });
+            }
+            _ => {
+                if let Some(alias) = config.console_aliases.get(name) {
+                    let depth = command.depth + 1;
+                    if depth > MAX_COMMAND_DEPTH {
+                        return Err(format!("{}: aliases execute each other too deep", name));
+                    }
+                    let expanded = substitute_args(alias, args);
+                    // the alias runs in place of this command
+                    let mut rest = std::mem::take(&mut self.queue);
+                    self.queue_with_depth(&expanded, depth);
+                    self.queue.append(&mut rest);
+                } else if let Some(res) = commands.handle_command(name, args, output) {
+                    res?;
+                } else if args.is_empty() {
+                    output.push(format!(
+                        "{} is {}",
+                        name,
+                        config
+                            .get_var(name)
+                            .ok_or_else(|| format!("unknown command or variable: {}", name))?
+                    ));
+                } else {
+                    config
+                        .set_var(name, &args[0])
+                        .map_err(|err| format!("{}: {}", name, err))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{config::Config, filesys::FileSystem, io_batcher::IOBatcher};
+
+    use super::{
+        quote_arg, split_command, split_commands, Console, NoConsoleCommands, MAX_COMMANDS_PER_RUN,
+    };
+
+    #[test]
+    fn quoting() {
+        for arg in ["a", "a b", "a;unbind x", "\"a\" \\", ""] {
+            assert_eq!(split_command(&quote_arg(arg)), vec![arg.to_string()]);
+            assert_eq!(split_commands(&format!("echo {}", quote_arg(arg))).len(), 1);
+        }
+        assert_eq!(
+            split_commands("echo \"a;b\"; echo c"),
+            vec!["echo \"a;b\"".to_string(), " echo c".to_string()]
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        let fs = Arc::new(FileSystem::new());
+        let io_batcher = Arc::new(Mutex::new(IOBatcher::new(
+            tokio::runtime::Builder::new_multi_thread().build().unwrap(),
+        )));
+        let mut config = Config::new();
+        let mut console = Console::new();
+        console.queue("alias all \"echo $*\"; alias swap \"echo $2 $1 $3\"");
+        // arguments are not substituted again and can't add commands
+        console.queue("all $2 \"b;unbind x\"; swap $1 b");
+        assert_eq!(
+            console.run(&mut config, &fs, &io_batcher, &mut NoConsoleCommands),
+            vec!["$2 b;unbind x".to_string(), "b $1".to_string()]
+        );
+    }
+
+    #[test]
+    fn command_budget() {
+        let fs = Arc::new(FileSystem::new());
+        let io_batcher = Arc::new(Mutex::new(IOBatcher::new(
+            tokio::runtime::Builder::new_multi_thread().build().unwrap(),
+        )));
+        let mut config = Config::new();
+        let mut console = Console::new();
+        // stays below the depth limit for 4^16 commands
+        console.queue("alias a \"a;a;a;a\"; a");
+        let output = console.run(&mut config, &fs, &io_batcher, &mut NoConsoleCommands);
+        let budget_err = format!("more than {}", MAX_COMMANDS_PER_RUN);
+        assert_eq!(
+            output
+                .iter()
+                .filter(|line| line.starts_with(&budget_err))
+                .count(),
+            1
+        );
+        assert!(output.last().unwrap().starts_with(&budget_err));
+        // the skipped commands don't run later
+        assert!(console
+            .run(&mut config, &fs, &io_batcher, &mut NoConsoleCommands)
+            .is_empty());
+    }
+}
@@ -1,8 +1,32 @@
+use std::sync::Arc;
+
 use arrayvec::ArrayString;
 use directories::ProjectDirs;
 
+use crate::filesys_archive::FileSystemArchive;
+
 const MAX_PATH_LEN: usize = 256;
 
+/**
+ * Turns a name (player name, map name, module name etc.) into a file name
+ * without path characters. Names that had to be changed get a hash of the original name,
+ * so different names never share a file (e.g. "a/b" and "a?b").
+ */
+pub fn file_name_from_name(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric()) {
+        return name.to_string();
+    }
+    // fnv-1a, the file names must stay the same across versions
+    let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}_{:08x}", sanitized, hash)
+}
+
 pub enum FileSystemType {
     // read write has a higher priority, since it contains user modifications
     ReadWrite,
@@ -25,13 +49,19 @@ pub struct FileSystem {
     config_dir_index: usize,
     data_dir_index: usize,
     exec_dir_index: usize,
+    // the mounted content packs, in the order of their priority
+    packs: Vec<String>,
+    // archives have a lower priority than all directories except the data,
+    // the last mounted archive has the highest priority
+    archives: Vec<(String, Arc<FileSystemArchive>)>,
 }
 
 impl FileSystem {
     pub fn new() -> Self {
         let mut config_dir: String = String::new();
         if let Some(proj_dirs) = ProjectDirs::from("org", "", "DDNet") {
-            config_dir = proj_dirs.config_dir().to_str().unwrap().to_string();
+            // the file paths are appended to the directories
+            config_dir = proj_dirs.config_dir().to_str().unwrap().to_string() + "/";
         }
         let mut paths: Vec<String> = Vec::new();
         paths.push(config_dir);
@@ -39,7 +69,7 @@ impl FileSystem {
         paths.push("data/".to_string());
         let data_dir_index = paths.len() - 1;
         if let Ok(exec_path) = std::env::current_dir() {
-            paths.push(exec_path.to_str().unwrap().to_string());
+            paths.push(exec_path.to_str().unwrap().to_string() + "/");
         }
         // if worst case this is equal to the data dir
         let exec_dir_index = paths.len() - 1;
@@ -48,6 +78,131 @@ impl FileSystem {
             config_dir_index,
             data_dir_index,
             exec_dir_index,
+            packs: Vec::new(),
+            archives: Vec::new(),
+        }
+    }
+
+    /**
+     * Like [`FileSystem::new`], but writes into another directory, e.g. for tests
+     */
+    pub fn with_write_dir(write_dir: &str) -> Self {
+        let mut fs = Self::new();
+        fs.paths[fs.config_dir_index] = write_dir.trim_end_matches('/').to_string() + "/";
+        fs
+    }
+
+    /**
+     * Mounts a content pack (a directory) that overrides the files of the data
+     * and of all packs that were mounted before.
+     * User modifications in the read-write directory still have the highest priority.
+     */
+    pub fn mount_pack(&mut self, pack_path: &str) {
+        let mut pack_path = pack_path.to_string();
+        if !pack_path.ends_with('/') {
+            pack_path.push('/');
+        }
+        if self.packs.contains(&pack_path) {
+            return;
+        }
+        // directly behind the read-write directory
+        self.paths
+            .insert(self.config_dir_index + 1, pack_path.clone());
+        self.data_dir_index += 1;
+        self.exec_dir_index += 1;
+        self.packs.push(pack_path);
+    }
+
+    pub fn unmount_pack(&mut self, pack_path: &str) {
+        let mut pack_path = pack_path.to_string();
+        if !pack_path.ends_with('/') {
+            pack_path.push('/');
+        }
+        if let Some(pack_index) = self.packs.iter().position(|pack| *pack == pack_path) {
+            self.packs.remove(pack_index);
+            // packs are inserted behind the read-write directory in reverse order
+            self.paths
+                .remove(self.config_dir_index + self.packs.len() + 1 - pack_index);
+            self.data_dir_index -= 1;
+            self.exec_dir_index -= 1;
+        }
+    }
+
+    /**
+     * Mounts a zip archive (`.zip`/`.pk`) read-only, without extracting it
+     */
+    pub fn mount_archive(&mut self, archive_path: &str) -> anyhow::Result<()> {
+        if self.archives.iter().any(|(path, _)| path == archive_path) {
+            return Ok(());
+        }
+        let archive = FileSystemArchive::open(archive_path)?;
+        self.archives
+            .push((archive_path.to_string(), Arc::new(archive)));
+        Ok(())
+    }
+
+    pub fn unmount_archive(&mut self, archive_path: &str) {
+        self.archives.retain(|(path, _)| path != archive_path);
+    }
+
+    /**
+     * Writes are always redirected to the read-write directory,
+     * so the data and the content packs are never modified
+     */
+    pub async fn write_file(&self, file_path: &str, data: Vec<u8>) -> std::io::Result<()> {
+        let path = self.get_path(file_path, FileSystemPath::OfType(FileSystemType::ReadWrite));
+        if let Some(parent) = std::path::Path::new(path.as_str()).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path.as_str(), data).await
+    }
+
+    /**
+     * Removes a file of the read-write directory, files of all other directories can't be removed
+     */
+    pub async fn remove_file(&self, file_path: &str) -> std::io::Result<()> {
+        let path = self.get_path(file_path, FileSystemPath::OfType(FileSystemType::ReadWrite));
+        tokio::fs::remove_file(path.as_str()).await
+    }
+
+    /**
+     * Renames a file of the read-write directory, an existing file at `new_path` is replaced
+     */
+    pub async fn rename_file(&self, file_path: &str, new_path: &str) -> std::io::Result<()> {
+        let path = self.get_path(file_path, FileSystemPath::OfType(FileSystemType::ReadWrite));
+        let new_path = self.get_path(new_path, FileSystemPath::OfType(FileSystemType::ReadWrite));
+        tokio::fs::rename(path.as_str(), new_path.as_str()).await
+    }
+
+    /**
+     * All files of the directory and its sub directories, but only in the read-write directory.
+     * The file names are relative to the directory and separated by `/`
+     */
+    pub async fn files_of_write_dir_recursive<T>(&self, path: &str, file_read_cb: &mut T)
+    where
+        T: FnMut(String, Vec<u8>) + Send + Sync,
+    {
+        let full_path = self.get_path(path, FileSystemPath::OfType(FileSystemType::ReadWrite));
+        let mut dirs = vec![String::new()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(mut dir_reader) = tokio::fs::read_dir(full_path.to_string() + &dir).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = dir_reader.next_entry().await {
+                let Some(file_name) = entry.file_name().to_str().map(|name| dir.clone() + name)
+                else {
+                    continue;
+                };
+                match entry.file_type().await {
+                    Ok(file_type) if file_type.is_dir() => dirs.push(file_name + "/"),
+                    Ok(file_type) if file_type.is_file() => {
+                        if let Ok(file) = tokio::fs::read(entry.path()).await {
+                            file_read_cb(file_name, file);
+                        }
+                    }
+                    _ => {}
+                }
+            }
         }
     }
 
@@ -68,6 +223,21 @@ impl FileSystem {
 
     pub async fn open_file(&self, file_path: &str) -> std::io::Result<Vec<u8>> {
         for (path_index, _) in self.paths.iter().enumerate() {
+            if path_index == self.data_dir_index {
+                if let Some((_, archive)) = self
+                    .archives
+                    .iter()
+                    .rev()
+                    .find(|(_, archive)| archive.contains(file_path))
+                {
+                    // reading the archive blocks
+                    let archive = archive.clone();
+                    let file_path = file_path.to_string();
+                    return tokio::task::spawn_blocking(move || archive.read(&file_path))
+                        .await
+                        .map_err(std::io::Error::other)?;
+                }
+            }
             let file = tokio::fs::read(
                 self.get_path(file_path, FileSystemPath::Index(path_index))
                     .as_str(),
@@ -112,12 +282,42 @@ impl FileSystem {
         }
     }
 
+    async fn archive_files_of_dir<T>(
+        &self,
+        path: &str,
+        file_read_cb: &mut T,
+        file_list: &mut Vec<String>,
+    ) where
+        T: FnMut(String, Vec<u8>) + Send + Sync,
+    {
+        for (_, archive) in self.archives.iter().rev() {
+            for file_path in archive.files_of_dir(path) {
+                let file_name = file_path.rsplit('/').next().unwrap_or_default().to_string();
+                if file_list.contains(&file_name) {
+                    continue;
+                }
+                // reading the archive blocks
+                let archive = archive.clone();
+                let file = tokio::task::spawn_blocking(move || archive.read(&file_path)).await;
+                if let Ok(Ok(f)) = file {
+                    file_list.push(file_name.clone());
+                    file_read_cb(file_name, f);
+                }
+            }
+        }
+    }
+
     pub async fn files_of_dir<'a, T>(&self, path: &str, file_read_cb: &'a mut T)
     where
         T: FnMut(String, Vec<u8>) + Send + Sync,
     {
         let mut file_list = Vec::<String>::new();
         for (path_index, _) in self.paths.iter().enumerate() {
+            // same priority as in `open_file`
+            if path_index == self.data_dir_index {
+                self.archive_files_of_dir(path, file_read_cb, &mut file_list)
+                    .await;
+            }
             self.files_of_dir_impl(
                 path,
                 file_read_cb,
@@ -128,3 +328,19 @@ impl FileSystem {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::file_name_from_name;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name_from_name("name1"), "name1");
+        let names = ["a/b", "a?b", "a_b", "a_b_", "../..", ""];
+        let file_names: Vec<String> = names.iter().map(|name| file_name_from_name(name)).collect();
+        for (i, file_name) in file_names.iter().enumerate() {
+            assert!(file_name.chars().all(|c| c.is_alphanumeric() || c == '_'));
+            assert!(!file_names[i + 1..].contains(file_name));
+        }
+    }
+}
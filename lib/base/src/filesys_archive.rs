@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    sync::Mutex,
+};
+
+use anyhow::anyhow;
+
+// end of central directory record
+const EOCD_SIGNATURE: u32 = 0x06054b50;
+const EOCD_MIN_SIZE: usize = 22;
+const CENTRAL_DIR_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const LOCAL_HEADER_SIZE: usize = 30;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+// deflate can't compress better than this, larger uncompressed sizes are broken
+const DEFLATE_MAX_RATIO: u64 = 1032;
+
+struct ArchiveEntry {
+    method: u16,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: u64,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/**
+ * A read-only zip archive (also `.pk` files) that is mounted into the file system.
+ * The index of all files is built once when the archive is opened,
+ * a file is only decompressed when it's read.
+ * Only stored and deflated files are supported, no zip64 and no encryption.
+ */
+pub struct FileSystemArchive {
+    file: Mutex<File>,
+    entries: HashMap<String, ArchiveEntry>,
+}
+
+impl FileSystemArchive {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut file = File::open(path)?;
+        let file_size = file.seek(SeekFrom::End(0))?;
+
+        // the end of central directory record is at the end of the file,
+        // followed by a comment of at most u16::MAX bytes
+        let tail_size = file_size.min((EOCD_MIN_SIZE + u16::MAX as usize) as u64);
+        let mut tail = vec![0; tail_size as usize];
+        file.seek(SeekFrom::Start(file_size - tail_size))?;
+        file.read_exact(&mut tail)?;
+        if tail.len() < EOCD_MIN_SIZE {
+            return Err(anyhow!("{} is not a zip archive", path));
+        }
+        let eocd = (0..=tail.len().saturating_sub(EOCD_MIN_SIZE))
+            .rev()
+            .find(|offset| read_u32(&tail, *offset) == EOCD_SIGNATURE)
+            .ok_or_else(|| anyhow!("{} is not a zip archive", path))?;
+        let entry_count = read_u16(&tail, eocd + 10) as usize;
+        let central_dir_size = read_u32(&tail, eocd + 12) as usize;
+        let central_dir_offset = read_u32(&tail, eocd + 16) as u64;
+        // all sizes come from the archive, they are checked before anything is allocated
+        if central_dir_offset + central_dir_size as u64 > file_size {
+            return Err(anyhow!("the central directory of {} is broken", path));
+        }
+
+        let mut central_dir = vec![0; central_dir_size];
+        file.seek(SeekFrom::Start(central_dir_offset))?;
+        file.read_exact(&mut central_dir)?;
+
+        let mut entries = HashMap::with_capacity(entry_count);
+        let mut offset = 0;
+        for _ in 0..entry_count {
+            if offset + 46 > central_dir.len()
+                || read_u32(&central_dir, offset) != CENTRAL_DIR_SIGNATURE
+            {
+                return Err(anyhow!("the central directory of {} is broken", path));
+            }
+            let name_len = read_u16(&central_dir, offset + 28) as usize;
+            let extra_len = read_u16(&central_dir, offset + 30) as usize;
+            let comment_len = read_u16(&central_dir, offset + 32) as usize;
+            let name_bytes = central_dir
+                .get(offset + 46..offset + 46 + name_len)
+                .ok_or_else(|| anyhow!("the central directory of {} is broken", path))?;
+            let name = String::from_utf8_lossy(name_bytes).replace('\\', "/");
+            // directories have no content
+            if !name.ends_with('/') {
+                let entry = ArchiveEntry {
+                    method: read_u16(&central_dir, offset + 10),
+                    compressed_size: read_u32(&central_dir, offset + 20) as u64,
+                    uncompressed_size: read_u32(&central_dir, offset + 24) as u64,
+                    local_header_offset: read_u32(&central_dir, offset + 42) as u64,
+                };
+                if entry.local_header_offset + LOCAL_HEADER_SIZE as u64 + entry.compressed_size
+                    > file_size
+                    || entry.uncompressed_size
+                        > entry.compressed_size.saturating_mul(DEFLATE_MAX_RATIO)
+                {
+                    return Err(anyhow!("the size of {} in {} is broken", name, path));
+                }
+                entries.insert(name, entry);
+            }
+            offset += 46 + name_len + extra_len + comment_len;
+        }
+
+        Ok(Self {
+            file: Mutex::new(file),
+            entries,
+        })
+    }
+
+    pub fn contains(&self, file_path: &str) -> bool {
+        self.entries.contains_key(file_path)
+    }
+
+    /**
+     * The paths of all files directly inside of `dir_path`, without sub directories
+     */
+    pub fn files_of_dir(&self, dir_path: &str) -> Vec<String> {
+        let mut dir_path = dir_path.to_string();
+        if !dir_path.is_empty() && !dir_path.ends_with('/') {
+            dir_path.push('/');
+        }
+        self.entries
+            .keys()
+            .filter(|name| {
+                name.strip_prefix(dir_path.as_str())
+                    .is_some_and(|file_name| !file_name.contains('/'))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn read(&self, file_path: &str) -> std::io::Result<Vec<u8>> {
+        let entry = self
+            .entries
+            .get(file_path)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "file not found"))?;
+        let invalid_data =
+            |err: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string());
+
+        let mut compressed = vec![0; entry.compressed_size as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            let mut local_header = [0; LOCAL_HEADER_SIZE];
+            file.seek(SeekFrom::Start(entry.local_header_offset))?;
+            file.read_exact(&mut local_header)?;
+            if read_u32(&local_header, 0) != LOCAL_HEADER_SIGNATURE {
+                return Err(invalid_data("broken local file header"));
+            }
+            // the extra field can differ from the one in the central directory
+            let data_offset = LOCAL_HEADER_SIZE as u64
+                + read_u16(&local_header, 26) as u64
+                + read_u16(&local_header, 28) as u64;
+            file.seek(SeekFrom::Start(entry.local_header_offset + data_offset))?;
+            file.read_exact(&mut compressed)?;
+        }
+
+        match entry.method {
+            METHOD_STORED => Ok(compressed),
+            METHOD_DEFLATE => {
+                let mut res = Vec::with_capacity(entry.uncompressed_size as usize);
+                // one byte more than expected is enough to detect a wrong size
+                flate2::read::DeflateDecoder::new(compressed.as_slice())
+                    .take(entry.uncompressed_size + 1)
+                    .read_to_end(&mut res)?;
+                if res.len() as u64 != entry.uncompressed_size {
+                    return Err(invalid_data("the decompressed size does not match"));
+                }
+                Ok(res)
+            }
+            _ => Err(invalid_data("unsupported compression method")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::filesys::FileSystem;
+
+    use super::FileSystemArchive;
+
+    // an archive with stored (uncompressed) files
+    fn stored_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut central_dir = Vec::new();
+        for (name, content) in files {
+            let local_header_offset = zip.len() as u32;
+            let size = (content.len() as u32).to_le_bytes();
+            // signature, version, flags, method, time, date, crc
+            zip.extend([0x50, 0x4b, 0x03, 0x04, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            zip.extend([0, 0, 0, 0]);
+            zip.extend(size);
+            zip.extend(size);
+            zip.extend((name.len() as u16).to_le_bytes());
+            zip.extend([0, 0]);
+            zip.extend(name.as_bytes());
+            zip.extend(*content);
+
+            // signature, versions, flags, method, time, date, crc
+            central_dir.extend([0x50, 0x4b, 0x01, 0x02, 20, 0, 20, 0, 0, 0, 0, 0]);
+            central_dir.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+            central_dir.extend(size);
+            central_dir.extend(size);
+            central_dir.extend((name.len() as u16).to_le_bytes());
+            // extra, comment, disk, attributes
+            central_dir.extend([0; 12]);
+            central_dir.extend(local_header_offset.to_le_bytes());
+            central_dir.extend(name.as_bytes());
+        }
+        let central_dir_offset = zip.len() as u32;
+        zip.extend(&central_dir);
+        zip.extend([0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0]);
+        zip.extend((files.len() as u16).to_le_bytes());
+        zip.extend((files.len() as u16).to_le_bytes());
+        zip.extend((central_dir.len() as u32).to_le_bytes());
+        zip.extend(central_dir_offset.to_le_bytes());
+        zip.extend([0, 0]);
+        zip
+    }
+
+    #[test]
+    fn files_of_archive_dir() {
+        let path = std::env::temp_dir().join("filesys_archive_dir_test.zip");
+        let path_str = path.to_str().unwrap();
+        std::fs::write(
+            &path,
+            stored_zip(&[
+                ("archive_test_dir/a.txt", b"a"),
+                ("archive_test_dir/sub/b.txt", b"b"),
+                ("archive_test_other/c.txt", b"c"),
+            ]),
+        )
+        .unwrap();
+
+        let mut fs = FileSystem::new();
+        fs.mount_archive(path_str).unwrap();
+        let mut files = Vec::new();
+        tokio::runtime::Runtime::new().unwrap().block_on(
+            fs.files_of_dir("archive_test_dir/", &mut |file_name, file| {
+                files.push((file_name, file))
+            }),
+        );
+        assert_eq!(files, vec![("a.txt".to_string(), b"a".to_vec())]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn broken_archives() {
+        let path = std::env::temp_dir().join("filesys_archive_test.zip");
+        let path_str = path.to_str().unwrap();
+
+        // shorter than the end of central directory record
+        std::fs::write(&path, [0x50, 0x4b, 0x05]).unwrap();
+        assert!(FileSystemArchive::open(path_str).is_err());
+
+        // a central directory that is larger than the file
+        let mut eocd = vec![0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0, 1, 0, 1, 0];
+        eocd.extend(u32::MAX.to_le_bytes());
+        eocd.extend([0, 0, 0, 0, 0, 0]);
+        std::fs::write(&path, &eocd).unwrap();
+        assert!(FileSystemArchive::open(path_str).is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSystemEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSystemEvent {
+    pub path: PathBuf,
+    pub kind: FileSystemEventKind,
+}
+
+pub struct FileSystemWatcherSettings {
+    pub recursive: bool,
+    // only files with one of these extensions (without dot) are watched,
+    // empty means all files
+    pub extensions: Vec<String>,
+    // a change is only reported once the file did not change for this time,
+    // e.g. editors that write a file in multiple steps
+    pub debounce: Duration,
+}
+
+impl Default for FileSystemWatcherSettings {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            extensions: Vec::new(),
+            debounce: Duration::from_millis(200),
+        }
+    }
+}
+
+/**
+ * Watches the files under a path by polling their modification times,
+ * so hot reloading (shaders, maps, ui mods) knows exactly what changed.
+ * Polling is cheap enough for the few directories that are watched
+ * and works the same on every platform.
+ */
+pub struct FileSystemWatcher {
+    path: PathBuf,
+    settings: FileSystemWatcherSettings,
+    files: HashMap<PathBuf, SystemTime>,
+    // changes that wait for the debounce time, the time of the last change
+    pending: HashMap<PathBuf, (FileSystemEventKind, Instant)>,
+}
+
+impl FileSystemWatcher {
+    pub fn new(path: &str, settings: FileSystemWatcherSettings) -> Self {
+        let mut res = Self {
+            path: PathBuf::from(path),
+            settings,
+            files: Default::default(),
+            pending: Default::default(),
+        };
+        res.files = res.scan();
+        res
+    }
+
+    fn is_watched_file(&self, path: &Path) -> bool {
+        self.settings.extensions.is_empty()
+            || path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    self.settings
+                        .extensions
+                        .iter()
+                        .any(|watched_ext| watched_ext.eq_ignore_ascii_case(ext))
+                })
+    }
+
+    fn scan_dir(&self, dir: &Path, files: &mut HashMap<PathBuf, SystemTime>) {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                let path = entry.path();
+                if meta.is_dir() {
+                    if self.settings.recursive {
+                        self.scan_dir(&path, files);
+                    }
+                } else if self.is_watched_file(&path) {
+                    files.insert(path, meta.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+                }
+            }
+        }
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let mut files = HashMap::new();
+        self.scan_dir(&self.path, &mut files);
+        files
+    }
+
+    fn add_pending(&mut self, path: PathBuf, kind: FileSystemEventKind, now: Instant) {
+        let kind = match (self.pending.get(&path).map(|(kind, _)| *kind), kind) {
+            // a file that was created and modified in the debounce time was only created
+            (Some(FileSystemEventKind::Created), FileSystemEventKind::Modified) => {
+                FileSystemEventKind::Created
+            }
+            // removed and created again is a modification
+            (Some(FileSystemEventKind::Removed), FileSystemEventKind::Created) => {
+                FileSystemEventKind::Modified
+            }
+            // created and removed again, nobody saw the file
+            (Some(FileSystemEventKind::Created), FileSystemEventKind::Removed) => {
+                self.pending.remove(&path);
+                return;
+            }
+            (_, kind) => kind,
+        };
+        self.pending.insert(path, (kind, now));
+    }
+
+    /**
+     * Returns all changes since the last poll that are older than the debounce time
+     */
+    pub fn poll(&mut self) -> Vec<FileSystemEvent> {
+        let now = Instant::now();
+        let files = self.scan();
+        let old_files = std::mem::take(&mut self.files);
+        for (path, modified) in &files {
+            match old_files.get(path) {
+                None => self.add_pending(path.clone(), FileSystemEventKind::Created, now),
+                Some(old_modified) if old_modified != modified => {
+                    self.add_pending(path.clone(), FileSystemEventKind::Modified, now)
+                }
+                _ => {}
+            }
+        }
+        for path in old_files.keys().filter(|path| !files.contains_key(*path)) {
+            self.add_pending(path.clone(), FileSystemEventKind::Removed, now);
+        }
+        self.files = files;
+
+        let debounce = self.settings.debounce;
+        let mut res: Vec<FileSystemEvent> = Vec::new();
+        self.pending.retain(|path, (kind, last_change)| {
+            if now.duration_since(*last_change) >= debounce {
+                res.push(FileSystemEvent {
+                    path: path.clone(),
+                    kind: *kind,
+                });
+                false
+            } else {
+                true
+            }
+        });
+        res.sort_by(|ev1, ev2| ev1.path.cmp(&ev2.path));
+        res
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// the namespace of the actions of the client itself, mods use their own name
+pub const CLIENT_ACTIONS_NAMESPACE: &str = "client";
+// the binds of the console, the actions are the commands that the inputs execute
+pub const CONSOLE_ACTIONS_NAMESPACE: &str = "console";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputActionConflict {
+    pub input: String,
+    // (namespace, action) of all actions that use the input
+    pub actions: Vec<(String, String)>,
+}
+
+/**
+ * Maps actions (e.g. "jump") to physical inputs (e.g. "space", "mouse1", "gamepad_a").
+ * Game mods and ui pages query actions instead of key codes, so players can rebind them.
+ * Every mod has its own namespace, an input can be bound in multiple namespaces
+ * (e.g. a mod that is only active in some menu), but should only be used once per namespace.
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputActions {
+    // namespace -> action -> inputs
+    binds: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+impl InputActions {
+    fn action_mut(&mut self, namespace: &str, action: &str) -> &mut Vec<String> {
+        self.binds
+            .entry(namespace.to_string())
+            .or_default()
+            .entry(action.to_string())
+            .or_default()
+    }
+
+    /**
+     * Registers an action with its default inputs, if the player did not bind it already
+     */
+    pub fn register(&mut self, namespace: &str, action: &str, default_inputs: &[&str]) {
+        let namespace_binds = self.binds.entry(namespace.to_string()).or_default();
+        if !namespace_binds.contains_key(action) {
+            namespace_binds.insert(
+                action.to_string(),
+                default_inputs
+                    .iter()
+                    .map(|input| input.to_lowercase())
+                    .collect(),
+            );
+        }
+    }
+
+    pub fn bind(&mut self, namespace: &str, action: &str, input: &str) {
+        let input = input.to_lowercase();
+        let inputs = self.action_mut(namespace, action);
+        if !inputs.contains(&input) {
+            inputs.push(input);
+        }
+    }
+
+    pub fn unbind(&mut self, namespace: &str, action: &str, input: &str) {
+        let input = input.to_lowercase();
+        self.action_mut(namespace, action)
+            .retain(|bound_input| *bound_input != input);
+    }
+
+    /**
+     * Removes the input from all actions of the namespace,
+     * actions without any inputs left are removed completely
+     */
+    pub fn unbind_input(&mut self, namespace: &str, input: &str) {
+        let input = input.to_lowercase();
+        if let Some(namespace_binds) = self.binds.get_mut(namespace) {
+            namespace_binds.retain(|_, inputs| {
+                inputs.retain(|bound_input| *bound_input != input);
+                !inputs.is_empty()
+            });
+        }
+    }
+
+    pub fn inputs(&self, namespace: &str, action: &str) -> &[String] {
+        self.binds
+            .get(namespace)
+            .and_then(|namespace_binds| namespace_binds.get(action))
+            .map_or(&[], |inputs| inputs.as_slice())
+    }
+
+    /**
+     * All actions of the namespace that the input triggers
+     */
+    pub fn actions<'a>(&'a self, namespace: &str, input: &str) -> Vec<&'a str> {
+        let input = input.to_lowercase();
+        self.binds
+            .get(namespace)
+            .map(|namespace_binds| {
+                namespace_binds
+                    .iter()
+                    .filter(|(_, inputs)| inputs.contains(&input))
+                    .map(|(action, _)| action.as_str())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn is_action(&self, namespace: &str, action: &str, input: &str) -> bool {
+        let input = input.to_lowercase();
+        self.inputs(namespace, action).contains(&input)
+    }
+
+    /**
+     * Inputs that trigger more than one action in the namespaces,
+     * e.g. to warn in the bind settings about namespaces that are active at the same time
+     */
+    pub fn conflicts(&self, namespaces: &[&str]) -> Vec<InputActionConflict> {
+        let mut actions_per_input: BTreeMap<&str, Vec<(String, String)>> = BTreeMap::new();
+        for namespace in namespaces {
+            if let Some(namespace_binds) = self.binds.get(*namespace) {
+                for (action, inputs) in namespace_binds {
+                    for input in inputs {
+                        actions_per_input
+                            .entry(input)
+                            .or_default()
+                            .push((namespace.to_string(), action.clone()));
+                    }
+                }
+            }
+        }
+        actions_per_input
+            .into_iter()
+            .filter(|(_, actions)| actions.len() > 1)
+            .map(|(input, actions)| InputActionConflict {
+                input: input.to_string(),
+                actions,
+            })
+            .collect()
+    }
+}
@@ -1,17 +1,48 @@
 use std::{
     collections::VecDeque,
     future::Future,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        Arc,
+    },
 };
 
 use arrayvec::ArrayString;
 use tokio::task::JoinHandle;
 
+/**
+ * Shared between a task and its owner, the task must check for cancellation
+ * itself (e.g. between chunks of a download) and can report its progress
+ */
+#[derive(Clone, Default)]
+pub struct IOBatcherTaskControl {
+    cancelled: Arc<AtomicBool>,
+    progress_done: Arc<AtomicU64>,
+    progress_total: Arc<AtomicU64>,
+}
+
+impl IOBatcherTaskControl {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /**
+     * E.g. bytes done and total bytes
+     */
+    pub fn set_progress(&self, done: u64, total: u64) {
+        self.progress_total
+            .store(total, std::sync::atomic::Ordering::Relaxed);
+        self.progress_done
+            .store(done, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
 pub struct IOBatcherTask<S> {
     pub queue_id: u64,
     storage: Option<S>,
     storage_task: Arc<tokio::sync::Mutex<(Option<S>, Option<ArrayString<4096>>)>>,
     is_finished: Arc<AtomicBool>,
+    control: IOBatcherTaskControl,
 }
 
 impl<S> IOBatcherTask<S> {
@@ -32,6 +63,34 @@ impl<S> IOBatcherTask<S> {
     pub fn is_finished(&self) -> bool {
         self.is_finished.load(std::sync::atomic::Ordering::SeqCst)
     }
+
+    /**
+     * Asks the task to stop, it still has to be waited for
+     */
+    pub fn cancel(&self) {
+        self.control
+            .cancelled
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+
+    /**
+     * (done, total), `None` if the task never reported a progress
+     */
+    pub fn progress(&self) -> Option<(u64, u64)> {
+        let total = self
+            .control
+            .progress_total
+            .load(std::sync::atomic::Ordering::Relaxed);
+        let done = self
+            .control
+            .progress_done
+            .load(std::sync::atomic::Ordering::Relaxed);
+        (total > 0).then_some((done.min(total), total))
+    }
 }
 
 pub struct IOBatcher {
@@ -53,6 +112,24 @@ impl IOBatcher {
     where
         F: Future<Output = Result<S, ArrayString<4096>>> + Send + Sync + 'static,
     {
+        self.spawn_with_control(|_| task)
+    }
+
+    /**
+     * Like `spawn`, but the task gets a control to check for cancellation
+     * and to report its progress
+     */
+    pub fn spawn_with_control<S: Send + Sync + 'static, F, T>(
+        &mut self,
+        task: T,
+    ) -> IOBatcherTask<S>
+    where
+        T: FnOnce(IOBatcherTaskControl) -> F,
+        F: Future<Output = Result<S, ArrayString<4096>>> + Send + Sync + 'static,
+    {
+        let control = IOBatcherTaskControl::default();
+        let task = task(control.clone());
+
         let id = self.task_id;
         self.task_id += 1;
 
@@ -85,6 +162,7 @@ impl IOBatcher {
             storage: None,
             storage_task: storage_task_clone,
             is_finished: task_finished,
+            control,
         }
     }
 
@@ -1,5 +1,9 @@
 pub mod config;
+pub mod console;
 pub mod filesys;
+pub mod filesys_archive;
+pub mod filesys_watcher;
+pub mod input_actions;
 pub mod io_batcher;
 pub mod system;
 pub mod benchmark;
@@ -1,7 +1,72 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use arrayvec::ArrayString;
 
+// the last log messages of all systems, e.g. for crash reports
+const RECENT_LOGS_MAX: usize = 256;
+static RECENT_LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/**
+ * The last log messages, oldest first
+ */
+pub fn recent_logs() -> Vec<String> {
+    // the logs are also read while panicking, so never block
+    match RECENT_LOGS.try_lock() {
+        Ok(logs) => logs.iter().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn add_recent_log(line: String) {
+    // a thread that panicked while logging does not make the logs invalid
+    let mut logs = RECENT_LOGS.lock().unwrap_or_else(|err| err.into_inner());
+    if logs.len() >= RECENT_LOGS_MAX {
+        logs.pop_front();
+    }
+    logs.push_back(line);
+}
+
+/**
+ * Prints the line and keeps it for crash reports,
+ * for code that has no access to the system's log, see `log_println!`
+ */
+pub fn log_line(line: String) {
+    println!("{}", line);
+    add_recent_log(line);
+}
+
+/**
+ * Only prints the line, for lines with personal information
+ * (e.g. player names, addresses or config values) that must not end up in crash reports
+ */
+pub fn log_line_private(line: String) {
+    println!("{}", line);
+}
+
+/**
+ * `println!` that also keeps the line for crash reports
+ */
+#[macro_export]
+macro_rules! log_println {
+    ($($arg:tt)*) => {
+        $crate::system::log_line(format!($($arg)*))
+    };
+}
+
+/**
+ * `println!` for lines that are not kept for crash reports, see `log_line_private`
+ */
+#[macro_export]
+macro_rules! log_println_private {
+    ($($arg:tt)*) => {
+        $crate::system::log_line_private(format!($($arg)*))
+    };
+}
+
 #[derive(Clone)]
 pub struct LogItem {
     msg: ArrayString<4096>,
@@ -48,7 +113,7 @@ impl<'a> LogItemConcat<'a> {
 
 impl<'a> Drop for LogItemConcat<'a> {
     fn drop(&mut self) {
-        println!("{}", self.log_item.msg.as_str());
+        log_line(self.log_item.msg.to_string());
     }
 }
 
@@ -41,6 +41,7 @@ use crate::{
 };
 
 use base::config::EDebugGFXModes;
+use base::log_println;
 use base::system::{self, SystemLogInterface};
 use math::math::vector::{vec2, vec4};
 
@@ -3206,11 +3207,11 @@ impl VulkanBackend {
         _pUserData: *mut c_void,
     ) -> vk::Bool32 {
         if !(MessageSeverity & vk::DebugUtilsMessageSeverityFlagsEXT::ERROR).is_empty() {
-            println!("[vulkan debug] error: {}", unsafe {
+            log_println!("[vulkan debug] error: {}", unsafe {
                 CStr::from_ptr((*pCallbackData).p_message).to_str().unwrap()
             });
         } else {
-            println!("[vulkan debug] {}", unsafe {
+            log_println!("[vulkan debug] {}", unsafe {
                 CStr::from_ptr((*pCallbackData).p_message).to_str().unwrap()
             });
         }
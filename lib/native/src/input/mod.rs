@@ -1,6 +1,13 @@
 pub mod sdl_to_egui;
 
-use sdl2::{keyboard::Scancode, mouse::MouseButton, *};
+use std::cell::RefCell;
+
+use sdl2::{
+    controller::{Button, GameController},
+    keyboard::Scancode,
+    mouse::MouseButton,
+    *,
+};
 
 use super::native::Native;
 
@@ -10,6 +17,8 @@ pub trait InputEventHandler {
     fn mouse_down(&mut self, device: u32, x: i32, y: i32, btn: MouseButton);
     fn mouse_up(&mut self, device: u32, x: i32, y: i32, btn: MouseButton);
     fn mouse_move(&mut self, device: u32, x: i32, y: i32, xrel: i32, yrel: i32);
+    fn controller_button_down(&mut self, device: u32, btn: Button);
+    fn controller_button_up(&mut self, device: u32, btn: Button);
 
     // returns true if the event has handled, indicating that it will be ignored for further logic
     fn raw_event(&mut self, window: &sdl2::video::Window, event: &sdl2::event::Event) -> bool;
@@ -22,11 +31,19 @@ pub trait WindowEventHandler {
 
 pub struct Input {
     sdl2: Sdl,
+    // controllers only send events while they are opened
+    controller_subsystem: Option<GameControllerSubsystem>,
+    controllers: RefCell<Vec<GameController>>,
 }
 
 impl Input {
     pub fn new(native: Native) -> Input {
-        let inp = Input { sdl2: native.sdl2 };
+        let controller_subsystem = native.sdl2.game_controller().ok();
+        let inp = Input {
+            sdl2: native.sdl2,
+            controller_subsystem,
+            controllers: Default::default(),
+        };
         inp.init();
         inp
     }
@@ -42,6 +59,24 @@ impl Input {
     ) -> bool {
         let mut event_queue = self.sdl2.event_pump().unwrap();
         for event in event_queue.poll_iter() {
+            // sdl also sends this for the controllers that are connected at startup
+            match event {
+                event::Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(controller) = self
+                        .controller_subsystem
+                        .as_ref()
+                        .and_then(|subsystem| subsystem.open(which).ok())
+                    {
+                        self.controllers.borrow_mut().push(controller);
+                    }
+                }
+                event::Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers
+                        .borrow_mut()
+                        .retain(|controller| controller.instance_id() != which);
+                }
+                _ => {}
+            }
             if !inp_handler.raw_event(window_handler.borrow_window(), &event) {
                 match event {
                     event::Event::KeyDown { scancode, .. } => {
@@ -84,6 +119,12 @@ impl Input {
                     } => {
                         inp_handler.mouse_move(which, x, y, xrel, yrel);
                     }
+                    event::Event::ControllerButtonDown { which, button, .. } => {
+                        inp_handler.controller_button_down(which, button);
+                    }
+                    event::Event::ControllerButtonUp { which, button, .. } => {
+                        inp_handler.controller_button_up(which, button);
+                    }
                     event::Event::Window {
                         timestamp: _,
                         window_id: _,
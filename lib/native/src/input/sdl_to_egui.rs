@@ -5,6 +5,7 @@
  */
 use egui::{Key, Modifiers, PointerButton, Pos2, RawInput};
 
+use sdl2::controller::{Axis, Button};
 use sdl2::keyboard::Keycode;
 use sdl2::keyboard::Mod;
 use sdl2::mouse::{Cursor, MouseButton, SystemCursor};
@@ -96,10 +97,29 @@ pub fn translate_virtual_key_code(key: sdl2::keyboard::Keycode) -> Option<egui::
     })
 }
 
+const GAMEPAD_AXIS_DEAD_ZONE: f32 = 0.2;
+// in points per second at full stick deflection
+const GAMEPAD_CURSOR_SPEED: f32 = 600.0;
+
+#[derive(Default)]
+pub struct GamepadState {
+    // the last input came from a gamepad, pages can show focus/button hints
+    pub active: bool,
+    // the left stick moves a cursor and A clicks, instead of moving the focus
+    pub virtual_cursor: bool,
+    pub cursor_pos: Pos2,
+    axis: (f32, f32),
+}
+
 pub struct EguiSDL2State {
     pub raw_input: RawInput,
     pub modifiers: Modifiers,
     pub cur_zoom_level: f32,
+    pub gamepad: GamepadState,
+    // an input method (e.g. for CJK) is composing text
+    is_composing: bool,
+    // the text input (and so the input method) is only active while egui wants text
+    text_input_active: bool,
     // pub fused_cursor: FusedCursor,
 }
 
@@ -115,9 +135,14 @@ impl EguiSDL2State {
         }
 
         use sdl2::event::Event::*;
+        // controller events don't belong to a window
+        if self.gamepad_input_to_egui(event) {
+            return;
+        }
         if event.get_window_id() != Some(window.id()) {
             return;
         }
+        self.gamepad.active = false;
         match event {
             MouseButtonDown {
                 mouse_btn, x, y, ..
@@ -241,7 +266,32 @@ impl EguiSDL2State {
             }
 
             TextInput { text, .. } => {
-                self.raw_input.events.push(egui::Event::Text(text.clone()));
+                if self.is_composing {
+                    // the input method committed the composed text
+                    self.is_composing = false;
+                    self.raw_input
+                        .events
+                        .push(egui::Event::CompositionEnd(text.clone()));
+                } else {
+                    self.raw_input.events.push(egui::Event::Text(text.clone()));
+                }
+            }
+            TextEditing { text, .. } => {
+                if !text.is_empty() {
+                    if !self.is_composing {
+                        self.is_composing = true;
+                        self.raw_input.events.push(egui::Event::CompositionStart);
+                    }
+                    self.raw_input
+                        .events
+                        .push(egui::Event::CompositionUpdate(text.clone()));
+                } else if self.is_composing {
+                    // the composition was canceled
+                    self.is_composing = false;
+                    self.raw_input
+                        .events
+                        .push(egui::Event::CompositionEnd(String::new()));
+                }
             }
             MouseWheel { x, y, .. } => {
                 let delta = egui::vec2(*x as f32 * 8.0, *y as f32 * 8.0);
@@ -271,6 +321,92 @@ impl EguiSDL2State {
         }
     }
 
+    fn push_key(&mut self, key: Key, pressed: bool, modifiers: Modifiers) {
+        self.raw_input.events.push(egui::Event::Key {
+            key,
+            pressed,
+            modifiers,
+            repeat: false,
+        });
+    }
+
+    /**
+     * Maps the gamepad to the keyboard navigation of egui:
+     * the dpad moves the focus (tab), A accepts (enter), B goes back (escape)
+     * and Y toggles the virtual cursor mode.
+     * Returns true if the event was a gamepad event.
+     */
+    fn gamepad_input_to_egui(&mut self, event: &sdl2::event::Event) -> bool {
+        use sdl2::event::Event::*;
+        match event {
+            ControllerButtonDown { button, .. } | ControllerButtonUp { button, .. } => {
+                let pressed = matches!(event, ControllerButtonDown { .. });
+                self.gamepad.active = true;
+                match button {
+                    Button::DPadDown | Button::DPadRight => {
+                        self.push_key(Key::Tab, pressed, Modifiers::NONE)
+                    }
+                    Button::DPadUp | Button::DPadLeft => {
+                        self.push_key(Key::Tab, pressed, Modifiers::SHIFT)
+                    }
+                    Button::A if self.gamepad.virtual_cursor => {
+                        self.raw_input.events.push(egui::Event::PointerButton {
+                            pos: self.gamepad.cursor_pos,
+                            button: PointerButton::Primary,
+                            pressed,
+                            modifiers: Modifiers::NONE,
+                        })
+                    }
+                    Button::A => self.push_key(Key::Enter, pressed, Modifiers::NONE),
+                    Button::B => self.push_key(Key::Escape, pressed, Modifiers::NONE),
+                    Button::Y if pressed => {
+                        self.gamepad.virtual_cursor = !self.gamepad.virtual_cursor
+                    }
+                    _ => {}
+                }
+                true
+            }
+            ControllerAxisMotion { axis, value, .. } => {
+                let value = *value as f32 / i16::MAX as f32;
+                match axis {
+                    Axis::LeftX => self.gamepad.axis.0 = value,
+                    Axis::LeftY => self.gamepad.axis.1 = value,
+                    _ => {}
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /**
+     * Moves the virtual cursor of the gamepad, should be called once per frame
+     */
+    pub fn update_gamepad_cursor(&mut self, delta_secs: f32, screen_rect: egui::Rect) {
+        if !self.gamepad.virtual_cursor {
+            return;
+        }
+        let dead_zone = |value: f32| {
+            if value.abs() < GAMEPAD_AXIS_DEAD_ZONE {
+                0.0
+            } else {
+                value
+            }
+        };
+        let (x, y) = (
+            dead_zone(self.gamepad.axis.0),
+            dead_zone(self.gamepad.axis.1),
+        );
+        if x == 0.0 && y == 0.0 {
+            return;
+        }
+        self.gamepad.cursor_pos = screen_rect
+            .clamp(self.gamepad.cursor_pos + egui::vec2(x, y) * GAMEPAD_CURSOR_SPEED * delta_secs);
+        self.raw_input
+            .events
+            .push(egui::Event::PointerMoved(self.gamepad.cursor_pos));
+    }
+
     pub fn new(cur_zoom: f32) -> Self {
         let raw_input = RawInput {
             ..RawInput::default()
@@ -281,6 +417,9 @@ impl EguiSDL2State {
             modifiers,
             //fused_cursor: FusedCursor::new(),
             cur_zoom_level: cur_zoom,
+            gamepad: Default::default(),
+            is_composing: false,
+            text_input_active: true,
         }
     }
 
@@ -297,6 +436,30 @@ impl EguiSDL2State {
                 }
             }
         }
+        // the candidate window of the input method is placed at the text cursor
+        let text_input = window.subsystem().text_input();
+        match egui_output.text_cursor_pos {
+            Some(pos) => {
+                if !self.text_input_active {
+                    text_input.start();
+                    self.text_input_active = true;
+                }
+                text_input.set_rect(sdl2::rect::Rect::new(
+                    (pos.x * self.cur_zoom_level) as i32,
+                    (pos.y * self.cur_zoom_level) as i32,
+                    1,
+                    // roughly one line of text
+                    (20.0 * self.cur_zoom_level) as u32,
+                ));
+            }
+            None => {
+                if self.text_input_active {
+                    text_input.stop();
+                    self.text_input_active = false;
+                    self.is_composing = false;
+                }
+            }
+        }
         //EguiSDL2State::translate_cursor(&mut self.fused_cursor, egui_output.cursor_icon);
     }
 
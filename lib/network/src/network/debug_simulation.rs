@@ -0,0 +1,106 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::Mutex as StdMutex,
+    time::Duration,
+};
+
+/**
+ * Artificial network conditions, only meant for testing (e.g. prediction & interpolation).
+ * Applied to every sent and every received packet, so the round trip time grows by twice the latency.
+ * Reordering happens because every packet is delayed independently by the jitter.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NetworkDebugSimulationSettings {
+    pub latency: Duration,
+    // the latency is randomly increased by up to this value
+    pub jitter: Duration,
+    // 0.0 - 1.0
+    pub loss: f64,
+}
+
+impl NetworkDebugSimulationSettings {
+    pub fn is_active(&self) -> bool {
+        !self.latency.is_zero() || !self.jitter.is_zero() || self.loss > 0.0
+    }
+}
+
+pub enum NetworkSimulatedPacket {
+    Direct,
+    Delayed(Duration),
+    Lost,
+}
+
+/**
+ * Can be changed at any time, also affects existing connections.
+ */
+#[derive(Debug)]
+pub struct NetworkDebugSimulation {
+    // settings + xorshift state
+    inner: StdMutex<(NetworkDebugSimulationSettings, u64)>,
+}
+
+impl Default for NetworkDebugSimulation {
+    fn default() -> Self {
+        // random seed without an extra dependency, must never be 0 for xorshift
+        let seed = RandomState::new().build_hasher().finish() | 1;
+        Self {
+            inner: StdMutex::new((Default::default(), seed)),
+        }
+    }
+}
+
+impl NetworkDebugSimulation {
+    pub fn set(&self, settings: NetworkDebugSimulationSettings) {
+        self.inner.lock().unwrap().0 = settings;
+    }
+
+    pub fn get(&self) -> NetworkDebugSimulationSettings {
+        self.inner.lock().unwrap().0
+    }
+
+    /**
+     * random value in [0, 1)
+     */
+    fn random(state: &mut u64) -> f64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn simulate(&self, reliable: bool) -> NetworkSimulatedPacket {
+        let mut inner = self.inner.lock().unwrap();
+        let (settings, state) = &mut *inner;
+        if !settings.is_active() {
+            return NetworkSimulatedPacket::Direct;
+        }
+        let mut delay = settings.latency + settings.jitter.mul_f64(Self::random(state));
+        if Self::random(state) < settings.loss {
+            if !reliable {
+                return NetworkSimulatedPacket::Lost;
+            }
+            // reliable packets are resent by the protocol, which roughly costs another round trip
+            delay += settings.latency * 2 + settings.jitter.mul_f64(Self::random(state));
+        }
+        if delay.is_zero() {
+            NetworkSimulatedPacket::Direct
+        } else {
+            NetworkSimulatedPacket::Delayed(delay)
+        }
+    }
+
+    /**
+     * For packets that are sent over streams, these are never lost, but delayed instead
+     */
+    pub fn simulate_reliable(&self) -> NetworkSimulatedPacket {
+        self.simulate(true)
+    }
+
+    /**
+     * For datagrams
+     */
+    pub fn simulate_unreliable(&self) -> NetworkSimulatedPacket {
+        self.simulate(false)
+    }
+}
@@ -1,4 +1,6 @@
+pub mod debug_simulation;
 pub mod network;
 pub mod quinn_network;
 pub mod quinnminimal;
+pub mod traffic_shaping;
 //pub mod tungstenite_network;
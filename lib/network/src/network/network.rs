@@ -16,7 +16,18 @@ use quinn::VarInt;
 use rcgen::Certificate;
 use tokio::sync::{Mutex as TokioMutex, RwLock as TokioRwLock};
 
-use base::system::{SystemTime, SystemTimeInterface};
+use base::{
+    log_println, log_println_private,
+    system::{SystemTime, SystemTimeInterface},
+};
+
+use super::debug_simulation::{
+    NetworkDebugSimulation, NetworkDebugSimulationSettings, NetworkSimulatedPacket,
+};
+use super::traffic_shaping::{
+    NetworkBandwidthLimits, NetworkConnectionLimits, NetworkPacketPriority, NetworkSendQueue,
+    NetworkTokenBucket, NetworkTrafficCounters,
+};
 
 pub struct NetworkConnectionIDCounter(AtomicU64);
 
@@ -41,14 +52,43 @@ impl NetworkConnectionIDCounter {
     }
 }
 
-#[derive(Clone)]
+/**
+ * Quality of a connection, generated every time a new round trip time was measured
+ */
+#[derive(Debug, Default, Clone)]
 pub struct NetworkStats {
+    // latest round trip time
     pub ping: Duration,
+    // over the last few seconds
+    pub ping_min: Duration,
+    pub ping_avg: Duration,
+    pub ping_max: Duration,
+    // average difference between two consecutive round trip times
+    pub ping_jitter: Duration,
+    // 0.0 - 1.0, pings that were never answered
+    pub packet_loss: f64,
+    // bytes of user packets, indexed by the priority
+    pub bytes_sent: [u64; NetworkPacketPriority::COUNT],
+    pub bytes_received: u64,
+    // of the underlying protocol
+    pub packets_sent: u64,
+    // these were resent
+    pub packets_lost: u64,
+}
+
+/**
+ * Statistics of the underlying protocol
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetworkTransportStats {
+    pub packets_sent: u64,
+    pub packets_lost: u64,
 }
 
 #[derive(Clone)]
 pub enum NetworkGameEvent {
-    Connected,
+    // the address of the other side of the connection
+    Connected(SocketAddr),
     Disconnected(String),
     ConnectingFailed(String),
     NetworkStats(NetworkStats),
@@ -74,7 +114,7 @@ enum NetworkEvent {
     Connect(NetworkConnectionID, String),
     Disconnect(NetworkConnectionID),
     Close(),
-    Send((NetworkConnectionID, Vec<u8>)),
+    Send((NetworkConnectionID, Vec<u8>, NetworkPacketPriority)),
 }
 
 #[derive(bincode::Encode, bincode::Decode)]
@@ -108,6 +148,7 @@ pub struct NetworkConnectionPingHandleImpl {
     handle_timestamp: Duration,
 
     ping_pong_peng_start_timestamp: Duration,
+    answered: bool,
 }
 
 impl NetworkConnectionPingHandleImpl {
@@ -117,18 +158,34 @@ impl NetworkConnectionPingHandleImpl {
             handle_timestamp: add_timestamp,
 
             ping_pong_peng_start_timestamp: Duration::ZERO,
+            answered: false,
         }
     }
 }
 
+// pings that are not answered yet
+const MAX_PING_HANDLES: usize = 50;
+// the round trip times the ping stats are calculated from
+const PING_RTT_HISTORY_LEN: usize = 50;
+// the answered or lost pings the packet loss is calculated from
+const PING_OUTCOME_HISTORY_LEN: usize = 100;
+
 pub struct NetworkConnectionPingHandle {
     list: VecDeque<NetworkConnectionPingHandleImpl>,
+
+    // the latest round trip times
+    rtts: VecDeque<Duration>,
+    // if the outdated ping handles were answered
+    outcomes: VecDeque<bool>,
 }
 
 impl NetworkConnectionPingHandle {
     pub fn new() -> Self {
         Self {
             list: VecDeque::new(),
+
+            rtts: VecDeque::new(),
+            outcomes: VecDeque::new(),
         }
     }
 
@@ -136,13 +193,48 @@ impl NetworkConnectionPingHandle {
         // check if there are outdated ping handles
         while !self.list.is_empty() {
             if cur_time - self.list.front().unwrap().handle_timestamp > Duration::from_secs(2) {
-                self.list.pop_front();
+                let handle = self.list.pop_front().unwrap();
+                if self.outcomes.len() >= PING_OUTCOME_HISTORY_LEN {
+                    self.outcomes.pop_front();
+                }
+                self.outcomes.push_back(handle.answered);
             } else {
                 break;
             }
         }
     }
 
+    pub fn add_rtt(&mut self, rtt: Duration) {
+        if self.rtts.len() >= PING_RTT_HISTORY_LEN {
+            self.rtts.pop_front();
+        }
+        self.rtts.push_back(rtt);
+    }
+
+    /**
+     * Fills the round trip time & loss related stats
+     */
+    pub fn fill_stats(&self, stats: &mut NetworkStats) {
+        if !self.rtts.is_empty() {
+            stats.ping_min = *self.rtts.iter().min().unwrap();
+            stats.ping_max = *self.rtts.iter().max().unwrap();
+            stats.ping_avg = self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32;
+        }
+        if self.rtts.len() > 1 {
+            stats.ping_jitter = self
+                .rtts
+                .iter()
+                .zip(self.rtts.iter().skip(1))
+                .map(|(prev, next)| prev.abs_diff(*next))
+                .sum::<Duration>()
+                / (self.rtts.len() - 1) as u32;
+        }
+        if !self.outcomes.is_empty() {
+            stats.packet_loss = self.outcomes.iter().filter(|answered| !**answered).count() as f64
+                / self.outcomes.len() as f64;
+        }
+    }
+
     pub fn try_get_mut(
         &mut self,
         identifier: &u64,
@@ -163,8 +255,7 @@ impl NetworkConnectionPingHandle {
     ) -> Result<&mut NetworkConnectionPingHandleImpl, ()> {
         self.remove_outdated(cur_time);
 
-        /* TODO: 50 should not be harcoded */
-        if self.list.len() < 50 {
+        if self.list.len() < MAX_PING_HANDLES {
             self.list
                 .push_back(NetworkConnectionPingHandleImpl::new(identifier, cur_time));
             Ok(self.list.back_mut().unwrap())
@@ -179,6 +270,12 @@ pub struct NetworkConnection<C: Send + Sync, Z: Send + Sync> {
     connecting: Option<Z>,
 
     ping_handles: NetworkConnectionPingHandle,
+
+    send_queue: Arc<NetworkSendQueue>,
+
+    debug_simulation: Arc<NetworkDebugSimulation>,
+
+    traffic: Arc<NetworkTrafficCounters>,
 }
 
 #[async_trait::async_trait]
@@ -198,6 +295,13 @@ where
     fn make_client_endpoint(bind_addr: SocketAddr, server_certs: &[&[u8]]) -> anyhow::Result<Self>;
 }
 
+/**
+ * A connection whose handshake is still running
+ */
+pub trait NetworkConnectingInterface {
+    fn remote_addr(&self) -> SocketAddr;
+}
+
 pub struct NetworkThread<E, C: Send + Sync, Z: Send + Sync> {
     is_server: bool,
     endpoint: E,
@@ -208,6 +312,9 @@ pub struct NetworkThread<E, C: Send + Sync, Z: Send + Sync> {
         )>,
     >,
     game_event_generator: Arc<TokioMutex<dyn NetworkEventToGameEventGenerator + Send>>,
+    bandwidth_limits: Arc<NetworkBandwidthLimits>,
+    connection_limits: Arc<NetworkConnectionLimits>,
+    debug_simulation: Arc<NetworkDebugSimulation>,
     sys: Arc<SystemTime>,
 }
 
@@ -217,6 +324,7 @@ where
     Self: Sync + Send,
 {
     async fn write(&mut self, buf: &[u8]) -> anyhow::Result<usize>;
+    async fn write_all(&mut self, buf: &[u8]) -> anyhow::Result<()>;
     async fn finish(&mut self) -> anyhow::Result<()>;
 }
 
@@ -237,6 +345,8 @@ where
     fn close(&self, error_code: VarInt, reason: &[u8]);
 
     fn send_datagram(&self, data: Bytes) -> anyhow::Result<()>;
+    fn transport_stats(&self) -> NetworkTransportStats;
+    fn remote_addr(&self) -> SocketAddr;
     async fn read_datagram(&self) -> Result<Vec<u8>, String>;
 
     async fn accept_bi(&self) -> Result<(S, R), String>;
@@ -265,6 +375,9 @@ where
 
     // for the client to remember the last server it connected to
     connecting_connection_id: NetworkConnectionID,
+    bandwidth_limits: Arc<NetworkBandwidthLimits>,
+    connection_limits: Arc<NetworkConnectionLimits>,
+    debug_simulation: Arc<NetworkDebugSimulation>,
     sys: Arc<SystemTime>,
 
     s: PhantomData<S>,
@@ -273,25 +386,62 @@ where
 
 impl<E, C, Z, S, R> Network<E, C, Z, S, R>
 where
-    S: NetworkConnectionSendStreamInterface,
+    S: NetworkConnectionSendStreamInterface + 'static,
     R: NetworkConnectionRecvStreamInterface,
     C: NetworkConnectionInterface<S, R> + Clone + Send + Sync + 'static,
-    Z: Send + Sync + 'static + Future<Output = Result<C, String>> + Unpin,
+    Z: NetworkConnectingInterface
+        + Send
+        + Sync
+        + 'static
+        + Future<Output = Result<C, String>>
+        + Unpin,
     E: NetworkEndpointInterface<Z> + Clone + Send + Sync + 'static,
 {
-    async fn send_datagram(connection: &C, packet: &NetworkPacket) -> Result<(), ()> {
+    async fn send_datagram(
+        connection: &C,
+        packet: &NetworkPacket,
+        debug_simulation: &NetworkDebugSimulation,
+    ) -> Result<(), ()> {
         let packet = bincode::encode_to_vec(packet, bincode::config::standard());
         if let Ok(packet) = packet {
             let pack_bytes = bytes::Bytes::copy_from_slice(&packet[..]);
-            let res = connection.send_datagram(pack_bytes);
-            if let Err(_) = res {
-                return Err(());
+            match debug_simulation.simulate_unreliable() {
+                NetworkSimulatedPacket::Direct => {
+                    let res = connection.send_datagram(pack_bytes);
+                    if let Err(_) = res {
+                        return Err(());
+                    }
+                }
+                NetworkSimulatedPacket::Delayed(delay) => {
+                    let connection = connection.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = connection.send_datagram(pack_bytes);
+                    });
+                }
+                NetworkSimulatedPacket::Lost => {}
             }
             return Ok(());
         }
         Err(())
     }
 
+    fn connection_stats(connection: &NetworkConnection<C, Z>, ping: Duration) -> NetworkStats {
+        let mut stats = NetworkStats {
+            ping,
+            bytes_sent: connection.traffic.sent(),
+            bytes_received: connection.traffic.received(),
+            ..Default::default()
+        };
+        connection.ping_handles.fill_stats(&mut stats);
+        if let Some(con) = connection.conn.as_ref() {
+            let transport_stats = con.transport_stats();
+            stats.packets_sent = transport_stats.packets_sent;
+            stats.packets_lost = transport_stats.packets_lost;
+        }
+        stats
+    }
+
     async fn handle_internal_packet(
         sys: &Arc<SystemTime>,
         game_event_generator_clone: &Arc<TokioMutex<dyn NetworkEventToGameEventGenerator + Send>>,
@@ -314,6 +464,7 @@ where
                         let con_res = con_g.conn.as_ref();
                         if let Some(con_ref) = con_res {
                             let con = con_ref.clone();
+                            let debug_simulation = con_g.debug_simulation.clone();
                             drop(con_g);
                             Self::send_datagram(
                                 &con,
@@ -321,6 +472,7 @@ where
                                     *identifier,
                                     InternalPingNetworkPackets::Pong,
                                 )),
+                                &debug_simulation,
                             )
                             .await;
                         } else {
@@ -334,14 +486,17 @@ where
                     let mut con_g = connection.write().await;
                     let handle_res = con_g.ping_handles.try_get_mut(identifier, sys);
                     if let Some(handle) = handle_res {
+                        handle.answered = true;
                         let ping = cur_time - handle.ping_pong_peng_start_timestamp;
+                        con_g.ping_handles.add_rtt(ping);
+                        let stats = Self::connection_stats(&con_g, ping);
                         drop(con_g);
                         // generate network stats
                         let mut ge_gen = game_event_generator_clone.lock().await;
                         ge_gen.generate_from_network_event(
                             cur_time,
                             con_id,
-                            &NetworkGameEvent::NetworkStats(NetworkStats { ping: ping }),
+                            &NetworkGameEvent::NetworkStats(stats),
                         );
                         drop(ge_gen);
                         // also send a peng
@@ -349,6 +504,7 @@ where
                         let con_res = con_g.conn.as_ref();
                         if let Some(con_ref) = con_res {
                             let con = con_ref.clone();
+                            let debug_simulation = con_g.debug_simulation.clone();
                             drop(con_g);
                             Self::send_datagram(
                                 &con,
@@ -356,6 +512,7 @@ where
                                     *identifier,
                                     InternalPingNetworkPackets::Peng,
                                 )),
+                                &debug_simulation,
                             )
                             .await;
                         } else {
@@ -369,14 +526,17 @@ where
                     let mut con_g = connection.write().await;
                     let handle_res = con_g.ping_handles.try_get_mut(identifier, sys);
                     if let Some(handle) = handle_res {
+                        handle.answered = true;
                         let ping = cur_time - handle.ping_pong_peng_start_timestamp;
+                        con_g.ping_handles.add_rtt(ping);
+                        let stats = Self::connection_stats(&con_g, ping);
                         drop(con_g);
                         // generate network stats
                         let mut ge_gen = game_event_generator_clone.lock().await;
                         ge_gen.generate_from_network_event(
                             cur_time,
                             con_id,
-                            &NetworkGameEvent::NetworkStats(NetworkStats { ping: ping }),
+                            &NetworkGameEvent::NetworkStats(stats),
                         );
                         drop(ge_gen);
                     }
@@ -385,6 +545,84 @@ where
         }
     }
 
+    async fn handle_recv_packet(
+        sys: &Arc<SystemTime>,
+        game_event_generator_clone: &Arc<TokioMutex<dyn NetworkEventToGameEventGenerator + Send>>,
+        connection_identifier: &NetworkConnectionID,
+        connection_async: &Arc<TokioRwLock<NetworkConnection<C, Z>>>,
+        res: Vec<u8>,
+    ) {
+        let timestamp = sys.time_get_nanoseconds();
+        let res_packet =
+            bincode::decode_from_slice::<NetworkPacket, _>(&res[..], bincode::config::standard());
+        if let Ok((res_packet, _)) = &res_packet {
+            match res_packet {
+                NetworkPacket::Internal(internal_packet) => {
+                    Self::handle_internal_packet(
+                        sys,
+                        game_event_generator_clone,
+                        connection_identifier,
+                        connection_async,
+                        internal_packet,
+                    )
+                    .await;
+                }
+                NetworkPacket::User(user_packet) => {
+                    game_event_generator_clone
+                        .lock()
+                        .await
+                        .generate_from_binary(
+                            timestamp,
+                            connection_identifier,
+                            user_packet.as_slice(),
+                        );
+                }
+            }
+        }
+    }
+
+    /**
+     * Delayed packets are handled in their own task, so the receiving is not blocked
+     */
+    async fn handle_recv_packet_simulated(
+        simulated: NetworkSimulatedPacket,
+        sys: &Arc<SystemTime>,
+        game_event_generator_clone: &Arc<TokioMutex<dyn NetworkEventToGameEventGenerator + Send>>,
+        connection_identifier: NetworkConnectionID,
+        connection_async: &Arc<TokioRwLock<NetworkConnection<C, Z>>>,
+        res: Vec<u8>,
+    ) {
+        match simulated {
+            NetworkSimulatedPacket::Direct => {
+                Self::handle_recv_packet(
+                    sys,
+                    game_event_generator_clone,
+                    &connection_identifier,
+                    connection_async,
+                    res,
+                )
+                .await;
+            }
+            NetworkSimulatedPacket::Delayed(delay) => {
+                let sys = sys.clone();
+                let game_event_generator_clone = game_event_generator_clone.clone();
+                let connection_async = connection_async.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    Self::handle_recv_packet(
+                        &sys,
+                        &game_event_generator_clone,
+                        &connection_identifier,
+                        &connection_async,
+                        res,
+                    )
+                    .await;
+                });
+            }
+            NetworkSimulatedPacket::Lost => {}
+        }
+    }
+
     async fn handle_connection_recv_datagram(
         connection_async: Arc<TokioRwLock<NetworkConnection<C, Z>>>,
         connections_clone: Arc<
@@ -397,6 +635,10 @@ where
         connection_identifier: NetworkConnectionID,
         sys: Arc<SystemTime>,
     ) {
+        let conn_g = connection_async.read().await;
+        let debug_simulation = conn_g.debug_simulation.clone();
+        let traffic = conn_g.traffic.clone();
+        drop(conn_g);
         'conn_loop: loop {
             let conn = connection_async.read().await;
             let connection_res = conn.conn.as_ref();
@@ -406,40 +648,21 @@ where
                 drop(conn);
                 let datagram = connection.read_datagram().await;
                 match datagram {
-                    Ok(recv_stream) => {
-                        let res = recv_stream;
-                        let timestamp = sys.time_get_nanoseconds();
-                        let res_packet = bincode::decode_from_slice::<NetworkPacket, _>(
-                            &res.as_slice(),
-                            bincode::config::standard(),
-                        );
-                        if let Ok((res_packet, _)) = &res_packet {
-                            match res_packet {
-                                NetworkPacket::Internal(internal_packet) => {
-                                    Self::handle_internal_packet(
-                                        &sys,
-                                        &game_event_generator_clone,
-                                        &connection_identifier,
-                                        &connection_async,
-                                        internal_packet,
-                                    )
-                                    .await;
-                                }
-                                NetworkPacket::User(_user_packet) => {
-                                    game_event_generator_clone
-                                        .lock()
-                                        .await
-                                        .generate_from_binary(
-                                            timestamp,
-                                            &connection_identifier,
-                                            res.as_slice(),
-                                        );
-                                }
-                            }
-                        }
+                    Ok(res) => {
+                        traffic.add_received(res.len() as u64);
+                        let simulated = debug_simulation.simulate_unreliable();
+                        Self::handle_recv_packet_simulated(
+                            simulated,
+                            &sys,
+                            &game_event_generator_clone,
+                            connection_identifier,
+                            &connection_async,
+                            res,
+                        )
+                        .await;
                     }
                     Err(recv_err) => {
-                        println!("connection stream acception failed {}", recv_err);
+                        log_println!("connection stream acception failed {}", recv_err);
                         let mut connections = connections_clone.lock().await;
                         let _con_rem_res = connections.1.remove(&connection_identifier);
                         drop(connections);
@@ -473,8 +696,14 @@ where
         >,
         game_event_generator_clone: Arc<TokioMutex<dyn NetworkEventToGameEventGenerator + Send>>,
         connection_identifier: NetworkConnectionID,
+        bandwidth_limits: Arc<NetworkBandwidthLimits>,
         sys: Arc<SystemTime>,
     ) {
+        let mut download_bucket = NetworkTokenBucket::default();
+        let conn_g = connection_async.read().await;
+        let debug_simulation = conn_g.debug_simulation.clone();
+        let traffic = conn_g.traffic.clone();
+        drop(conn_g);
         'conn_loop: loop {
             let conn = connection_async.read().await;
             let connection_res = conn.conn.as_ref();
@@ -489,35 +718,26 @@ where
 
                         match read_res {
                             Ok(res) => {
-                                let timestamp = sys.time_get_nanoseconds();
-                                let res_packet = bincode::decode_from_slice::<NetworkPacket, _>(
-                                    &res[..],
-                                    bincode::config::standard(),
+                                traffic.add_received(res.len() as u64);
+                                // not accepting new streams for a while slows down the sender
+                                let wait = download_bucket.take(
+                                    res.len() as u64,
+                                    bandwidth_limits.download(),
+                                    sys.time_get_nanoseconds(),
                                 );
-                                if let Ok((res_packet, _)) = &res_packet {
-                                    match res_packet {
-                                        NetworkPacket::Internal(internal_packet) => {
-                                            Self::handle_internal_packet(
-                                                &sys,
-                                                &game_event_generator_clone,
-                                                &connection_identifier,
-                                                &connection_async,
-                                                internal_packet,
-                                            )
-                                            .await;
-                                        }
-                                        NetworkPacket::User(user_packet) => {
-                                            game_event_generator_clone
-                                                .lock()
-                                                .await
-                                                .generate_from_binary(
-                                                    timestamp,
-                                                    &connection_identifier,
-                                                    user_packet.as_slice(),
-                                                );
-                                        }
-                                    }
+                                if !wait.is_zero() {
+                                    tokio::time::sleep(wait).await;
                                 }
+                                let simulated = debug_simulation.simulate_reliable();
+                                Self::handle_recv_packet_simulated(
+                                    simulated,
+                                    &sys,
+                                    &game_event_generator_clone,
+                                    connection_identifier,
+                                    &connection_async,
+                                    res,
+                                )
+                                .await;
                             }
                             Err(err) => {
                                 let mut connections = connections_clone.lock().await;
@@ -538,7 +758,7 @@ where
                         }
                     }
                     Err(recv_err) => {
-                        println!("connection stream acception failed {}", recv_err);
+                        log_println!("connection stream acception failed {}", recv_err);
                         let mut connections = connections_clone.lock().await;
                         let _con_rem_res = connections.1.remove(&connection_identifier);
                         drop(connections);
@@ -562,6 +782,111 @@ where
         }
     }
 
+    /**
+     * Sends the queued packets of the connection, ordered by their priority
+     * and throttled by the upload limit.
+     * The packets are written one after another from this task,
+     * so a packet is never overtaken by one with a lower priority.
+     */
+    async fn handle_connection_send(
+        connection_async: Arc<TokioRwLock<NetworkConnection<C, Z>>>,
+        bandwidth_limits: Arc<NetworkBandwidthLimits>,
+        sys: Arc<SystemTime>,
+    ) {
+        let conn_g = connection_async.read().await;
+        let send_queue = conn_g.send_queue.clone();
+        let debug_simulation = conn_g.debug_simulation.clone();
+        let traffic = conn_g.traffic.clone();
+        drop(conn_g);
+        let (release_sender, release_receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::select! {
+            _ = Self::release_connection_send(send_queue, bandwidth_limits, debug_simulation, sys, release_sender) => {}
+            _ = Self::write_connection_send(connection_async, traffic, release_receiver) => {}
+        }
+    }
+
+    /**
+     * Takes the packets from the send queue and gives each of them the time
+     * at which it may be written.
+     * A simulated latency only moves this time, so the delayed packets
+     * are still sent in parallel instead of waiting for each other.
+     */
+    async fn release_connection_send(
+        send_queue: Arc<NetworkSendQueue>,
+        bandwidth_limits: Arc<NetworkBandwidthLimits>,
+        debug_simulation: Arc<NetworkDebugSimulation>,
+        sys: Arc<SystemTime>,
+        release_sender: tokio::sync::mpsc::UnboundedSender<(
+            Vec<u8>,
+            NetworkPacketPriority,
+            tokio::time::Instant,
+        )>,
+    ) {
+        let mut upload_bucket = NetworkTokenBucket::default();
+        loop {
+            let (packet, priority) = send_queue.pop().await;
+            let wait = upload_bucket.take(
+                packet.len() as u64,
+                bandwidth_limits.upload(),
+                sys.time_get_nanoseconds(),
+            );
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            let mut release_at = tokio::time::Instant::now();
+            if let NetworkSimulatedPacket::Delayed(delay) = debug_simulation.simulate_reliable() {
+                release_at += delay;
+            }
+            if release_sender.send((packet, priority, release_at)).is_err() {
+                break;
+            }
+        }
+    }
+
+    /**
+     * Writes the released packets in the order they were taken from the queue.
+     */
+    async fn write_connection_send(
+        connection_async: Arc<TokioRwLock<NetworkConnection<C, Z>>>,
+        traffic: Arc<NetworkTrafficCounters>,
+        mut release_receiver: tokio::sync::mpsc::UnboundedReceiver<(
+            Vec<u8>,
+            NetworkPacketPriority,
+            tokio::time::Instant,
+        )>,
+    ) {
+        while let Some((packet, priority, release_at)) = release_receiver.recv().await {
+            tokio::time::sleep_until(release_at).await;
+            let conn_g = connection_async.read().await;
+            let con_res = conn_g.conn.as_ref();
+            if let Some(con) = con_res {
+                let con_clone = con.clone();
+                drop(conn_g);
+                let uni = con_clone.open_bi().await;
+                if let Ok((mut stream, _)) = uni {
+                    let written_bytes = stream.write_all(packet.as_slice()).await;
+                    if let Err(_written_bytes) = written_bytes {
+                        log_println!("packet write failed.");
+                    } else {
+                        traffic.add_sent(packet.len() as u64, priority);
+                        // finishing waits for the peer to acknowledge the packet,
+                        // which must not hold back the next packets
+                        tokio::spawn(async move {
+                            let finish_res = stream.finish().await;
+                            if let Err(err) = finish_res {
+                                log_println!("packet finish failed: {}", err.to_string());
+                            }
+                        });
+                    }
+                } else if let Err(stream_err) = uni {
+                    log_println!("sent stream err: {}", stream_err.to_string());
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
     async fn ping(
         sys: Arc<SystemTime>,
         connection_async: Arc<TokioRwLock<NetworkConnection<C, Z>>>,
@@ -581,6 +906,7 @@ where
                 let con_res = con_g.conn.as_ref();
                 if let Some(con) = con_res {
                     let connection = con.clone();
+                    let debug_simulation = con_g.debug_simulation.clone();
                     drop(con_g);
                     let cur_time = sys.time_get_nanoseconds();
                     let mut con_g = conn.write().await;
@@ -595,6 +921,7 @@ where
                                 identifier_copy,
                                 InternalPingNetworkPackets::Ping,
                             )),
+                            &debug_simulation,
                         )
                         .await;
                     }
@@ -613,16 +940,38 @@ where
         game_event_generator: &Arc<TokioMutex<dyn NetworkEventToGameEventGenerator + Send>>,
         conn: Z,
         pre_defined_id: &NetworkConnectionID,
+        bandwidth_limits: &Arc<NetworkBandwidthLimits>,
+        connection_limits: &Arc<NetworkConnectionLimits>,
+        debug_simulation: &Arc<NetworkDebugSimulation>,
         sys: Arc<SystemTime>,
-        _is_server: bool,
+        is_server: bool,
     ) {
-        println!("handling connecting request");
+        log_println!("handling connecting request");
+        // counted before the handshake, so half open connections count too
+        let mut limited_ip = None;
+        if is_server {
+            let ip = conn.remote_addr().ip();
+            if !connection_limits.try_add(ip) {
+                log_println_private!("too many connections from {}", ip);
+                // dropping the connection while connecting refuses it
+                return;
+            }
+            limited_ip = Some(ip);
+        }
         let connection = Arc::new(TokioRwLock::new(NetworkConnection::<C, Z> {
             conn: None,
             connecting: Some(conn),
 
             ping_handles: NetworkConnectionPingHandle::new(),
+
+            send_queue: Default::default(),
+
+            debug_simulation: debug_simulation.clone(),
+
+            traffic: Default::default(),
         }));
+        let bandwidth_limits = bandwidth_limits.clone();
+        let connection_limits = connection_limits.clone();
         let connection_async = connection.clone();
         let connections_clone = connections.clone();
         let game_event_generator_clone = game_event_generator.clone();
@@ -646,8 +995,9 @@ where
                 std::mem::swap(&mut connecting, &mut conn.connecting);
                 match connecting.unwrap().await {
                     Ok(connection) => {
+                        let remote_addr = connection.remote_addr();
                         conn.conn = Some(connection);
-                        println!("connecting established");
+                        log_println!("connecting established");
                         let timestamp = sys.time_get_nanoseconds();
                         game_event_generator_clone
                             .lock()
@@ -655,11 +1005,11 @@ where
                             .generate_from_network_event(
                                 timestamp,
                                 &connection_identifier,
-                                &NetworkGameEvent::Connected,
+                                &NetworkGameEvent::Connected(remote_addr),
                             );
                     }
                     Err(err) => {
-                        println!("Connection failed to resolve (connecting failed)");
+                        log_println!("Connection failed to resolve (connecting failed)");
                         let timestamp = sys.time_get_nanoseconds();
                         game_event_generator_clone
                             .lock()
@@ -676,12 +1026,16 @@ where
             tokio::spawn(async move {
                 let mut ping_interval = tokio::time::interval(Duration::from_secs(1) / 10); // TODO currently 10 times per second
                 tokio::select! {
-                    _ = Self::handle_connection_recv(connection_async.clone(), connections_clone.clone(), game_event_generator_clone.clone(), connection_identifier, sys.clone()) => {}
+                    _ = Self::handle_connection_recv(connection_async.clone(), connections_clone.clone(), game_event_generator_clone.clone(), connection_identifier, bandwidth_limits.clone(), sys.clone()) => {}
                     _ = Self::handle_connection_recv_datagram(connection_async.clone(), connections_clone, game_event_generator_clone, connection_identifier, sys.clone()) => {}
+                    _ = Self::handle_connection_send(connection_async.clone(), bandwidth_limits, sys.clone()) => {}
                     _ = Self::ping( sys, connection_async, &mut ping_interval) => {}
                 }
-                println!("connection dropped.");
-            })
+                if let Some(ip) = limited_ip {
+                    connection_limits.remove(ip);
+                }
+                log_println!("connection dropped.");
+            });
         });
     }
 
@@ -701,7 +1055,7 @@ where
                 while !events.events.is_empty() {
                     match &events.events[0] {
                         NetworkEvent::Connect(con_id, addr) => {
-                            println!("connecting to {}", addr);
+                            log_println_private!("connecting to {}", addr);
                             let conn_res = thread
                                 .endpoint
                                 .connect(addr.as_str().parse().unwrap(), "localhost");
@@ -712,6 +1066,9 @@ where
                                         &thread.game_event_generator,
                                         conn,
                                         con_id,
+                                        &thread.bandwidth_limits,
+                                        &thread.connection_limits,
+                                        &thread.debug_simulation,
                                         thread.sys.clone(),
                                         thread.is_server,
                                     );
@@ -736,7 +1093,7 @@ where
                             }
                         }
                         NetworkEvent::Disconnect(connection_id) => {
-                            println!("disconnecting");
+                            log_println!("disconnecting");
                             let connections_ = thread.connections.clone();
                             let con_id = *connection_id;
                             tokio::spawn(async move {
@@ -759,10 +1116,11 @@ where
                             });
                         }
                         NetworkEvent::Close() => thread.endpoint.close(VarInt::default(), &[]),
-                        NetworkEvent::Send((connection_id, packet)) => {
+                        NetworkEvent::Send((connection_id, packet, priority)) => {
                             let connections_ = thread.connections.clone();
                             let packet_send = NetworkPacket::User(packet.clone());
                             let con_id = *connection_id;
+                            let priority = *priority;
                             tokio::spawn(async move {
                                 let connections_guard = connections_.lock().await;
                                 let (_, connections) = &*connections_guard;
@@ -771,35 +1129,16 @@ where
                                 if let Some(conn) = connection {
                                     let connection = conn.clone();
                                     drop(connections_guard);
-                                    let conn_g = connection.read().await;
-                                    let con_res = conn_g.conn.as_ref();
-                                    if let Some(con) = con_res {
-                                        let con_clone = con.clone();
-                                        drop(conn_g);
-                                        let uni = con_clone.open_bi().await;
-                                        if let Ok((mut stream, _)) = uni {
-                                            let write_packet_res = bincode::encode_to_vec(
-                                                packet_send,
-                                                bincode::config::standard(),
-                                            );
-                                            if let Ok(write_packet) = write_packet_res {
-                                                let written_bytes =
-                                                    stream.write(&write_packet.as_slice()).await;
-                                                if let Err(_written_bytes) = written_bytes {
-                                                    println!("packet write failed.");
-                                                } else {
-                                                    let finish_res = stream.finish().await;
-                                                    if let Err(err) = finish_res {
-                                                        println!(
-                                                            "packet finish failed: {}",
-                                                            err.to_string()
-                                                        );
-                                                    }
-                                                }
-                                            }
-                                        } else if let Err(stream_err) = uni {
-                                            println!("sent stream err: {}", stream_err.to_string());
-                                        }
+                                    let write_packet_res = bincode::encode_to_vec(
+                                        packet_send,
+                                        bincode::config::standard(),
+                                    );
+                                    if let Ok(write_packet) = write_packet_res {
+                                        connection
+                                            .read()
+                                            .await
+                                            .send_queue
+                                            .push(write_packet, priority);
                                     }
                                 }
                             });
@@ -837,11 +1176,14 @@ where
         let server_addr = addr.parse().unwrap();
         let server = E::make_server_endpoint(server_addr, cert);
         if let Err(err) = &server {
-            println!("{}", err);
+            log_println!("{}", err);
         }
         let (endpoint, server_cert) = server.unwrap();
 
         let counter = Arc::new(NetworkConnectionIDCounter::new());
+        let bandwidth_limits = Arc::new(NetworkBandwidthLimits::default());
+        let connection_limits = Arc::new(NetworkConnectionLimits::default());
+        let debug_simulation = Arc::new(NetworkDebugSimulation::default());
 
         let endpoint_thread = endpoint.clone();
         let mut res = Network {
@@ -857,6 +1199,9 @@ where
                 endpoint: endpoint_thread,
                 connections: Arc::new(TokioMutex::new((counter.clone(), HashMap::new()))),
                 game_event_generator: game_event_generator,
+                bandwidth_limits: bandwidth_limits.clone(),
+                connection_limits: connection_limits.clone(),
+                debug_simulation: debug_simulation.clone(),
                 sys: sys.clone(),
             })),
             events: Arc::new(StdMutex::new(NetworkEvents {
@@ -866,6 +1211,9 @@ where
             run_thread: None,
             connection_id_generator: counter,
             connecting_connection_id: INVALID_NETWORK_CON_IDENTIFIER,
+            bandwidth_limits,
+            connection_limits,
+            debug_simulation,
             sys: sys,
             r: Default::default(),
             s: Default::default(),
@@ -893,6 +1241,9 @@ where
         let endpoint = E::make_client_endpoint(client_addr, &[server_cert]).unwrap();
 
         let counter = Arc::new(NetworkConnectionIDCounter::new());
+        let bandwidth_limits = Arc::new(NetworkBandwidthLimits::default());
+        let connection_limits = Arc::new(NetworkConnectionLimits::default());
+        let debug_simulation = Arc::new(NetworkDebugSimulation::default());
 
         let endpoint_thread = endpoint.clone();
         let mut res = Self {
@@ -908,6 +1259,9 @@ where
                 endpoint: endpoint_thread,
                 connections: Arc::new(TokioMutex::new((counter.clone(), HashMap::new()))),
                 game_event_generator: game_event_generator,
+                bandwidth_limits: bandwidth_limits.clone(),
+                connection_limits: connection_limits.clone(),
+                debug_simulation: debug_simulation.clone(),
                 sys: sys.clone(),
             })),
             events: Arc::new(StdMutex::new(NetworkEvents {
@@ -917,6 +1271,9 @@ where
             run_thread: None,
             connection_id_generator: counter,
             connecting_connection_id: INVALID_NETWORK_CON_IDENTIFIER,
+            bandwidth_limits,
+            connection_limits,
+            debug_simulation,
             sys,
             r: Default::default(),
             s: Default::default(),
@@ -938,19 +1295,25 @@ where
             let endpoint = network_thread.endpoint.clone();
             let connections = network_thread.connections.clone();
             let game_event_generator = network_thread.game_event_generator.clone();
+            let bandwidth_limits = network_thread.bandwidth_limits.clone();
+            let connection_limits = network_thread.connection_limits.clone();
+            let debug_simulation = network_thread.debug_simulation.clone();
             let sys = network_thread.sys.clone();
 
             let is_server = network_thread.is_server;
             if is_server {
                 tokio::spawn(async move {
-                    println!("server: starting to accept connections");
+                    log_println!("server: starting to accept connections");
                     while let Some(conn) = endpoint.accept().await {
-                        println!("server: accepted a connection");
+                        log_println!("server: accepted a connection");
                         Self::handle_connection(
                             &connections,
                             &game_event_generator,
                             conn,
                             &INVALID_NETWORK_CON_IDENTIFIER,
+                            &bandwidth_limits,
+                            &connection_limits,
+                            &debug_simulation,
                             sys.clone(),
                             is_server,
                         );
@@ -1003,24 +1366,71 @@ where
     pub fn send_to<T>(&mut self, msg: &T, connection_id: &NetworkConnectionID)
     where
         T: bincode::enc::Encode,
+    {
+        self.send_to_with_priority(msg, connection_id, NetworkPacketPriority::default());
+    }
+
+    pub fn send_to_with_priority<T>(
+        &mut self,
+        msg: &T,
+        connection_id: &NetworkConnectionID,
+        priority: NetworkPacketPriority,
+    ) where
+        T: bincode::enc::Encode,
     {
         let mut writer = self.events.lock().unwrap();
         let packet = bincode::encode_to_vec(msg, bincode::config::standard()).unwrap();
         writer
             .events
-            .push_back(NetworkEvent::Send((*connection_id, packet)));
+            .push_back(NetworkEvent::Send((*connection_id, packet, priority)));
         self.events_cond.notify_all();
     }
 
+    /**
+     * Up- and download limit of every connection in bytes per second, 0 means unlimited
+     */
+    pub fn set_bandwidth_limits(
+        &self,
+        upload_bytes_per_second: u64,
+        download_bytes_per_second: u64,
+    ) {
+        self.bandwidth_limits
+            .set(upload_bytes_per_second, download_bytes_per_second);
+    }
+
+    /**
+     * Maximum of concurrent connections from the same ip, 0 means unlimited
+     */
+    pub fn set_max_connections_per_ip(&self, max_per_ip: u32) {
+        self.connection_limits.set_max_per_ip(max_per_ip);
+    }
+
+    /**
+     * Artificial latency, jitter and loss for every connection, only for testing
+     */
+    pub fn set_debug_simulation(&self, settings: NetworkDebugSimulationSettings) {
+        self.debug_simulation.set(settings);
+    }
+
     /**
      * Only use this if `connect` was used
      */
     pub fn send_to_server<T>(&mut self, msg: &T)
+    where
+        T: bincode::enc::Encode,
+    {
+        self.send_to_server_with_priority(msg, NetworkPacketPriority::default());
+    }
+
+    /**
+     * Only use this if `connect` was used
+     */
+    pub fn send_to_server_with_priority<T>(&mut self, msg: &T, priority: NetworkPacketPriority)
     where
         T: bincode::enc::Encode,
     {
         if self.connecting_connection_id != INVALID_NETWORK_CON_IDENTIFIER {
-            self.send_to(msg, &self.connecting_connection_id.clone());
+            self.send_to_with_priority(msg, &self.connecting_connection_id.clone(), priority);
         }
     }
 
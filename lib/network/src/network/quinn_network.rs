@@ -2,8 +2,9 @@ use std::{future::Future, pin::Pin};
 
 use super::{
     network::{
-        Network, NetworkConnectionInterface, NetworkConnectionRecvStreamInterface,
-        NetworkConnectionSendStreamInterface, NetworkEndpointInterface,
+        Network, NetworkConnectingInterface, NetworkConnectionInterface,
+        NetworkConnectionRecvStreamInterface, NetworkConnectionSendStreamInterface,
+        NetworkEndpointInterface, NetworkTransportStats,
     },
     quinnminimal::{make_client_endpoint, make_server_endpoint},
 };
@@ -23,6 +24,10 @@ impl NetworkConnectionSendStreamInterface for QuinnNetworkSendStreamWrapper {
         let res = self.stream.write(buf).await?;
         Ok(res)
     }
+    async fn write_all(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        self.stream.write_all(buf).await?;
+        Ok(())
+    }
     async fn finish(&mut self) -> anyhow::Result<()> {
         let res = self.stream.finish().await?;
         Ok(res)
@@ -53,6 +58,18 @@ impl NetworkConnectionInterface<QuinnNetworkSendStreamWrapper, QuinnNetworkRecvS
         Ok(res)
     }
 
+    fn transport_stats(&self) -> NetworkTransportStats {
+        let stats = self.con.stats();
+        NetworkTransportStats {
+            packets_sent: stats.path.sent_packets,
+            packets_lost: stats.path.lost_packets,
+        }
+    }
+
+    fn remote_addr(&self) -> std::net::SocketAddr {
+        self.con.remote_address()
+    }
+
     fn close(&self, error_code: quinn::VarInt, reason: &[u8]) {
         self.con.close(error_code, reason)
     }
@@ -112,6 +129,12 @@ impl Future for QuinnNetworkConnectingWrapper {
     }
 }
 
+impl NetworkConnectingInterface for QuinnNetworkConnectingWrapper {
+    fn remote_addr(&self) -> std::net::SocketAddr {
+        self.connecting.remote_address()
+    }
+}
+
 #[derive(Clone)]
 pub struct QuinnEndpointWrapper {
     endpoint: quinn::Endpoint,
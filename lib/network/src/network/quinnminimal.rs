@@ -67,6 +67,9 @@ fn configure_server(cert: &Certificate) -> anyhow::Result<(ServerConfig, Vec<u8>
         .unwrap()
         .max_concurrent_uni_streams(0_u8.into())
         .keep_alive_interval(Some(Duration::from_millis(1000)));
+    // stateless retry: the client has to prove that it owns its address,
+    // before the server keeps any state, so spoofed floods can't exhaust the server
+    server_config.use_retry(true);
 
     Ok((server_config, cert_der))
 }
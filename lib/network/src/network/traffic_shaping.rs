@@ -0,0 +1,223 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering as AtomicOrdering},
+        Mutex as StdMutex,
+    },
+    time::Duration,
+};
+
+/**
+ * Packets with a higher priority are sent first, if the upload is throttled
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NetworkPacketPriority {
+    Input = 0,
+    // game state, e.g. snapshots
+    #[default]
+    Game,
+    Chat,
+    // maps, resources etc.
+    Download,
+}
+
+impl NetworkPacketPriority {
+    pub const COUNT: usize = 4;
+}
+
+/**
+ * Bytes of user packets that went through a connection
+ */
+#[derive(Debug, Default)]
+pub struct NetworkTrafficCounters {
+    // indexed by the priority
+    sent: [AtomicU64; NetworkPacketPriority::COUNT],
+    received: AtomicU64,
+}
+
+impl NetworkTrafficCounters {
+    pub fn add_sent(&self, bytes: u64, priority: NetworkPacketPriority) {
+        self.sent[priority as usize].fetch_add(bytes, AtomicOrdering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: u64) {
+        self.received.fetch_add(bytes, AtomicOrdering::Relaxed);
+    }
+
+    pub fn sent(&self) -> [u64; NetworkPacketPriority::COUNT] {
+        std::array::from_fn(|i| self.sent[i].load(AtomicOrdering::Relaxed))
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/**
+ * Up- and download limits of every connection in bytes per second, 0 means unlimited.
+ * Can be changed at any time, also affects existing connections.
+ */
+#[derive(Debug, Default)]
+pub struct NetworkBandwidthLimits {
+    upload: AtomicU64,
+    download: AtomicU64,
+}
+
+impl NetworkBandwidthLimits {
+    pub fn set(&self, upload_bytes_per_second: u64, download_bytes_per_second: u64) {
+        self.upload
+            .store(upload_bytes_per_second, AtomicOrdering::Relaxed);
+        self.download
+            .store(download_bytes_per_second, AtomicOrdering::Relaxed);
+    }
+
+    pub fn upload(&self) -> u64 {
+        self.upload.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn download(&self) -> u64 {
+        self.download.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/**
+ * Limits the concurrent connections per ip, 0 means unlimited.
+ * Can be changed at any time, only affects new connections.
+ */
+#[derive(Debug, Default)]
+pub struct NetworkConnectionLimits {
+    max_per_ip: AtomicU32,
+    connections: StdMutex<HashMap<IpAddr, u32>>,
+}
+
+impl NetworkConnectionLimits {
+    pub fn set_max_per_ip(&self, max_per_ip: u32) {
+        self.max_per_ip.store(max_per_ip, AtomicOrdering::Relaxed);
+    }
+
+    /**
+     * Returns false if the ip already has too many connections,
+     * else the connection must be removed with `remove` later
+     */
+    pub fn try_add(&self, ip: IpAddr) -> bool {
+        let max_per_ip = self.max_per_ip.load(AtomicOrdering::Relaxed);
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_default();
+        if max_per_ip != 0 && *count >= max_per_ip {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    pub fn remove(&self, ip: IpAddr) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&ip) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                connections.remove(&ip);
+            }
+        }
+    }
+}
+
+/**
+ * Token bucket: `rate` tokens are added per second, at most one second worth of tokens is kept,
+ * so short bursts are allowed.
+ */
+#[derive(Debug, Default)]
+pub struct NetworkTokenBucket {
+    tokens: f64,
+    last_update: Duration,
+}
+
+impl NetworkTokenBucket {
+    /**
+     * Takes `amount` tokens, returns how long the caller has to wait
+     * until the tokens are actually available (the bucket goes into debt).
+     * A rate of 0 means unlimited.
+     */
+    pub fn take(&mut self, amount: u64, rate: u64, cur_time: Duration) -> Duration {
+        if rate == 0 {
+            self.tokens = 0.0;
+            self.last_update = cur_time;
+            return Duration::ZERO;
+        }
+        let passed = cur_time.saturating_sub(self.last_update).as_secs_f64();
+        self.last_update = cur_time;
+        self.tokens = (self.tokens + passed * rate as f64).min(rate as f64);
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate as f64)
+        }
+    }
+}
+
+struct NetworkQueuedPacket {
+    priority: NetworkPacketPriority,
+    // packets of the same priority are sent in order
+    seq: u64,
+    packet: Vec<u8>,
+}
+
+impl PartialEq for NetworkQueuedPacket {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for NetworkQueuedPacket {}
+
+impl PartialOrd for NetworkQueuedPacket {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NetworkQueuedPacket {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the binary heap pops the largest element first
+        (other.priority, other.seq).cmp(&(self.priority, self.seq))
+    }
+}
+
+/**
+ * The packets of a connection that still have to be sent, ordered by priority
+ */
+#[derive(Default)]
+pub struct NetworkSendQueue {
+    packets: StdMutex<(u64, BinaryHeap<NetworkQueuedPacket>)>,
+    notify: tokio::sync::Notify,
+}
+
+impl NetworkSendQueue {
+    pub fn push(&self, packet: Vec<u8>, priority: NetworkPacketPriority) {
+        let mut packets = self.packets.lock().unwrap();
+        let (seq, heap) = &mut *packets;
+        *seq += 1;
+        heap.push(NetworkQueuedPacket {
+            priority,
+            seq: *seq,
+            packet,
+        });
+        drop(packets);
+        self.notify.notify_one();
+    }
+
+    /**
+     * Waits until there is a packet
+     */
+    pub async fn pop(&self) -> (Vec<u8>, NetworkPacketPriority) {
+        loop {
+            let packet = self.packets.lock().unwrap().1.pop();
+            if let Some(packet) = packet {
+                return (packet.packet, packet.priority);
+            }
+            self.notify.notified().await;
+        }
+    }
+}
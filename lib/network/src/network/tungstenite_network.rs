@@ -48,6 +48,10 @@ impl NetworkConnectionSendStreamInterface for TungsteniteNetworkSendStreamWrappe
         // Ok(res)
         Ok(0)
     }
+    async fn write_all(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        //self.stream.write_all(buf).await?;
+        Ok(())
+    }
     async fn finish(&mut self) -> anyhow::Result<()> {
         //let res = self.stream.finish().await?;
         //Ok(res)
@@ -1,6 +1,15 @@
+mod mod_fs;
+mod mod_storage;
 mod relaxed_atomic_optional_ptr;
 
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use anyhow::anyhow;
+
+use base::{filesys::FileSystem, io_batcher::IOBatcher, log_println};
 
 use graphics::graphics::Graphics;
 use graphics_traits::GraphicsStreamHandler;
@@ -8,18 +17,67 @@ use graphics_types::{
     rendering::{GL_SVertex, State},
     types::DrawModes,
 };
+use mod_fs::ModFileSystem;
+pub use mod_fs::ModFileSystemSettings;
+use mod_storage::ModStorage;
 use relaxed_atomic_optional_ptr::RelaxedAtomicPtrOption;
+use wasm_runtime_types::host_calls::HostCall;
 use wasmer::{
     imports, CompilerConfig, Cranelift, Function, FunctionEnv, FunctionEnvMut, Instance, Module,
     Store, TypedFunction,
 };
 
+/**
+ * The api version of the host, must be increased for every change of the imports/exports
+ */
+pub const HOST_API_VERSION: u32 = 6;
+
+/**
+ * The oldest module api version that each host api version can still run.
+ * Indexed by the host api version - 1.
+ */
+const API_COMPATIBILITY: [u32; HOST_API_VERSION as usize] = [1, 1, 1, 1, 1, 1];
+
+fn check_api_version(module_version: u32) -> anyhow::Result<()> {
+    let oldest_compatible = API_COMPATIBILITY[HOST_API_VERSION as usize - 1];
+    if module_version > HOST_API_VERSION {
+        Err(anyhow!(
+            "the module was built for api v{}, but the host only has v{}, please update the client",
+            module_version,
+            HOST_API_VERSION
+        ))
+    } else if module_version < oldest_compatible {
+        Err(anyhow!(
+            "the module was built for api v{}, but the host has v{} (needs at least v{}), please update the module",
+            module_version,
+            HOST_API_VERSION,
+            oldest_compatible
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/**
+ * What a module wants to do with the simulation of the game,
+ * the host decides if (and how) it's applied, e.g. by asking the server
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WasmStateRequest {
+    Pause,
+    Resume,
+    Step(u64),
+}
+
 pub struct WasmManagerLogic {
     // this pointer should only be modified
     // before a wasm instance is called and
     // should be invalidated otherwise
     // TODO: force null check somehow
     graphics: RelaxedAtomicPtrOption<Graphics>,
+
+    state_paused: AtomicBool,
+    state_requests: Mutex<Vec<WasmStateRequest>>,
 }
 
 impl WasmManagerLogic {
@@ -39,6 +97,18 @@ impl WasmManagerLogic {
             println!("Hello, world!");
         }
     }
+
+    // 0 if the module is called outside of rendering
+    fn canvas_size(&self) -> (u32, u32) {
+        self.graphics
+            .load()
+            .map(|graphics| (graphics.canvas_width(), graphics.canvas_height()))
+            .unwrap_or_default()
+    }
+
+    fn state_request(&self, request: WasmStateRequest) {
+        self.state_requests.lock().unwrap().push(request);
+    }
 }
 
 unsafe impl Send for WasmManagerLogic {}
@@ -51,12 +121,38 @@ unsafe impl Sync for WasmManagerLogic {}
 pub struct WasmManager {
     store: Store,
     instance: Instance,
+    env: FunctionEnv<RawBytesEnv>,
 
     logic: Arc<WasmManagerLogic>,
 }
 
+struct RawBytesEnv {
+    raw_bytes: Vec<u8>,
+    raw_bytes2: Vec<u8>,
+    raw_bytes3: Vec<u8>,
+    raw_bytes4: Vec<u8>,
+    // bytes the module can read back, e.g. the result of a storage get
+    result_bytes: Vec<u8>,
+    storage: ModStorage,
+    fs: ModFileSystem,
+}
+
 impl WasmManager {
-    pub fn new(wasm_bytes: &[u8]) -> anyhow::Result<Self> {
+    /**
+     * The module name is used to namespace the persistent storage
+     * and the private directory of the module
+     */
+    pub fn new(
+        wasm_bytes: &[u8],
+        module_name: &str,
+        fs_settings: ModFileSystemSettings,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+    ) -> anyhow::Result<Self> {
+        // the storage and the private files are read while the module compiles
+        let storage = ModStorage::load(module_name, fs, io_batcher);
+        let mod_fs = ModFileSystem::new(module_name, fs_settings, fs, io_batcher);
+
         let compiler = Cranelift::new();
         //compiler.opt_level(wasmer::CraneliftOptLevel::None);
         //compiler.enable_verifier();
@@ -67,19 +163,32 @@ impl WasmManager {
 
         let logic = Arc::new(WasmManagerLogic {
             graphics: RelaxedAtomicPtrOption::new(std::ptr::null_mut()),
+
+            state_paused: AtomicBool::new(false),
+            state_requests: Default::default(),
         });
 
         let logic_clone = logic.clone();
+        let logic_clone_batch = logic.clone();
+        let logic_clone_width = logic.clone();
+        let logic_clone_height = logic.clone();
+        let logic_clone_pause = logic.clone();
+        let logic_clone_resume = logic.clone();
+        let logic_clone_step = logic.clone();
+        let logic_clone_paused = logic.clone();
 
-        #[derive(Default, Clone)]
-        struct RawBytesEnv {
-            raw_bytes: Vec<u8>,
-            raw_bytes2: Vec<u8>,
-            raw_bytes3: Vec<u8>,
-            raw_bytes4: Vec<u8>,
-        }
-
-        let println_env = FunctionEnv::new(&mut store, RawBytesEnv::default());
+        let println_env = FunctionEnv::new(
+            &mut store,
+            RawBytesEnv {
+                raw_bytes: Default::default(),
+                raw_bytes2: Default::default(),
+                raw_bytes3: Default::default(),
+                raw_bytes4: Default::default(),
+                result_bytes: Default::default(),
+                storage,
+                fs: mod_fs,
+            },
+        );
 
         fn raw_bytes_add_u64_impl(bytes: &mut Vec<u8>, byte_stream: u64, byte_count: u8) {
             // put bytes into our raw byte array
@@ -127,12 +236,69 @@ impl WasmManager {
             raw_bytes_add_u64_impl(&mut env.data_mut().raw_bytes4, byte_stream, byte_count)
         }
 
+        // newer modules queue `HostCall::Println`, this is still imported by older ones
         fn println(mut env: FunctionEnvMut<RawBytesEnv>) {
             let mut text: Vec<u8> = Default::default();
             std::mem::swap(&mut text, &mut env.data_mut().raw_bytes);
             let text_str = String::from_utf8(text);
             if let Ok(print_str) = text_str {
-                println!("{}", print_str);
+                log_println!("{}", print_str);
+            }
+        }
+
+        fn result_bytes_len(env: FunctionEnvMut<RawBytesEnv>) -> u64 {
+            env.data().result_bytes.len() as u64
+        }
+
+        fn result_bytes_get_u64(env: FunctionEnvMut<RawBytesEnv>, offset: u64) -> u64 {
+            let bytes = &env.data().result_bytes;
+            let offset = (offset as usize).min(bytes.len());
+            let chunk = &bytes[offset..(offset + std::mem::size_of::<u64>()).min(bytes.len())];
+            let mut chunk_full: [u8; std::mem::size_of::<u64>()] = [0; std::mem::size_of::<u64>()];
+            chunk_full[0..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(chunk_full)
+        }
+
+        // the key is in the first raw bytes, returns 1 if the value was found
+        // and written to the result bytes
+        fn storage_get(mut env: FunctionEnvMut<RawBytesEnv>) -> u32 {
+            let data = env.data_mut();
+            let key = String::from_utf8(std::mem::take(&mut data.raw_bytes)).unwrap_or_default();
+            match data.storage.get(&key) {
+                Some(value) => {
+                    data.result_bytes = value.as_bytes().to_vec();
+                    1
+                }
+                None => {
+                    data.result_bytes.clear();
+                    0
+                }
+            }
+        }
+
+        // the key is in the first raw bytes, the value in the second,
+        // returns 1 on success
+        fn storage_set(mut env: FunctionEnvMut<RawBytesEnv>) -> u32 {
+            let data = env.data_mut();
+            let key = String::from_utf8(std::mem::take(&mut data.raw_bytes));
+            let value = String::from_utf8(std::mem::take(&mut data.raw_bytes2));
+            match (key, value) {
+                (Ok(key), Ok(value)) => match data.storage.set(&key, &value) {
+                    Ok(_) => 1,
+                    Err(err) => {
+                        log_println!("mod storage: {}", err);
+                        0
+                    }
+                },
+                _ => 0,
+            }
+        }
+
+        // the key is in the first raw bytes
+        fn storage_remove(mut env: FunctionEnvMut<RawBytesEnv>) {
+            let data = env.data_mut();
+            if let Ok(key) = String::from_utf8(std::mem::take(&mut data.raw_bytes)) {
+                data.storage.remove(&key);
             }
         }
 
@@ -159,6 +325,75 @@ impl WasmManager {
             )
         }
 
+        // the path is in the first raw bytes, returns 1 if the file was read
+        // into the result bytes
+        fn fs_read(mut env: FunctionEnvMut<RawBytesEnv>) -> u32 {
+            let data = env.data_mut();
+            let path = String::from_utf8(std::mem::take(&mut data.raw_bytes)).unwrap_or_default();
+            match data.fs.read(&path) {
+                Ok(file) => {
+                    data.result_bytes = file;
+                    1
+                }
+                Err(_) => {
+                    data.result_bytes.clear();
+                    0
+                }
+            }
+        }
+
+        // the path is in the first raw bytes, the file in the second,
+        // returns 1 on success
+        fn fs_write(mut env: FunctionEnvMut<RawBytesEnv>) -> u32 {
+            let data = env.data_mut();
+            let path = String::from_utf8(std::mem::take(&mut data.raw_bytes)).unwrap_or_default();
+            let file = std::mem::take(&mut data.raw_bytes2);
+            match data.fs.write(&path, &file) {
+                Ok(_) => 1,
+                Err(err) => {
+                    log_println!("mod file system: {}", err);
+                    0
+                }
+            }
+        }
+
+        // the path is in the first raw bytes, returns 1 on success
+        fn fs_remove(mut env: FunctionEnvMut<RawBytesEnv>) -> u32 {
+            let data = env.data_mut();
+            let path = String::from_utf8(std::mem::take(&mut data.raw_bytes)).unwrap_or_default();
+            data.fs.remove(&path).is_ok() as u32
+        }
+
+        // runs all calls the module queued, in the order they were queued
+        fn run_batch(logic_clone: &Arc<WasmManagerLogic>, mut env: FunctionEnvMut<RawBytesEnv>) {
+            let batch_bytes = std::mem::take(&mut env.data_mut().raw_bytes4);
+            // the bytes come from the module, so they can't be trusted
+            let calls: Vec<HostCall> = match bincode::decode_from_slice(
+                batch_bytes.as_slice(),
+                bincode::config::standard(),
+            ) {
+                Ok((calls, _)) => calls,
+                Err(err) => {
+                    log_println!("module sent an invalid batch of host calls: {}", err);
+                    return;
+                }
+            };
+            calls.into_iter().for_each(|call| match call {
+                HostCall::Println(text) => log_println!("{}", text),
+                HostCall::FlushVertices {
+                    vertices,
+                    state,
+                    vertices_offset,
+                    draw_mode,
+                } => logic_clone.flush_vertices(
+                    &vertices,
+                    &state,
+                    vertices_offset as usize,
+                    draw_mode,
+                ),
+            });
+        }
+
         // We then create an import object so that the `Module`'s imports can be satisfied.
         let import_object = imports! {
             "env" => {
@@ -167,6 +402,22 @@ impl WasmManager {
                 "host_raw_bytes_add_u64_3" => Function::new_typed_with_env(&mut store, &println_env.clone(), raw_bytes_add_u64_3),
                 "host_raw_bytes_add_u64_4" => Function::new_typed_with_env(&mut store, &println_env.clone(), raw_bytes_add_u64_4),
                 "host_println" => Function::new_typed_with_env(&mut store, &println_env, println),
+                "host_result_bytes_len" => Function::new_typed_with_env(&mut store, &println_env, result_bytes_len),
+                "host_result_bytes_get_u64" => Function::new_typed_with_env(&mut store, &println_env, result_bytes_get_u64),
+                "host_storage_get" => Function::new_typed_with_env(&mut store, &println_env, storage_get),
+                "host_storage_set" => Function::new_typed_with_env(&mut store, &println_env, storage_set),
+                "host_storage_remove" => Function::new_typed_with_env(&mut store, &println_env, storage_remove),
+                "host_canvas_width" => Function::new_typed_with_env(&mut store, &println_env, move |_: FunctionEnvMut<RawBytesEnv>| logic_clone_width.canvas_size().0),
+                "host_canvas_height" => Function::new_typed_with_env(&mut store, &println_env, move |_: FunctionEnvMut<RawBytesEnv>| logic_clone_height.canvas_size().1),
+                "host_fs_read" => Function::new_typed_with_env(&mut store, &println_env, fs_read),
+                "host_fs_write" => Function::new_typed_with_env(&mut store, &println_env, fs_write),
+                "host_fs_remove" => Function::new_typed_with_env(&mut store, &println_env, fs_remove),
+                "host_state_pause" => Function::new_typed_with_env(&mut store, &println_env, move |_: FunctionEnvMut<RawBytesEnv>| logic_clone_pause.state_request(WasmStateRequest::Pause)),
+                "host_state_resume" => Function::new_typed_with_env(&mut store, &println_env, move |_: FunctionEnvMut<RawBytesEnv>| logic_clone_resume.state_request(WasmStateRequest::Resume)),
+                "host_state_step" => Function::new_typed_with_env(&mut store, &println_env, move |_: FunctionEnvMut<RawBytesEnv>, ticks: u64| logic_clone_step.state_request(WasmStateRequest::Step(ticks))),
+                "host_state_is_paused" => Function::new_typed_with_env(&mut store, &println_env, move |_: FunctionEnvMut<RawBytesEnv>| logic_clone_paused.state_paused.load(Ordering::Relaxed) as u32),
+                "host_run_batch" => Function::new_typed_with_env(&mut store, &println_env, move |env: FunctionEnvMut<RawBytesEnv>| run_batch(&logic_clone_batch, env)),
+                // modules before api v3 flush their vertices without batching
                 "flush_vertices" => Function::new_typed_with_env(&mut store, &println_env, move |env: FunctionEnvMut<RawBytesEnv>, vertices_offset: u64| flush_vertices(&logic_clone, env, vertices_offset)),
             }
         };
@@ -175,11 +426,31 @@ impl WasmManager {
         //
         // An `Instance` is a compiled WebAssembly module that has been set up
         // and is ready to execute.
-        let instance = Instance::new(&mut store, &module, &import_object)?;
+        let instance = Instance::new(&mut store, &module, &import_object).map_err(|err| {
+            anyhow!(
+                "the module could not be instantiated, maybe it was built for another api than v{}: {}",
+                HOST_API_VERSION,
+                err
+            )
+        })?;
+
+        // check the version before anything of the module is called,
+        // else old modules trap on missing imports in the middle of the game
+        let api_version_func: TypedFunction<(), u32> = instance
+            .exports
+            .get_typed_function(&mut store, "api_version")
+            .map_err(|_| {
+                anyhow!(
+                    "the module was built without api version, the host has api v{}",
+                    HOST_API_VERSION
+                )
+            })?;
+        check_api_version(api_version_func.call(&mut store)?)?;
 
         Ok(Self {
             store: store,
             instance: instance,
+            env: println_env,
             logic: logic,
         })
     }
@@ -197,8 +468,27 @@ impl WasmManager {
         // Finally, we call our exported Wasm function which will call our "say_hello"
         // function and return.
         self.logic.graphics.store(graphics);
-        run_func.call(&mut self.store)?;
+        let res = run_func.call(&mut self.store);
         self.logic.graphics.store(std::ptr::null_mut());
+        // everything the module stored during this run is written at once
+        let env = self.env.as_mut(&mut self.store);
+        env.storage.flush();
+        env.fs.flush();
+        res?;
         Ok(())
     }
+
+    /**
+     * If the game the module sees is paused, set before the module runs
+     */
+    pub fn set_state_paused(&self, paused: bool) {
+        self.logic.state_paused.store(paused, Ordering::Relaxed);
+    }
+
+    /**
+     * The simulation requests the module made since the last call, in order
+     */
+    pub fn take_state_requests(&self) -> Vec<WasmStateRequest> {
+        std::mem::take(&mut *self.logic.state_requests.lock().unwrap())
+    }
 }
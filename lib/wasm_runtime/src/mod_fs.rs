@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use arrayvec::ArrayString;
+use base::{
+    filesys::{file_name_from_name, FileSystem},
+    io_batcher::{IOBatcher, IOBatcherTask},
+    log_println,
+};
+
+const MOD_DATA_DIR: &str = "mod_data";
+
+pub struct ModFileSystemSettings {
+    // the size all files in the private directory of the module can have together
+    pub quota_bytes: u64,
+    // relative paths outside of the private directory that the module can read
+    pub shared_read_paths: Vec<String>,
+}
+
+impl Default for ModFileSystemSettings {
+    fn default() -> Self {
+        Self {
+            quota_bytes: 4 * 1024 * 1024,
+            shared_read_paths: Vec::new(),
+        }
+    }
+}
+
+/**
+ * The files a wasm module can access. Every module gets a private directory
+ * that only it can write to, everything else can at most be read,
+ * if it's in one of the shared paths.
+ * All checks happen on the host, the module only ever sees relative paths.
+ * The private directory is limited by the quota, so it's read once when the module is loaded
+ * and kept in memory, changes are written by [`ModFileSystem::flush`].
+ */
+pub struct ModFileSystem {
+    private_dir: String,
+    quota_bytes: u64,
+    shared_read_paths: Vec<String>,
+
+    fs: Arc<FileSystem>,
+    io_batcher: Arc<Mutex<IOBatcher>>,
+    load_task: Option<IOBatcherTask<HashMap<String, Vec<u8>>>>,
+    files: HashMap<String, Vec<u8>>,
+    files_size: u64,
+    // the changes since the last write was started, `None` removes the file
+    changes: HashMap<String, Option<Vec<u8>>>,
+    write_task: Option<IOBatcherTask<()>>,
+    // every write waits for the previous one, so changes of a file are applied in order
+    write_done: Option<tokio::sync::oneshot::Receiver<()>>,
+}
+
+/**
+ * Only allows plain relative paths, so a module can never leave the directory
+ * the path is joined to
+ */
+fn sanitize_path(path: &str) -> anyhow::Result<String> {
+    let path = Path::new(path);
+    let components: Option<Vec<&str>> = path
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    match components {
+        Some(components) if !components.is_empty() => Ok(components.join("/")),
+        _ => Err(anyhow!(
+            "mod file system paths must be relative and must not contain \"..\""
+        )),
+    }
+}
+
+fn is_in_dir(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl ModFileSystem {
+    /**
+     * Starts reading the private directory, the files are available
+     * after the first access waited for the read
+     */
+    pub fn new(
+        module_name: &str,
+        settings: ModFileSystemSettings,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+    ) -> Self {
+        let private_dir = format!("{}/{}/", MOD_DATA_DIR, file_name_from_name(module_name));
+        let fs_clone = fs.clone();
+        let task_dir = private_dir.clone();
+        let load_task = io_batcher.lock().unwrap().spawn(async move {
+            let mut files = HashMap::new();
+            fs_clone
+                .files_of_write_dir_recursive(&task_dir, &mut |path, file| {
+                    files.insert(path, file);
+                })
+                .await;
+            Ok(files)
+        });
+        Self {
+            private_dir,
+            quota_bytes: settings.quota_bytes,
+            shared_read_paths: settings
+                .shared_read_paths
+                .iter()
+                .filter_map(|path| sanitize_path(path).ok())
+                .collect(),
+
+            fs: fs.clone(),
+            io_batcher: io_batcher.clone(),
+            load_task: Some(load_task),
+            files: Default::default(),
+            files_size: 0,
+            changes: Default::default(),
+            write_task: None,
+            write_done: None,
+        }
+    }
+
+    fn ensure_loaded(&mut self) {
+        if let Some(mut task) = self.load_task.take() {
+            self.io_batcher.lock().unwrap().wait_finished(&mut task);
+            self.files = task.get_storage().unwrap_or_default();
+            self.files_size = self.files.values().map(|file| file.len() as u64).sum();
+        }
+    }
+
+    fn spawn_write(&mut self) -> IOBatcherTask<()> {
+        let changes = std::mem::take(&mut self.changes);
+        let prev_write_done = self.write_done.take();
+        let (write_done_sender, write_done) = tokio::sync::oneshot::channel();
+        self.write_done = Some(write_done);
+        let fs = self.fs.clone();
+        let private_dir = self.private_dir.clone();
+        self.io_batcher.lock().unwrap().spawn(async move {
+            if let Some(prev_write_done) = prev_write_done {
+                // also continues if the previous write failed
+                let _ = prev_write_done.await;
+            }
+            let mut res = Ok(());
+            for (path, change) in changes {
+                let path = private_dir.clone() + &path;
+                let change_res = match change {
+                    Some(file) => fs.write_file(&path, file).await,
+                    None => fs.remove_file(&path).await,
+                };
+                if let Err(err) = change_res {
+                    res = Err(
+                        ArrayString::from(&format!("could not write {}: {}", path, err))
+                            .unwrap_or_default(),
+                    );
+                }
+            }
+            let _ = write_done_sender.send(());
+            res
+        })
+    }
+
+    /**
+     * Starts writing the changed files, if no other write is still running.
+     * Should be called regularly, e.g. after every run of the module
+     */
+    pub fn flush(&mut self) {
+        if let Some(task) = &mut self.write_task {
+            if !task.is_finished() {
+                return;
+            }
+            if let Err(err) = task.get_storage() {
+                log_println!("mod file system: {}", err);
+            }
+            self.write_task = None;
+        }
+        if !self.changes.is_empty() {
+            self.write_task = Some(self.spawn_write());
+        }
+    }
+
+    /**
+     * Reads a file of the private directory, or of the shared paths
+     * if the private directory does not contain it
+     */
+    pub fn read(&mut self, path: &str) -> anyhow::Result<Vec<u8>> {
+        self.ensure_loaded();
+        let path = sanitize_path(path)?;
+        if let Some(file) = self.files.get(&path) {
+            return Ok(file.clone());
+        }
+        if self
+            .shared_read_paths
+            .iter()
+            .any(|shared_path| is_in_dir(&path, shared_path))
+        {
+            let fs = self.fs.clone();
+            let mut task = self.io_batcher.lock().unwrap().spawn(async move {
+                fs.open_file(&path)
+                    .await
+                    .map_err(|err| ArrayString::from(&err.to_string()).unwrap_or_default())
+            });
+            self.io_batcher.lock().unwrap().wait_finished(&mut task);
+            return task.get_storage().map_err(|err| anyhow!("{}", err));
+        }
+        Err(anyhow!("file not found or not readable by the mod"))
+    }
+
+    pub fn write(&mut self, path: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.ensure_loaded();
+        let path = sanitize_path(path)?;
+        if self
+            .files
+            .keys()
+            .any(|file_path| file_path != &path && is_in_dir(file_path, &path))
+        {
+            return Err(anyhow!("the mod can't write to a directory"));
+        }
+        if self
+            .files
+            .keys()
+            .any(|file_path| file_path != &path && is_in_dir(&path, file_path))
+        {
+            return Err(anyhow!("the mod can't write into a file"));
+        }
+        // an overwritten file does not count to the quota anymore
+        let overwritten_size = self.files.get(&path).map(|file| file.len()).unwrap_or(0) as u64;
+        let new_size = self.files_size - overwritten_size + data.len() as u64;
+        if new_size > self.quota_bytes {
+            return Err(anyhow!(
+                "the mod exceeded its file system quota of {} bytes",
+                self.quota_bytes
+            ));
+        }
+        self.files_size = new_size;
+        self.files.insert(path.clone(), data.to_vec());
+        self.changes.insert(path, Some(data.to_vec()));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &str) -> anyhow::Result<()> {
+        self.ensure_loaded();
+        let path = sanitize_path(path)?;
+        let file = self
+            .files
+            .remove(&path)
+            .ok_or_else(|| anyhow!("file not found"))?;
+        self.files_size -= file.len() as u64;
+        self.changes.insert(path, None);
+        Ok(())
+    }
+}
+
+impl Drop for ModFileSystem {
+    fn drop(&mut self) {
+        // the write finishes in the background, after the writes that are still running
+        if !self.changes.is_empty() {
+            self.spawn_write();
+        }
+    }
+}
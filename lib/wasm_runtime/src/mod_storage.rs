@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use arrayvec::ArrayString;
+use base::{
+    filesys::{file_name_from_name, FileSystem},
+    io_batcher::{IOBatcher, IOBatcherTask},
+    log_println,
+};
+
+const MOD_STORAGE_DIR: &str = "mod_storage";
+
+const MAX_KEY_LEN: usize = 256;
+const MAX_VALUE_LEN: usize = 4096;
+const MAX_ENTRIES: usize = 256;
+
+/**
+ * Small persistent key-value storage of a single wasm module,
+ * e.g. for user preferences of UI pages.
+ * Every module only sees its own storage file, so it does not need
+ * general file system access.
+ * Changes are only written by [`ModStorage::flush`], so a module
+ * that sets many values per frame still only causes one write.
+ */
+pub struct ModStorage {
+    path: String,
+    values: HashMap<String, String>,
+
+    fs: Arc<FileSystem>,
+    io_batcher: Arc<Mutex<IOBatcher>>,
+    load_task: Option<IOBatcherTask<Vec<u8>>>,
+    write_task: Option<IOBatcherTask<()>>,
+    // changed since the last write was started
+    dirty: bool,
+    // writes that finish out of order never overwrite newer values
+    write_generation: u64,
+    written_generation: Arc<tokio::sync::Mutex<u64>>,
+}
+
+impl ModStorage {
+    /**
+     * Starts reading the storage file, the values are available
+     * after the first access waited for the read
+     */
+    pub fn load(
+        module_name: &str,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+    ) -> Self {
+        let path = format!(
+            "{}/{}.json",
+            MOD_STORAGE_DIR,
+            file_name_from_name(module_name)
+        );
+        let fs_clone = fs.clone();
+        let task_path = path.clone();
+        let load_task = io_batcher.lock().unwrap().spawn(async move {
+            fs_clone.open_file(&task_path).await.map_err(|err| {
+                ArrayString::from(&format!("could not read {}: {}", task_path, err))
+                    .unwrap_or_default()
+            })
+        });
+        Self {
+            path,
+            values: Default::default(),
+
+            fs: fs.clone(),
+            io_batcher: io_batcher.clone(),
+            load_task: Some(load_task),
+            write_task: None,
+            dirty: false,
+            write_generation: 0,
+            written_generation: Default::default(),
+        }
+    }
+
+    fn ensure_loaded(&mut self) {
+        if let Some(mut task) = self.load_task.take() {
+            self.io_batcher.lock().unwrap().wait_finished(&mut task);
+            // a missing or broken file is an empty storage
+            self.values = task
+                .get_storage()
+                .ok()
+                .and_then(|file| serde_json::from_slice(&file).ok())
+                .unwrap_or_default();
+        }
+    }
+
+    fn spawn_write(&mut self) -> IOBatcherTask<()> {
+        self.dirty = false;
+        self.write_generation += 1;
+        let generation = self.write_generation;
+        let written_generation = self.written_generation.clone();
+        let file = serde_json::to_vec(&self.values).unwrap_or_default();
+        let fs = self.fs.clone();
+        let path = self.path.clone();
+        self.io_batcher.lock().unwrap().spawn(async move {
+            let mut written_generation = written_generation.lock().await;
+            if *written_generation < generation {
+                fs.write_file(&path, file).await.map_err(|err| {
+                    ArrayString::from(&format!("could not write {}: {}", path, err))
+                        .unwrap_or_default()
+                })?;
+                *written_generation = generation;
+            }
+            Ok(())
+        })
+    }
+
+    /**
+     * Starts writing the changed values, if no other write is still running.
+     * Should be called regularly, e.g. after every run of the module
+     */
+    pub fn flush(&mut self) {
+        if let Some(task) = &mut self.write_task {
+            if !task.is_finished() {
+                return;
+            }
+            if let Err(err) = task.get_storage() {
+                log_println!("mod storage: {}", err);
+            }
+            self.write_task = None;
+        }
+        if self.dirty {
+            self.write_task = Some(self.spawn_write());
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&String> {
+        self.ensure_loaded();
+        self.values.get(key)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.ensure_loaded();
+        if key.len() > MAX_KEY_LEN || value.len() > MAX_VALUE_LEN {
+            return Err(anyhow!(
+                "mod storage keys can have at most {} bytes and values at most {} bytes",
+                MAX_KEY_LEN,
+                MAX_VALUE_LEN
+            ));
+        }
+        if !self.values.contains_key(key) && self.values.len() >= MAX_ENTRIES {
+            return Err(anyhow!(
+                "mod storage can have at most {} entries",
+                MAX_ENTRIES
+            ));
+        }
+        if self.values.get(key).map(|v| v.as_str()) != Some(value) {
+            self.values.insert(key.to_string(), value.to_string());
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.ensure_loaded();
+        if self.values.remove(key).is_some() {
+            self.dirty = true;
+        }
+    }
+}
+
+impl Drop for ModStorage {
+    fn drop(&mut self) {
+        // the write finishes in the background, e.g. while a rebuilt module is loaded
+        if self.dirty {
+            self.spawn_write();
+        }
+    }
+}
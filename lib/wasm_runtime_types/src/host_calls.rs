@@ -0,0 +1,23 @@
+use bincode::{Decode, Encode};
+
+use graphics_types::{
+    rendering::{SVertex, State},
+    types::DrawModes,
+};
+
+/**
+ * A call of a wasm module to the host that does not need a result,
+ * so it can be queued and sent together with other calls in one batch.
+ * Every call across the wasm boundary is expensive, so render heavy modules
+ * should never call the host per draw call.
+ */
+#[derive(Encode, Decode)]
+pub enum HostCall {
+    Println(String),
+    FlushVertices {
+        vertices: Vec<SVertex>,
+        state: State,
+        vertices_offset: u64,
+        draw_mode: DrawModes,
+    },
+}
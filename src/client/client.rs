@@ -1,5 +1,5 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, VecDeque},
     num::NonZeroUsize,
     sync::{atomic::AtomicBool, Arc},
     time::Duration,
@@ -8,30 +8,48 @@ use std::{
 use base::{
     benchmark,
     config::Config,
+    console::{Console, NoConsoleCommands},
     filesys::FileSystem,
     io_batcher::IOBatcher,
+    log_println, log_println_private,
     system::{System, SystemLogInterface, SystemTimeInterface},
 };
 use native::{input::Input, native::Native};
-use network::network::{network::NetworkConnectionID, quinn_network::QuinnNetwork};
-use wasm_runtime::WasmManager;
+use network::network::{
+    debug_simulation::NetworkDebugSimulationSettings,
+    network::{NetworkConnectionID, NetworkStats},
+    quinn_network::QuinnNetwork,
+    traffic_shaping::NetworkPacketPriority,
+};
+use wasm_runtime::{WasmManager, WasmStateRequest};
 
 use crate::{
     client::input::{InputHandling, InputPipe},
     client_map::ClientMap,
     game::{
+        ghost::GhostPath,
+        match_stats::MatchStatsReport,
         simulation_pipe::{LocalPlayerInput, LocalPlayers, SimulationPipe, SimulationPlayerInput},
-        snapshot::SnapshotManager,
-        state::GameState,
+        snapshot::{
+            SnapshotClientRole, SnapshotManager, SnapshotSpectatorCamera, SnapshotViewArea,
+        },
+        state::{GameState, GameStateInterface},
         TGameElementID, INVALID_GAME_ELEMENT_ID,
     },
     id_gen::IDGeneratorIDType,
     network::{
         game_event_generator::GameEventGenerator,
-        messages::{ClientToServerMessage, GameMessage},
+        messages::{
+            ClientToServerMessage, GameMessage, MsgClSimulation, MsgObjPlayerInfo,
+            MsgObjResourceHint, MsgObjStageError, MsgObjVoteProgress, MsgObjVoteProposal,
+            MsgSvBookmark, MsgSvChat, MsgSvPlayerInput,
+        },
     },
     ui::{
-        pages::menu::main_menu::{MainMenu, MainMenuUIFeedback},
+        pages::{
+            loading::loading_page,
+            menu::main_menu::{MainMenu, MainMenuUIFeedback},
+        },
         types::UIPipe,
         ui::UI,
         ui_manager::UIManager,
@@ -46,9 +64,17 @@ use super::{
         ComponentComponent, ComponentLoadIOPipe, ComponentLoadPipe, ComponentLoadWhileIOPipe,
         ComponentLoadable, ComponentRenderPipe, ComponentUpdatePipe,
     },
-    components::{client_stats::ClientStats, network_logic::NetworkLogic, skins::Skins},
+    components::{
+        client_stats::ClientStats, hud::Hud, map_entities::MapEntities,
+        network_logic::NetworkLogic, players::Players,
+    },
+    console_commands::GameConsoleCommands,
+    crash_report::{
+        crash_report_set_config, discard_crash_report, install_crash_handler, pending_crash_reports,
+    },
     game_events::{GameEventPipeline, GameEventsClient},
     input::{self},
+    prediction::{PredictionSettings, PredictionSmoothing},
     render_pipe::{Camera, ClientInterface, RenderPipeline},
 };
 
@@ -61,25 +87,77 @@ use graphics::{
 
 pub struct ClientData {
     pub cur_server: NetworkConnectionID,
+    // the address the client connected to, e.g. for per server settings
+    pub server_addr: Option<String>,
     pub server_connect_time: Duration,
 
     // the ping between the client and the server
     pub ping: Duration,
+    // the latest quality of the connection to the server, e.g. for a net graph
+    pub net_stats: NetworkStats,
 
     pub player_id_on_server: TGameElementID,
+    // spectators have no player id
+    pub role_on_server: SnapshotClientRole,
+
+    // the vote that currently runs on the server
+    pub vote: Option<(MsgObjVoteProposal, MsgObjVoteProgress)>,
+    // why the last stage (team) action failed, e.g. for the team panel
+    pub stage_error: Option<MsgObjStageError>,
+    // the statistics of the last finished match, e.g. for the scoreboard
+    pub last_match_stats: Option<MatchStatsReport>,
+    // the confirmed state of the snapshot before the server's next checksum (by monotonic tick),
+    // to detect desyncs with the server, see `resimulated_state_checksum`
+    pub state_checksum_base: Option<(u64, GameState)>,
+    // the ghosts of race mods for the current map, by player
+    pub ghosts: Vec<(TGameElementID, GhostPath)>,
+    // the translated system messages, newest last
+    pub system_messages: VecDeque<String>,
+    // the chat messages of the players, newest last
+    pub chat_messages: VecDeque<MsgSvChat>,
+    // the last input of the player that is watched as spectator, for the input overlay
+    // TODO: demos should record these too
+    pub watched_player_input: Option<MsgSvPlayerInput>,
+    // assets the server expects to be needed soon, oldest first
+    pub resource_hints: Vec<MsgObjResourceHint>,
+    // the bookmarks of the mod since the connect, for the demo timeline
+    pub bookmarks: Vec<MsgSvBookmark>,
+    // the infos of the players on the server, e.g. for their skins
+    pub player_infos: HashMap<TGameElementID, MsgObjPlayerInfo>,
+    // the smoothed position of the own character, the camera and the character are rendered there
+    pub prediction_smoothing: PredictionSmoothing,
     pub snapshot_timestamp: Duration,
+    // why the client left the server on its own, shown in the menu
+    pub disconnect_reason: Option<String>,
 }
 
 impl Default for ClientData {
     fn default() -> Self {
         Self {
             cur_server: Default::default(),
+            server_addr: None,
             server_connect_time: Duration::default(),
 
             ping: Duration::default(),
+            net_stats: NetworkStats::default(),
 
             player_id_on_server: INVALID_GAME_ELEMENT_ID,
+            role_on_server: SnapshotClientRole::default(),
+
+            vote: None,
+            stage_error: None,
+            last_match_stats: None,
+            state_checksum_base: None,
+            ghosts: Vec::new(),
+            system_messages: VecDeque::new(),
+            chat_messages: VecDeque::new(),
+            watched_player_input: None,
+            resource_hints: Vec::new(),
+            bookmarks: Vec::new(),
+            player_infos: HashMap::new(),
+            prediction_smoothing: PredictionSmoothing::default(),
             snapshot_timestamp: Duration::ZERO,
+            disconnect_reason: None,
         }
     }
 }
@@ -124,6 +202,9 @@ pub struct Client<'a> {
 impl<'a> ClientInterface for Client<'a> {}
 
 pub fn ddnet_main(mut sys: System, cert: &[u8]) {
+    let fs = Arc::new(FileSystem::new());
+    install_crash_handler(&fs);
+
     let mut local_players = LocalPlayers::new();
 
     let native = Native::new();
@@ -140,10 +221,18 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
     config.dbg_bench = true;
 
     let mut network_logic = NetworkLogic::new();
-    let mut skins = Skins::new();
+    let mut players = Players::new();
     let mut client_stats = ClientStats::new(&sys);
-    let components: Vec<&mut dyn ComponentComponent> =
-        vec![&mut network_logic, &mut skins, &mut client_stats];
+    let mut map_entities = MapEntities::new();
+    let mut hud = Hud::new();
+    // the map entities are rendered below the players
+    let components: Vec<&mut dyn ComponentComponent> = vec![
+        &mut network_logic,
+        &mut map_entities,
+        &mut players,
+        &mut client_stats,
+        &mut hud,
+    ];
     let mut client = Client {
         components: components,
         components_that_update: Vec::new(),
@@ -169,9 +258,28 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
         .build()
         .unwrap();
 
-    let fs = Arc::new(FileSystem::new());
     let mut io_batcher = Arc::new(std::sync::Mutex::new(IOBatcher::new(rt)));
 
+    // TODO: offer the upload, once there is an http client
+    let fs_clone = fs.clone();
+    io_batcher.lock().unwrap().spawn::<(), _>(async move {
+        for (name, content) in pending_crash_reports(&fs_clone).await {
+            // every report is only shown once, the report is not part of the next report
+            log_println_private!("the client crashed before:\n{}", content);
+            discard_crash_report(&fs_clone, &name).await;
+        }
+        Ok(())
+    });
+
+    // the autoexec is searched in the config directory first, like all user files
+    let mut console = Console::new();
+    console.queue("exec autoexec.cfg");
+    // the console output can contain config values
+    for line in console.run_blocking(&mut config, &fs, &io_batcher, &mut NoConsoleCommands) {
+        log_println_private!("{}", line);
+    }
+    crash_report_set_config(&config);
+
     // load all components
 
     benchmark!(
@@ -205,8 +313,9 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
         });
     }
 
-    let mut ui_manager = UIManager::new();
+    let mut ui_manager = UIManager::new(&fs, &io_batcher);
     let mut ui = UI::new(1.5);
+    ui.load_fonts(&fs, &io_batcher);
 
     // prepare network stuff while waiting for io
     let mut game_events = GameEventsClient::new();
@@ -221,6 +330,12 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
         game_event_generator_client.clone(),
         sys.time.clone(),
     );
+    network_client.set_bandwidth_limits(config.net_upload_limit, config.net_download_limit);
+    network_client.set_debug_simulation(NetworkDebugSimulationSettings {
+        latency: Duration::from_millis(config.dbg_net_latency_ms),
+        jitter: Duration::from_millis(config.dbg_net_jitter_ms),
+        loss: config.dbg_net_loss_percent.min(100) as f64 / 100.0,
+    });
 
     // then prepare components allocations etc.
     let mut thread_pool = Arc::new(
@@ -301,13 +416,18 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
     }
 
     //network_client.connect("127.0.0.1:8305");
-    let mut main_menu = MainMenu::new(&mut graphics);
+    let mut main_menu = MainMenu::new(&mut graphics, &fs, &io_batcher);
 
     let mut cur_time = sys.time_get_nanoseconds();
     let mut last_tick_time = cur_time;
     let mut last_inp_time = cur_time;
     let time_until_tick = Duration::from_secs(1).as_nanos() / 50;
+    let mut last_frame_time = cur_time;
+    let mut was_menu_frame = false;
     let time_until_inp = Duration::from_secs(1).as_nanos() / 100;
+    let mut last_view_area: Option<SnapshotViewArea> = None;
+    let mut last_view_area_time = cur_time;
+    let time_until_view_area = Duration::from_secs(1).as_nanos() / 10;
 
     while game_events.update(&mut GameEventPipeline {
         event_generator: &*game_event_generator_client,
@@ -326,6 +446,8 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
             pipe: InputPipe {
                 local_players: &mut local_players.players,
                 ui: &mut ui,
+                config: &config,
+                console: &mut console,
             },
         },
         &mut WindowHandling {
@@ -335,6 +457,18 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
         },
     ) {
         cur_time = sys.time_get_nanoseconds();
+        // e.g. the commands of pressed binds
+        for line in console.run(
+            &mut config,
+            &fs,
+            &io_batcher,
+            &mut GameConsoleCommands {
+                network: &mut network_client,
+                client_data: &mut client.client_data,
+            },
+        ) {
+            log_println_private!("{}", line);
+        }
         // update components that want to be updated
         for comp_update in &client.components_that_update {
             client.components[*comp_update].update(&mut ComponentUpdatePipe {
@@ -350,12 +484,36 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
 
         while (cur_time - last_inp_time).as_nanos() > time_until_inp {
             let net_inp = local_players.players[0].input.to_net_obj();
+            network_client.send_to_server_with_priority(
+                &GameMessage::ClientToServer(ClientToServerMessage::Input(net_inp)),
+                NetworkPacketPriority::Input,
+            );
+            last_inp_time += Duration::from_nanos(time_until_inp as u64);
+        }
+
+        // the server only snaps the entities close to the camera,
+        // it's resent every second in case the server forgot it (e.g. after a reconnect)
+        let view_area = SnapshotViewArea {
+            x: cam.x,
+            y: cam.y,
+            zoom: cam.zoom,
+        };
+        let time_since_view_area = (cur_time - last_view_area_time).as_nanos();
+        if time_since_view_area > time_until_view_area
+            && (last_view_area != Some(view_area)
+                || time_since_view_area > Duration::from_secs(1).as_nanos())
+        {
             network_client.send_to_server(&GameMessage::ClientToServer(
-                ClientToServerMessage::Input(net_inp),
+                ClientToServerMessage::ViewArea(view_area),
             ));
-            last_inp_time += Duration::from_nanos(time_until_inp as u64);
+            last_view_area = Some(view_area);
+            last_view_area_time = cur_time;
         }
 
+        let prediction =
+            PredictionSettings::new(&config, client.client_data.server_addr.as_deref());
+
+        let mut predicted_ticks = 0;
         let has_map = client
             .map
             .get(
@@ -387,8 +545,15 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
                 last_tick_time += Duration::from_nanos(time_until_tick as u64);
             }
 
-            // after the tick there is always a prediction tick
-            client.game.pred_tick(&mut sim_pipe);
+            // the own character is predicted as far ahead as the inputs need to reach the server
+            // while the server paused the simulation, there is nothing to predict
+            if !client.game.is_paused() {
+                predicted_ticks = prediction.ticks_to_predict(
+                    client.client_data.ping,
+                    Duration::from_nanos(time_until_tick as u64),
+                );
+            }
+            client.game.pred_tick(predicted_ticks, &mut sim_pipe);
 
             // check if the server tick can be increased
             while (cur_time - client.client_data.snapshot_timestamp).as_nanos() > time_until_tick {
@@ -413,6 +578,25 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
         }
 
         // rendering
+        if let Some(reason) = client.client_data.disconnect_reason.take() {
+            main_menu.set_disconnect_reason(reason);
+            ui.ui_state.is_ui_open = true;
+            ui.request_repaint();
+        }
+        // the loading screen is also shown while the menu is closed (e.g. after connecting)
+        let map_loading_progress = client.map.loading_progress().filter(|_| !has_map);
+        let is_ui_shown = ui.ui_state.is_ui_open || map_loading_progress.is_some();
+        // in the menu the frame only consists of the ui,
+        // so the last frame stays on screen while the ui does not change
+        let is_menu_frame = is_ui_shown && !has_map;
+        if main_menu.update(cur_time) || !was_menu_frame || map_loading_progress.is_some() {
+            ui.request_repaint();
+        }
+        was_menu_frame = is_menu_frame;
+        let ui_changed = is_ui_shown
+            && ui.begin_frame(graphics.canvas_width(), graphics.canvas_height(), cur_time);
+        let skip_frame = is_menu_frame && !ui_changed;
+
         if has_map {
             let map = client.map.unwrap();
 
@@ -426,15 +610,26 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
                     .find(|char| char.cores[0].player_id == player_id);
                 it.is_some()
             });
-            if let Some(stage) = stage {
-                let char = stage
+            let char = stage.and_then(|stage| {
+                stage
                     .get_world()
                     .get_characters()
                     .iter()
-                    .find(|char| char.cores[0].player_id == player_id);
-                if let Some(char) = char {
-                    cam.x = char.cores[1].core.pos.x;
-                    cam.y = char.cores[1].core.pos.y;
+                    .find(|char| char.cores[0].player_id == player_id)
+            });
+            match char {
+                Some(char) => {
+                    // the second core is the predicted one
+                    let core_index = if predicted_ticks > 0 { 1 } else { 0 };
+                    let ticks_passed =
+                        (cur_time - last_frame_time).as_nanos() as f32 / time_until_tick as f32;
+                    let pos = client.client_data.prediction_smoothing.update(
+                        &prediction,
+                        char.cores[core_index].core.pos,
+                        ticks_passed,
+                    );
+                    cam.x = pos.x;
+                    cam.y = pos.y;
 
                     /* sys.log("client")
                     .msg(
@@ -447,6 +642,26 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
                     )
                     .msg(format!("x: {}, y: {}", cam.x, cam.y).as_str());*/
                 }
+                // e.g. spectating
+                None => {
+                    client.client_data.prediction_smoothing.reset();
+                    if let SnapshotClientRole::Spectator(SnapshotSpectatorCamera::Chase(
+                        chased_id,
+                    )) = client.client_data.role_on_server
+                    {
+                        let chased = client.game.get_stages().iter().find_map(|stage| {
+                            stage
+                                .get_world()
+                                .get_characters()
+                                .iter()
+                                .find(|char| char.cores[0].player_id == chased_id)
+                        });
+                        if let Some(chased) = chased {
+                            cam.x = chased.cores[0].core.pos.x;
+                            cam.y = chased.cores[0].core.pos.y;
+                        }
+                    }
+                }
             }
 
             let mut render_pipe = RenderPipeline::new(
@@ -462,37 +677,77 @@ pub fn ddnet_main(mut sys: System, cert: &[u8]) {
             );
             map.render.render(&mut render_pipe);
         }
+        last_frame_time = cur_time;
+
         // render components that want to be rendered
-        for comp_update in &client.components_that_render {
-            client.components[*comp_update].render(&mut ComponentRenderPipe {
-                graphics: &mut graphics,
-                sys: &sys,
-                runtime_thread_pool: &thread_pool,
-                config: &mut config,
-                client_data: &client.client_data,
-                game: &client.game,
-            });
+        if !skip_frame {
+            for comp_update in &client.components_that_render {
+                client.components[*comp_update].render(&mut ComponentRenderPipe {
+                    graphics: &mut graphics,
+                    sys: &sys,
+                    runtime_thread_pool: &thread_pool,
+                    config: &mut config,
+                    client_data: &client.client_data,
+                    game: &client.game,
+                    camera: &cam,
+                });
+            }
         }
 
         // render ui last
-        if ui.ui_state.is_ui_open {
+        if is_ui_shown && !skip_frame {
             ui.render(
-                |egui_ui, pipe, ui_state| main_menu.render_func(egui_ui, pipe, ui_state),
+                |egui_ui, pipe, ui_state| match map_loading_progress {
+                    Some(progress) => loading_page(egui_ui, progress),
+                    None => main_menu.render_func(egui_ui, pipe, ui_state),
+                },
                 &mut UIPipe {
                     graphics: &mut graphics,
                     sys: &sys,
-                    ui_feedback: &mut MainMenuUIFeedback::new(&mut network_client),
+                    ui_feedback: &mut MainMenuUIFeedback::new(
+                        &mut network_client,
+                        &mut client.client_data.server_addr,
+                    ),
                     runtime_thread_pool: &thread_pool,
                     config: &mut config,
                 },
             );
         }
 
-        ui_manager.run(&mut graphics);
+        if !skip_frame {
+            ui_manager.run(&mut graphics, client.game.is_paused());
+        }
+        // e.g. the editor's test play, the server decides if it allows it
+        for request in ui_manager.take_state_requests() {
+            let simulation = match request {
+                WasmStateRequest::Pause => MsgClSimulation::Pause,
+                WasmStateRequest::Resume => MsgClSimulation::Resume,
+                WasmStateRequest::Step(ticks) => MsgClSimulation::Step(ticks),
+            };
+            network_client.send_to_server(&GameMessage::ClientToServer(
+                ClientToServerMessage::Simulation(simulation),
+            ));
+        }
 
-        graphics.swap();
+        if !skip_frame {
+            graphics.swap();
+        }
 
         // time related stuff
         //std::thread::sleep(Duration::from_millis(1000 / 50));
+        // don't poll an unchanged menu as fast as possible
+        if skip_frame && config.cl_menu_idle_fps > 0 {
+            let frame_time = Duration::from_secs(1) / config.cl_menu_idle_fps;
+            let elapsed = sys.time_get_nanoseconds().saturating_sub(cur_time);
+            if elapsed < frame_time {
+                std::thread::sleep(frame_time - elapsed);
+            }
+        }
     }
+
+    // the components and modules start their last writes when they are dropped
+    drop(main_menu);
+    drop(ui_manager);
+    drop(client);
+    io_batcher.lock().unwrap().finish_all();
 }
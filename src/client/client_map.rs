@@ -18,10 +18,12 @@ use crate::{
     client_map_buffered::{ClientMapBufferUploadData, ClientMapBuffered},
     datafile::{
         CDatafileWrapper, MapFileImageReadOptions, MapFileLayersReadOptions, MapFileOpenOptions,
-        ReadFile,
+        MapLoadProgress, ReadFile,
     },
     game::collision::Collision,
     join_all,
+    map_chunks::legacy_map_data,
+    map_untrusted::MapReadLimits,
     mapdef::{MapImage, MapLayer},
     worker::{Worker, WorkerTask},
 };
@@ -41,10 +43,14 @@ pub struct ClientMapFileProcessed {
     pub buffered_map: ClientMapBuffered,
     pub upload_data: ClientMapBufferUploadData,
     pub collision: Collision,
+    // the layers of the far groups, they are read after the map is usable
+    pub unread_layers: Vec<(usize, MapLayer)>,
+    pub file: Vec<u8>,
 }
 
 pub struct ClientMapLoadingFile {
     pub task: WorkerTask<ClientMapFileProcessed>,
+    pub progress: Arc<MapLoadProgress>,
 }
 
 pub struct ClientMapImage {
@@ -57,6 +63,8 @@ pub struct ClientMapFile {
     pub render: RenderMap,
     pub collision: Collision,
     pub buffered_map: ClientMapBuffered,
+    // reads the layers of the far groups, until then they are empty
+    pub unread_layers: Option<WorkerTask<Vec<(usize, MapLayer)>>>,
 }
 
 impl ClientMapFile {
@@ -78,12 +86,19 @@ impl ClientMapFile {
         let sys_time = sys.clone();
         let runtime_tp = thread_pool.clone();
         let graphics_mt = graphics.get_graphics_mt();
+        let progress = Arc::new(MapLoadProgress::default());
+        let load_progress = progress.clone();
         ClientMapLoadingFile {
+            progress,
             task: worker.spawn(move || {
                 // Load the map file
                 let fs_clone = file_system.clone();
                 let mut file_read_task = io_batcher.lock().unwrap().spawn(async move {
-                    let mapfile = fs_clone.open_file(map_file_name.as_str()).await;
+                    let mapfile = fs_clone
+                        .open_file(map_file_name.as_str())
+                        .await
+                        .map_err(anyhow::Error::from)
+                        .and_then(legacy_map_data);
                     if let Ok(map_) = mapfile {
                         return Ok(map_);
                     }
@@ -99,11 +114,11 @@ impl ClientMapFile {
 
                 let file = file_read_task.get_storage().unwrap();
 
-                // open the map file
+                // open the map file, the server decides which map is loaded,
+                // so it is treated like a downloaded map
                 let mut file_wrap = CDatafileWrapper::new();
-                let mut data_start: &[u8] = &[];
-                benchmark!(do_benchmark, &sys_time, "opening the full map file", || {
-                    let res = file_wrap.Open(
+                let res = benchmark!(do_benchmark, &sys_time, "opening the full map file", || {
+                    file_wrap.read_untrusted(
                         &file,
                         map_file.as_str(),
                         runtime_tp.as_ref(),
@@ -111,13 +126,18 @@ impl ClientMapFile {
                             do_benchmark,
                             ..Default::default()
                         },
+                        &MapReadLimits::default(),
                         &sys_time,
-                    );
-
-                    if let Ok(data_start_res) = res {
-                        data_start = data_start_res;
-                    }
+                    )
                 });
+                let data_start = match res {
+                    Ok(data_start) => data_start,
+                    Err(map_err) => {
+                        let mut err = ArrayString::new();
+                        let _ = err.try_push_str(map_err.to_string().as_str());
+                        return Err(err);
+                    }
+                };
 
                 // read content files
                 let mut read_files: HashMap<String, ReadFile> = HashMap::new();
@@ -140,6 +160,7 @@ impl ClientMapFile {
                 });
 
                 let mut collision = Collision::default();
+                let mut unread_layers: Vec<(usize, MapLayer)> = Vec::new();
                 let render_map = RenderMap::new();
 
                 // load images, external images and do map buffering
@@ -345,16 +366,23 @@ impl ClientMapFile {
                             );
                         },
                         || {
-                            CDatafileWrapper::read_map_layers(
+                            // physics and close groups first, the far groups are
+                            // read after the map is usable (see `ClientMap::get`)
+                            let mut stages = file_wrap.layer_load_stages();
+                            let far_stage = stages.pop().unwrap_or_default();
+                            CDatafileWrapper::read_map_layers_staged(
                                 &file_wrap.data_file,
                                 &mut file_wrap.layers,
+                                &stages,
                                 data_start,
                                 &sys_time,
                                 &MapFileLayersReadOptions {
                                     do_benchmark: do_benchmark,
+                                    progress: Some(load_progress.clone()),
                                     ..Default::default()
                                 },
                             );
+                            unread_layers = file_wrap.take_unread_layers(&far_stage);
 
                             // meanwhile prepare map layers
                             benchmark!(
@@ -363,6 +391,7 @@ impl ClientMapFile {
                                 "initialzing the map layers",
                                 || {
                                     file_wrap.InitLayers(&runtime_tp);
+                                    file_wrap.build_spatial_indices(&runtime_tp);
                                 },
                             );
 
@@ -474,6 +503,8 @@ impl ClientMapFile {
                     collision: collision,
                     buffered_map: map_buffered,
                     upload_data: upload_data,
+                    unread_layers,
+                    file,
                 })
             }),
         }
@@ -483,6 +514,8 @@ impl ClientMapFile {
 pub enum ClientMap {
     Map(ClientMapFile),
     UploadingImagesAndMapBuffer(ClientMapLoadingFile),
+    // the map could not be loaded, e.g. because it is broken
+    Err(String),
     None,
 }
 
@@ -501,10 +534,36 @@ impl ClientMap {
         false
     }
 
+    /**
+     * Frees the textures and buffers of a loaded map,
+     * must be called before the map is replaced (e.g. on a map change of the server)
+     */
+    pub fn unload(&mut self, graphics: &mut Graphics) {
+        if let Self::Map(map) = self {
+            map.images
+                .iter_mut()
+                .for_each(|img| graphics.unload_texture(&mut img.texture_index));
+            map.buffered_map.map_destroy(graphics);
+        }
+        *self = Self::None;
+    }
+
+    /**
+     * The progress of reading the map layers (0.0 - 1.0),
+     * `None` if no map is loading
+     */
+    pub fn loading_progress(&self) -> Option<f32> {
+        match self {
+            Self::UploadingImagesAndMapBuffer(map_upload) => Some(map_upload.progress.progress()),
+            Self::Map(_) => Some(1.0),
+            Self::Err(_) | Self::None => None,
+        }
+    }
+
     pub fn get(
         &mut self,
         thread_pool: &Arc<rayon::ThreadPool>,
-        _worker: &mut Worker,
+        worker: &mut Worker,
         _io_batcher: &Arc<std::sync::Mutex<IOBatcher>>,
         _fs: &Arc<FileSystem>,
         graphics: &mut Graphics,
@@ -515,7 +574,13 @@ impl ClientMap {
         match self {
             Self::UploadingImagesAndMapBuffer(map_upload) => {
                 if map_upload.task.is_finished() {
-                    let mut map_file = map_upload.task.get_storage().unwrap();
+                    let mut map_file = match map_upload.task.get_storage() {
+                        Ok(map_file) => map_file,
+                        Err(err) => {
+                            *self = Self::Err(err.to_string());
+                            return None;
+                        }
+                    };
                     let runtime_tp = thread_pool.clone();
                     let mut images: Vec<ClientMapImage> = Default::default();
 
@@ -576,12 +641,38 @@ impl ClientMap {
                         },
                     );
 
+                    let unread_layers = if map_file.unread_layers.is_empty() {
+                        None
+                    } else {
+                        let data_file = map_file.raw.data_file.clone();
+                        let data_offset = map_file.raw.data_offset();
+                        let mut layers = map_file.unread_layers;
+                        let file = map_file.file;
+                        Some(worker.spawn(move || {
+                            let data_start = file.get(data_offset..).ok_or_else(|| {
+                                ArrayString::from("the map file has no data section").unwrap()
+                            })?;
+                            runtime_tp.install(|| {
+                                CDatafileWrapper::read_layers(
+                                    &data_file,
+                                    &mut layers,
+                                    data_start,
+                                    &Default::default(),
+                                )
+                            });
+                            Ok(layers)
+                        }))
+                    };
+                    // the task has its own copy if it needs them
+                    map_file.raw.release_uncompressed_datas();
+
                     *self = Self::Map(ClientMapFile {
                         raw: map_file.raw,
                         images: images,
                         render: map_file.render,
                         collision: map_file.collision,
                         buffered_map: map_file.buffered_map,
+                        unread_layers,
                     });
 
                     return Some(self.unwrap());
@@ -589,8 +680,39 @@ impl ClientMap {
                     None
                 }
             }
-            Self::Map(map) => Some(map),
-            Self::None => None,
+            Self::Map(map) => {
+                if map
+                    .unread_layers
+                    .as_ref()
+                    .is_some_and(|task| task.is_finished())
+                {
+                    let mut task = map.unread_layers.take().unwrap();
+                    // without the far groups the map is still usable
+                    if let Ok(mut layers) = task.get_storage() {
+                        benchmark!(
+                            do_benchmark,
+                            &sys,
+                            "creating the buffers of the far map layers",
+                            || {
+                                let layer_indices: Vec<usize> =
+                                    layers.iter().map(|(index, _)| *index).collect();
+                                layers.drain(..).for_each(|(index, layer)| {
+                                    map.raw.layers[index] = layer;
+                                    map.raw.update_spatial_index(index);
+                                });
+                                let upload_data = map.buffered_map.prepare_upload_layers(
+                                    &graphics.get_graphics_mt(),
+                                    &map.raw,
+                                    &layer_indices,
+                                );
+                                map.buffered_map.upload_map(graphics, upload_data);
+                            },
+                        );
+                    }
+                }
+                Some(map)
+            }
+            Self::Err(_) | Self::None => None,
         }
     }
 }
@@ -703,7 +703,7 @@ impl ClientMapBuffered {
         Self::default()
     }
 
-    fn map_destroy(&mut self, graphics: &mut Graphics) {
+    pub fn map_destroy(&mut self, graphics: &mut Graphics) {
         //clear everything and destroy all buffers
         if !self.tile_layer_visuals.is_empty() {
             let s = self.tile_layer_visuals.len();
@@ -732,8 +732,39 @@ impl ClientMapBuffered {
         graphics_mt: &GraphicsMultiThreaded,
         map: &CDatafileWrapper,
         background_only: bool,
+    ) -> ClientMapBufferUploadData {
+        self.prepare_upload_impl(graphics_mt, map, background_only, None)
+    }
+
+    /**
+     * Prepares the visuals of the given layers again, e.g. after they were read
+     * later than the rest of the map. All other visuals stay untouched.
+     * The visuals must already exist (see `prepare_upload`) and the layers must not
+     * have a buffer yet (e.g. because they were empty, see `CDatafileWrapper::take_unread_layers`).
+     */
+    pub fn prepare_upload_layers(
+        &mut self,
+        graphics_mt: &GraphicsMultiThreaded,
+        map: &CDatafileWrapper,
+        layer_indices: &[usize],
+    ) -> ClientMapBufferUploadData {
+        self.prepare_upload_impl(graphics_mt, map, false, Some(layer_indices))
+    }
+
+    fn prepare_upload_impl(
+        &mut self,
+        graphics_mt: &GraphicsMultiThreaded,
+        map: &CDatafileWrapper,
+        background_only: bool,
+        only_layers: Option<&[usize]>,
     ) -> ClientMapBufferUploadData {
         let mut upload_data = ClientMapBufferUploadData::default();
+        let prepare_layer = |layer_index: usize| {
+            only_layers.map_or(true, |layer_indices| layer_indices.contains(&layer_index))
+        };
+        // only new visuals are added, the indices of the visuals never change
+        let mut tile_visual_count = 0;
+        let mut quad_visual_count = 0;
 
         let mut passed_game_layer = false;
 
@@ -795,18 +826,22 @@ impl ClientMapBuffered {
                     let mut cur_overlay = 0;
                     while cur_overlay < overlay_count + 1 {
                         // We can later just count the tile layers to get the idx in the vector
-                        let tile_layer_visuals = &mut self.tile_layer_visuals;
-                        let visual_index = tile_layer_visuals.len();
-                        tile_layer_visuals.push(STileLayerVisuals::default());
+                        let visual_index = tile_visual_count;
+                        tile_visual_count += 1;
+                        if only_layers.is_none() {
+                            self.tile_layer_visuals.push(STileLayerVisuals::default());
+                        }
 
                         tile_layers.push((visual_index, g, layer_index, cur_overlay));
 
                         cur_overlay += 1;
                     }
                 } else if let MapLayer::Quads(_q_layer) = layer {
-                    let quad_layer_visuals = &mut self.quad_layer_visuals;
-                    let visual_index = quad_layer_visuals.len();
-                    quad_layer_visuals.push(SQuadLayerVisuals::default());
+                    let visual_index = quad_visual_count;
+                    quad_visual_count += 1;
+                    if only_layers.is_none() {
+                        self.quad_layer_visuals.push(SQuadLayerVisuals::default());
+                    }
 
                     quad_layers.push((visual_index, g, layer_index));
                 }
@@ -823,6 +858,10 @@ impl ClientMapBuffered {
             .enumerate()
             .for_each(|(index, (visuals, upload_data))| {
                 let (visual_index, group_index, layer_index, cur_overlay) = tile_layers[index];
+                if !prepare_layer(layer_index) {
+                    return;
+                }
+                *visuals = STileLayerVisuals::default();
 
                 let group = map.get_group(group_index);
                 let layer = map.get_layer(layer_index);
@@ -1326,6 +1365,9 @@ impl ClientMapBuffered {
             .enumerate()
             .for_each(|(index, (_q_layer_visuals, upload_data))| {
                 let (visual_index, group_index, layer_index) = quad_layers[index];
+                if !prepare_layer(layer_index) {
+                    return;
+                }
                 let _group = map.get_group(group_index);
                 let layer = map.get_layer(layer_index);
 
@@ -18,7 +18,7 @@ use crate::{
     worker::Worker,
 };
 
-use super::client::ClientData;
+use super::{client::ClientData, render_pipe::Camera};
 
 pub struct ComponentLoadPipe<'a> {
     pub graphics: &'a mut Graphics,
@@ -87,6 +87,7 @@ pub struct ComponentRenderPipe<'a> {
     pub config: &'a mut Config,
     pub game: &'a GameState,
     pub client_data: &'a ClientData,
+    pub camera: &'a Camera,
 }
 
 pub trait ComponentRenderable {
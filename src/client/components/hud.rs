@@ -0,0 +1,258 @@
+use arrayvec::ArrayString;
+use egui::{Align2, Color32};
+
+use crate::{
+    client::{
+        client::ClientData,
+        component::{
+            ComponentComponent, ComponentGameMsg, ComponentLoadIOPipe, ComponentLoadPipe,
+            ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderPipe, ComponentRenderable,
+            ComponentUpdatable, ComponentUpdatePipe,
+        },
+    },
+    game::state::GameState,
+    network::messages::{MsgObjStageError, MsgObjVoteKind},
+    ui::{
+        types::{UIFeedbackInterface, UIPipe, UIState},
+        ui::UI,
+    },
+};
+
+pub struct HudUIFeedbackDummy {}
+
+impl UIFeedbackInterface for HudUIFeedbackDummy {}
+
+/**
+ * The name of a player for the hud, falls back to the id for unknown players
+ */
+fn player_name(client_data: &ClientData, player_id: &crate::game::TGameElementID) -> String {
+    client_data
+        .player_infos
+        .get(player_id)
+        .map(|info| info.name.as_str().to_string())
+        .unwrap_or_else(|| format!("#{}", player_id.0))
+}
+
+fn render_vote(ui: &mut egui::Ui, client_data: &ClientData) {
+    let Some((proposal, progress)) = &client_data.vote else {
+        return;
+    };
+    egui::Window::new("vote")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(Align2::LEFT_CENTER, [5.0, 0.0])
+        .show(ui.ctx(), |ui| {
+            let title = match &proposal.kind {
+                MsgObjVoteKind::Kick(player_id) => {
+                    format!("Kick {}", player_name(client_data, player_id))
+                }
+                MsgObjVoteKind::ChangeMap(map) => format!("Change map to {}", map.as_str()),
+                MsgObjVoteKind::Custom(name) => name.as_str().to_string(),
+            };
+            ui.strong(title);
+            ui.label(format!(
+                "called by {}",
+                player_name(client_data, &proposal.caller_player_id)
+            ));
+            if !proposal.reason.as_str().is_empty() {
+                ui.label(format!("Reason: {}", proposal.reason.as_str()));
+            }
+            ui.horizontal(|ui| {
+                ui.colored_label(Color32::GREEN, format!("Yes: {}", progress.yes));
+                ui.colored_label(Color32::RED, format!("No: {}", progress.no));
+                ui.label(format!("of {}", progress.participants));
+            });
+            ui.label(format!(
+                "{}s left",
+                (progress.remaining_time_millis + 999) / 1000
+            ));
+            ui.weak("vote yes / vote no");
+        });
+}
+
+fn render_chat(ui: &mut egui::Ui, client_data: &ClientData) {
+    egui::Window::new("chat")
+        .title_bar(false)
+        .resizable(false)
+        .frame(egui::Frame::none())
+        .anchor(Align2::LEFT_BOTTOM, [5.0, -5.0])
+        .show(ui.ctx(), |ui| {
+            for text in client_data.system_messages.iter().rev().take(3).rev() {
+                ui.colored_label(Color32::YELLOW, format!("*** {}", text));
+            }
+            for chat in client_data.chat_messages.iter().rev().take(8).rev() {
+                let text = format!(
+                    "{}: {}",
+                    player_name(client_data, &chat.player_id),
+                    chat.msg.as_str()
+                );
+                if chat.team {
+                    ui.colored_label(Color32::LIGHT_GREEN, text);
+                } else {
+                    ui.colored_label(Color32::WHITE, text);
+                }
+            }
+        });
+}
+
+fn stage_error_text(err: &MsgObjStageError) -> &'static str {
+    match err {
+        MsgObjStageError::UnknownPlayer => "Spectators can't join a team",
+        MsgObjStageError::UnknownStage => "This team doesn't exist",
+        MsgObjStageError::AlreadyInStage => "You are already in this team",
+        MsgObjStageError::Locked => "This team is locked",
+        MsgObjStageError::Full => "This team is full",
+        MsgObjStageError::LockingNotAllowed => "This team can't be locked",
+    }
+}
+
+/**
+ * Lists the teams of the snapshot with their players,
+ * the first stage is the one of all players without a team
+ */
+fn render_teams(ui: &mut egui::Ui, client_data: &ClientData, game: &GameState) {
+    egui::Window::new("teams")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(Align2::RIGHT_TOP, [-5.0, 5.0])
+        .show(ui.ctx(), |ui| {
+            for stage in game.get_stages().iter().skip(1) {
+                let players: Vec<String> = stage
+                    .get_world()
+                    .get_characters()
+                    .iter()
+                    .map(|character| player_name(client_data, &character.cores[0].player_id))
+                    .collect();
+                if players.is_empty() {
+                    continue;
+                }
+                let lock = if stage.locked { " (locked)" } else { "" };
+                ui.strong(format!("Team {}{}", stage.game_element_id.0, lock));
+                ui.label(players.join(", "));
+            }
+            if let Some(err) = &client_data.stage_error {
+                ui.colored_label(Color32::RED, stage_error_text(err));
+            }
+            ui.weak("team_create / team_join <id> / team_lock 0|1");
+        });
+}
+
+/**
+ * The raw input of the player that is watched with `watch_input`
+ */
+fn render_input_overlay(ui: &mut egui::Ui, client_data: &ClientData) {
+    let Some(watched) = &client_data.watched_player_input else {
+        return;
+    };
+    let input = &watched.input;
+    egui::Window::new("input overlay")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_BOTTOM, [0.0, -5.0])
+        .show(ui.ctx(), |ui| {
+            ui.label(player_name(client_data, &watched.player_id));
+            ui.horizontal(|ui| {
+                let key = |ui: &mut egui::Ui, text: &str, pressed: bool| {
+                    let color = if pressed {
+                        Color32::WHITE
+                    } else {
+                        Color32::DARK_GRAY
+                    };
+                    ui.colored_label(color, text);
+                };
+                key(ui, "<", input.dir < 0);
+                key(ui, ">", input.dir > 0);
+                key(ui, "jump", input.jump);
+                key(ui, "hook", input.hook);
+                // like the ddnet input, the fire counter is odd while fire is held
+                key(ui, "fire", input.fire & 1 != 0);
+            });
+        });
+}
+
+/**
+ * The in-game overlay with the information the server sends to the player,
+ * e.g. the currently running vote, the chat, the teams and the watched input
+ */
+pub struct Hud {
+    ui: UI,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        let mut ui = UI::new(2.5);
+        ui.set_main_panel_color(&Color32::TRANSPARENT);
+        Self { ui: ui }
+    }
+
+    fn render_hud(
+        ui: &mut egui::Ui,
+        _pipe: &mut UIPipe,
+        _ui_state: &mut UIState,
+        client_data: &ClientData,
+        game: &GameState,
+    ) {
+        render_vote(ui, client_data);
+        render_chat(ui, client_data);
+        render_teams(ui, client_data, game);
+        render_input_overlay(ui, client_data);
+    }
+}
+
+impl ComponentLoadable for Hud {
+    fn load_io(&mut self, _io_pipe: &mut ComponentLoadIOPipe) {}
+
+    fn init_while_io(&mut self, _pipe: &mut ComponentLoadWhileIOPipe) {}
+
+    fn init(&mut self, _pipe: &mut ComponentLoadPipe) -> Result<(), ArrayString<4096>> {
+        Ok(())
+    }
+}
+
+impl ComponentUpdatable for Hud {
+    fn update(&mut self, _pipe: &mut ComponentUpdatePipe) {}
+}
+
+impl ComponentRenderable for Hud {
+    fn render(&mut self, pipe: &mut ComponentRenderPipe) {
+        let client_data = pipe.client_data;
+        let game = pipe.game;
+        self.ui.render(
+            |ui, ui_pipe, ui_state| Self::render_hud(ui, ui_pipe, ui_state, client_data, game),
+            &mut UIPipe {
+                ui_feedback: &mut HudUIFeedbackDummy {},
+                graphics: pipe.graphics,
+                sys: pipe.sys,
+                runtime_thread_pool: pipe.runtime_thread_pool,
+                config: pipe.config,
+            },
+        )
+    }
+}
+
+impl ComponentGameMsg for Hud {
+    // the messages are collected in the client data by the network logic
+    fn on_msg(
+        &mut self,
+        _timestamp: &std::time::Duration,
+        _msg: &crate::network::messages::ServerToClientMessage,
+        _pipe: &mut crate::client::component::GameMsgPipeline,
+    ) {
+    }
+
+    fn on_connect(&mut self, _timestamp: &std::time::Duration) {}
+
+    fn on_disconnect(&mut self, _timestamp: &std::time::Duration) {}
+}
+
+impl ComponentComponent for Hud {
+    fn does_update(&self) -> bool {
+        false
+    }
+    fn does_render(&self) -> bool {
+        true
+    }
+    fn handles_msgs(&self) -> bool {
+        false
+    }
+}
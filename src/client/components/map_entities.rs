@@ -0,0 +1,115 @@
+use arrayvec::ArrayString;
+use graphics_base::streaming::DrawScopeImpl;
+use graphics_types::{rendering::State, types::CQuadItem};
+
+use crate::{
+    client::{
+        component::{
+            ComponentComponent, ComponentGameMsg, ComponentLoadIOPipe, ComponentLoadPipe,
+            ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderPipe, ComponentRenderable,
+            ComponentUpdatable, ComponentUpdatePipe,
+        },
+        render_tools::RenderTools,
+    },
+    game::entities::map_entity::{MapEntityCore, MapEntityKind},
+};
+
+/**
+ * The color of the entity, opened doors are only hinted
+ */
+fn entity_color(core: &MapEntityCore) -> (f32, f32, f32, f32) {
+    match core.kind {
+        MapEntityKind::Door { closed: true, .. } => (0.6, 0.4, 0.2, 1.0),
+        MapEntityKind::Door { closed: false, .. } => (0.6, 0.4, 0.2, 0.25),
+        MapEntityKind::MovingPlatform { .. } => (0.5, 0.5, 0.55, 1.0),
+        MapEntityKind::Pickup { .. } => (1.0, 0.85, 0.2, 1.0),
+    }
+}
+
+/**
+ * Renders the entities that mods spawn at runtime (doors, moving platforms, pickups)
+ * as colored boxes in the world
+ */
+pub struct MapEntities {}
+
+impl MapEntities {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl ComponentLoadable for MapEntities {
+    fn load_io(&mut self, _io_pipe: &mut ComponentLoadIOPipe) {}
+
+    fn init_while_io(&mut self, _pipe: &mut ComponentLoadWhileIOPipe) {}
+
+    fn init(&mut self, _pipe: &mut ComponentLoadPipe) -> Result<(), ArrayString<4096>> {
+        Ok(())
+    }
+}
+
+impl ComponentUpdatable for MapEntities {
+    fn update(&mut self, _pipe: &mut ComponentUpdatePipe) {}
+}
+
+impl ComponentRenderable for MapEntities {
+    fn render(&mut self, pipe: &mut ComponentRenderPipe) {
+        let mut state = State::new();
+        let mut points: [f32; 4] = [0.0; 4];
+        RenderTools::map_canvas_to_world(
+            pipe.camera.x,
+            pipe.camera.y,
+            100.0,
+            100.0,
+            100.0,
+            0.0,
+            0.0,
+            pipe.graphics.canvas_aspect(),
+            pipe.camera.zoom,
+            &mut points,
+        );
+        state.map_canvas(points[0], points[1], points[2], points[3]);
+
+        for stage in pipe.game.get_stages() {
+            for ent in stage.get_world().get_map_entities() {
+                let bounds = ent.core.bounds();
+                let (r, g, b, a) = entity_color(&ent.core);
+                let mut draw_quads = pipe.graphics.backend_handle.quads_begin();
+                draw_quads.get_draw_scope().set_state(&state);
+                draw_quads.set_colors_from_single(r, g, b, a);
+                draw_quads.quads_draw_tl(&[CQuadItem::new(
+                    bounds.x - bounds.width / 2.0,
+                    bounds.y - bounds.height / 2.0,
+                    bounds.width,
+                    bounds.height,
+                )]);
+            }
+        }
+    }
+}
+
+impl ComponentGameMsg for MapEntities {
+    fn on_msg(
+        &mut self,
+        _timestamp: &std::time::Duration,
+        _msg: &crate::network::messages::ServerToClientMessage,
+        _pipe: &mut crate::client::component::GameMsgPipeline,
+    ) {
+    }
+
+    fn on_connect(&mut self, _timestamp: &std::time::Duration) {}
+
+    fn on_disconnect(&mut self, _timestamp: &std::time::Duration) {}
+}
+
+impl ComponentComponent for MapEntities {
+    fn does_update(&self) -> bool {
+        false
+    }
+    fn does_render(&self) -> bool {
+        true
+    }
+    fn handles_msgs(&self) -> bool {
+        false
+    }
+}
@@ -1,4 +1,6 @@
 pub mod client_stats;
+pub mod hud;
+pub mod map_entities;
 pub mod network_logic;
 pub mod players;
 pub mod skins;
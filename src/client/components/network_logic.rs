@@ -1,19 +1,39 @@
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use arrayvec::ArrayString;
 
 use math::math::vector::vec4_base;
 
+use base::{
+    filesys::FileSystem,
+    io_batcher::{IOBatcher, IOBatcherTask},
+    log_println, log_println_private,
+};
+
 use crate::{
     client::component::{
         ComponentComponent, ComponentGameMsg, ComponentLoadIOPipe, ComponentLoadPipe,
         ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderable, ComponentUpdatable,
         ComponentUpdatePipe, GameMsgPipeline,
     },
+    client::localization::Localization,
     client_map::{ClientMap, ClientMapFile},
+    game::{
+        ghost::GhostPath,
+        simulation_pipe::LocalPlayerInput,
+        snapshot::Snapshot,
+        snapshot_delta::SnapshotDeltaReceiver,
+        state::GameState,
+        state_checksum::{resimulated_state_checksum, STATE_CHECKSUM_INTERVAL_TICKS},
+        INVALID_GAME_ELEMENT_ID,
+    },
+    map_hash::MapSectionHash,
     network::messages::{
         ClientToServerMessage, ColorChannel, GameMessage, MsgClReady, MsgObjGameSkinPartInfo,
-        MsgObjGameWeaponInfo, MsgObjPlayerInfo, NetworkStr, ServerToClientMessage,
+        MsgObjGameWeaponInfo, MsgObjPlayerInfo, MsgSvStageEvent, NetworkStr, ServerToClientMessage,
     },
 };
 
@@ -34,26 +54,101 @@ pub enum ClientConnectionState {
  */
 pub struct NetworkLogic {
     cur_map: String,
+    cur_map_physics_hash: MapSectionHash,
     cur_client_connection_state: ClientConnectionState,
+
+    localization: Localization,
+    localization_task: Option<IOBatcherTask<Vec<u8>>>,
+
+    snapshot_deltas: SnapshotDeltaReceiver,
+
+    // the fastest ghost per player since the last map change, see `save_ghosts`
+    unsaved_ghosts: Vec<GhostPath>,
+    // the saved ghosts of the current map, loaded in the background
+    ghosts_task: Option<IOBatcherTask<Vec<GhostPath>>>,
+    // to save the ghosts, also on a disconnect and when the client closes
+    fs: Option<Arc<FileSystem>>,
+    io_batcher: Option<Arc<Mutex<IOBatcher>>>,
 }
 
 impl ComponentLoadable for NetworkLogic {
-    fn load_io(&mut self, _io_pipe: &mut ComponentLoadIOPipe) {}
+    fn load_io(&mut self, io_pipe: &mut ComponentLoadIOPipe) {
+        self.fs = Some(io_pipe.fs.clone());
+        self.io_batcher = Some(io_pipe.batcher.clone());
+
+        let language = io_pipe.config.cl_language.clone();
+        // english is the fallback text of the server
+        if language.is_empty() || language == "en" {
+            return;
+        }
+        let fs = io_pipe.fs.clone();
+        self.localization_task = Some(io_pipe.batcher.lock().unwrap().spawn::<Vec<u8>, _>(
+            async move {
+                fs.open_file(&format!("languages/{}.json", language))
+                    .await
+                    .map_err(|err| {
+                        ArrayString::from(&format!("language file not found: {}", err))
+                            .unwrap_or_default()
+                    })
+            },
+        ));
+    }
 
     fn init_while_io(&mut self, _pipe: &mut ComponentLoadWhileIOPipe) {}
 
     fn init(&mut self, _pipe: &mut ComponentLoadPipe) -> Result<(), ArrayString<4096>> {
+        if let Some(mut task) = self.localization_task.take() {
+            // a missing translation is not fatal
+            match task.get_storage() {
+                Ok(file) => match Localization::from_json(&file) {
+                    Ok(localization) => self.localization = localization,
+                    Err(err) => log_println!("language file is invalid: {}", err),
+                },
+                Err(err) => log_println!("{}", err),
+            }
+        }
         Ok(())
     }
 }
 
 impl ComponentUpdatable for NetworkLogic {
     fn update(&mut self, pipe: &mut ComponentUpdatePipe) {
+        if let Some(mut task) = self.ghosts_task.take_if(|task| task.is_finished()) {
+            // the saved ghosts of the map have no player on this server
+            pipe.client_data.ghosts.extend(
+                task.get_storage()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|ghost| (INVALID_GAME_ELEMENT_ID, ghost)),
+            );
+        }
         match self.cur_client_connection_state {
             ClientConnectionState::Connecting => {
                 // check if the client is ready
+                if let ClientMap::Err(err) = &*pipe.map {
+                    let reason = format!("the map {} could not be loaded: {}", self.cur_map, err);
+                    log_println!("{}", reason);
+                    pipe.network
+                        .disconnect(&pipe.network.get_current_connect_id());
+                    pipe.client_data.disconnect_reason = Some(reason);
+                    self.cur_client_connection_state = ClientConnectionState::NotConnected;
+                    return;
+                }
                 // check if the map is loaded
                 if pipe.map.is_fully_loaded() {
+                    // the client would predict different physics than the server simulates
+                    if pipe.map.unwrap().raw.physics_hash() != self.cur_map_physics_hash {
+                        let reason = format!(
+                            "the physics of the map {} differ from the server's map",
+                            self.cur_map
+                        );
+                        log_println!("{}", reason);
+                        pipe.network
+                            .disconnect(&pipe.network.get_current_connect_id());
+                        pipe.client_data.disconnect_reason = Some(reason);
+                        self.cur_client_connection_state = ClientConnectionState::NotConnected;
+                        return;
+                    }
                     pipe.network.send_to_server(&GameMessage::ClientToServer(
                         ClientToServerMessage::Ready(MsgClReady {
                             player_info: MsgObjPlayerInfo {
@@ -166,8 +261,14 @@ impl ComponentUpdatable for NetworkLogic {
                                     effect_name: NetworkStr::from("TODO").unwrap(),
                                 },
                             },
+                            as_spectator: pipe.config.cl_join_as_spectator,
                         }),
                     ));
+                    if pipe.config.cl_race_ghost {
+                        pipe.network.send_to_server(&GameMessage::ClientToServer(
+                            ClientToServerMessage::GhostSubscribe(true),
+                        ));
+                    }
                     self.cur_client_connection_state = ClientConnectionState::Ready;
                 }
             }
@@ -187,7 +288,20 @@ impl ComponentGameMsg for NetworkLogic {
     ) {
         match msg {
             ServerToClientMessage::ServerInfo(info) => {
+                // also sent on a map change, the client then joins again after loading the map
+                self.cur_client_connection_state = ClientConnectionState::Connecting;
+                self.snapshot_deltas.clear();
                 self.cur_map = info.map.as_str().to_string();
+                self.cur_map_physics_hash = info.map_physics_hash;
+                // the ghosts are saved before the saved ghosts are loaded, the map might be the same
+                pipe.client_data.ghosts.clear();
+                self.ghosts_task = self.save_ghosts_and_load(
+                    pipe.config.cl_race_ghost.then_some(info.map_physics_hash),
+                );
+                // the server sends them again after the client joined
+                pipe.client_data.player_infos.clear();
+                pipe.client_data.prediction_smoothing.reset();
+                pipe.map.unload(pipe.graphics);
                 *pipe.map = ClientMap::UploadingImagesAndMapBuffer(ClientMapFile::new(
                     &pipe.runtime_thread_pool,
                     info.map.as_str(),
@@ -198,17 +312,153 @@ impl ComponentGameMsg for NetworkLogic {
                     pipe.config,
                     &pipe.sys.time,
                 ));
-                println!("{}", info.map.as_str());
+                log_println!("{}", info.map.as_str());
                 let ping = *timestamp - pipe.client_data.server_connect_time;
                 // set the first ping based on the intial packets,
                 // later prefer the network stats
                 pipe.client_data.ping = ping;
             }
             ServerToClientMessage::Snapshot(snap) => {
-                pipe.snap_shot_builder
-                    .convert_to_game_state(snap, &mut pipe.game);
-                pipe.client_data.player_id_on_server = snap.recv_player_id;
-                pipe.client_data.snapshot_timestamp = *timestamp;
+                Self::on_snapshot(snap, timestamp, pipe);
+            }
+            ServerToClientMessage::SnapshotDelta(delta) => {
+                match self.snapshot_deltas.apply(delta) {
+                    Ok(snap) => {
+                        Self::on_snapshot(&snap, timestamp, pipe);
+                        pipe.network.send_to_server(&GameMessage::ClientToServer(
+                            ClientToServerMessage::SnapshotAck(delta.monotonic_tick),
+                        ));
+                    }
+                    // the next delta without this baseline will work again
+                    Err(err) => log_println!("snapshot delta could not be applied: {}", err),
+                }
+            }
+            ServerToClientMessage::VoteStarted(vote) => {
+                pipe.client_data.vote = Some((vote.proposal.clone(), vote.progress));
+            }
+            ServerToClientMessage::VoteProgress(progress) => {
+                if let Some((_, cur_progress)) = &mut pipe.client_data.vote {
+                    *cur_progress = *progress;
+                }
+            }
+            ServerToClientMessage::VoteEnded(_) => {
+                pipe.client_data.vote = None;
+            }
+            ServerToClientMessage::StageEvent(ev) => match ev {
+                MsgSvStageEvent::Joined { player_id, .. } => {
+                    if *player_id == pipe.client_data.player_id_on_server {
+                        pipe.client_data.stage_error = None;
+                    }
+                }
+                MsgSvStageEvent::LockChanged { .. } => {}
+                MsgSvStageEvent::Failed(err) => {
+                    pipe.client_data.stage_error = Some(*err);
+                }
+            },
+            ServerToClientMessage::MatchStats(report) => {
+                pipe.client_data.last_match_stats = Some(report.clone());
+            }
+            ServerToClientMessage::SystemMessage(msg) => {
+                let text = self.localization.format(msg);
+                // system messages contain player names
+                log_println_private!("{}", text);
+                if pipe.client_data.system_messages.len() >= 100 {
+                    pipe.client_data.system_messages.pop_front();
+                }
+                pipe.client_data.system_messages.push_back(text);
+            }
+            ServerToClientMessage::Chat(chat) => {
+                if pipe.client_data.chat_messages.len() >= 100 {
+                    pipe.client_data.chat_messages.pop_front();
+                }
+                pipe.client_data.chat_messages.push_back(chat.clone());
+            }
+            ServerToClientMessage::Bookmark(bookmark) => {
+                pipe.client_data.bookmarks.push(bookmark.clone());
+            }
+            ServerToClientMessage::ResourceHints(hints) => {
+                // hints add to the known ones, e.g. the map of a vote next to the hints of the mod
+                let known_hints = &mut pipe.client_data.resource_hints;
+                for hint in hints {
+                    match known_hints.iter_mut().find(|known| {
+                        known.kind == hint.kind && known.name.as_str() == hint.name.as_str()
+                    }) {
+                        Some(known) => {
+                            if hint.hash.is_some() {
+                                known.hash = hint.hash;
+                            }
+                        }
+                        None => {
+                            if known_hints.len() >= 32 {
+                                known_hints.remove(0);
+                            }
+                            known_hints.push(hint.clone());
+                        }
+                    }
+                }
+            }
+            ServerToClientMessage::PlayerInfo(player_info) => {
+                pipe.client_data
+                    .player_infos
+                    .insert(player_info.player_id, player_info.info.clone());
+            }
+            ServerToClientMessage::PlayerInput(inp) => {
+                pipe.client_data.watched_player_input = Some(*inp);
+            }
+            ServerToClientMessage::Ghost(ghost) => {
+                if ghost.ghost.map_physics_hash == self.cur_map_physics_hash {
+                    match self
+                        .unsaved_ghosts
+                        .iter_mut()
+                        .find(|unsaved| unsaved.player_name == ghost.ghost.player_name)
+                    {
+                        Some(unsaved) => {
+                            if ghost.ghost.finish_ticks < unsaved.finish_ticks {
+                                *unsaved = ghost.ghost.clone();
+                            }
+                        }
+                        None => self.unsaved_ghosts.push(ghost.ghost.clone()),
+                    }
+                    // only keep the newest ghost per player
+                    pipe.client_data
+                        .ghosts
+                        .retain(|(player_id, _)| *player_id != ghost.player_id);
+                    pipe.client_data
+                        .ghosts
+                        .push((ghost.player_id, ghost.ghost.clone()));
+                }
+            }
+            ServerToClientMessage::StateChecksum(server) => {
+                // without the snapshot of the tick before, there is nothing to compare
+                let base = pipe.client_data.state_checksum_base.take();
+                let (ClientMap::Map(map), Some((base_tick, mut base))) = (&*pipe.map, base) else {
+                    return;
+                };
+                if base_tick + 1 != server.monotonic_tick {
+                    return;
+                }
+                let inputs: Vec<(_, LocalPlayerInput)> = server
+                    .inputs
+                    .iter()
+                    .map(|(player_id, input)| (*player_id, LocalPlayerInput::from_net_obj(input)))
+                    .collect();
+                let own_checksum = resimulated_state_checksum(&mut base, &inputs, &map.collision);
+                if own_checksum != server.checksum {
+                    log_println!(
+                        "desync at tick {}: server checksum {:x}, own checksum {:x}",
+                        server.monotonic_tick,
+                        server.checksum,
+                        own_checksum
+                    );
+                    // the server answers with a full snapshot
+                    pipe.network.send_to_server(&GameMessage::ClientToServer(
+                        ClientToServerMessage::Desync {
+                            monotonic_tick: server.monotonic_tick,
+                            server_checksum: server.checksum,
+                            client_checksum: own_checksum,
+                        },
+                    ));
+                }
             }
             _ => {}
         }
@@ -220,6 +470,8 @@ impl ComponentGameMsg for NetworkLogic {
 
     fn on_disconnect(&mut self, _timestamp: &Duration) {
         self.cur_client_connection_state = ClientConnectionState::NotConnected;
+        self.ghosts_task = None;
+        self.save_ghosts();
     }
 }
 
@@ -236,7 +488,77 @@ impl NetworkLogic {
     pub fn new() -> Self {
         Self {
             cur_map: String::new(),
+            cur_map_physics_hash: Default::default(),
             cur_client_connection_state: ClientConnectionState::NotConnected,
+
+            localization: Localization::default(),
+            localization_task: None,
+
+            snapshot_deltas: SnapshotDeltaReceiver::default(),
+
+            unsaved_ghosts: Vec::new(),
+            ghosts_task: None,
+            fs: None,
+            io_batcher: None,
         }
     }
+
+    /**
+     * The ghosts are written on map changes, disconnects and when the client closes,
+     * instead of every time a player finishes.
+     * If a map is given, its saved ghosts are loaded after the writes.
+     */
+    fn save_ghosts_and_load(
+        &mut self,
+        load_map_physics_hash: Option<MapSectionHash>,
+    ) -> Option<IOBatcherTask<Vec<GhostPath>>> {
+        let (Some(fs), Some(io_batcher)) = (&self.fs, &self.io_batcher) else {
+            return None;
+        };
+        if self.unsaved_ghosts.is_empty() && load_map_physics_hash.is_none() {
+            return None;
+        }
+        let fs = fs.clone();
+        let ghosts = std::mem::take(&mut self.unsaved_ghosts);
+        Some(io_batcher.lock().unwrap().spawn(async move {
+            for ghost in ghosts {
+                if let Err(err) = ghost.save(&fs).await {
+                    log_println!("failed to save ghost: {}", err);
+                }
+            }
+            Ok(match load_map_physics_hash {
+                Some(map_physics_hash) => GhostPath::load_for_map(&fs, &map_physics_hash).await,
+                None => Vec::new(),
+            })
+        }))
+    }
+
+    /**
+     * Doesn't wait for the writes, the client finishes all io tasks before it closes
+     */
+    fn save_ghosts(&mut self) {
+        self.save_ghosts_and_load(None);
+    }
+
+    fn on_snapshot(snap: &Snapshot, timestamp: &Duration, pipe: &mut GameMsgPipeline) {
+        pipe.snap_shot_builder
+            .convert_to_game_state(snap, &mut pipe.game);
+        pipe.client_data.player_id_on_server = snap.recv_player_id;
+        pipe.client_data.role_on_server = snap.recv_role;
+        pipe.client_data.snapshot_timestamp = *timestamp;
+        pipe.client_data.prediction_smoothing.on_snapshot();
+        // the server sends the checksum of the next tick, simulated from this snapshot
+        if (snap.monotonic_tick + 1) % STATE_CHECKSUM_INTERVAL_TICKS == 0 && !snap.paused {
+            let mut base = GameState::new();
+            pipe.snap_shot_builder
+                .convert_to_game_state(snap, &mut base);
+            pipe.client_data.state_checksum_base = Some((snap.monotonic_tick, base));
+        }
+    }
+}
+
+impl Drop for NetworkLogic {
+    fn drop(&mut self) {
+        self.save_ghosts();
+    }
 }
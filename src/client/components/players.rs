@@ -1,14 +1,19 @@
 use arrayvec::ArrayString;
+use base::log_println;
 use graphics_types::{
     command_buffer::SRenderSpriteInfo,
     rendering::{ColorRGBA, ETextureIndex, State},
 };
 
 use crate::{
-    client::component::{
-        ComponentComponent, ComponentGameMsg, ComponentLoadIOPipe, ComponentLoadPipe,
-        ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderPipe, ComponentRenderable,
-        ComponentUpdatable,
+    client::{
+        component::{
+            ComponentComponent, ComponentGameMsg, ComponentLoadIOPipe, ComponentLoadPipe,
+            ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderPipe, ComponentRenderable,
+            ComponentUpdatable, ComponentUpdatePipe,
+        },
+        components::skins::Skins,
+        render_tools::RenderTools,
     },
     game::weapons::definitions::Weapons,
     render::{
@@ -29,6 +34,9 @@ use graphics::graphics::{
  * all weapons, and all players
  */
 pub struct Players {
+    // the skins are only needed to render the players
+    skins: Skins,
+
     quad_container_index: QuadContainerIndex,
 
     tee_renderer: Option<RenderTee>,
@@ -44,12 +52,20 @@ pub struct Players {
 }
 
 impl ComponentLoadable for Players {
-    fn load_io(&mut self, io_pipe: &mut ComponentLoadIOPipe) {}
+    fn load_io(&mut self, io_pipe: &mut ComponentLoadIOPipe) {
+        self.skins.load_io(io_pipe);
+    }
 
-    fn init_while_io(&mut self, _pipe: &mut ComponentLoadWhileIOPipe) {}
+    fn init_while_io(&mut self, pipe: &mut ComponentLoadWhileIOPipe) {
+        self.skins.init_while_io(pipe);
+    }
 
     fn init(&mut self, pipe: &mut ComponentLoadPipe) -> Result<(), ArrayString<4096>> {
         self.tee_renderer = Some(RenderTee::new(pipe.graphics));
+        // players are still rendered without skin textures
+        if let Err(err) = self.skins.init(pipe) {
+            log_println!("the default skin could not be loaded: {}", err);
+        }
 
         self.quad_container_index = pipe
             .graphics
@@ -139,10 +155,17 @@ impl ComponentLoadable for Players {
     }
 }
 
-impl ComponentUpdatable for Players {}
+impl ComponentUpdatable for Players {
+    fn update(&mut self, pipe: &mut ComponentUpdatePipe) {
+        self.skins.update(pipe);
+    }
+}
 
 impl ComponentRenderable for Players {
     fn render(&mut self, pipe: &mut ComponentRenderPipe) {
+        // uploads the skins that finished loading
+        self.skins.render(pipe);
+
         // first render the hooks
         // OLD: render everyone else's hook, then our own
 
@@ -410,58 +433,109 @@ impl ComponentRenderable for Players {
         // render afk state <- state effect?
         // render tee emote
         let mut state = State::new();
+        let mut points: [f32; 4] = [0.0; 4];
+        RenderTools::map_canvas_to_world(
+            pipe.camera.x,
+            pipe.camera.y,
+            100.0,
+            100.0,
+            100.0,
+            0.0,
+            0.0,
+            pipe.graphics.canvas_aspect(),
+            pipe.camera.zoom,
+            &mut points,
+        );
+        state.map_canvas(points[0], points[1], points[2], points[3]);
 
-        let tee_render_info = TeeRenderInfo {
-            render_skin: TeeRenderSkinTextures::Original(Default::default()),
-            color_body: ColorRGBA {
-                r: 1.0,
-                g: 1.0,
-                b: 1.0,
-                a: 1.0,
-            },
-            color_feet: ColorRGBA {
-                r: 1.0,
-                g: 1.0,
-                b: 1.0,
-                a: 1.0,
-            },
-            got_air_jump: false,
-            feet_flipped: false,
-            size: 64.0,
+        let Some(tee_renderer) = &self.tee_renderer else {
+            return;
         };
-
-        self.tee_renderer.as_mut().unwrap().render_tee(
-            pipe.graphics,
-            &AnimState {
-                ..Default::default()
-            },
-            &tee_render_info,
-            TeeEyeEmote::Normal,
-            &vec2::new(0.0, 1.0),
-            &vec2::new(0.0, 1.0),
-            1.0,
-            &state,
-        );
+        for stage in pipe.game.get_stages() {
+            for char in stage.get_world().get_characters() {
+                let skin_name = pipe
+                    .client_data
+                    .player_infos
+                    .get(&char.cores[0].player_id)
+                    .map_or("default", |info| info.skin_body.name.as_str());
+                let skin_textures = self
+                    .skins
+                    .get_or_default(skin_name)
+                    .map(|skin| skin.render_textures())
+                    .unwrap_or_default();
+
+                // the own character is rendered where the prediction is, like the camera
+                let pos = match pipe.client_data.prediction_smoothing.render_pos() {
+                    Some(pos)
+                        if char.cores[0].player_id == pipe.client_data.player_id_on_server =>
+                    {
+                        pos
+                    }
+                    _ => char.cores[0].core.pos,
+                };
+
+                let tee_render_info = TeeRenderInfo {
+                    render_skin: TeeRenderSkinTextures::Original(skin_textures),
+                    color_body: ColorRGBA {
+                        r: 1.0,
+                        g: 1.0,
+                        b: 1.0,
+                        a: 1.0,
+                    },
+                    color_feet: ColorRGBA {
+                        r: 1.0,
+                        g: 1.0,
+                        b: 1.0,
+                        a: 1.0,
+                    },
+                    got_air_jump: false,
+                    feet_flipped: false,
+                    size: 64.0,
+                };
+
+                tee_renderer.render_tee(
+                    pipe.graphics,
+                    &AnimState {
+                        ..Default::default()
+                    },
+                    &tee_render_info,
+                    TeeEyeEmote::Normal,
+                    &vec2::new(1.0, 0.0),
+                    &pos,
+                    1.0,
+                    &state,
+                );
+            }
+        }
     }
 }
 
 impl ComponentGameMsg for Players {}
 
-impl ComponentComponent for Players {}
+impl ComponentComponent for Players {
+    fn does_update(&self) -> bool {
+        true
+    }
+    fn does_render(&self) -> bool {
+        true
+    }
+}
 
 impl Players {
     pub fn new() -> Self {
         Self {
+            skins: Skins::new(),
+
             quad_container_index: None,
             tee_renderer: None,
 
             hook_chain_texture: ETextureIndex::Invalid,
             hook_head_texture: ETextureIndex::Invalid,
 
-            hook_chain_quad_offset: todo!(),
-            hook_head_quad_offset: todo!(),
-            weapon_textures: todo!(),
-            weapon_quad_offsets: todo!(),
+            hook_chain_quad_offset: 0,
+            hook_head_quad_offset: 0,
+            weapon_textures: [ETextureIndex::Invalid; 4],
+            weapon_quad_offsets: [0; 4],
         }
     }
 }
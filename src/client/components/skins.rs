@@ -1,13 +1,19 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
 
 use arrayvec::ArrayString;
 
 use crate::client::{
     component::{
         ComponentComponent, ComponentGameMsg, ComponentLoadIOPipe, ComponentLoadPipe,
-        ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderable, ComponentUpdatable,
+        ComponentLoadWhileIOPipe, ComponentLoadable, ComponentRenderPipe, ComponentRenderable,
+        ComponentUpdatable, ComponentUpdatePipe,
     },
     image::png::load_png_image,
+    render::tee::SkinTextures,
 };
 
 use graphics::graphics::{Graphics, GraphicsTextureAllocations};
@@ -18,7 +24,9 @@ use graphics_types::{
     types::ImageFormat,
 };
 
-use base::{filesys::FileSystem, io_batcher::IOBatcherTask};
+use base::{
+    filesys::FileSystem, io_batcher::IOBatcherTask, log_println, system::SystemTimeInterface,
+};
 
 #[derive(Default, Clone)]
 pub struct Skin {
@@ -31,6 +39,30 @@ pub struct Skin {
     right_foot: ETextureIndex,
     left_eye: ETextureIndex,
     right_eye: ETextureIndex,
+    // the memory of all textures together
+    memory_size: usize,
+}
+
+impl Skin {
+    /**
+     * The textures for the tee renderer, skins have no outlines and a single eye texture yet
+     */
+    pub fn render_textures(&self) -> SkinTextures {
+        SkinTextures {
+            body: self.body,
+            body_outline: self.body,
+            left_foot: self.left_foot,
+            left_foot_outline: self.left_foot,
+            right_foot: self.right_foot,
+            right_foot_outline: self.right_foot,
+            left_hand_foot: self.left_hand,
+            left_hand_outline: self.left_hand,
+            right_hand_foot: self.right_hand,
+            right_hand_outline: self.right_hand,
+            left_eyes: [self.left_eye; 9],
+            right_eyes: [self.right_eye; 9],
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -66,13 +98,22 @@ impl LoadSkin {
  * The skin component initializes all skins in a
  * lazy loaded style.
  * The only exception to this is the default skin
- * which must always be available.
+ * which is loaded at the start.
  * For UI it provides a list of skin names, so the UI can load skins on fly
- *
+ * Skins that are not loaded yet are requested with `get_or_default`,
+ * which returns the default skin until the skin is loaded in the background.
  */
 pub struct Skins {
     pub skins: HashMap<String, Skin>,
     pub load_task: Option<IOBatcherTask<HashMap<String, LoadSkin>>>,
+
+    requested_skins: HashSet<String>,
+    loading_skins: HashMap<String, IOBatcherTask<HashMap<String, LoadSkin>>>,
+    // skins that could not be loaded, so they are not requested every frame
+    failed_skins: HashSet<String>,
+
+    last_used: HashMap<String, Duration>,
+    cur_time: Duration,
 }
 
 impl ComponentLoadable for Skins {
@@ -97,46 +138,228 @@ impl ComponentLoadable for Skins {
     fn init_while_io(&mut self, _pipe: &mut ComponentLoadWhileIOPipe) {}
 
     fn init(&mut self, pipe: &mut ComponentLoadPipe) -> Result<(), ArrayString<4096>> {
-        let load_skins = self.load_task.as_mut().unwrap().get_storage().unwrap();
+        // without the default skin, tees are rendered without textures
+        let load_skins = self.load_task.as_mut().unwrap().get_storage()?;
         for (load_skin_name, load_skin) in load_skins {
-            let mut skin = Skin::default();
-            skin.body =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.body, &load_skin_name);
-            skin.marking =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.marking, &load_skin_name);
-            skin.decoration =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.decoration, &load_skin_name);
-            skin.left_hand =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.left_hand, &load_skin_name);
-            skin.right_hand =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.right_hand, &load_skin_name);
-            skin.left_foot =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.left_foot, &load_skin_name);
-            skin.right_foot =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.right_foot, &load_skin_name);
-            skin.left_eye =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.left_eye, &load_skin_name);
-            skin.right_eye =
-                Self::load_file_into_texture(pipe.graphics, &load_skin.right_eye, &load_skin_name);
+            let skin = Self::create_skin(pipe.graphics, &load_skin, &load_skin_name)?;
+            self.last_used.insert(load_skin_name.clone(), self.cur_time);
             self.skins.insert(load_skin_name, skin);
         }
         Ok(())
     }
 }
 
-impl ComponentUpdatable for Skins {}
+impl ComponentUpdatable for Skins {
+    fn update(&mut self, pipe: &mut ComponentUpdatePipe) {
+        for skin_name in self.requested_skins.drain() {
+            let fs = pipe.fs.clone();
+            let load_skin_name = skin_name.clone();
+            let task = pipe
+                .batcher
+                .lock()
+                .unwrap()
+                .spawn::<HashMap<String, LoadSkin>, _>(async move {
+                    let mut storage = HashMap::<String, LoadSkin>::default();
+                    Self::load_skin(&fs, &mut storage, &load_skin_name).await?;
+                    Ok(storage)
+                });
+            self.loading_skins.insert(skin_name, task);
+        }
+    }
+}
+
+impl ComponentRenderable for Skins {
+    fn render(&mut self, pipe: &mut ComponentRenderPipe) {
+        self.cur_time = pipe.sys.time_get_nanoseconds();
+        self.unload_unused(
+            pipe.graphics,
+            pipe.config.cl_skins_memory_budget_kib as usize * 1024,
+            Duration::from_secs(pipe.config.cl_skins_unused_secs as u64),
+        );
 
-impl ComponentRenderable for Skins {}
+        // only upload one skin per frame, so many new skins don't cause a frame hitch
+        let finished_skin = self
+            .loading_skins
+            .iter()
+            .find(|(_, task)| task.is_finished())
+            .map(|(skin_name, _)| skin_name.clone());
+        if let Some(skin_name) = finished_skin {
+            let mut task = self.loading_skins.remove(&skin_name).unwrap();
+            match task.get_storage() {
+                Ok(load_skins) => {
+                    for (load_skin_name, load_skin) in load_skins {
+                        match Self::create_skin(pipe.graphics, &load_skin, &load_skin_name) {
+                            Ok(skin) => {
+                                // else it counts as unused since the start and is unloaded right away
+                                self.last_used.insert(load_skin_name.clone(), self.cur_time);
+                                self.skins.insert(load_skin_name, skin);
+                            }
+                            Err(err) => {
+                                log_println!("skin {} is invalid: {}", load_skin_name, err);
+                                self.failed_skins.insert(load_skin_name);
+                            }
+                        }
+                    }
+                }
+                Err(_) => {
+                    // TODO: download the skin from the resource server
+                    // (and verify its hash) instead, needs an http client first
+                    self.failed_skins.insert(skin_name);
+                }
+            }
+        }
+    }
+}
 
 impl ComponentGameMsg for Skins {}
 
-impl ComponentComponent for Skins {}
+impl ComponentComponent for Skins {
+    fn does_update(&self) -> bool {
+        true
+    }
+    fn does_render(&self) -> bool {
+        true
+    }
+}
 
 impl Skins {
     pub fn new() -> Self {
         Self {
             skins: Default::default(),
             load_task: None,
+
+            requested_skins: Default::default(),
+            loading_skins: Default::default(),
+            failed_skins: Default::default(),
+
+            last_used: Default::default(),
+            cur_time: Duration::ZERO,
+        }
+    }
+
+    /**
+     * Skin names come from other players, so only plain
+     * file names are allowed, no paths.
+     */
+    fn is_valid_skin_name(skin_name: &str) -> bool {
+        !skin_name.is_empty()
+            && !skin_name.contains('/')
+            && !skin_name.contains('\\')
+            && !skin_name.contains("..")
+    }
+
+    /**
+     * Returns the default skin while the skin is not loaded (or does not exist)
+     * and requests to load it in the background.
+     * Invalid skin names are never requested.
+     * `None` if the default skin could not be loaded either.
+     */
+    pub fn get_or_default(&mut self, skin_name: &str) -> Option<&Skin> {
+        if Self::is_valid_skin_name(skin_name)
+            && !self.skins.contains_key(skin_name)
+            && !self.loading_skins.contains_key(skin_name)
+            && !self.failed_skins.contains(skin_name)
+        {
+            self.requested_skins.insert(skin_name.to_string());
+        }
+        // only loaded skins can be unloaded, so only they need the time
+        if let Some(last_used) = self.last_used.get_mut(skin_name) {
+            *last_used = self.cur_time;
+        }
+        self.skins
+            .get(skin_name)
+            .or_else(|| self.skins.get("default"))
+    }
+
+    /**
+     * On an error the already uploaded textures of the skin are unloaded again.
+     */
+    fn create_skin(
+        graphics: &mut Graphics,
+        load_skin: &LoadSkin,
+        skin_name: &str,
+    ) -> Result<Skin, ArrayString<4096>> {
+        let mut skin = Skin::default();
+        if let Err(err) = Self::create_skin_textures(graphics, load_skin, skin_name, &mut skin) {
+            Self::unload_skin(graphics, &mut skin);
+            return Err(err);
+        }
+        Ok(skin)
+    }
+
+    fn create_skin_textures(
+        graphics: &mut Graphics,
+        load_skin: &LoadSkin,
+        skin_name: &str,
+        skin: &mut Skin,
+    ) -> Result<(), ArrayString<4096>> {
+        let size = &mut skin.memory_size;
+        skin.body = Self::load_file_into_texture(graphics, &load_skin.body, skin_name, size)?;
+        skin.marking = Self::load_file_into_texture(graphics, &load_skin.marking, skin_name, size)?;
+        skin.decoration =
+            Self::load_file_into_texture(graphics, &load_skin.decoration, skin_name, size)?;
+        skin.left_hand =
+            Self::load_file_into_texture(graphics, &load_skin.left_hand, skin_name, size)?;
+        skin.right_hand =
+            Self::load_file_into_texture(graphics, &load_skin.right_hand, skin_name, size)?;
+        skin.left_foot =
+            Self::load_file_into_texture(graphics, &load_skin.left_foot, skin_name, size)?;
+        skin.right_foot =
+            Self::load_file_into_texture(graphics, &load_skin.right_foot, skin_name, size)?;
+        skin.left_eye =
+            Self::load_file_into_texture(graphics, &load_skin.left_eye, skin_name, size)?;
+        skin.right_eye =
+            Self::load_file_into_texture(graphics, &load_skin.right_eye, skin_name, size)?;
+        Ok(())
+    }
+
+    fn unload_skin(graphics: &mut Graphics, skin: &mut Skin) {
+        graphics.unload_texture(&mut skin.body);
+        graphics.unload_texture(&mut skin.marking);
+        graphics.unload_texture(&mut skin.decoration);
+        graphics.unload_texture(&mut skin.left_hand);
+        graphics.unload_texture(&mut skin.right_hand);
+        graphics.unload_texture(&mut skin.left_foot);
+        graphics.unload_texture(&mut skin.right_foot);
+        graphics.unload_texture(&mut skin.left_eye);
+        graphics.unload_texture(&mut skin.right_eye);
+    }
+
+    /**
+     * Unloads the least recently used skins that were not used for `unused_time`,
+     * until all skins fit into the memory budget again.
+     * The default skin is never unloaded.
+     */
+    fn unload_unused(&mut self, graphics: &mut Graphics, budget: usize, unused_time: Duration) {
+        let mut memory_size: usize = self.skins.values().map(|skin| skin.memory_size).sum();
+        if memory_size <= budget {
+            return;
+        }
+        let mut unused_skins: Vec<(String, Duration)> = self
+            .skins
+            .keys()
+            .filter(|skin_name| *skin_name != "default")
+            .map(|skin_name| {
+                (
+                    skin_name.clone(),
+                    self.last_used
+                        .get(skin_name)
+                        .copied()
+                        .unwrap_or(self.cur_time),
+                )
+            })
+            .filter(|(_, last_used)| self.cur_time.saturating_sub(*last_used) >= unused_time)
+            .collect();
+        unused_skins.sort_by_key(|(_, last_used)| *last_used);
+        for (skin_name, _) in unused_skins {
+            if memory_size <= budget {
+                break;
+            }
+            if let Some(mut skin) = self.skins.remove(&skin_name) {
+                memory_size -= skin.memory_size;
+                Self::unload_skin(graphics, &mut skin);
+            }
+            self.last_used.remove(&skin_name);
         }
     }
 
@@ -144,13 +367,19 @@ impl Skins {
         graphics: &mut Graphics,
         file: &Vec<u8>,
         name: &str,
-    ) -> ETextureIndex {
+        memory_size: &mut usize,
+    ) -> Result<ETextureIndex, ArrayString<4096>> {
         let mut img_data = Vec::<u8>::new();
         let part_img = load_png_image(&file, |size| {
             img_data = vec![0; size];
             &mut img_data
         })
-        .unwrap();
+        .map_err(|err| {
+            let mut err_str = ArrayString::<4096>::new();
+            let _ = err_str.try_push_str(&("not a valid png: ".to_string() + &err.to_string()));
+            err_str
+        })?;
+        *memory_size += part_img.width as usize * part_img.height as usize * 4;
         let mut texture_id = Default::default();
         graphics.load_texture_slow(
             &mut texture_id,
@@ -162,7 +391,7 @@ impl Skins {
             TexFlags::empty(),
             name,
         );
-        texture_id
+        Ok(texture_id)
     }
 
     async fn load_skin_part(
@@ -185,7 +414,7 @@ impl Skins {
                 skin_path_def.push_str("/");
                 skin_path_def.push_str(part);
                 skin_path_def.push_str(".png");
-                let file_def = fs.open_file(skin_full_path.as_str()).await;
+                let file_def = fs.open_file(skin_path_def.as_str()).await;
                 if let Err(err) = file_def {
                     return Err(ArrayString::from(
                         ("default skin part (".to_string()
@@ -218,6 +447,9 @@ impl Skins {
         skin_map: &mut HashMap<String, LoadSkin>,
         skin_name: &str,
     ) -> Result<(), ArrayString<4096>> {
+        if !Self::is_valid_skin_name(skin_name) {
+            return Err(ArrayString::from("skin name is not a valid file name").unwrap());
+        }
         let mut skin_path = ArrayString::<4096>::from_str("skins/").unwrap();
         skin_path.push_str(skin_name);
         skin_path.push_str("/");
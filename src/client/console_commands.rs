@@ -0,0 +1,148 @@
+use base::console::ConsoleCommandHandler;
+use network::network::quinn_network::QuinnNetwork;
+
+use crate::{
+    game::{snapshot::SnapshotSpectatorCamera, TGameElementID},
+    id_gen::IDGeneratorIDType,
+    network::messages::{
+        ClientToServerMessage, GameMessage, MsgClCallVote, MsgClChat, MsgObjVoteKind, NetworkStr,
+    },
+};
+
+use super::client::ClientData;
+
+/**
+ * The console commands that send game messages to the server (e.g. `vote`),
+ * so they can be bound to keys like all other commands
+ */
+pub struct GameConsoleCommands<'a> {
+    pub network: &'a mut QuinnNetwork,
+    pub client_data: &'a mut ClientData,
+}
+
+impl<'a> GameConsoleCommands<'a> {
+    fn send(&mut self, msg: ClientToServerMessage) {
+        self.network
+            .send_to_server(&GameMessage::ClientToServer(msg));
+    }
+
+    /**
+     * The player with exactly this name
+     */
+    fn player_by_name(&self, name: &str) -> Result<TGameElementID, String> {
+        self.client_data
+            .player_infos
+            .iter()
+            .find(|(_, info)| info.name.as_str() == name)
+            .map(|(player_id, _)| *player_id)
+            .ok_or_else(|| format!("there is no player named {}", name))
+    }
+
+    fn chat(&mut self, args: &[String], team: bool) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("usage: say <message>".to_string());
+        }
+        self.send(ClientToServerMessage::Chat(MsgClChat {
+            msg: NetworkStr::from_truncated(&args.join(" ")),
+            team,
+        }));
+        Ok(())
+    }
+
+    fn call_vote(&mut self, args: &[String]) -> Result<(), String> {
+        let usage = || "usage: callvote kick|map|custom <name> [reason]".to_string();
+        let (kind, name) = match args {
+            [kind, name, ..] => (kind.as_str(), name.as_str()),
+            _ => return Err(usage()),
+        };
+        let kind = match kind {
+            "kick" => MsgObjVoteKind::Kick(self.player_by_name(name)?),
+            "map" => MsgObjVoteKind::ChangeMap(
+                NetworkStr::from(name).map_err(|_| "callvote: the map name is too long")?,
+            ),
+            "custom" => MsgObjVoteKind::Custom(
+                NetworkStr::from(name).map_err(|_| "callvote: the vote name is too long")?,
+            ),
+            _ => return Err(usage()),
+        };
+        let reason = args.get(2).map(|reason| reason.as_str()).unwrap_or("");
+        self.send(ClientToServerMessage::CallVote(MsgClCallVote {
+            kind,
+            reason: NetworkStr::from_truncated(reason),
+        }));
+        Ok(())
+    }
+}
+
+impl<'a> ConsoleCommandHandler for GameConsoleCommands<'a> {
+    fn handle_command(
+        &mut self,
+        name: &str,
+        args: &[String],
+        _output: &mut Vec<String>,
+    ) -> Option<Result<(), String>> {
+        let res = match name {
+            "callvote" => self.call_vote(args),
+            "vote" => match args.first().map(|arg| arg.as_str()) {
+                Some("yes") => {
+                    self.send(ClientToServerMessage::Vote(true));
+                    Ok(())
+                }
+                Some("no") => {
+                    self.send(ClientToServerMessage::Vote(false));
+                    Ok(())
+                }
+                _ => Err("usage: vote yes|no".to_string()),
+            },
+            "spectate" => {
+                // without a player the camera is moved freely
+                let camera = match args.first() {
+                    Some(name) => self
+                        .player_by_name(name)
+                        .map(SnapshotSpectatorCamera::Chase),
+                    None => Ok(SnapshotSpectatorCamera::Free),
+                };
+                camera.map(|camera| self.send(ClientToServerMessage::SpectatorCamera(camera)))
+            }
+            "watch_input" => {
+                // without a player the overlay is stopped
+                let player_id = match args.first() {
+                    Some(name) => self.player_by_name(name).map(Some),
+                    None => Ok(None),
+                };
+                player_id.map(|player_id| {
+                    self.client_data.watched_player_input = None;
+                    self.send(ClientToServerMessage::WatchPlayerInput(player_id));
+                })
+            }
+            "say" => self.chat(args, false),
+            "say_team" => self.chat(args, true),
+            "team_create" => {
+                self.send(ClientToServerMessage::StageCreate);
+                Ok(())
+            }
+            "team_join" => match args.first().map(|arg| arg.parse::<u64>()) {
+                Some(Ok(stage_id)) => {
+                    self.send(ClientToServerMessage::StageJoin(IDGeneratorIDType(
+                        stage_id,
+                    )));
+                    Ok(())
+                }
+                _ => Err("usage: team_join <team id>".to_string()),
+            },
+            "team_lock" => match args.first().map(|arg| arg.as_str()) {
+                Some("1") => {
+                    self.send(ClientToServerMessage::StageLock(true));
+                    Ok(())
+                }
+                Some("0") => {
+                    self.send(ClientToServerMessage::StageLock(false));
+                    Ok(())
+                }
+                _ => Err("usage: team_lock 0|1".to_string()),
+            },
+            _ => return None,
+        };
+        Some(res)
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    panic::PanicHookInfo,
+    sync::Mutex,
+};
+
+use base::{
+    config::Config,
+    filesys::{FileSystem, FileSystemPath, FileSystemType},
+    system::recent_logs,
+};
+use sha2::{Digest, Sha256};
+
+const CRASH_REPORT_DIR: &str = "crash_reports/";
+
+/**
+ * What the client knows about its environment,
+ * collected while running, since the crash handler can't query it anymore
+ */
+#[derive(Default)]
+struct CrashReportInfo {
+    gpu: Option<String>,
+    // the config values that differ from the default, only numbers and bools with their value
+    config_diff: Vec<String>,
+    // mod name -> sha256 of the wasm module
+    wasm_mods: BTreeMap<String, String>,
+}
+
+static CRASH_REPORT_INFO: Mutex<Option<CrashReportInfo>> = Mutex::new(None);
+
+fn with_info(f: impl FnOnce(&mut CrashReportInfo)) {
+    if let Ok(mut info) = CRASH_REPORT_INFO.lock() {
+        f(info.get_or_insert_with(Default::default));
+    }
+}
+
+/**
+ * Writes a crash report into the read-write directory when the client panics,
+ * the default panic output stays
+ */
+pub fn install_crash_handler(fs: &FileSystem) {
+    let dir = fs
+        .get_path(
+            CRASH_REPORT_DIR,
+            FileSystemPath::OfType(FileSystemType::ReadWrite),
+        )
+        .to_string();
+    let prev_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        match write_crash_report(&dir, panic_info) {
+            Ok(path) => println!("a crash report was written to {}", path),
+            Err(err) => println!("the crash report could not be written: {}", err),
+        }
+        prev_hook(panic_info);
+    }));
+}
+
+pub fn crash_report_set_gpu(gpu: &str) {
+    with_info(|info| info.gpu = Some(gpu.to_string()));
+}
+
+/**
+ * Only the values that differ from the default are part of the report.
+ * Strings, lists and maps (e.g. `sv_name`, aliases or the server overrides)
+ * can contain names, addresses or commands, so only their names are added
+ */
+pub fn crash_report_set_config(config: &Config) {
+    let (Ok(serde_json::Value::Object(cur)), Ok(serde_json::Value::Object(default))) = (
+        serde_json::to_value(config),
+        serde_json::to_value(Config::new()),
+    ) else {
+        return;
+    };
+    let config_diff = cur
+        .iter()
+        .filter(|(name, val)| default.get(*name) != Some(*val))
+        .map(|(name, val)| match val {
+            serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {
+                format!("{} = {}", name, val)
+            }
+            _ => format!("{} = <changed>", name),
+        })
+        .collect();
+    with_info(|info| info.config_diff = config_diff);
+}
+
+pub fn crash_report_add_wasm_mod(name: &str, wasm_bytes: &[u8]) {
+    let hash: String = Sha256::digest(wasm_bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    with_info(|info| {
+        info.wasm_mods.insert(name.to_string(), hash);
+    });
+}
+
+fn is_address(word: &str) -> bool {
+    // shorter words are e.g. `::1` or parts of rust paths
+    word.len() >= 7 && (word.parse::<IpAddr>().is_ok() || word.parse::<SocketAddr>().is_ok())
+}
+
+/**
+ * Replaces the ip addresses (with or without port) that are a word on their own,
+ * e.g. of servers or of the players that connected
+ */
+fn redact_addresses(text: &str) -> String {
+    let is_addr_char = |c: char| c.is_ascii_hexdigit() || matches!(c, '.' | ':' | '[' | ']');
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut res = String::with_capacity(text.len());
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        if !is_addr_char(c) {
+            res.push(c);
+            i += c.len_utf8();
+            continue;
+        }
+        let len = text[i..]
+            .find(|c: char| !is_addr_char(c))
+            .unwrap_or(text.len() - i);
+        let word = &text[i..i + len];
+        // e.g. the end of a sentence
+        let trimmed = word.trim_end_matches(['.', ':']);
+        let is_whole_word = !res.chars().next_back().is_some_and(is_word_char)
+            && !text[i + len..].chars().next().is_some_and(is_word_char);
+        if is_whole_word && is_address(trimmed) {
+            res.push_str("<address>");
+            res.push_str(&word[trimmed.len()..]);
+        } else {
+            res.push_str(word);
+        }
+        i += len;
+    }
+    res
+}
+
+/**
+ * Removes the personal information that reports usually contain,
+ * like the user name in file paths or addresses.
+ * Log lines with player names, chat or config values are never kept
+ * for the report, see `log_println_private!`
+ */
+fn redact(report: &str) -> String {
+    let mut res = redact_addresses(report);
+    for var in ["HOME", "USERPROFILE"] {
+        if let Ok(home) = std::env::var(var) {
+            if !home.is_empty() {
+                res = res.replace(&home, "<home>");
+            }
+        }
+    }
+    for var in ["USER", "USERNAME"] {
+        if let Ok(user) = std::env::var(var) {
+            // very short names would replace parts of normal words
+            if user.len() >= 3 {
+                res = res.replace(&user, "<user>");
+            }
+        }
+    }
+    res
+}
+
+fn write_crash_report(dir: &str, panic_info: &PanicHookInfo) -> anyhow::Result<String> {
+    let mut report = String::new();
+    let _ = writeln!(report, "panic: {}", panic_info);
+    let _ = writeln!(
+        report,
+        "version: {} ({} {})",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+
+    // the panic might have happened while the info was changed
+    if let Ok(info) = CRASH_REPORT_INFO.try_lock() {
+        if let Some(info) = info.as_ref() {
+            let _ = writeln!(report, "gpu: {}", info.gpu.as_deref().unwrap_or("unknown"));
+            let _ = writeln!(report, "\nwasm mods:");
+            for (name, hash) in &info.wasm_mods {
+                let _ = writeln!(report, "{}: {}", name, hash);
+            }
+            let _ = writeln!(report, "\nconfig (changed values):");
+            for line in &info.config_diff {
+                let _ = writeln!(report, "{}", line);
+            }
+        }
+    }
+
+    let _ = writeln!(report, "\nlast logs:");
+    for line in recent_logs() {
+        let _ = writeln!(report, "{}", line);
+    }
+
+    let _ = writeln!(
+        report,
+        "\nbacktrace:\n{}",
+        std::backtrace::Backtrace::force_capture()
+    );
+
+    // the panic hook can't wait for the io batcher, its runtime might be the one that panicked
+    std::fs::create_dir_all(dir)?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = format!("{}crash_{}.txt", dir, timestamp);
+    std::fs::write(&path, redact(&report))?;
+    Ok(path)
+}
+
+/**
+ * The reports (name, content) of earlier crashes that were not handled yet,
+ * e.g. to offer the upload on the next start
+ */
+pub async fn pending_crash_reports(fs: &FileSystem) -> Vec<(String, String)> {
+    let mut res: Vec<(String, String)> = Vec::new();
+    fs.files_of_write_dir_recursive(CRASH_REPORT_DIR, &mut |name, file| {
+        if name.ends_with(".txt") {
+            res.push((name, String::from_utf8_lossy(&file).to_string()));
+        }
+    })
+    .await;
+    res.sort();
+    res
+}
+
+/**
+ * After the report was uploaded or the user did not want to
+ */
+pub async fn discard_crash_report(fs: &FileSystem, name: &str) {
+    let _ = fs.remove_file(&(CRASH_REPORT_DIR.to_string() + name)).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::redact_addresses;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            redact_addresses("connecting to 192.168.0.10:8303."),
+            "connecting to <address>."
+        );
+        assert_eq!(
+            redact_addresses("from [2001:db8::1]:8303 and 2001:db8::2"),
+            "from <address> and <address>"
+        );
+        // paths, hashes and versions stay readable
+        let text = "at std::fs::read, hash deadbeef, version 1.2.3, src/a1.2.3.4b.rs";
+        assert_eq!(redact_addresses(text), text);
+    }
+}
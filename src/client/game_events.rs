@@ -8,7 +8,9 @@ use crate::{
     worker::Worker,
 };
 
-use base::{config::Config, filesys::FileSystem, io_batcher::IOBatcher, system::System};
+use base::{
+    config::Config, filesys::FileSystem, io_batcher::IOBatcher, log_println, system::System,
+};
 use network::network::{network::NetworkGameEvent, quinn_network::QuinnNetwork};
 
 use super::{client::Client, component::GameMsgPipeline};
@@ -43,8 +45,8 @@ impl GameEventsClient {
             for (con_id, timestamp_nanos, event) in &generator.events {
                 match &event {
                     GameEvents::NetworkEvent(net_ev) => match net_ev {
-                        NetworkGameEvent::Connected => {
-                            println!("connect time cl: {}", timestamp_nanos.as_nanos());
+                        NetworkGameEvent::Connected(_) => {
+                            log_println!("connect time cl: {}", timestamp_nanos.as_nanos());
                             pipe.client.client_data.cur_server = *con_id;
                             pipe.client.client_data.server_connect_time = *timestamp_nanos;
                             pipe.client
@@ -65,13 +67,11 @@ impl GameEventsClient {
                                 pipe.client.client_data.cur_server = Default::default();
                             }
                         }
-                        NetworkGameEvent::NetworkStats(_stats) => {
-                            /*println!(
-                                "ping: {}, inc latency: {}, out latency: {}",
-                                stats.ping.unwrap_or_default().as_millis(),
-                                stats.incoming_latency.unwrap_or_default().as_millis(),
-                                stats.outgoing_latency.unwrap_or_default().as_millis()
-                            );*/
+                        NetworkGameEvent::NetworkStats(stats) => {
+                            if *con_id == pipe.client.client_data.cur_server {
+                                pipe.client.client_data.ping = stats.ping;
+                                pipe.client.client_data.net_stats = stats.clone();
+                            }
                         }
                         _ => todo!(),
                     },
@@ -3,46 +3,95 @@ use crate::{game::simulation_pipe::ClientPlayer, ui::ui::UI};
 pub struct InputPipe<'a> {
     pub local_players: &'a mut [ClientPlayer; 4],
     pub ui: &'a mut UI,
+    pub config: &'a Config,
+    pub console: &'a mut Console,
 }
-use sdl2::keyboard::Scancode;
+use base::{config::Config, console::Console, input_actions::CLIENT_ACTIONS_NAMESPACE};
 
 use native::input::InputEventHandler;
+use sdl2::{controller::Button, mouse::MouseButton};
+
+/**
+ * The name of the mouse button as it is used in the binds
+ */
+fn mouse_input_name(btn: MouseButton) -> Option<&'static str> {
+    match btn {
+        MouseButton::Left => Some("mouse1"),
+        MouseButton::Right => Some("mouse2"),
+        MouseButton::Middle => Some("mouse3"),
+        MouseButton::X1 => Some("mouse4"),
+        MouseButton::X2 => Some("mouse5"),
+        MouseButton::Unknown => None,
+    }
+}
+
+/**
+ * The name of the gamepad button as it is used in the binds, e.g. "gamepad_a"
+ */
+fn gamepad_input_name(btn: Button) -> String {
+    format!("gamepad_{}", btn.string())
+}
 
 pub struct InputHandling<'a> {
     pub pipe: InputPipe<'a>,
 }
 
-impl<'a> InputEventHandler for InputHandling<'a> {
-    fn key_down(&mut self, _device: u32, key: sdl2::keyboard::Scancode) {
-        if key == Scancode::A {
-            self.pipe.local_players[0].input.dir = -1;
-        }
-        if key == Scancode::D {
-            self.pipe.local_players[0].input.dir = 1;
+impl<'a> InputHandling<'a> {
+    /**
+     * Keys, mouse and gamepad buttons all trigger the bound actions the same way
+     */
+    fn input_down(&mut self, input: &str) {
+        let actions = &self.pipe.config.cl_input_actions;
+        for action in actions.actions(CLIENT_ACTIONS_NAMESPACE, input) {
+            match action {
+                "left" => self.pipe.local_players[0].input.dir = -1,
+                "right" => self.pipe.local_players[0].input.dir = 1,
+                "jump" => self.pipe.local_players[0].input.jump = true,
+                _ => {}
+            }
         }
-        if key == Scancode::Space {
-            self.pipe.local_players[0].input.jump = true;
+        self.pipe.console.input_pressed(self.pipe.config, input);
+    }
+
+    fn input_up(&mut self, input: &str) {
+        let actions = &self.pipe.config.cl_input_actions;
+        for action in actions.actions(CLIENT_ACTIONS_NAMESPACE, input) {
+            match action {
+                "left" | "right" => self.pipe.local_players[0].input.dir = 0,
+                "jump" => self.pipe.local_players[0].input.jump = false,
+                _ => {}
+            }
         }
     }
+}
+
+impl<'a> InputEventHandler for InputHandling<'a> {
+    fn key_down(&mut self, _device: u32, key: sdl2::keyboard::Scancode) {
+        self.input_down(key.name());
+    }
 
     fn key_up(&mut self, _device: u32, key: sdl2::keyboard::Scancode) {
-        if key == Scancode::A {
-            self.pipe.local_players[0].input.dir = 0;
-        }
-        if key == Scancode::D {
-            self.pipe.local_players[0].input.dir = 0;
+        self.input_up(key.name());
+    }
+
+    fn mouse_down(&mut self, _device: u32, _x: i32, _y: i32, btn: MouseButton) {
+        if let Some(input) = mouse_input_name(btn) {
+            self.input_down(input);
         }
-        if key == Scancode::Space {
-            self.pipe.local_players[0].input.jump = false;
+    }
+
+    fn mouse_up(&mut self, _device: u32, _x: i32, _y: i32, btn: MouseButton) {
+        if let Some(input) = mouse_input_name(btn) {
+            self.input_up(input);
         }
     }
 
-    fn mouse_down(&mut self, _device: u32, _x: i32, _y: i32, _btn: sdl2::mouse::MouseButton) {
-        //
+    fn controller_button_down(&mut self, _device: u32, btn: Button) {
+        self.input_down(&gamepad_input_name(btn));
     }
 
-    fn mouse_up(&mut self, _device: u32, _x: i32, _y: i32, _btn: sdl2::mouse::MouseButton) {
-        //
+    fn controller_button_up(&mut self, _device: u32, btn: Button) {
+        self.input_up(&gamepad_input_name(btn));
     }
 
     fn mouse_move(&mut self, _device: u32, _x: i32, _y: i32, _xrel: i32, _yrel: i32) {
@@ -50,7 +99,9 @@ impl<'a> InputEventHandler for InputHandling<'a> {
     }
 
     fn raw_event(&mut self, window: &sdl2::video::Window, event: &sdl2::event::Event) -> bool {
-        if self.pipe.ui.ui_state.is_ui_open && !event.is_window() && event.get_window_id().is_some()
+        if self.pipe.ui.ui_state.is_ui_open
+            && !event.is_window()
+            && (event.get_window_id().is_some() || event.is_controller())
         {
             self.pipe
                 .ui
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+
+use crate::network::messages::MsgSvSystemMessage;
+
+/**
+ * The translated texts of the system messages by their id,
+ * parameters are written as `{0}`, `{1}`...
+ */
+#[derive(Default)]
+pub struct Localization {
+    texts: HashMap<String, String>,
+}
+
+impl Localization {
+    pub fn from_json(file: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            texts: serde_json::from_slice(file)?,
+        })
+    }
+
+    /**
+     * Messages without translation use the english text of the server
+     */
+    pub fn format(&self, msg: &MsgSvSystemMessage) -> String {
+        match self.texts.get(msg.id.as_str()) {
+            Some(text) => msg
+                .params
+                .iter()
+                .enumerate()
+                .fold(text.clone(), |text, (index, param)| {
+                    text.replace(&format!("{{{}}}", index), param.as_str())
+                }),
+            None => msg.fallback.as_str().to_string(),
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     client_map_buffered::ClientMapBuffered,
     datafile::CDatafileWrapper,
     game::state::GameStateInterface,
+    map_spatial::MapLayerSpatialIndex,
     mapdef::{
         CEnvPoint, CMapItemGroup, CMapItemLayerQuads, CMapItemLayerTilemap, CQuad, CSpeedupTile,
         CSwitchTile, CTeleTile, CTile, CTuneTile, LayerFlag, MapLayer, MapLayerTile, MapLayerTypes,
@@ -755,6 +756,7 @@ impl RenderMap {
         pQuadLayer: &CMapItemLayerQuads,
         pQuads: &Vec<CQuad>,
         _pGroup: &CMapItemGroup,
+        SpatialIndex: &MapLayerSpatialIndex,
         Force: bool,
     ) {
         let Visuals = &buffered_map.quad_layer_visuals[LayerIndex];
@@ -762,6 +764,31 @@ impl RenderMap {
             return; //no visuals were created
         }
 
+        // only the quads on the screen, in the order of the buffer
+        let QuadCount = (pQuadLayer.num_quads as usize).min(pQuads.len());
+        let QuadIndices: Vec<usize> = match SpatialIndex {
+            MapLayerSpatialIndex::Quads(Bvh) if !Force => {
+                let (mut ScreenX0, mut ScreenY0, mut ScreenX1, mut ScreenY1) = (0.0, 0.0, 0.0, 0.0);
+                state.get_canvas_mapping(
+                    &mut ScreenX0,
+                    &mut ScreenY0,
+                    &mut ScreenX1,
+                    &mut ScreenY1,
+                );
+                // the quad points are fixed point coordinates
+                let mut Res: Vec<usize> = Vec::new();
+                Bvh.query_rect(
+                    [(ScreenX0 * 1024.0) as i32, (ScreenY0 * 1024.0) as i32],
+                    [(ScreenX1 * 1024.0) as i32, (ScreenY1 * 1024.0) as i32],
+                    &mut Res,
+                );
+                Res.retain(|i| *i < QuadCount);
+                Res.sort_unstable();
+                Res
+            }
+            _ => (0..QuadCount).collect(),
+        };
+
         if !Force
         // TODO: && (!g_Config.m_ClShowQuads || g_Config.m_ClOverlayEntities == 100)) {
             && false
@@ -774,9 +801,26 @@ impl RenderMap {
         s_vQuadRenderInfo.resize(pQuadLayer.num_quads as usize, Default::default());
         let mut QuadsRenderCount = 0;
         let mut CurQuadOffset = 0;
-        for i in 0..pQuadLayer.num_quads as usize {
+        for i in QuadIndices {
             let pQuad = &pQuads[i];
 
+            // the quads in between were culled, so the batch ends here
+            if i != CurQuadOffset + QuadsRenderCount {
+                if QuadsRenderCount > 0 {
+                    s_vQuadRenderInfo.resize(QuadsRenderCount, Default::default());
+                    pipe.graphics.RenderQuadLayer(
+                        &state,
+                        &Visuals.buffer_container_index,
+                        s_vQuadRenderInfo.clone(),
+                        QuadsRenderCount,
+                        CurQuadOffset,
+                    );
+                    s_vQuadRenderInfo.resize(pQuadLayer.num_quads as usize, Default::default());
+                }
+                QuadsRenderCount = 0;
+                CurQuadOffset = i;
+            }
+
             let mut Color = ColorRGBA {
                 r: 1.0,
                 g: 1.0,
@@ -1324,6 +1368,7 @@ impl RenderMap {
                                         &pQLayer.0,
                                         &pQLayer.1,
                                         pGroup,
+                                        pipe.map.get_layer_spatial_index(layer_index),
                                         true,
                                     );
                                 } else {
@@ -1359,6 +1404,7 @@ impl RenderMap {
                                     &pQLayer.0,
                                     &pQLayer.1,
                                     pGroup,
+                                    pipe.map.get_layer_spatial_index(layer_index),
                                     false,
                                 );
                             } else {
@@ -3,11 +3,16 @@ pub mod client_map;
 pub mod client_map_buffered;
 mod component;
 mod components;
+mod console_commands;
+mod crash_report;
 mod game_events;
 mod image;
 mod input;
+mod localization;
 mod map;
+mod prediction;
 pub mod render;
 mod render_pipe;
 mod render_tools;
+pub mod server_browser;
 pub mod ui;
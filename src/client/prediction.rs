@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use base::config::Config;
+use math::math::{length, vector::vec2};
+
+// every predicted tick simulates the whole world again each frame,
+// so a high ping must not let the prediction grow without a limit
+const PREDICTION_MAX_AHEAD: Duration = Duration::from_secs(1);
+
+/**
+ * How the client predicts the own character,
+ * resolved from the config and the overrides of the current server
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictionSettings {
+    pub enabled: bool,
+    pub margin: Duration,
+    pub input_delay_ticks: u32,
+    pub smoothing_ticks: u32,
+}
+
+impl PredictionSettings {
+    /**
+     * Resolved every frame, so changes of the config apply directly
+     */
+    pub fn new(config: &Config, server_addr: Option<&str>) -> Self {
+        let server_override = server_addr
+            .and_then(|addr| config.cl_prediction_server_overrides.get(addr))
+            .cloned()
+            .unwrap_or_default();
+        Self {
+            enabled: server_override.prediction.unwrap_or(config.cl_prediction),
+            margin: Duration::from_millis(
+                server_override
+                    .margin_ms
+                    .unwrap_or(config.cl_prediction_margin_ms) as u64,
+            ),
+            input_delay_ticks: server_override
+                .input_delay_ticks
+                .unwrap_or(config.cl_prediction_input_delay_ticks),
+            smoothing_ticks: server_override
+                .smoothing_ticks
+                .unwrap_or(config.cl_prediction_smoothing_ticks),
+        }
+    }
+
+    /**
+     * How many ticks the own character is ahead of the last snapshot,
+     * the input delay shortens the prediction, so the own input shows up later.
+     * At most `PREDICTION_MAX_AHEAD` is predicted.
+     */
+    pub fn ticks_to_predict(&self, ping: Duration, tick_time: Duration) -> u64 {
+        if !self.enabled || tick_time.is_zero() {
+            return 0;
+        }
+        let ahead = ping + self.margin;
+        // rounded up, a too short prediction is worse than a too long one
+        let ticks = ((ahead.as_nanos() + tick_time.as_nanos() - 1) / tick_time.as_nanos()) as u64;
+        let max_ticks = (PREDICTION_MAX_AHEAD.as_nanos() / tick_time.as_nanos()) as u64;
+        ticks
+            .saturating_sub(self.input_delay_ticks as u64)
+            .min(max_ticks)
+    }
+}
+
+// larger corrections are teleports (e.g. a respawn), those are not smoothed
+const SMOOTHING_MAX_DISTANCE: f32 = 32.0 * 10.0;
+
+/**
+ * Spreads the corrections of the predicted position over a few ticks,
+ * so the character does not jump when a snapshot disagrees with the prediction.
+ * Only the correction is smoothed, without corrections the predicted position is rendered.
+ */
+#[derive(Default)]
+pub struct PredictionSmoothing {
+    render_pos: Option<vec2>,
+    // the predicted position of the last frame and the movement per tick since the frame before
+    last_pos: Option<(vec2, vec2)>,
+    // old predicted position minus the new one, it decays to zero
+    offset: vec2,
+    // a snapshot replaced the state that the prediction starts from
+    snapshot_received: bool,
+}
+
+impl PredictionSmoothing {
+    /**
+     * The next `update` compares the prediction with the one of the last frame
+     */
+    pub fn on_snapshot(&mut self) {
+        self.snapshot_received = true;
+    }
+
+    /**
+     * `ticks_passed` is the (fractional) amount of ticks since the last call,
+     * returns the position that should be rendered
+     */
+    pub fn update(&mut self, settings: &PredictionSettings, pos: vec2, ticks_passed: f32) -> vec2 {
+        let ticks_passed = ticks_passed.max(0.0);
+        if settings.smoothing_ticks == 0 {
+            self.offset = vec2::default();
+        } else {
+            let decay = (1.0 - ticks_passed / settings.smoothing_ticks as f32).clamp(0.0, 1.0);
+            self.offset = self.offset * decay;
+            if let (true, Some((last_pos, last_vel))) = (self.snapshot_received, self.last_pos) {
+                // where the old prediction would be now
+                let old_pos = last_pos + last_vel * ticks_passed;
+                self.offset = self.offset + (old_pos - pos);
+            }
+            if length(&self.offset) >= SMOOTHING_MAX_DISTANCE {
+                self.offset = vec2::default();
+            }
+        }
+        let vel = match self.last_pos {
+            // the movement of this frame contains the correction
+            Some((_, last_vel)) if self.snapshot_received || ticks_passed <= 0.0 => last_vel,
+            Some((last_pos, _)) => (pos - last_pos) * (1.0 / ticks_passed),
+            None => vec2::default(),
+        };
+        self.last_pos = Some((pos, vel));
+        self.snapshot_received = false;
+
+        let render_pos = pos + self.offset;
+        self.render_pos = Some(render_pos);
+        render_pos
+    }
+
+    /**
+     * The last returned position, `None` after a reset
+     */
+    pub fn render_pos(&self) -> Option<vec2> {
+        self.render_pos
+    }
+
+    /**
+     * On a (re)connect or a map change the old position is meaningless
+     */
+    pub fn reset(&mut self) {
+        self.render_pos = None;
+        self.last_pos = None;
+        self.offset = vec2::default();
+        self.snapshot_received = false;
+    }
+}
@@ -20,23 +20,23 @@ pub enum TeeEyeEmote {
 
 #[derive(Default)]
 pub struct SkinTextures {
-    body: ETextureIndex,
-    body_outline: ETextureIndex,
+    pub body: ETextureIndex,
+    pub body_outline: ETextureIndex,
 
-    left_foot: ETextureIndex,
-    left_foot_outline: ETextureIndex,
+    pub left_foot: ETextureIndex,
+    pub left_foot_outline: ETextureIndex,
 
-    right_foot: ETextureIndex,
-    right_foot_outline: ETextureIndex,
+    pub right_foot: ETextureIndex,
+    pub right_foot_outline: ETextureIndex,
 
-    left_hand_foot: ETextureIndex,
-    left_hand_outline: ETextureIndex,
+    pub left_hand_foot: ETextureIndex,
+    pub left_hand_outline: ETextureIndex,
 
-    right_hand_foot: ETextureIndex,
-    right_hand_outline: ETextureIndex,
+    pub right_hand_foot: ETextureIndex,
+    pub right_hand_outline: ETextureIndex,
 
-    left_eyes: [ETextureIndex; 9],
-    right_eyes: [ETextureIndex; 9],
+    pub left_eyes: [ETextureIndex; 9],
+    pub right_eyes: [ETextureIndex; 9],
 }
 
 pub enum TeeRenderSkinTextures {
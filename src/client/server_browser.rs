@@ -0,0 +1,264 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use arrayvec::ArrayString;
+use base::{
+    filesys::FileSystem,
+    io_batcher::{IOBatcher, IOBatcherTask},
+    log_println,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::network::lan_discovery::LanServerAnnouncement;
+
+const FAVORITES_FILE: &str = "favorites.json";
+
+/**
+ * The info of a server that the server itself reports
+ */
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerBrowserInfo {
+    pub name: String,
+    pub game_type: String,
+    pub map: String,
+    pub players: u32,
+    pub max_players: u32,
+    pub passworded: bool,
+}
+
+impl From<&LanServerAnnouncement> for ServerBrowserInfo {
+    fn from(announcement: &LanServerAnnouncement) -> Self {
+        Self {
+            name: announcement.name.as_str().to_string(),
+            game_type: announcement.game_type.as_str().to_string(),
+            map: announcement.map.as_str().to_string(),
+            players: announcement.players,
+            max_players: announcement.max_players,
+            passworded: announcement.passworded,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerBrowserServer {
+    pub address: String,
+    pub info: ServerBrowserInfo,
+    pub ping: Option<Duration>,
+    // when the server last announced itself
+    pub info_refreshed_at: Option<Duration>,
+    // found over the lan discovery
+    pub is_lan: bool,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ServerBrowserFilter {
+    // case insensitive, matched against the server name and the map
+    pub search: String,
+    pub game_type: Option<String>,
+    pub hide_empty: bool,
+    pub hide_full: bool,
+    pub hide_passworded: bool,
+    pub favorites_only: bool,
+    pub lan_only: bool,
+    pub max_ping: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ServerBrowserSorting {
+    #[default]
+    Name,
+    Map,
+    Players,
+    Ping,
+}
+
+/**
+ * The server list of the browser ui.
+ * Currently it is only filled by the lan discovery,
+ * there is no master server list yet.
+ */
+pub struct ServerBrowser {
+    servers: HashMap<String, ServerBrowserServer>,
+    favorites: BTreeSet<String>,
+
+    fs: Arc<FileSystem>,
+    io_batcher: Arc<Mutex<IOBatcher>>,
+    favorites_load_task: Option<IOBatcherTask<Vec<u8>>>,
+    favorites_write_task: Option<IOBatcherTask<()>>,
+    // changed since the last write was started
+    favorites_dirty: bool,
+}
+
+impl ServerBrowser {
+    pub fn new(fs: &Arc<FileSystem>, io_batcher: &Arc<Mutex<IOBatcher>>) -> Self {
+        let fs_clone = fs.clone();
+        let favorites_load_task = io_batcher.lock().unwrap().spawn(async move {
+            fs_clone.open_file(FAVORITES_FILE).await.map_err(|err| {
+                ArrayString::from(&format!("could not read {}: {}", FAVORITES_FILE, err))
+                    .unwrap_or_default()
+            })
+        });
+        Self {
+            servers: Default::default(),
+            favorites: Default::default(),
+
+            fs: fs.clone(),
+            io_batcher: io_batcher.clone(),
+            favorites_load_task: Some(favorites_load_task),
+            favorites_write_task: None,
+            favorites_dirty: false,
+        }
+    }
+
+    /**
+     * Finishes loading the favorites and starts writing changed favorites,
+     * should be called every frame
+     */
+    pub fn update(&mut self) {
+        if let Some(mut task) = self.favorites_load_task.take_if(|task| task.is_finished()) {
+            // a missing file means no favorites, favorites that were set while loading stay
+            if let Some(favorites) = task
+                .get_storage()
+                .ok()
+                .and_then(|file| serde_json::from_slice::<BTreeSet<String>>(&file).ok())
+            {
+                self.favorites.extend(favorites);
+            }
+        }
+        if let Some(mut task) = self.favorites_write_task.take_if(|task| task.is_finished()) {
+            if let Err(err) = task.get_storage() {
+                log_println!("{}", err);
+            }
+        }
+        // the file would be overwritten before it was loaded otherwise
+        if self.favorites_dirty
+            && self.favorites_load_task.is_none()
+            && self.favorites_write_task.is_none()
+        {
+            self.save_favorites();
+        }
+    }
+
+    /**
+     * A server announced itself in the local network
+     * Returns true if the server is new or its info changed
+     */
+    pub fn update_lan_server(
+        &mut self,
+        address: &str,
+        info: ServerBrowserInfo,
+        cur_time: Duration,
+    ) -> bool {
+        let server =
+            self.servers
+                .entry(address.to_string())
+                .or_insert_with(|| ServerBrowserServer {
+                    address: address.to_string(),
+                    info: Default::default(),
+                    ping: None,
+                    info_refreshed_at: None,
+                    is_lan: true,
+                });
+        let changed = !server.is_lan || server.info != info;
+        server.info = info;
+        server.info_refreshed_at = Some(cur_time);
+        server.is_lan = true;
+        changed
+    }
+
+    /**
+     * Removes the lan servers that did not announce themselves for `timeout`
+     * Returns true if any server was removed
+     */
+    pub fn remove_outdated_lan_servers(&mut self, cur_time: Duration, timeout: Duration) -> bool {
+        let server_count = self.servers.len();
+        self.servers.retain(|_, server| {
+            !server.is_lan
+                || server.info_refreshed_at.map_or(false, |refreshed_at| {
+                    cur_time.saturating_sub(refreshed_at) < timeout
+                })
+        });
+        self.servers.len() != server_count
+    }
+
+    pub fn servers(
+        &self,
+        filter: &ServerBrowserFilter,
+        sorting: ServerBrowserSorting,
+    ) -> Vec<&ServerBrowserServer> {
+        let search = filter.search.to_lowercase();
+        let mut res: Vec<&ServerBrowserServer> = self
+            .servers
+            .values()
+            .filter(|server| {
+                let info = &server.info;
+                (search.is_empty()
+                    || info.name.to_lowercase().contains(&search)
+                    || info.map.to_lowercase().contains(&search))
+                    && filter
+                        .game_type
+                        .as_ref()
+                        .map_or(true, |game_type| *game_type == info.game_type)
+                    && (!filter.hide_empty || info.players > 0)
+                    && (!filter.hide_full || info.players < info.max_players)
+                    && (!filter.hide_passworded || !info.passworded)
+                    && (!filter.favorites_only || self.favorites.contains(&server.address))
+                    && (!filter.lan_only || server.is_lan)
+                    && filter.max_ping.map_or(true, |max_ping| {
+                        server.ping.map_or(false, |ping| ping <= max_ping)
+                    })
+            })
+            .collect();
+        match sorting {
+            ServerBrowserSorting::Name => res.sort_by(|a, b| a.info.name.cmp(&b.info.name)),
+            ServerBrowserSorting::Map => res.sort_by(|a, b| a.info.map.cmp(&b.info.map)),
+            ServerBrowserSorting::Players => {
+                res.sort_by(|a, b| b.info.players.cmp(&a.info.players))
+            }
+            // servers without a ping are last
+            ServerBrowserSorting::Ping => {
+                res.sort_by_key(|server| server.ping.unwrap_or(Duration::MAX))
+            }
+        }
+        res
+    }
+
+    pub fn is_favorite(&self, address: &str) -> bool {
+        self.favorites.contains(address)
+    }
+
+    pub fn set_favorite(&mut self, address: &str, favorite: bool) {
+        if favorite {
+            self.favorites.insert(address.to_string());
+        } else {
+            self.favorites.remove(address);
+        }
+        self.favorites_dirty = true;
+    }
+
+    fn save_favorites(&mut self) {
+        self.favorites_dirty = false;
+        let Ok(file) = serde_json::to_vec_pretty(&self.favorites) else {
+            return;
+        };
+        let fs = self.fs.clone();
+        self.favorites_write_task = Some(self.io_batcher.lock().unwrap().spawn(async move {
+            fs.write_file(FAVORITES_FILE, file).await.map_err(|err| {
+                ArrayString::from(&format!("could not write {}: {}", FAVORITES_FILE, err))
+                    .unwrap_or_default()
+            })
+        }));
+    }
+}
+
+impl Drop for ServerBrowser {
+    fn drop(&mut self) {
+        // the write finishes in the background, the client finishes all io tasks before it closes
+        if self.favorites_dirty && self.favorites_load_task.is_none() {
+            self.save_favorites();
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use base::{
+    filesys::FileSystem,
+    io_batcher::{IOBatcher, IOBatcherTask},
+};
+use egui::{FontData, FontDefinitions, FontFamily};
+
+// the fonts in this directory are used (sorted by their file name) for all glyphs
+// that the default fonts don't have, e.g. CJK or more emojis
+const FALLBACK_FONTS_DIR: &str = "fonts/fallback/";
+
+/**
+ * Loads the fallback fonts of the ui in the background,
+ * until they are loaded the ui renders with egui's default fonts.
+ */
+pub struct UIFontFallbacks {
+    load_task: Option<IOBatcherTask<Vec<(String, Vec<u8>)>>>,
+}
+
+impl UIFontFallbacks {
+    pub fn new(fs: &Arc<FileSystem>, batcher: &Arc<std::sync::Mutex<IOBatcher>>) -> Self {
+        let fs = fs.clone();
+        let load_task = batcher
+            .lock()
+            .unwrap()
+            .spawn::<Vec<(String, Vec<u8>)>, _>(async move {
+                let mut fonts: Vec<(String, Vec<u8>)> = Vec::new();
+                fs.files_of_dir(FALLBACK_FONTS_DIR, &mut |file_name, file| {
+                    if file_name.ends_with(".ttf")
+                        || file_name.ends_with(".otf")
+                        || file_name.ends_with(".ttc")
+                    {
+                        fonts.push((file_name, file));
+                    }
+                })
+                .await;
+                fonts.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+                Ok(fonts)
+            });
+        Self {
+            load_task: Some(load_task),
+        }
+    }
+
+    /**
+     * Installs the fallback fonts once they are loaded,
+     * returns true if the fonts changed
+     */
+    pub fn update(&mut self, egui_ctx: &egui::Context) -> bool {
+        let is_finished = self
+            .load_task
+            .as_ref()
+            .map_or(false, |task| task.is_finished());
+        if !is_finished {
+            return false;
+        }
+        let Ok(fonts) = self.load_task.take().unwrap().get_storage() else {
+            return false;
+        };
+        if fonts.is_empty() {
+            return false;
+        }
+        let mut font_defs = FontDefinitions::default();
+        for (font_name, font) in fonts {
+            font_defs
+                .font_data
+                .insert(font_name.clone(), FontData::from_owned(font));
+            // behind the default fonts, so they are only used for missing glyphs
+            for family in [FontFamily::Proportional, FontFamily::Monospace] {
+                font_defs
+                    .families
+                    .entry(family)
+                    .or_default()
+                    .push(font_name.clone());
+            }
+        }
+        egui_ctx.set_fonts(font_defs);
+        true
+    }
+}
@@ -1,3 +1,4 @@
+pub mod fonts;
 pub mod pages;
 pub mod types;
 pub mod ui;
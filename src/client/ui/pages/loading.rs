@@ -0,0 +1,9 @@
+/**
+ * Shown while the map of the server loads, `progress` is between 0.0 and 1.0
+ */
+pub fn loading_page(ui: &mut egui::Ui, progress: f32) {
+    ui.vertical_centered(|ui| {
+        ui.label("Loading map");
+        ui.add(egui::ProgressBar::new(progress).show_percentage());
+    });
+}
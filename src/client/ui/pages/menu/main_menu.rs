@@ -1,18 +1,39 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base::{filesys::FileSystem, io_batcher::IOBatcher};
 use graphics::graphics::Graphics;
 use network::network::quinn_network::QuinnNetwork;
 
-use crate::ui::{
-    pages::{
-        demo::demo_page,
-        editor::tee::{TeeEditor, TeeEditorPipe},
-        test::ColorTest,
+use crate::{
+    client::server_browser::{
+        ServerBrowser, ServerBrowserFilter, ServerBrowserInfo, ServerBrowserSorting,
+    },
+    network::lan_discovery::LanDiscoveryListener,
+    ui::{
+        pages::{
+            demo::demo_page,
+            editor::tee::{TeeEditor, TeeEditorPipe},
+            test::ColorTest,
+        },
+        types::{UIFeedbackInterface, UIPipe, UIState},
     },
-    types::{UIFeedbackInterface, UIPipe, UIState},
 };
 
+// servers announce themselves about once per second
+const LAN_SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct MainMenu {
     // attributes
     connect_addr: String,
+    // why the client left the last server on its own
+    disconnect_reason: Option<String>,
+
+    server_browser: ServerBrowser,
+    // `None` if another client on this machine already listens
+    lan_listener: Option<LanDiscoveryListener>,
 
     tee_editor: TeeEditor,
 
@@ -20,15 +41,50 @@ pub struct MainMenu {
 }
 
 impl MainMenu {
-    pub fn new(graphics: &mut Graphics) -> Self {
+    pub fn new(
+        graphics: &mut Graphics,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+    ) -> Self {
         Self {
             connect_addr: "127.0.0.1:8305".to_string(),
+            disconnect_reason: None,
+
+            server_browser: ServerBrowser::new(fs, io_batcher),
+            lan_listener: LanDiscoveryListener::new().ok(),
 
             tee_editor: TeeEditor::new(graphics),
             color_test: ColorTest::default(),
         }
     }
 
+    /**
+     * Updates the content that changes without any input (e.g. the lan servers or the favorites),
+     * must be called every frame, even while the ui is not rendered.
+     * Returns true if the ui has to be rendered again
+     */
+    pub fn update(&mut self, cur_time: Duration) -> bool {
+        let mut changed = false;
+        self.server_browser.update();
+        if let Some(listener) = &self.lan_listener {
+            for (addr, announcement) in listener.poll() {
+                changed |= self.server_browser.update_lan_server(
+                    &addr.to_string(),
+                    ServerBrowserInfo::from(&announcement),
+                    cur_time,
+                );
+            }
+        }
+        changed |= self
+            .server_browser
+            .remove_outdated_lan_servers(cur_time, LAN_SERVER_TIMEOUT);
+        changed
+    }
+
+    pub fn set_disconnect_reason(&mut self, reason: String) {
+        self.disconnect_reason = Some(reason);
+    }
+
     pub fn render_func(&mut self, ui: &mut egui::Ui, pipe: &mut UIPipe, ui_state: &mut UIState) {
         match pipe.config.ui_path.name.as_str() {
             "" => {
@@ -44,8 +100,12 @@ impl MainMenu {
                     pipe.config.ui_path.route("demo");
                     pipe.config.save();
                 }
+                if let Some(reason) = &self.disconnect_reason {
+                    ui.label(format!("Disconnected: {}", reason));
+                }
                 if ui.button("Connect to server").clicked() {
                     pipe.ui_feedback.network_connect(&self.connect_addr);
+                    self.disconnect_reason = None;
                     ui_state.is_ui_open = false;
                 }
                 if ui.button("Disconnect from server").clicked() {
@@ -55,6 +115,30 @@ impl MainMenu {
                     ui.label("Server addr: ");
                     ui.text_edit_singleline(&mut self.connect_addr);
                 });
+
+                ui.label("LAN servers:");
+                let filter = ServerBrowserFilter {
+                    lan_only: true,
+                    ..Default::default()
+                };
+                for server in self
+                    .server_browser
+                    .servers(&filter, ServerBrowserSorting::Name)
+                {
+                    let label = format!(
+                        "{} - {} ({}) {}/{}",
+                        server.info.name,
+                        server.info.map,
+                        server.info.game_type,
+                        server.info.players,
+                        server.info.max_players
+                    );
+                    if ui.button(label).clicked() {
+                        self.connect_addr = server.address.clone();
+                        pipe.ui_feedback.network_connect(&server.address);
+                        ui_state.is_ui_open = false;
+                    }
+                }
             }
             "editor/tee" => {
                 self.tee_editor.tee_editor_page(
@@ -80,17 +164,22 @@ impl MainMenu {
 
 pub struct MainMenuUIFeedback<'a> {
     network: &'a mut QuinnNetwork,
+    server_addr: &'a mut Option<String>,
 }
 
 impl<'a> MainMenuUIFeedback<'a> {
-    pub fn new(network: &'a mut QuinnNetwork) -> Self {
-        Self { network: network }
+    pub fn new(network: &'a mut QuinnNetwork, server_addr: &'a mut Option<String>) -> Self {
+        Self {
+            network: network,
+            server_addr,
+        }
     }
 }
 
 impl<'a> UIFeedbackInterface for MainMenuUIFeedback<'a> {
     fn network_connect(&mut self, addr: &str) {
         self.network.connect(addr);
+        *self.server_addr = Some(addr.to_string());
     }
 
     fn network_disconnect(&mut self) {
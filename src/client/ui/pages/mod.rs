@@ -1,4 +1,5 @@
 pub mod demo;
 pub mod editor;
+pub mod loading;
 pub mod menu;
 pub mod test;
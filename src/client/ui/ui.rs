@@ -1,18 +1,21 @@
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use bincode::Encode;
 use egui::{
     epaint::{
         self,
         ahash::{HashMap, HashMapExt},
-        Primitive,
+        ClippedPrimitive, Primitive,
     },
     Color32, ImageData, TextureId,
 };
 use graphics_base::streaming::DrawScopeImpl;
 use rayon::prelude::{IndexedParallelIterator, ParallelIterator};
 
-use base::system::SystemTimeInterface;
+use base::{filesys::FileSystem, io_batcher::IOBatcher, system::SystemTimeInterface};
 
 use math::math::vector::vec2;
 
@@ -23,7 +26,10 @@ use graphics_types::{
     types::ImageFormat,
 };
 
-use super::types::{UIPipe, UIState};
+use super::{
+    fonts::UIFontFallbacks,
+    types::{UIPipe, UIState},
+};
 
 /**
  * UI is not a client component, it should be cleanly separated from any game logic (but can read it)
@@ -35,6 +41,16 @@ pub struct UI {
     draw_ranges: Vec<(usize, usize, usize, TextureId, egui::Rect)>,
     mesh_index_offsets: Vec<usize>,
 
+    // the output of the last frame egui actually ran, reused while nothing changed
+    cached_primitives: Vec<ClippedPrimitive>,
+    last_screen_rect: egui::Rect,
+    // egui's own repaint request (e.g. animations, blinking cursor)
+    next_repaint_time: Duration,
+    is_idle: bool,
+    last_render_time: Duration,
+
+    font_fallbacks: Option<UIFontFallbacks>,
+
     pub ui_state: UIState,
 
     pub main_panel_color: Color32,
@@ -52,6 +68,14 @@ impl UI {
             draw_ranges: Vec::new(),
             mesh_index_offsets: Vec::new(),
 
+            cached_primitives: Vec::new(),
+            last_screen_rect: egui::Rect::NOTHING,
+            next_repaint_time: Duration::ZERO,
+            is_idle: false,
+            last_render_time: Duration::ZERO,
+
+            font_fallbacks: None,
+
             main_panel_color: Color32::TRANSPARENT,
         };
         let vis = egui::style::Visuals::dark();
@@ -59,10 +83,66 @@ impl UI {
         res
     }
 
+    /**
+     * Starts loading the fallback fonts (CJK etc.) in the background
+     */
+    pub fn load_fonts(&mut self, fs: &Arc<FileSystem>, batcher: &Arc<Mutex<IOBatcher>>) {
+        self.font_fallbacks = Some(UIFontFallbacks::new(fs, batcher));
+    }
+
     pub fn set_main_panel_color(&mut self, main_panel_color: &Color32) {
         self.main_panel_color = *main_panel_color;
     }
 
+    /**
+     * Runs the ui again in the next frame,
+     * for content that changes without any input (e.g. a loaded server list)
+     */
+    pub fn request_repaint(&mut self) {
+        self.next_repaint_time = Duration::ZERO;
+    }
+
+    fn screen_rect(&self, canvas_width: u32, canvas_height: u32) -> egui::Rect {
+        egui::Rect {
+            min: egui::Pos2 { x: 0.0, y: 0.0 },
+            max: egui::Pos2 {
+                x: canvas_width as f32 / self.ui_state.zoom_level,
+                y: canvas_height as f32 / self.ui_state.zoom_level,
+            },
+        }
+    }
+
+    /**
+     * Collects the input of this frame, must be called before `render`.
+     * Without input, resize or repaint request the ui looks exactly like in the last frame,
+     * then it returns false and the caller can skip the frame (e.g. in the menu)
+     */
+    pub fn begin_frame(
+        &mut self,
+        canvas_width: u32,
+        canvas_height: u32,
+        cur_time: Duration,
+    ) -> bool {
+        if let Some(font_fallbacks) = &mut self.font_fallbacks {
+            if font_fallbacks.update(&self.egui_ctx) {
+                // the cached output still uses the old fonts
+                self.next_repaint_time = Duration::ZERO;
+            }
+        }
+
+        let screen_rect = self.screen_rect(canvas_width, canvas_height);
+        self.ui_state.sdl2_state.update_gamepad_cursor(
+            cur_time.saturating_sub(self.last_render_time).as_secs_f32(),
+            screen_rect,
+        );
+        self.last_render_time = cur_time;
+
+        self.is_idle = self.ui_state.sdl2_state.raw_input.events.is_empty()
+            && screen_rect == self.last_screen_rect
+            && cur_time < self.next_repaint_time;
+        !self.is_idle
+    }
+
     fn main_panel(main_panel_color: &Color32) -> egui::CentralPanel {
         let standard_frame = egui::containers::Frame {
             inner_margin: egui::style::Margin {
@@ -98,29 +178,33 @@ impl UI {
         let canvas_width = pipe.graphics.canvas_width();
         let canvas_height = pipe.graphics.canvas_height();
 
+        // the ui did not change, but the rest of the frame (e.g. the game) is rendered again
+        if self.is_idle {
+            let clipped_primitives = std::mem::take(&mut self.cached_primitives);
+            self.draw_primitives(&clipped_primitives, canvas_width, canvas_height, pipe);
+            self.cached_primitives = clipped_primitives;
+            return;
+        }
+
         // Gather input (mouse, touches, keyboard, screen size, etc):
         let mut raw_input: egui::RawInput = egui::RawInput::default();
-        let screen_rect = egui::Rect {
-            min: egui::Pos2 { x: 0.0, y: 0.0 },
-            max: egui::Pos2 {
-                x: canvas_width as f32 / self.ui_state.zoom_level,
-                y: canvas_height as f32 / self.ui_state.zoom_level,
-            },
-        };
+        let screen_rect = self.screen_rect(canvas_width, canvas_height);
         raw_input.screen_rect = Some(screen_rect);
         raw_input.pixels_per_point = Some(self.ui_state.zoom_level);
-        let cur_time_secs = pipe.sys.time_get_nanoseconds().as_nanos() as f64
-            / (Duration::from_secs(1).as_nanos() as f64);
+        let cur_time = pipe.sys.time_get_nanoseconds();
+        let cur_time_secs = cur_time.as_nanos() as f64 / (Duration::from_secs(1).as_nanos() as f64);
         raw_input.time = Some(cur_time_secs);
         std::mem::swap(
             &mut raw_input.events,
             &mut self.ui_state.sdl2_state.raw_input.events,
         );
+        self.last_screen_rect = screen_rect;
 
         let full_output = self.egui_ctx.run(raw_input, |egui_ctx| {
             Self::main_panel(&self.main_panel_color)
                 .show(egui_ctx, |ui| render_func(ui, pipe, &mut self.ui_state));
         });
+        self.next_repaint_time = cur_time.saturating_add(full_output.repaint_after);
 
         full_output
             .textures_delta
@@ -189,6 +273,38 @@ impl UI {
             });
 
         let clipped_primitives = self.egui_ctx.tessellate(full_output.shapes); // creates triangles to paint
+        self.draw_primitives(&clipped_primitives, canvas_width, canvas_height, pipe);
+        self.cached_primitives = clipped_primitives;
+
+        // we delete textures now, so any kind of drawing has to be finished
+        full_output.textures_delta.free.iter().for_each(|tex_id| {
+            let tex = self.textures.get_mut(tex_id);
+            if let Some(tex) = tex {
+                pipe.graphics.unload_texture(tex);
+
+                self.textures.remove(tex_id);
+            }
+        });
+
+        self.ui_state
+            .sdl2_state
+            .process_output(pipe.graphics.borrow_window(), &full_output.platform_output);
+    }
+
+    fn draw_primitives(
+        &mut self,
+        clipped_primitives: &Vec<ClippedPrimitive>,
+        canvas_width: u32,
+        canvas_height: u32,
+        pipe: &mut UIPipe,
+    ) {
+        let screen_rect = egui::Rect {
+            min: egui::Pos2 { x: 0.0, y: 0.0 },
+            max: egui::Pos2 {
+                x: canvas_width as f32 / self.ui_state.zoom_level,
+                y: canvas_height as f32 / self.ui_state.zoom_level,
+            },
+        };
 
         let mut last_tex_index = TextureId::default();
         let mut last_clip_rect = egui::Rect::NOTHING;
@@ -311,19 +427,5 @@ impl UI {
                     });
             },
         );
-
-        // we delete textures now, so any kind of drawing has to be finished
-        full_output.textures_delta.free.iter().for_each(|tex_id| {
-            let tex = self.textures.get_mut(tex_id);
-            if let Some(tex) = tex {
-                pipe.graphics.unload_texture(tex);
-
-                self.textures.remove(tex_id);
-            }
-        });
-
-        self.ui_state
-            .sdl2_state
-            .process_output(pipe.graphics.borrow_window(), &full_output.platform_output);
     }
 }
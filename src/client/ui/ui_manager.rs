@@ -1,20 +1,105 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base::{
+    filesys::FileSystem,
+    filesys_watcher::{FileSystemEventKind, FileSystemWatcher, FileSystemWatcherSettings},
+    io_batcher::IOBatcher,
+    log_println,
+};
 use graphics::graphics::Graphics;
-use wasm_runtime::WasmManager;
+use wasm_runtime::{WasmManager, WasmStateRequest};
+
+use crate::client::crash_report::crash_report_add_wasm_mod;
+
+// the ui module is rebuilt into this directory
+const UI_MODULE_DIR: &str = "target/wasm32-unknown-unknown/debug";
+const UI_MODULE_FILE: &str = "ui.wasm";
+const UI_MODULE_WATCH_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct UIManager {
     manager: WasmManager,
+    fs: Arc<FileSystem>,
+    io_batcher: Arc<Mutex<IOBatcher>>,
+
+    // reloads the module after it was rebuilt
+    watcher: FileSystemWatcher,
+    last_watch_time: Instant,
 }
 
 impl UIManager {
-    pub fn new() -> Self {
+    pub fn new(fs: &Arc<FileSystem>, io_batcher: &Arc<Mutex<IOBatcher>>) -> Self {
         let wasm_bytes = include_bytes!("../../../target/wasm32-unknown-unknown/debug/ui.wasm");
 
-        let mut manager = WasmManager::new(wasm_bytes).unwrap();
+        crash_report_add_wasm_mod("ui", wasm_bytes);
+        let manager =
+            WasmManager::new(wasm_bytes, "ui", Default::default(), fs, io_batcher).unwrap();
+
+        Self {
+            manager,
+            fs: fs.clone(),
+            io_batcher: io_batcher.clone(),
 
-        Self { manager }
+            watcher: FileSystemWatcher::new(
+                UI_MODULE_DIR,
+                FileSystemWatcherSettings {
+                    recursive: false,
+                    extensions: vec!["wasm".to_string()],
+                    ..Default::default()
+                },
+            ),
+            last_watch_time: Instant::now(),
+        }
     }
 
-    pub fn run(&mut self, graphics: &mut Graphics) {
+    /**
+     * Replaces the module if it was rebuilt, a broken module keeps the old one running
+     */
+    fn reload_if_rebuilt(&mut self) {
+        if self.last_watch_time.elapsed() < UI_MODULE_WATCH_INTERVAL {
+            return;
+        }
+        self.last_watch_time = Instant::now();
+        let rebuilt = self.watcher.poll().into_iter().any(|ev| {
+            ev.kind != FileSystemEventKind::Removed
+                && ev.path.file_name() == Some(UI_MODULE_FILE.as_ref())
+        });
+        if !rebuilt {
+            return;
+        }
+        let path = Path::new(UI_MODULE_DIR).join(UI_MODULE_FILE);
+        let res = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|wasm_bytes| {
+                let manager = WasmManager::new(
+                    &wasm_bytes,
+                    "ui",
+                    Default::default(),
+                    &self.fs,
+                    &self.io_batcher,
+                )?;
+                crash_report_add_wasm_mod("ui", &wasm_bytes);
+                Ok(manager)
+            });
+        match res {
+            Ok(manager) => {
+                self.manager = manager;
+                log_println!("reloaded the ui module");
+            }
+            Err(err) => log_println!("failed to reload the ui module: {}", err),
+        }
+    }
+
+    pub fn run(&mut self, graphics: &mut Graphics, game_paused: bool) {
+        self.reload_if_rebuilt();
+        self.manager.set_state_paused(game_paused);
         self.manager.run(graphics).unwrap();
     }
+
+    pub fn take_state_requests(&self) -> Vec<WasmStateRequest> {
+        self.manager.take_state_requests()
+    }
 }
@@ -0,0 +1,456 @@
+use std::{collections::HashMap, hash::Hash, time::Duration};
+
+use base::{filesys::FileSystem, log_println};
+use serde::{Deserialize, Serialize};
+
+const FLOOD_PROTECTION_FILE: &str = "flood_protection.json";
+
+/**
+ * The kinds of client messages that are rate limited independently
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloodMessageKind {
+    Chat = 0,
+    Vote,
+    InfoRequest,
+    JoinAttempt,
+    // full snapshots after a desync
+    Resync,
+}
+
+impl FloodMessageKind {
+    pub const COUNT: usize = 5;
+    pub const ALL: [Self; Self::COUNT] = [
+        Self::Chat,
+        Self::Vote,
+        Self::InfoRequest,
+        Self::JoinAttempt,
+        Self::Resync,
+    ];
+
+    /**
+     * Chat, votes and resyncs come from a player, so they are limited per connection
+     * and players behind the same ip don't share their limits.
+     * Join attempts and info requests are limited per ip, a new connection
+     * must not reset them.
+     */
+    pub fn is_per_connection(&self) -> bool {
+        match self {
+            Self::Chat | Self::Vote | Self::Resync => true,
+            Self::InfoRequest | Self::JoinAttempt => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FloodLimit {
+    // messages per second that are allowed on average
+    pub rate: f64,
+    // messages that are allowed at once
+    pub burst: f64,
+    // limited messages in a row until the client is muted for this kind,
+    // 0 means never
+    #[serde(default)]
+    pub violations_until_mute: u32,
+    #[serde(default)]
+    pub mute_secs: u64,
+    // mutes until the client is banned, 0 means never
+    #[serde(default)]
+    pub mutes_until_ban: u32,
+    #[serde(default)]
+    pub ban_secs: u64,
+}
+
+impl FloodLimit {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            violations_until_mute: 0,
+            mute_secs: 0,
+            mutes_until_ban: 0,
+            ban_secs: 0,
+        }
+    }
+}
+
+/**
+ * Kinds that are missing in the file keep their default limit
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FloodProtectionConfig {
+    pub chat: FloodLimit,
+    pub vote: FloodLimit,
+    pub info_request: FloodLimit,
+    pub join_attempt: FloodLimit,
+    pub resync: FloodLimit,
+}
+
+impl Default for FloodProtectionConfig {
+    fn default() -> Self {
+        Self {
+            chat: FloodLimit {
+                violations_until_mute: 5,
+                mute_secs: 30,
+                mutes_until_ban: 3,
+                ban_secs: 5 * 60,
+                ..FloodLimit::new(1.0, 5.0)
+            },
+            vote: FloodLimit {
+                violations_until_mute: 3,
+                mute_secs: 60,
+                ..FloodLimit::new(1.0 / 10.0, 2.0)
+            },
+            info_request: FloodLimit::new(2.0, 10.0),
+            join_attempt: FloodLimit {
+                violations_until_mute: 5,
+                mute_secs: 10,
+                mutes_until_ban: 3,
+                ban_secs: 60,
+                ..FloodLimit::new(1.0, 3.0)
+            },
+            // at most one every 5 seconds
+            resync: FloodLimit::new(1.0 / 5.0, 1.0),
+        }
+    }
+}
+
+impl FloodProtectionConfig {
+    /**
+     * A missing or broken file gives the default limits
+     */
+    pub async fn load(fs: &FileSystem) -> Self {
+        match fs.open_file(FLOOD_PROTECTION_FILE).await {
+            Ok(file) => serde_json::from_slice(&file).unwrap_or_else(|err| {
+                log_println!("the flood protection config is invalid: {}", err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn limit(&self, kind: FloodMessageKind) -> &FloodLimit {
+        match kind {
+            FloodMessageKind::Chat => &self.chat,
+            FloodMessageKind::Vote => &self.vote,
+            FloodMessageKind::InfoRequest => &self.info_request,
+            FloodMessageKind::JoinAttempt => &self.join_attempt,
+            FloodMessageKind::Resync => &self.resync,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodCheckResult {
+    Allowed,
+    // drop this message
+    Limited,
+    // all messages of this kind are dropped until the given time
+    Muted(Duration),
+    // the client should be disconnected, all messages are dropped until the given time
+    Banned(Duration),
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FloodBucket {
+    tokens: f64,
+    last_update: Option<Duration>,
+    violations: u32,
+    mutes: u32,
+    muted_until: Duration,
+}
+
+#[derive(Debug, Default)]
+struct FloodClient {
+    buckets: [FloodBucket; FloodMessageKind::COUNT],
+    banned_until: Duration,
+}
+
+/**
+ * Token bucket rate limiting per client and message kind,
+ * clients that keep flooding are muted and finally banned temporarily.
+ * The key should identify the client (e.g. the connection or the ip),
+ * see [`FloodMessageKind::is_per_connection`].
+ */
+pub struct FloodProtection<K: Hash + Eq + Clone> {
+    config: FloodProtectionConfig,
+    clients: HashMap<K, FloodClient>,
+}
+
+impl<K: Hash + Eq + Clone> FloodProtection<K> {
+    pub fn new(config: FloodProtectionConfig) -> Self {
+        Self {
+            config,
+            clients: HashMap::new(),
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        key: &K,
+        kind: FloodMessageKind,
+        cur_time: Duration,
+    ) -> FloodCheckResult {
+        let client = self.clients.entry(key.clone()).or_default();
+        if client.banned_until > cur_time {
+            return FloodCheckResult::Banned(client.banned_until);
+        }
+        let limit = self.config.limit(kind);
+        let bucket = &mut client.buckets[kind as usize];
+        if bucket.muted_until > cur_time {
+            return FloodCheckResult::Muted(bucket.muted_until);
+        }
+
+        let passed = bucket.last_update.map_or(limit.burst, |last_update| {
+            cur_time.saturating_sub(last_update).as_secs_f64() * limit.rate
+        });
+        bucket.tokens = (bucket.tokens + passed).min(limit.burst);
+        bucket.last_update = Some(cur_time);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.violations = 0;
+            return FloodCheckResult::Allowed;
+        }
+
+        bucket.violations += 1;
+        if limit.violations_until_mute == 0 || bucket.violations < limit.violations_until_mute {
+            return FloodCheckResult::Limited;
+        }
+        bucket.violations = 0;
+        bucket.mutes += 1;
+        if limit.mutes_until_ban != 0 && bucket.mutes >= limit.mutes_until_ban {
+            bucket.mutes = 0;
+            client.banned_until = cur_time + Duration::from_secs(limit.ban_secs);
+            return FloodCheckResult::Banned(client.banned_until);
+        }
+        bucket.muted_until = cur_time + Duration::from_secs(limit.mute_secs);
+        FloodCheckResult::Muted(bucket.muted_until)
+    }
+
+    /**
+     * Bans the client until the given time, e.g. the ip of a connection that was banned
+     */
+    pub fn ban(&mut self, key: &K, until: Duration) {
+        let client = self.clients.entry(key.clone()).or_default();
+        client.banned_until = client.banned_until.max(until);
+    }
+
+    pub fn banned_until(&self, key: &K, cur_time: Duration) -> Option<Duration> {
+        self.clients
+            .get(key)
+            .map(|client| client.banned_until)
+            .filter(|banned_until| *banned_until > cur_time)
+    }
+
+    /**
+     * Forgets the client, e.g. a connection that was closed
+     */
+    pub fn remove(&mut self, key: &K) {
+        self.clients.remove(key);
+    }
+
+    /**
+     * Forgets the clients that are neither muted nor banned anymore
+     * and whose buckets are full again
+     */
+    pub fn remove_outdated(&mut self, cur_time: Duration) {
+        let config = &self.config;
+        self.clients.retain(|_, client| {
+            client.banned_until > cur_time
+                || client
+                    .buckets
+                    .iter()
+                    .zip(FloodMessageKind::ALL)
+                    .any(|(bucket, kind)| {
+                        let limit = config.limit(kind);
+                        bucket.muted_until > cur_time
+                            || bucket.last_update.map_or(false, |last_update| {
+                                let refill = Duration::try_from_secs_f64(limit.burst / limit.rate)
+                                    .unwrap_or(Duration::MAX);
+                                cur_time.saturating_sub(last_update) < refill
+                            })
+                    })
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{
+        FloodCheckResult, FloodLimit, FloodMessageKind, FloodProtection, FloodProtectionConfig,
+    };
+
+    fn chat_config(limit: FloodLimit) -> FloodProtectionConfig {
+        FloodProtectionConfig {
+            chat: limit,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn token_bucket_refill() {
+        let mut flood = FloodProtection::new(chat_config(FloodLimit::new(2.0, 3.0)));
+        let start = Duration::from_secs(10);
+        for _ in 0..3 {
+            assert_eq!(
+                flood.check(&0, FloodMessageKind::Chat, start),
+                FloodCheckResult::Allowed
+            );
+        }
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, start),
+            FloodCheckResult::Limited
+        );
+        // other kinds and clients have their own buckets
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Vote, start),
+            FloodCheckResult::Allowed
+        );
+        assert_eq!(
+            flood.check(&1, FloodMessageKind::Chat, start),
+            FloodCheckResult::Allowed
+        );
+
+        // 2 messages per second
+        let half_sec = start + Duration::from_millis(500);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, half_sec),
+            FloodCheckResult::Allowed
+        );
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, half_sec),
+            FloodCheckResult::Limited
+        );
+
+        // the bucket never holds more than the burst
+        let later = half_sec + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(
+                flood.check(&0, FloodMessageKind::Chat, later),
+                FloodCheckResult::Allowed
+            );
+        }
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, later),
+            FloodCheckResult::Limited
+        );
+    }
+
+    #[test]
+    fn mute_and_ban_escalation() {
+        let mut flood = FloodProtection::new(chat_config(FloodLimit {
+            violations_until_mute: 2,
+            mute_secs: 10,
+            mutes_until_ban: 2,
+            ban_secs: 60,
+            ..FloodLimit::new(1.0, 1.0)
+        }));
+        let mut cur_time = Duration::from_secs(1);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, cur_time),
+            FloodCheckResult::Allowed
+        );
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, cur_time),
+            FloodCheckResult::Limited
+        );
+        let muted_until = cur_time + Duration::from_secs(10);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, cur_time),
+            FloodCheckResult::Muted(muted_until)
+        );
+        // a mute only affects its kind
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Vote, cur_time),
+            FloodCheckResult::Allowed
+        );
+
+        // flooding right after the mute bans the client
+        cur_time = muted_until;
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, cur_time),
+            FloodCheckResult::Allowed
+        );
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, cur_time),
+            FloodCheckResult::Limited
+        );
+        let banned_until = cur_time + Duration::from_secs(60);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, cur_time),
+            FloodCheckResult::Banned(banned_until)
+        );
+        // a ban affects all kinds
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Vote, cur_time),
+            FloodCheckResult::Banned(banned_until)
+        );
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Vote, banned_until),
+            FloodCheckResult::Allowed
+        );
+    }
+
+    #[test]
+    fn mute_expiry() {
+        let mut flood = FloodProtection::new(chat_config(FloodLimit {
+            violations_until_mute: 1,
+            mute_secs: 5,
+            ..FloodLimit::new(1.0, 1.0)
+        }));
+        let start = Duration::from_secs(1);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, start),
+            FloodCheckResult::Allowed
+        );
+        let muted_until = start + Duration::from_secs(5);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, start),
+            FloodCheckResult::Muted(muted_until)
+        );
+        assert_eq!(
+            flood.check(
+                &0,
+                FloodMessageKind::Chat,
+                muted_until - Duration::from_millis(1)
+            ),
+            FloodCheckResult::Muted(muted_until)
+        );
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::Chat, muted_until),
+            FloodCheckResult::Allowed
+        );
+
+        // the client is forgotten once the mute ended and the bucket is full again
+        flood.remove_outdated(muted_until);
+        assert!(!flood.clients.is_empty());
+        flood.remove_outdated(muted_until + Duration::from_secs(2));
+        assert!(flood.clients.is_empty());
+    }
+
+    #[test]
+    fn ban_of_other_key() {
+        let mut flood = FloodProtection::new(FloodProtectionConfig::default());
+        let start = Duration::from_secs(1);
+        let banned_until = start + Duration::from_secs(60);
+        // e.g. the ip of a connection that was banned
+        flood.ban(&0, banned_until);
+        assert_eq!(flood.banned_until(&0, start), Some(banned_until));
+        assert_eq!(flood.banned_until(&1, start), None);
+        assert_eq!(
+            flood.check(&0, FloodMessageKind::JoinAttempt, start),
+            FloodCheckResult::Banned(banned_until)
+        );
+        // a shorter ban never shortens the running one
+        flood.ban(&0, start);
+        assert_eq!(flood.banned_until(&0, start), Some(banned_until));
+        assert_eq!(flood.banned_until(&0, banned_until), None);
+
+        flood.remove(&0);
+        assert!(flood.clients.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+use std::time::Duration;
+
+use base::log_println;
+use serde::{Deserialize, Serialize};
+
+use crate::game::match_stats::MatchStatsReport;
+
+const MAP_ROTATION_FILE: &str = "map_rotation.json";
+
+fn default_game_type() -> String {
+    "idm".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MapRotationEntry {
+    pub map: String,
+    // the mod that is announced to the clients
+    #[serde(default = "default_game_type")]
+    pub game_type: String,
+    // map setting commands that override the settings of the map for this entry
+    #[serde(default)]
+    pub options: Vec<String>,
+    // 0 means unlimited
+    #[serde(default)]
+    pub time_limit_secs: u32,
+    // the kills of the best player that end the match, 0 means unlimited
+    #[serde(default)]
+    pub score_limit: u32,
+}
+
+impl MapRotationEntry {
+    pub fn new(map: &str) -> Self {
+        Self {
+            map: map.to_string(),
+            game_type: default_game_type(),
+            options: Vec::new(),
+            time_limit_secs: 0,
+            score_limit: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MapRotationConfig {
+    pub entries: Vec<MapRotationEntry>,
+}
+
+impl MapRotationConfig {
+    /**
+     * A missing or broken file gives an empty rotation, the server then stays on its start map
+     */
+    pub fn load() -> Self {
+        match std::fs::read(MAP_ROTATION_FILE) {
+            Ok(file) => serde_json::from_slice(&file).unwrap_or_else(|err| {
+                log_println!("the map rotation is invalid: {}", err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/**
+ * Decides when the current match ends and which map (and mod) follows,
+ * the server does the actual map change
+ */
+pub struct MapRotation {
+    config: MapRotationConfig,
+    cur: MapRotationEntry,
+    // the index of the next entry in the config
+    next_index: usize,
+    // e.g. from a passed vote, played before the rotation continues
+    next_override: Option<MapRotationEntry>,
+    match_start: Duration,
+}
+
+impl MapRotation {
+    /**
+     * `start_map` is only used if the rotation is empty
+     */
+    pub fn new(config: MapRotationConfig, start_map: &str, cur_time: Duration) -> Self {
+        let cur = config
+            .entries
+            .first()
+            .cloned()
+            .unwrap_or_else(|| MapRotationEntry::new(start_map));
+        Self {
+            next_index: 1 % config.entries.len().max(1),
+            config,
+            cur,
+            next_override: None,
+            match_start: cur_time,
+        }
+    }
+
+    pub fn cur_entry(&self) -> &MapRotationEntry {
+        &self.cur
+    }
+
+    /**
+     * The entry that is played after the current one, if there is one
+     */
+    pub fn next_entry(&self) -> Option<&MapRotationEntry> {
+        self.next_override
+            .as_ref()
+            .or_else(|| self.config.entries.get(self.next_index))
+    }
+
+    /**
+     * If the map is part of the rotation, e.g. to validate votes
+     */
+    pub fn contains_map(&self, map: &str) -> bool {
+        self.cur.map == map || self.config.entries.iter().any(|entry| entry.map == map)
+    }
+
+    /**
+     * Plays the map after the current match, the rotation continues after it
+     */
+    pub fn set_next_map(&mut self, map: &str) {
+        self.next_override = Some(
+            self.config
+                .entries
+                .iter()
+                .find(|entry| entry.map == map)
+                .cloned()
+                .unwrap_or_else(|| MapRotationEntry::new(map)),
+        );
+    }
+
+    /**
+     * If the time or score limit of the current entry was reached
+     */
+    pub fn is_match_over(&self, cur_time: Duration, stats: &MatchStatsReport) -> bool {
+        let time_over = self.cur.time_limit_secs > 0
+            && cur_time.saturating_sub(self.match_start)
+                >= Duration::from_secs(self.cur.time_limit_secs as u64);
+        let score_over = self.cur.score_limit > 0
+            && stats
+                .players
+                .iter()
+                .any(|player| player.kills >= self.cur.score_limit);
+        time_over || score_over
+    }
+
+    /**
+     * Removes the next entry from the rotation, `None` if there is nothing to rotate to.
+     * The entry only becomes the current one with `start_match`,
+     * after its map was loaded
+     */
+    pub fn take_next(&mut self) -> Option<MapRotationEntry> {
+        match self.next_override.take() {
+            Some(next) => Some(next),
+            None => {
+                let next = self.config.entries.get(self.next_index)?.clone();
+                self.next_index = (self.next_index + 1) % self.config.entries.len();
+                Some(next)
+            }
+        }
+    }
+
+    pub fn start_match(&mut self, entry: MapRotationEntry, cur_time: Duration) {
+        self.cur = entry;
+        self.match_start = cur_time;
+    }
+
+    /**
+     * Restarts the limits, e.g. if the map change failed and the current map is played again
+     */
+    pub fn restart_match(&mut self, cur_time: Duration) {
+        self.match_start = cur_time;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::game::match_stats::{MatchPlayerStats, MatchStatsReport};
+
+    use super::{MapRotation, MapRotationConfig, MapRotationEntry};
+
+    fn rotation(maps: &[&str]) -> MapRotationConfig {
+        MapRotationConfig {
+            entries: maps.iter().map(|map| MapRotationEntry::new(map)).collect(),
+        }
+    }
+
+    /**
+     * Rotates like the server: take the next entry and start it if its map loaded
+     */
+    fn rotate(rotation: &mut MapRotation, loads: bool, cur_time: Duration) -> Option<String> {
+        let next = rotation.take_next()?;
+        if loads {
+            rotation.start_match(next, cur_time);
+        } else {
+            rotation.restart_match(cur_time);
+        }
+        Some(rotation.cur_entry().map.clone())
+    }
+
+    #[test]
+    fn rotation_order() {
+        let mut rotation =
+            MapRotation::new(rotation(&["dm1", "dm2", "dm3"]), "ctf1", Duration::ZERO);
+        assert_eq!(rotation.cur_entry().map, "dm1");
+        assert_eq!(rotation.next_entry().unwrap().map, "dm2");
+        let played: Vec<String> = (0..4)
+            .map(|_| rotate(&mut rotation, true, Duration::ZERO).unwrap())
+            .collect();
+        assert_eq!(played, ["dm2", "dm3", "dm1", "dm2"]);
+
+        // a voted map is played once, then the rotation continues
+        rotation.set_next_map("ctf1");
+        assert_eq!(rotation.next_entry().unwrap().map, "ctf1");
+        assert_eq!(rotate(&mut rotation, true, Duration::ZERO).unwrap(), "ctf1");
+        assert_eq!(rotate(&mut rotation, true, Duration::ZERO).unwrap(), "dm3");
+    }
+
+    #[test]
+    fn empty_rotation() {
+        let mut rotation = MapRotation::new(MapRotationConfig::default(), "ctf1", Duration::ZERO);
+        assert_eq!(rotation.cur_entry().map, "ctf1");
+        assert!(rotation.next_entry().is_none());
+        assert!(rotate(&mut rotation, true, Duration::ZERO).is_none());
+        assert!(rotation.contains_map("ctf1"));
+        assert!(!rotation.contains_map("dm1"));
+    }
+
+    #[test]
+    fn skip_failed_map() {
+        let mut rotation =
+            MapRotation::new(rotation(&["dm1", "dm2", "dm3"]), "ctf1", Duration::ZERO);
+        let restart_time = Duration::from_secs(100);
+        // dm2 fails to load, the current map is played again
+        assert_eq!(rotate(&mut rotation, false, restart_time).unwrap(), "dm1");
+        // the broken map is skipped in the next rotation
+        assert_eq!(rotation.next_entry().unwrap().map, "dm3");
+        assert_eq!(rotate(&mut rotation, true, restart_time).unwrap(), "dm3");
+    }
+
+    #[test]
+    fn match_limits() {
+        let mut config = rotation(&["dm1"]);
+        config.entries[0].time_limit_secs = 60;
+        config.entries[0].score_limit = 10;
+        let start = Duration::from_secs(5);
+        let mut rotation = MapRotation::new(config, "ctf1", start);
+        let mut stats = MatchStatsReport::default();
+        stats.players.push(MatchPlayerStats {
+            kills: 9,
+            ..Default::default()
+        });
+        assert!(!rotation.is_match_over(start + Duration::from_secs(59), &stats));
+        assert!(rotation.is_match_over(start + Duration::from_secs(60), &stats));
+        // the limits start again after a failed map change
+        rotation.restart_match(start + Duration::from_secs(60));
+        assert!(!rotation.is_match_over(start + Duration::from_secs(61), &stats));
+        stats.players[0].kills = 10;
+        assert!(rotation.is_match_over(start + Duration::from_secs(61), &stats));
+    }
+}
@@ -1,2 +1,7 @@
+pub mod flood_protection;
+pub mod map_rotation;
 pub mod server;
 pub mod server_game;
+pub mod system_messages;
+pub mod tick_profiler;
+pub mod votes;
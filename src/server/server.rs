@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    net::{IpAddr, SocketAddr},
     num::NonZeroUsize,
     sync::{atomic::AtomicBool, Arc},
     time::Duration,
@@ -8,24 +9,59 @@ use std::{
 use network::network::{
     network::{Network, NetworkConnectionID, NetworkGameEvent},
     quinn_network::QuinnNetwork,
+    traffic_shaping::NetworkPacketPriority,
 };
 use rcgen::Certificate;
 use tokio::sync::Mutex;
 
 use crate::{
-    game::{simulation_pipe::SimulationPipe, snapshot::SnapshotClientInfo},
+    game::{
+        ghost::GhostPath,
+        simulation_pipe::{LocalPlayerInput, SimulationPipe},
+        snapshot::{
+            SnapshotClientInfo, SnapshotClientRole, SnapshotSpectatorCamera, SnapshotViewArea,
+        },
+        snapshot_delta::SnapshotBaselines,
+        state::{GameState, GameStateInterface},
+        state_checksum::{resimulated_state_checksum, STATE_CHECKSUM_INTERVAL_TICKS},
+        TGameElementID, INVALID_GAME_ELEMENT_ID,
+    },
     hash_queue::HashQueue,
     network::{
         game_event_generator::{GameEventGenerator, GameEvents},
+        lan_discovery::{LanDiscoveryAnnouncer, LanServerAnnouncement},
         messages::{
-            ClientToServerMessage, GameMessage, MsgSvPlayerInfo, MsgSvServerInfo, NetworkStr,
-            ServerToClientMessage,
+            ClientToServerMessage, GameMessage, MsgClChat, MsgClSimulation, MsgObjPlayerInfo,
+            MsgObjPlayerInput, MsgObjResourceHint, MsgObjResourceKind, MsgObjStageError,
+            MsgObjStateChecksum, MsgObjVoteKind, MsgObjVoteProposal, MsgObjVoteResult,
+            MsgSvBookmark, MsgSvChat, MsgSvGhost, MsgSvPlayerInfo, MsgSvPlayerInput,
+            MsgSvServerInfo, MsgSvStageEvent, MsgSvVoteStarted, NetworkStr, ServerToClientMessage,
         },
     },
-    server_game::{ServerGame, ServerGamePlayerInputForPipe, ServerPlayerID},
+    server_game::{ServerGame, ServerGamePlayerInputForPipe, ServerMap, ServerPlayerID},
+    worker::{Worker, WorkerTask},
+};
+
+use super::{
+    flood_protection::{
+        FloodCheckResult, FloodMessageKind, FloodProtection, FloodProtectionConfig,
+    },
+    map_rotation::{MapRotation, MapRotationConfig, MapRotationEntry},
+    system_messages::ServerSystemMessage,
+    tick_profiler::{ServerTickProfiler, ServerTickSection},
+    votes::{ServerVoteError, ServerVoteEvent, ServerVotes},
+};
+
+use base::{
+    config::Config,
+    filesys::FileSystem,
+    io_batcher::IOBatcher,
+    log_println,
+    system::{System, SystemTimeInterface},
 };
 
-use base::system::{System, SystemTimeInterface};
+// muted and banned clients are kept, so this only frees memory
+const FLOOD_PROTECTION_CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
 
 /**
  * A network queued client is a client that isn't actually part of the game,
@@ -68,8 +104,20 @@ impl ServerNetworkClient {
  */
 pub struct ServerClient {
     network_id: NetworkConnectionID,
+    // invalid for spectators
     player_id: ServerPlayerID,
+    role: SnapshotClientRole,
     connect_timestamp: Duration,
+    ghost_subscribed: bool,
+    // spectators (e.g. coaches) can see the raw inputs of one player
+    input_overlay_allowed: bool,
+    watched_player: Option<ServerPlayerID>,
+    // the snapshots that can be used for the delta of the next snapshot
+    snapshot_baselines: SnapshotBaselines,
+    // the camera of the client, `None` until the client sent it
+    view_area: Option<SnapshotViewArea>,
+    // the state of the snapshot before the next state checksum, see `resimulated_state_checksum`
+    state_checksum_base: Option<GameState>,
 }
 
 impl ServerClient {
@@ -77,7 +125,14 @@ impl ServerClient {
         Self {
             network_id: network_id,
             player_id: Default::default(),
+            role: SnapshotClientRole::Player,
             connect_timestamp: *connect_timestamp,
+            ghost_subscribed: false,
+            input_overlay_allowed: false,
+            watched_player: None,
+            snapshot_baselines: SnapshotBaselines::default(),
+            view_area: None,
+            state_checksum_base: None,
         }
     }
 }
@@ -99,6 +154,34 @@ pub struct Server {
 
     game: ServerGame,
 
+    // keyed by the ip, so a reconnect doesn't reset the limits or a ban
+    ip_flood_protection: FloodProtection<IpAddr>,
+    // the limits of the players, see `FloodMessageKind::is_per_connection`
+    connection_flood_protection: FloodProtection<NetworkConnectionID>,
+    client_ips: HashMap<NetworkConnectionID, IpAddr>,
+
+    votes: ServerVotes,
+
+    map_rotation: MapRotation,
+    // the map the server changes to after it was loaded in the background
+    pending_map: Option<(MapRotationEntry, WorkerTask<ServerMap>)>,
+    worker: Worker,
+
+    // announced to every client, e.g. the next map in the rotation or the wasm module of a mod
+    resource_hints: Vec<MsgObjResourceHint>,
+
+    // if all spectators may see the inputs of the players, else only the allowed ones
+    pub input_overlay_for_all_spectators: bool,
+
+    // `None` if broadcasting is not possible
+    lan_announcer: Option<LanDiscoveryAnnouncer>,
+
+    tick_profiler: ServerTickProfiler,
+
+    thread_pool: Arc<rayon::ThreadPool>,
+
+    config: Config,
+
     sys: System,
 }
 
@@ -108,6 +191,9 @@ impl Server {
         is_open: Arc<AtomicBool>,
         cert: &Certificate,
         max_clients: usize,
+        config: Config,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<std::sync::Mutex<IOBatcher>>,
     ) -> Self {
         let has_new_events_server = Arc::new(AtomicBool::new(false));
         let game_event_generator_server = Arc::new(Mutex::new(GameEventGenerator::new(
@@ -116,12 +202,14 @@ impl Server {
         )));
 
         let (network_server, _cert) = Network::init_server(
-            "127.0.0.1:8305",
+            &config.sv_bind_addr,
             game_event_generator_server.clone(),
             cert,
             sys.time.clone(),
             Some(2),
         );
+        network_server.set_max_connections_per_ip(config.sv_max_connections_per_ip);
+        network_server.set_bandwidth_limits(config.sv_upload_limit, config.sv_download_limit);
 
         let thread_pool = Arc::new(
             rayon::ThreadPoolBuilder::new()
@@ -136,6 +224,23 @@ impl Server {
                 .unwrap(),
         );
 
+        let fs_clone = fs.clone();
+        let mut flood_config_task = io_batcher
+            .lock()
+            .unwrap()
+            .spawn(async move { Ok(FloodProtectionConfig::load(&fs_clone).await) });
+        io_batcher
+            .lock()
+            .unwrap()
+            .wait_finished(&mut flood_config_task);
+        let flood_config = flood_config_task.get_storage().unwrap_or_default();
+
+        let map_rotation =
+            MapRotation::new(MapRotationConfig::load(), "cb2", sys.time_get_nanoseconds());
+        let mut game = ServerGame::new(&sys.time, &map_rotation.cur_entry().map, &thread_pool);
+        game.map
+            .apply_setting_overrides(&map_rotation.cur_entry().options);
+
         Self {
             network_queued_clients: HashQueue::new(),
             network_clients: HashMap::new(),
@@ -150,7 +255,37 @@ impl Server {
             has_new_events_server: has_new_events_server,
             game_event_generator_server: game_event_generator_server,
 
-            game: ServerGame::new(&sys.time, "cb2", &thread_pool),
+            game,
+
+            ip_flood_protection: FloodProtection::new(flood_config.clone()),
+            connection_flood_protection: FloodProtection::new(flood_config),
+            client_ips: HashMap::new(),
+
+            votes: ServerVotes::new(
+                Duration::from_secs(25),
+                config.sv_vote_kick_min_voters as usize,
+            ),
+
+            map_rotation,
+            pending_map: None,
+            worker: Worker::new(),
+
+            resource_hints: Vec::new(),
+
+            input_overlay_for_all_spectators: false,
+
+            lan_announcer: config
+                .sv_bind_addr
+                .parse::<SocketAddr>()
+                .ok()
+                .filter(|addr| !addr.ip().is_loopback())
+                .and_then(|_| LanDiscoveryAnnouncer::new().ok()),
+
+            tick_profiler: ServerTickProfiler::new(Duration::from_secs(1) / 50),
+
+            thread_pool,
+
+            config,
 
             sys: sys,
         }
@@ -162,15 +297,7 @@ impl Server {
             self.network_clients
                 .insert(*con_id, ServerNetworkClient::new(*con_id, timestamp));
 
-            // tell the client about all data required to join the server
-            let server_info = MsgSvServerInfo {
-                map: NetworkStr::from(&self.game.map.raw.name).unwrap(),
-                game_type: NetworkStr::from("idm").unwrap(),
-            };
-            self.network.send_to(
-                &GameMessage::ServerToClient(ServerToClientMessage::ServerInfo(server_info)),
-                con_id,
-            );
+            self.send_server_info(con_id);
         } else {
             // else add it to the network queue and inform it about that
             self.network_queued_clients.add_or_set(
@@ -182,7 +309,26 @@ impl Server {
         }
     }
 
+    /**
+     * Tells the client about all data required to join the server
+     */
+    fn send_server_info(&mut self, con_id: &NetworkConnectionID) {
+        let server_info = MsgSvServerInfo {
+            map: NetworkStr::from(&self.game.map.raw.name).unwrap(),
+            map_physics_hash: self.game.map.physics_hash,
+            game_type: NetworkStr::from_truncated(&self.map_rotation.cur_entry().game_type),
+        };
+        self.network.send_to(
+            &GameMessage::ServerToClient(ServerToClientMessage::ServerInfo(server_info)),
+            con_id,
+        );
+    }
+
     pub fn client_disconnect(&mut self, con_id: &NetworkConnectionID, _reason: &str) {
+        self.votes.client_left(con_id);
+        self.client_ips.remove(con_id);
+        self.connection_flood_protection.remove(con_id);
+
         // find client in queued clients
         if self.network_queued_clients.remove(con_id) {
             return;
@@ -206,7 +352,7 @@ impl Server {
         let found = self.network_clients.remove(con_id);
         match found {
             Some(net_client) => {
-                println!("client ready");
+                log_println!("client ready");
                 self.clients.insert(
                     *con_id,
                     ServerClient::new(*con_id, &net_client.connect_timestamp.clone()),
@@ -219,14 +365,408 @@ impl Server {
     }
 
     pub fn send_player_infos(&mut self, connection_id: &NetworkConnectionID) {
-        self.game.players.for_each_in_queue_order(|player| {
-            self.network.send_to(
-                &GameMessage::ServerToClient(ServerToClientMessage::PlayerInfo(MsgSvPlayerInfo {
-                    info: player.player_info.clone(),
-                })),
-                connection_id,
+        self.game
+            .players
+            .for_each_in_queue_order(|player_id, player| {
+                self.network.send_to(
+                    &GameMessage::ServerToClient(ServerToClientMessage::PlayerInfo(
+                        MsgSvPlayerInfo {
+                            player_id: *player_id,
+                            info: player.player_info.clone(),
+                            is_bot: player.is_bot,
+                        },
+                    )),
+                    connection_id,
+                );
+            })
+    }
+
+    fn broadcast(&mut self, msg: &GameMessage) {
+        self.broadcast_with_priority(msg, NetworkPacketPriority::default());
+    }
+
+    fn broadcast_with_priority(&mut self, msg: &GameMessage, priority: NetworkPacketPriority) {
+        for con_id in self.clients.keys() {
+            self.network.send_to_with_priority(msg, con_id, priority);
+        }
+    }
+
+    fn handle_vote_event(&mut self, event: ServerVoteEvent) {
+        match event {
+            ServerVoteEvent::Progress(progress) => {
+                self.broadcast(&GameMessage::ServerToClient(
+                    ServerToClientMessage::VoteProgress(progress),
+                ));
+            }
+            ServerVoteEvent::Ended(proposal, result) => {
+                self.broadcast(&GameMessage::ServerToClient(
+                    ServerToClientMessage::VoteEnded(result),
+                ));
+                match result {
+                    MsgObjVoteResult::Passed => {
+                        self.broadcast_system_message(&ServerSystemMessage::VotePassed)
+                    }
+                    MsgObjVoteResult::Failed => {
+                        self.broadcast_system_message(&ServerSystemMessage::VoteFailed)
+                    }
+                    MsgObjVoteResult::Canceled => {}
+                }
+                if result == MsgObjVoteResult::Passed {
+                    match &proposal.kind {
+                        MsgObjVoteKind::Kick(player_id) => {
+                            // the player might have left while the vote was running
+                            let Some(player) = self.game.players.get(player_id) else {
+                                return;
+                            };
+                            let is_bot = player.is_bot;
+                            let name = player.player_info.name.as_str().to_string();
+                            self.broadcast_system_message(&ServerSystemMessage::PlayerKicked {
+                                name: &name,
+                            });
+                            let con_id = self
+                                .clients
+                                .iter()
+                                .find(|(_, client)| {
+                                    client.role == SnapshotClientRole::Player
+                                        && client.player_id == *player_id
+                                })
+                                .map(|(con_id, _)| *con_id);
+                            match con_id {
+                                Some(con_id) => self.network.disconnect(&con_id),
+                                // bots have no connection
+                                None if is_bot => {
+                                    self.game.bot_leave(player_id);
+                                }
+                                None => {}
+                            }
+                        }
+                        MsgObjVoteKind::ChangeMap(map) => {
+                            self.map_rotation.set_next_map(map.as_str());
+                            self.rotate_map();
+                        }
+                        // already handled by the callback
+                        MsgObjVoteKind::Custom(_) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Mods mark important moments of the match, demos show them on the timeline
+     */
+    pub fn emit_bookmark(&mut self, name: &str, player_id: Option<ServerPlayerID>) {
+        self.broadcast(&GameMessage::ServerToClient(
+            ServerToClientMessage::Bookmark(MsgSvBookmark {
+                name: NetworkStr::from_truncated(name),
+                player_id,
+                monotonic_tick: self.game.game.cur_monotonic_tick,
+            }),
+        ));
+    }
+
+    /**
+     * Replaces the assets the clients should prefetch,
+     * the clients add them to the hints they already got (e.g. for a voted map)
+     */
+    pub fn set_resource_hints(&mut self, hints: Vec<MsgObjResourceHint>) {
+        self.resource_hints = hints;
+        self.broadcast(&GameMessage::ServerToClient(
+            ServerToClientMessage::ResourceHints(self.resource_hints.clone()),
+        ));
+    }
+
+    /**
+     * Allows the spectator to see the raw inputs of players, e.g. for coaches
+     */
+    pub fn set_input_overlay_allowed(&mut self, con_id: &NetworkConnectionID, allowed: bool) {
+        if let Some(client) = self.clients.get_mut(con_id) {
+            client.input_overlay_allowed = allowed;
+        }
+    }
+
+    /**
+     * Sends the input of the player to the spectators that watch it
+     */
+    fn forward_input_to_watchers(&mut self, player_id: &ServerPlayerID, inp: &MsgObjPlayerInput) {
+        let msg =
+            GameMessage::ServerToClient(ServerToClientMessage::PlayerInput(MsgSvPlayerInput {
+                player_id: *player_id,
+                input: *inp,
+            }));
+        for (con_id, client) in &self.clients {
+            let is_spectator = matches!(client.role, SnapshotClientRole::Spectator(_));
+            if is_spectator
+                && client.watched_player == Some(*player_id)
+                && (client.input_overlay_allowed || self.input_overlay_for_all_spectators)
+            {
+                self.network.send_to(&msg, con_id);
+            }
+        }
+    }
+
+    /**
+     * Team messages of players only reach the players of the sender's stage,
+     * team messages of spectators only reach the other spectators
+     */
+    fn send_chat(&mut self, con_id: &NetworkConnectionID, chat: &MsgClChat) {
+        let Some(client) = self.clients.get(con_id) else {
+            return;
+        };
+        let msg = GameMessage::ServerToClient(ServerToClientMessage::Chat(MsgSvChat {
+            player_id: client.player_id,
+            msg: chat.msg.clone(),
+            team: chat.team,
+        }));
+        if !chat.team {
+            self.broadcast_with_priority(&msg, NetworkPacketPriority::Chat);
+            return;
+        }
+        let stage_members = match client.role {
+            SnapshotClientRole::Player => self
+                .game
+                .player_stage(&client.player_id)
+                .map(|stage_id| self.game.stage_members(&stage_id)),
+            SnapshotClientRole::Spectator(_) => None,
+        };
+        for (receiver_con_id, receiver) in &self.clients {
+            let is_team_mate = match &stage_members {
+                Some(members) => {
+                    receiver.role == SnapshotClientRole::Player
+                        && members.contains(&receiver.player_id)
+                }
+                None => matches!(receiver.role, SnapshotClientRole::Spectator(_)),
+            };
+            if is_team_mate {
+                self.network.send_to_with_priority(
+                    &msg,
+                    receiver_con_id,
+                    NetworkPacketPriority::Chat,
+                );
+            }
+        }
+    }
+
+    fn broadcast_system_message(&mut self, msg: &ServerSystemMessage) {
+        self.broadcast_with_priority(
+            &GameMessage::ServerToClient(ServerToClientMessage::SystemMessage(msg.to_net())),
+            NetworkPacketPriority::Chat,
+        );
+    }
+
+    /**
+     * Adds a bot and tells all clients about it,
+     * the input of the bot is set with `ServerGame::bot_inp` every tick
+     */
+    pub fn add_bot(&mut self, player_info: &MsgObjPlayerInfo) -> ServerPlayerID {
+        let player_id = self.game.bot_join(player_info);
+        self.broadcast(&GameMessage::ServerToClient(
+            ServerToClientMessage::PlayerInfo(MsgSvPlayerInfo {
+                player_id,
+                info: player_info.clone(),
+                is_bot: true,
+            }),
+        ));
+        player_id
+    }
+
+    /**
+     * Race mods emit the ghost of a player's (best) run here,
+     * only the clients that subscribed receive it
+     */
+    pub fn emit_ghost(&mut self, player_id: &ServerPlayerID, ghost: &GhostPath) {
+        let msg = GameMessage::ServerToClient(ServerToClientMessage::Ghost(MsgSvGhost {
+            player_id: *player_id,
+            ghost: ghost.clone(),
+        }));
+        for (con_id, client) in &self.clients {
+            if client.ghost_subscribed {
+                // can be large, the game state is more important
+                self.network
+                    .send_to_with_priority(&msg, con_id, NetworkPacketPriority::Download);
+            }
+        }
+    }
+
+    /**
+     * Sends the statistics of the match to all clients
+     */
+    fn finish_match(&mut self) {
+        let report = self.game.game.end_match();
+        self.broadcast_system_message(&ServerSystemMessage::MatchEnded);
+        // TODO: also store it in the database, once there is one
+        self.broadcast(&GameMessage::ServerToClient(
+            ServerToClientMessage::MatchStats(report),
+        ));
+    }
+
+    /**
+     * Ends the match and starts loading the next map of the rotation,
+     * see `update_pending_map`
+     */
+    fn rotate_map(&mut self) {
+        // the match already ended, the map is still loading
+        if self.pending_map.is_some() {
+            return;
+        }
+        self.finish_match();
+        let Some(entry) = self.map_rotation.take_next() else {
+            // nothing to rotate to, the match starts again on the current map
+            self.map_rotation
+                .restart_match(self.sys.time_get_nanoseconds());
+            return;
+        };
+        // loading blocks too long for the tick loop
+        let map_file = entry.map.clone();
+        let thread_pool = self.thread_pool.clone();
+        let sys_time = self.sys.time.clone();
+        let task = self
+            .worker
+            .spawn(move || ServerMap::new(&map_file, &thread_pool, &sys_time));
+        self.pending_map = Some((entry, task));
+    }
+
+    /**
+     * Changes to the pending map once it finished loading.
+     * All clients load the new map and join again.
+     */
+    fn update_pending_map(&mut self) {
+        if !self
+            .pending_map
+            .as_ref()
+            .map_or(false, |(_, task)| task.is_finished())
+        {
+            return;
+        }
+        let (entry, mut task) = self.pending_map.take().unwrap();
+        let cur_time = self.sys.time_get_nanoseconds();
+        let map = match task.get_storage() {
+            Ok(map) => map,
+            Err(err) => {
+                // the entry is skipped, the match starts again on the current map
+                log_println!("map {} could not be loaded: {}", entry.map, err);
+                self.map_rotation.restart_match(cur_time);
+                return;
+            }
+        };
+        self.game.change_map(map, &entry.options);
+        self.map_rotation.start_match(entry, cur_time);
+        let con_ids: Vec<NetworkConnectionID> = self.clients.keys().copied().collect();
+        for con_id in con_ids {
+            let client = self.clients.remove(&con_id).unwrap();
+            self.network_clients.insert(
+                con_id,
+                ServerNetworkClient::new(con_id, &client.connect_timestamp),
             );
-        })
+            self.send_server_info(&con_id);
+        }
+    }
+
+    /**
+     * Checks the parts of a vote that come from the client
+     */
+    fn validate_vote(&self, kind: &MsgObjVoteKind) -> Result<(), ServerVoteError> {
+        match kind {
+            // the name is used as file name
+            MsgObjVoteKind::ChangeMap(map)
+                if !self.map_rotation.contains_map(map.as_str())
+                    && !ServerMap::exists(map.as_str()) =>
+            {
+                Err(ServerVoteError::UnknownMap)
+            }
+            // spectators have the invalid player id, only players can be kicked
+            MsgObjVoteKind::Kick(player_id)
+                if *player_id == INVALID_GAME_ELEMENT_ID
+                    || self.game.players.get(player_id).is_none() =>
+            {
+                Err(ServerVoteError::InvalidKickTarget)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_stage_result(
+        &mut self,
+        con_id: &NetworkConnectionID,
+        res: Result<MsgSvStageEvent, MsgObjStageError>,
+    ) {
+        match res {
+            Ok(ev) => {
+                self.broadcast(&GameMessage::ServerToClient(
+                    ServerToClientMessage::StageEvent(ev),
+                ));
+            }
+            Err(err) => {
+                self.network.send_to(
+                    &GameMessage::ServerToClient(ServerToClientMessage::StageEvent(
+                        MsgSvStageEvent::Failed(err),
+                    )),
+                    con_id,
+                );
+            }
+        }
+    }
+
+    /**
+     * Returns true if the message should be handled
+     */
+    fn check_flood(&mut self, con_id: &NetworkConnectionID, kind: FloodMessageKind) -> bool {
+        let Some(ip) = self.client_ips.get(con_id).copied() else {
+            return false;
+        };
+        let cur_time = self.sys.time_get_nanoseconds();
+        let res = if !kind.is_per_connection() {
+            self.ip_flood_protection.check(&ip, kind, cur_time)
+        } else if let Some(banned_until) = self.ip_flood_protection.banned_until(&ip, cur_time) {
+            FloodCheckResult::Banned(banned_until)
+        } else {
+            self.connection_flood_protection
+                .check(con_id, kind, cur_time)
+        };
+        match res {
+            FloodCheckResult::Allowed => true,
+            FloodCheckResult::Limited | FloodCheckResult::Muted(_) => false,
+            FloodCheckResult::Banned(banned_until) => {
+                // the ban holds for every connection of the ip
+                self.ip_flood_protection.ban(&ip, banned_until);
+                self.network.disconnect(con_id);
+                false
+            }
+        }
+    }
+
+    /**
+     * Writes the tick statistics in the prometheus text format to `sv_tick_stats_file`,
+     * so it can be collected by the textfile collector of a node exporter
+     */
+    fn write_tick_stats(&self) {
+        let file = &self.config.sv_tick_stats_file;
+        if file.is_empty() {
+            return;
+        }
+        // the collector must never read a half written file
+        let path = format!("{}.tmp", file);
+        if std::fs::write(&path, self.tick_profiler.to_prometheus_text()).is_ok() {
+            let _ = std::fs::rename(path, file);
+        }
+    }
+
+    fn announce_in_lan(&self) {
+        let Ok(bind_addr) = self.config.sv_bind_addr.parse::<SocketAddr>() else {
+            return;
+        };
+        if let Some(announcer) = &self.lan_announcer {
+            let announcement = LanServerAnnouncement::new(
+                bind_addr.port(),
+                NetworkStr::from_truncated(&self.config.sv_name),
+                NetworkStr::from_truncated(&self.map_rotation.cur_entry().game_type),
+                NetworkStr::from_truncated(&self.game.map.raw.name),
+                (self.clients.len() + self.network_clients.len()) as u32,
+                self.max_clients as u32,
+                false,
+            );
+            let _ = announcer.announce(&announcement);
+        }
     }
 
     pub fn run(&mut self) {
@@ -234,6 +774,8 @@ impl Server {
         let mut last_tick_time = cur_time;
         let _last_inp_time = cur_time;
         let time_until_tick = Duration::from_secs(1).as_nanos() / 50;
+        let mut last_lan_announce_time = Duration::ZERO;
+        let mut last_flood_cleanup_time = Duration::ZERO;
 
         let game_event_generator = self.game_event_generator_server.clone();
         while self.is_open.load(std::sync::atomic::Ordering::Relaxed) {
@@ -245,12 +787,13 @@ impl Server {
                 for (con_id, timestamp_nanos, event) in &game_ev_gen.events {
                     match event {
                         GameEvents::NetworkEvent(net_ev) => match net_ev {
-                            NetworkGameEvent::Connected => {
-                                println!("connect time sv: {}", timestamp_nanos.as_nanos());
+                            NetworkGameEvent::Connected(addr) => {
+                                log_println!("connect time sv: {}", timestamp_nanos.as_nanos());
+                                self.client_ips.insert(*con_id, addr.ip());
                                 self.try_client_connect(con_id, timestamp_nanos);
                             }
                             NetworkGameEvent::Disconnected(reason) => {
-                                println!("got connected event from network");
+                                log_println!("got connected event from network");
                                 self.client_disconnect(con_id, reason.as_str());
                             }
                             NetworkGameEvent::NetworkStats(_stats) => {
@@ -268,18 +811,30 @@ impl Server {
                                 GameMessage::ClientToServer(client_to_server_msg) => {
                                     match client_to_server_msg {
                                         ClientToServerMessage::Ready(player_info) => {
+                                            if !self
+                                                .check_flood(con_id, FloodMessageKind::JoinAttempt)
+                                            {
+                                                continue;
+                                            }
                                             // if client is actually waiting, make it part of the game
                                             let was_client_readied = self.try_client_ready(con_id);
                                             if was_client_readied {
-                                                let player_id = self
-                                                    .game
-                                                    .player_join(con_id, &player_info.player_info);
                                                 let mut client =
                                                     self.clients.get_mut(con_id).unwrap();
-                                                client.player_id = player_id;
+                                                if player_info.as_spectator {
+                                                    client.role = SnapshotClientRole::Spectator(
+                                                        SnapshotSpectatorCamera::Free,
+                                                    );
+                                                } else {
+                                                    client.player_id = self.game.player_join(
+                                                        con_id,
+                                                        &player_info.player_info,
+                                                    );
+                                                }
 
                                                 let snap_client = SnapshotClientInfo {
-                                                    client_player_id: player_id,
+                                                    client_player_id: client.player_id,
+                                                    role: client.role,
                                                     snap_everything: false,
                                                     snap_other_stages: false,
                                                     time_since_connect_nanos: (self
@@ -288,24 +843,246 @@ impl Server {
                                                         - client.connect_timestamp)
                                                         .as_nanos()
                                                         as u64,
+                                                    // the client did not send its camera yet
+                                                    view_area: None,
+                                                    tracked_players: &[],
                                                 };
                                                 self.send_player_infos(con_id);
                                                 let snap = self
                                                     .game
                                                     .snap_shot_builder
                                                     .build_for(&self.game.game, &snap_client);
-                                                self.network.send_to(
+                                                self.network.send_to_with_priority(
                                                     &GameMessage::ServerToClient(
                                                         ServerToClientMessage::Snapshot(snap),
                                                     ),
                                                     con_id,
+                                                    NetworkPacketPriority::Game,
                                                 );
+                                                if !self.resource_hints.is_empty() {
+                                                    self.network.send_to(
+                                                        &GameMessage::ServerToClient(
+                                                            ServerToClientMessage::ResourceHints(
+                                                                self.resource_hints.clone(),
+                                                            ),
+                                                        ),
+                                                        con_id,
+                                                    );
+                                                }
+                                                if !player_info.as_spectator {
+                                                    // the others need it e.g. for the skin
+                                                    self.broadcast(&GameMessage::ServerToClient(
+                                                        ServerToClientMessage::PlayerInfo(
+                                                            MsgSvPlayerInfo {
+                                                                player_id: snap_client
+                                                                    .client_player_id,
+                                                                info: player_info
+                                                                    .player_info
+                                                                    .clone(),
+                                                                is_bot: false,
+                                                            },
+                                                        ),
+                                                    ));
+                                                    self.broadcast_system_message(
+                                                        &ServerSystemMessage::PlayerJoined {
+                                                            name: player_info
+                                                                .player_info
+                                                                .name
+                                                                .as_str(),
+                                                        },
+                                                    );
+                                                }
                                             }
                                         }
                                         ClientToServerMessage::Input(inp) => {
                                             let player = self.clients.get_mut(con_id);
                                             if let Some(player) = player {
-                                                self.game.player_inp(&player.player_id, inp);
+                                                if player.role == SnapshotClientRole::Player {
+                                                    let player_id = player.player_id;
+                                                    self.game.player_inp(&player_id, inp);
+                                                    self.forward_input_to_watchers(&player_id, inp);
+                                                }
+                                            }
+                                        }
+                                        ClientToServerMessage::WatchPlayerInput(player_id) => {
+                                            if let Some(client) = self.clients.get_mut(con_id) {
+                                                client.watched_player = *player_id;
+                                            }
+                                        }
+                                        ClientToServerMessage::CallVote(call_vote) => {
+                                            if !self.check_flood(con_id, FloodMessageKind::Vote) {
+                                                continue;
+                                            }
+                                            let caller_player_id = match self.clients.get(con_id) {
+                                                Some(client) => client.player_id,
+                                                None => continue,
+                                            };
+                                            let cur_time = self.sys.time_get_nanoseconds();
+                                            let res = self.validate_vote(&call_vote.kind).and_then(
+                                                |_| {
+                                                    self.votes.call_vote(
+                                                        con_id,
+                                                        MsgObjVoteProposal {
+                                                            kind: call_vote.kind.clone(),
+                                                            reason: call_vote.reason.clone(),
+                                                            caller_player_id,
+                                                        },
+                                                        cur_time,
+                                                    )
+                                                },
+                                            );
+                                            if let Ok(proposal) = res {
+                                                // the map might be needed soon
+                                                if let MsgObjVoteKind::ChangeMap(map) =
+                                                    &proposal.kind
+                                                {
+                                                    self.broadcast(&GameMessage::ServerToClient(
+                                                        ServerToClientMessage::ResourceHints(vec![
+                                                            MsgObjResourceHint {
+                                                                kind: MsgObjResourceKind::Map,
+                                                                name: NetworkStr::from_truncated(
+                                                                    map.as_str(),
+                                                                ),
+                                                                hash: None,
+                                                            },
+                                                        ]),
+                                                    ));
+                                                }
+                                                let progress = self
+                                                    .votes
+                                                    .progress(self.clients.len(), cur_time)
+                                                    .unwrap_or_default();
+                                                self.broadcast(&GameMessage::ServerToClient(
+                                                    ServerToClientMessage::VoteStarted(
+                                                        MsgSvVoteStarted { proposal, progress },
+                                                    ),
+                                                ));
+                                            }
+                                        }
+                                        ClientToServerMessage::Vote(yes) => {
+                                            if self.clients.contains_key(con_id)
+                                                && self.check_flood(con_id, FloodMessageKind::Vote)
+                                            {
+                                                let _ = self.votes.vote(con_id, *yes);
+                                            }
+                                        }
+                                        ClientToServerMessage::SpectatorCamera(camera) => {
+                                            let client = self.clients.get_mut(con_id);
+                                            if let Some(client) = client {
+                                                // the target is validated in the snapshot
+                                                if let SnapshotClientRole::Spectator(_) =
+                                                    client.role
+                                                {
+                                                    client.role =
+                                                        SnapshotClientRole::Spectator(*camera);
+                                                }
+                                            }
+                                        }
+                                        ClientToServerMessage::Desync {
+                                            monotonic_tick,
+                                            server_checksum,
+                                            client_checksum,
+                                        } => {
+                                            // a full snapshot is expensive, don't let clients request it every tick
+                                            if !self.check_flood(con_id, FloodMessageKind::Resync) {
+                                                continue;
+                                            }
+                                            log_println!(
+                                                "client desynced at tick {}: server checksum {:x}, client checksum {:x}",
+                                                monotonic_tick,
+                                                server_checksum,
+                                                client_checksum
+                                            );
+                                            // resync with a full snapshot
+                                            if let Some(client) = self.clients.get(con_id) {
+                                                let snap_client = SnapshotClientInfo {
+                                                    client_player_id: client.player_id,
+                                                    role: client.role,
+                                                    snap_everything: false,
+                                                    snap_other_stages: false,
+                                                    time_since_connect_nanos: (self
+                                                        .sys
+                                                        .time_get_nanoseconds()
+                                                        - client.connect_timestamp)
+                                                        .as_nanos()
+                                                        as u64,
+                                                    view_area: client.view_area,
+                                                    tracked_players: client
+                                                        .watched_player
+                                                        .as_slice(),
+                                                };
+                                                let snap = self
+                                                    .game
+                                                    .snap_shot_builder
+                                                    .build_for(&self.game.game, &snap_client);
+                                                self.network.send_to_with_priority(
+                                                    &GameMessage::ServerToClient(
+                                                        ServerToClientMessage::Snapshot(snap),
+                                                    ),
+                                                    con_id,
+                                                    NetworkPacketPriority::Game,
+                                                );
+                                            }
+                                        }
+                                        ClientToServerMessage::SnapshotAck(monotonic_tick) => {
+                                            if let Some(client) = self.clients.get_mut(con_id) {
+                                                client.snapshot_baselines.ack(*monotonic_tick);
+                                            }
+                                        }
+                                        ClientToServerMessage::ViewArea(view_area) => {
+                                            if let Some(client) = self.clients.get_mut(con_id) {
+                                                client.view_area = Some(*view_area);
+                                            }
+                                        }
+                                        ClientToServerMessage::Chat(chat) => {
+                                            if self.check_flood(con_id, FloodMessageKind::Chat) {
+                                                self.send_chat(con_id, chat);
+                                            }
+                                        }
+                                        ClientToServerMessage::InfoRequest => {
+                                            if self
+                                                .check_flood(con_id, FloodMessageKind::InfoRequest)
+                                            {
+                                                self.send_server_info(con_id);
+                                            }
+                                        }
+                                        ClientToServerMessage::Simulation(simulation) => {
+                                            if self.config.sv_allow_simulation_control {
+                                                let game = &mut self.game.game;
+                                                match simulation {
+                                                    MsgClSimulation::Pause => game.pause(),
+                                                    MsgClSimulation::Resume => game.resume(),
+                                                    MsgClSimulation::Step(ticks) => {
+                                                        game.step(*ticks)
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        ClientToServerMessage::GhostSubscribe(subscribe) => {
+                                            if let Some(client) = self.clients.get_mut(con_id) {
+                                                client.ghost_subscribed = *subscribe;
+                                            }
+                                        }
+                                        ClientToServerMessage::StageCreate => {
+                                            if let Some(client) = self.clients.get(con_id) {
+                                                let res = self.game.stage_create(&client.player_id);
+                                                self.handle_stage_result(con_id, res);
+                                            }
+                                        }
+                                        ClientToServerMessage::StageJoin(stage_id) => {
+                                            if let Some(client) = self.clients.get(con_id) {
+                                                let res = self
+                                                    .game
+                                                    .stage_join(&client.player_id, stage_id);
+                                                self.handle_stage_result(con_id, res);
+                                            }
+                                        }
+                                        ClientToServerMessage::StageLock(locked) => {
+                                            if let Some(client) = self.clients.get(con_id) {
+                                                let res = self
+                                                    .game
+                                                    .stage_set_locked(&client.player_id, *locked);
+                                                self.handle_stage_result(con_id, res);
                                             }
                                         }
                                     }
@@ -326,36 +1103,128 @@ impl Server {
 
             while (cur_time - last_tick_time).as_nanos() > time_until_tick {
                 // game ticks
+                let section_start = self.sys.time_get_nanoseconds();
                 self.game.game.tick(&mut SimulationPipe {
                     player_inputs: &ServerGamePlayerInputForPipe {
                         players: &self.game.players,
                     },
                     collision: &self.game.map.collision,
                 });
+                // TODO: hand them to the mod, until then the pickups only disappear
+                self.game.map_entity_take_collected_pickups();
+                self.tick_profiler.add(
+                    ServerTickSection::GameTick,
+                    self.sys.time_get_nanoseconds() - section_start,
+                );
 
                 // snap shot building
-                for (con_id, client) in &self.clients {
+                for (con_id, client) in &mut self.clients {
                     let snap_client = SnapshotClientInfo {
                         client_player_id: client.player_id,
+                        role: client.role,
                         snap_everything: false,
                         snap_other_stages: false,
                         time_since_connect_nanos: (self.sys.time_get_nanoseconds()
                             - client.connect_timestamp)
                             .as_nanos() as u64,
+                        view_area: client.view_area,
+                        // spectators that watch the inputs of a player also see the player
+                        tracked_players: client.watched_player.as_slice(),
                     };
+                    let section_start = self.sys.time_get_nanoseconds();
                     let snap = self
                         .game
                         .snap_shot_builder
                         .build_for(&self.game.game, &snap_client);
-                    self.network.send_to(
-                        &GameMessage::ServerToClient(ServerToClientMessage::Snapshot(snap)),
+                    let delta = client.snapshot_baselines.encode(&snap);
+                    let send_start = self.sys.time_get_nanoseconds();
+                    self.network.send_to_with_priority(
+                        &GameMessage::ServerToClient(ServerToClientMessage::SnapshotDelta(delta)),
                         &con_id,
+                        NetworkPacketPriority::Game,
                     );
+                    // once per second, so clients can detect desyncs.
+                    // the client only knows the snapped content and its own inputs,
+                    // so both sides simulate the previous snapshot with the inputs of this tick
+                    if snap.monotonic_tick % STATE_CHECKSUM_INTERVAL_TICKS == 0 {
+                        if let Some(mut base) = client.state_checksum_base.take() {
+                            let inputs: Vec<(TGameElementID, LocalPlayerInput)> = base
+                                .get_stages()
+                                .iter()
+                                .flat_map(|stage| stage.get_world().get_characters())
+                                .map(|char| char.cores[0].player_id)
+                                .filter_map(|player_id| {
+                                    self.game
+                                        .players
+                                        .get(&player_id)
+                                        .map(|player| (player_id, player.local_input))
+                                })
+                                .collect();
+                            let checksum = resimulated_state_checksum(
+                                &mut base,
+                                &inputs,
+                                &self.game.map.collision,
+                            );
+                            self.network.send_to(
+                                &GameMessage::ServerToClient(ServerToClientMessage::StateChecksum(
+                                    MsgObjStateChecksum {
+                                        monotonic_tick: snap.monotonic_tick,
+                                        checksum,
+                                        inputs: inputs
+                                            .iter()
+                                            .map(|(player_id, input)| {
+                                                (*player_id, input.to_net_obj())
+                                            })
+                                            .collect(),
+                                    },
+                                )),
+                                &con_id,
+                            );
+                        }
+                    } else if (snap.monotonic_tick + 1) % STATE_CHECKSUM_INTERVAL_TICKS == 0
+                        && !snap.paused
+                    {
+                        let mut base = GameState::new();
+                        self.game
+                            .snap_shot_builder
+                            .convert_to_game_state(&snap, &mut base);
+                        client.state_checksum_base = Some(base);
+                    }
+                    let send_end = self.sys.time_get_nanoseconds();
+                    self.tick_profiler
+                        .add(ServerTickSection::SnapshotBuild, send_start - section_start);
+                    self.tick_profiler
+                        .add(ServerTickSection::NetworkSend, send_end - send_start);
                 }
+                self.tick_profiler.finish_tick();
 
                 last_tick_time += Duration::from_nanos(time_until_tick as u64);
             }
 
+            if let Some(event) = self.votes.update(self.clients.len(), cur_time) {
+                self.handle_vote_event(event);
+            }
+
+            if self
+                .map_rotation
+                .is_match_over(cur_time, &self.game.game.match_stats_report())
+            {
+                self.rotate_map();
+            }
+            self.update_pending_map();
+
+            if cur_time - last_flood_cleanup_time >= FLOOD_PROTECTION_CLEANUP_INTERVAL {
+                self.ip_flood_protection.remove_outdated(cur_time);
+                self.connection_flood_protection.remove_outdated(cur_time);
+                last_flood_cleanup_time = cur_time;
+            }
+
+            if cur_time - last_lan_announce_time >= Duration::from_secs(1) {
+                self.announce_in_lan();
+                self.write_tick_stats();
+                last_lan_announce_time = cur_time;
+            }
+
             // time and sleeps
             let next_tick_time = Duration::from_nanos(
                 (time_until_tick - (cur_time - last_tick_time).as_nanos()) as u64,
@@ -366,11 +1235,29 @@ impl Server {
             //guard = guard.ev_cond.wait_timeout(guard.into(), next_tick_time);
             std::thread::sleep(next_tick_time);
         }
+
+        log_println!("tick statistics:\n{}", self.tick_profiler.summary());
     }
 }
 
 pub fn ddnet_server_main(sys: System, cert: &Certificate, is_open: Arc<AtomicBool>) {
-    let mut server = Server::new(sys, is_open, cert, 16 /* TODO */);
+    let fs = Arc::new(FileSystem::new());
+    // tokio runtime for server side io tasks
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .max_blocking_threads(1)
+        .build()
+        .unwrap();
+    let io_batcher = Arc::new(std::sync::Mutex::new(IOBatcher::new(rt)));
+    let mut server = Server::new(
+        sys,
+        is_open,
+        cert,
+        16, /* TODO */
+        Config::load(),
+        &fs,
+        &io_batcher,
+    );
 
     server.run();
 }
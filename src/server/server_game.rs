@@ -7,24 +7,32 @@ use crate::{
     datafile::{CDatafileWrapper, MapFileLayersReadOptions, MapFileOpenOptions},
     game::{
         collision::Collision,
+        entities::map_entity::MapEntityCore,
         simulation_pipe::{LocalPlayerInput, SimulationPlayerInput},
         snapshot::SnapshotManager,
         state::GameState,
+        world::CollectedPickup,
         GameElementGenerator, TGameElementID,
     },
     hash_queue::HashQueue,
     id_gen::{IDGenerator, IDGeneratorIDType},
+    map_chunks::legacy_map_data,
+    map_hash::MapSectionHash,
+    map_settings::MapSettings,
     mapdef::{EEntityTiles, MapItemTypes},
-    network::messages::{MsgObjPlayerInfo, MsgObjPlayerInput},
+    network::messages::{MsgObjPlayerInfo, MsgObjPlayerInput, MsgObjStageError, MsgSvStageEvent},
 };
 
-use base::system::SystemTimeInterface;
+use base::{log_println, system::SystemTimeInterface};
 
 pub struct ServerPlayer {
+    // invalid for bots
     pub network_id: NetworkConnectionID,
     pub player_info: MsgObjPlayerInfo,
     pub input: MsgObjPlayerInput,
     pub local_input: LocalPlayerInput,
+    // not controlled over the network, the input is set by the server/mod every tick
+    pub is_bot: bool,
 }
 
 impl ServerPlayer {
@@ -34,6 +42,7 @@ impl ServerPlayer {
             player_info: player_info.clone(),
             input: MsgObjPlayerInput::default(),
             local_input: Default::default(),
+            is_bot: false,
         }
     }
 }
@@ -41,6 +50,9 @@ impl ServerPlayer {
 pub struct ServerMap {
     pub raw: CDatafileWrapper,
     pub collision: Collision,
+    pub physics_hash: MapSectionHash,
+    // the validated settings of the map, only contains settings the map is allowed to change
+    pub settings: MapSettings,
 }
 
 impl ServerMap {
@@ -50,7 +62,9 @@ impl ServerMap {
         sys: &Arc<impl SystemTimeInterface + Send + Sync + 'static>,
     ) -> Result<Self, ArrayString<4096>> {
         let map_file_name = map_file.to_string() + &".map";
-        let mapfile = std::fs::read("data/".to_string() + map_file_name.as_str());
+        let mapfile = std::fs::read("data/".to_string() + map_file_name.as_str())
+            .map_err(anyhow::Error::from)
+            .and_then(legacy_map_data);
         if let Ok(map_) = mapfile {
             let mut file_wrap = CDatafileWrapper::new();
             let mut load_options = MapFileOpenOptions::default();
@@ -60,6 +74,7 @@ impl ServerMap {
                 .for_each(|i| *i = true);
             load_options.dont_load_map_item[MapItemTypes::MAPITEMTYPE_GROUP as usize] = false;
             load_options.dont_load_map_item[MapItemTypes::MAPITEMTYPE_LAYER as usize] = false;
+            load_options.dont_load_map_item[MapItemTypes::MAPITEMTYPE_INFO as usize] = false;
             let res = file_wrap.Open(
                 &map_,
                 &map_file,
@@ -67,7 +82,15 @@ impl ServerMap {
                 &load_options,
                 &sys,
             );
+            let mut settings = MapSettings::default();
             if let Ok(data_start) = res {
+                let (map_settings, errors) =
+                    MapSettings::parse(&file_wrap.read_map_setting_commands(data_start));
+                errors.iter().for_each(|err| {
+                    log_println!("map {} has an invalid setting: {:?}", map_file, err);
+                });
+                settings = map_settings;
+
                 CDatafileWrapper::read_map_layers(
                     &file_wrap.data_file,
                     &mut file_wrap.layers,
@@ -81,6 +104,16 @@ impl ServerMap {
             }
             file_wrap.InitLayers(&runtime_thread_pool);
 
+            let diagnostics = file_wrap.validate();
+            diagnostics.warnings().for_each(|warning| {
+                log_println!("map {} has a warning: {:?}", map_file, warning.kind);
+            });
+            if let Some(err) = diagnostics.errors().next() {
+                let mut err_str = ArrayString::from("Map is broken: ").unwrap();
+                let _ = err_str.try_push_str(&format!("{:?}", err.kind));
+                return Err(err_str);
+            }
+
             let collision: Collision;
             let game_layer = file_wrap.get_game_layer();
             let w = game_layer.0.width as u32;
@@ -89,17 +122,70 @@ impl ServerMap {
             let tiles = game_layer.2.as_slice();
             collision = Collision::new(w, h, tiles);
 
+            let physics_hash = file_wrap.physics_hash();
+
             return Ok(Self {
                 raw: file_wrap,
                 collision,
+                physics_hash,
+                settings,
             });
         }
         Err(ArrayString::from("Map could not be loaded.").unwrap())
     }
+
+    /**
+     * If a map with this name exists, only plain names (without path characters) are accepted,
+     * e.g. for names that come from clients
+     */
+    pub fn exists(map_file: &str) -> bool {
+        !map_file.is_empty()
+            && map_file
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            && std::path::Path::new(&("data/".to_string() + map_file + ".map")).is_file()
+    }
+
+    /**
+     * Setting commands of the server (e.g. from the map rotation),
+     * they override the settings of the map
+     */
+    pub fn apply_setting_overrides(&mut self, setting_overrides: &[String]) {
+        if setting_overrides.is_empty() {
+            return;
+        }
+        // later commands override the earlier ones
+        let mut commands = self.settings.to_commands();
+        commands.extend(setting_overrides.iter().cloned());
+        let (settings, errors) = MapSettings::parse(&commands);
+        errors.iter().for_each(|err| {
+            log_println!("invalid setting for map {}: {:?}", self.raw.name, err);
+        });
+        self.settings = settings;
+    }
 }
 
 pub type ServerPlayerID = IDGeneratorIDType;
 
+/**
+ * The rules for stages (ddrace teams), mods can change them
+ */
+#[derive(Debug, Clone)]
+pub struct ServerStageRules {
+    // 0 means unlimited
+    pub max_players_per_stage: usize,
+    pub allow_locking: bool,
+}
+
+impl Default for ServerStageRules {
+    fn default() -> Self {
+        Self {
+            max_players_per_stage: 0,
+            allow_locking: true,
+        }
+    }
+}
+
 pub struct ServerGame {
     pub player_id_gen: IDGenerator,
     pub players: HashQueue<ServerPlayerID, ServerPlayer>,
@@ -107,6 +193,7 @@ pub struct ServerGame {
     pub map: ServerMap,
     pub game_el_gen: GameElementGenerator,
     pub snap_shot_builder: SnapshotManager,
+    pub stage_rules: ServerStageRules,
 }
 
 pub struct ServerGamePlayerInputForPipe<'a> {
@@ -136,6 +223,7 @@ impl ServerGame {
             map: ServerMap::new(start_map, runtime_thread_pool, sys).unwrap(),
             game_el_gen: GameElementGenerator::default(),
             snap_shot_builder: SnapshotManager::new(),
+            stage_rules: ServerStageRules::default(),
         }
     }
 
@@ -144,17 +232,71 @@ impl ServerGame {
         network_id: &NetworkConnectionID,
         player_info: &MsgObjPlayerInfo,
     ) -> ServerPlayerID {
+        self.player_join_impl(ServerPlayer::new(network_id, player_info))
+    }
+
+    /**
+     * Adds a player that is not controlled over the network (practice bots, tutorial npcs),
+     * the input must be set with `bot_inp`
+     */
+    pub fn bot_join(&mut self, player_info: &MsgObjPlayerInfo) -> ServerPlayerID {
+        let mut player = ServerPlayer::new(&NetworkConnectionID::default(), player_info);
+        player.is_bot = true;
+        self.player_join_impl(player)
+    }
+
+    /**
+     * Removes the bot and it's character
+     */
+    pub fn bot_leave(&mut self, player_id: &ServerPlayerID) -> bool {
+        if !self
+            .players
+            .get(player_id)
+            .map_or(false, |player| player.is_bot)
+        {
+            return false;
+        }
+        self.players.remove(player_id);
+        if let Some(index) = self.player_stage_index(player_id) {
+            let characters = self
+                .game
+                .get_stage_mut(index)
+                .get_world_mut()
+                .get_characters_mut();
+            characters.retain(|char| char.cores[0].player_id != *player_id);
+            if characters.is_empty() {
+                self.game.get_stages_mut().remove(index);
+            }
+        }
+        true
+    }
+
+    pub fn bot_inp(&mut self, player_id: &ServerPlayerID, inp: &LocalPlayerInput) {
+        if let Some(player) = self.players.get_mut(player_id) {
+            if player.is_bot {
+                player.input = inp.to_net_obj();
+                player.local_input = *inp;
+            }
+        }
+    }
+
+    fn player_join_impl(&mut self, player: ServerPlayer) -> ServerPlayerID {
         let player_id = self.player_id_gen.get_next();
-        self.players
-            .add_or_set(player_id, ServerPlayer::new(network_id, player_info));
+        self.players.add_or_set(player_id, player);
+
+        self.spawn_character(&player_id);
 
+        player_id
+    }
+
+    fn spawn_character(&mut self, player_id: &ServerPlayerID) {
         // spawn and send character info
         let stage_index = self.game.add_stage(&mut self.game_el_gen);
         let char = self
             .game
             .get_stage_mut(stage_index)
             .get_world_mut()
-            .add_character(&mut self.game_el_gen, &player_id);
+            .add_character(&mut self.game_el_gen, player_id);
 
         let w = self.map.raw.get_game_layer().0.width;
         self.map
@@ -174,8 +316,42 @@ impl ServerGame {
                 }
                 ControlFlow::Continue(())
             });
+    }
 
-        player_id
+    /**
+     * Replaces the map and starts a fresh game on it.
+     * Bots are spawned again, players that are controlled over the network
+     * are removed and must join again after they loaded the new map.
+     * The map should be loaded in the background (see `ServerMap::new`),
+     * loading it blocks for a while.
+     */
+    pub fn change_map(&mut self, mut map: ServerMap, setting_overrides: &[String]) {
+        map.apply_setting_overrides(setting_overrides);
+
+        let player_ids: Vec<ServerPlayerID> = self
+            .game
+            .get_stages()
+            .iter()
+            .flat_map(|stage| stage.get_world().get_characters().iter())
+            .map(|char| char.cores[0].player_id)
+            .collect();
+
+        self.map = map;
+        self.game = GameState::new();
+        self.game_el_gen = GameElementGenerator::default();
+        self.snap_shot_builder = SnapshotManager::new();
+
+        for player_id in player_ids {
+            if self
+                .players
+                .get(&player_id)
+                .map_or(false, |player| player.is_bot)
+            {
+                self.spawn_character(&player_id);
+            } else {
+                self.players.remove(&player_id);
+            }
+        }
     }
 
     pub fn player_inp(&mut self, player_id: &ServerPlayerID, inp: &MsgObjPlayerInput) {
@@ -183,4 +359,185 @@ impl ServerGame {
         player.input = *inp;
         player.local_input = LocalPlayerInput::from_net_obj(&inp)
     }
+
+    fn player_stage_index(&self, player_id: &ServerPlayerID) -> Option<usize> {
+        self.game.get_stages().iter().position(|stage| {
+            stage
+                .get_world()
+                .get_characters()
+                .iter()
+                .any(|char| char.cores[0].player_id == *player_id)
+        })
+    }
+
+    /**
+     * Moves the character of the player to another stage,
+     * the old stage is removed if it became empty
+     */
+    fn move_player_to_stage(&mut self, player_id: &ServerPlayerID, from: usize, to: usize) {
+        let characters = self
+            .game
+            .get_stage_mut(from)
+            .get_world_mut()
+            .get_characters_mut();
+        let char_index = characters
+            .iter()
+            .position(|char| char.cores[0].player_id == *player_id)
+            .unwrap();
+        let char = characters.remove(char_index);
+        let from_is_empty = characters.is_empty();
+        self.game
+            .get_stage_mut(to)
+            .get_world_mut()
+            .get_characters_mut()
+            .push(char);
+        if from_is_empty {
+            self.game.get_stages_mut().remove(from);
+        }
+    }
+
+    pub fn player_stage(&self, player_id: &ServerPlayerID) -> Option<TGameElementID> {
+        self.player_stage_index(player_id)
+            .map(|index| self.game.get_stage(index).game_element_id)
+    }
+
+    /**
+     * All players in the stage, e.g. to route team chat
+     */
+    pub fn stage_members(&self, stage_id: &TGameElementID) -> Vec<ServerPlayerID> {
+        self.game
+            .get_stage_by_game_el_id(stage_id)
+            .map(|stage| {
+                stage
+                    .get_world()
+                    .get_characters()
+                    .iter()
+                    .map(|char| char.cores[0].player_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /**
+     * Spawns an entity (door, moving platform, pickup) in the stage at runtime,
+     * it's replicated with the snapshots
+     */
+    pub fn map_entity_spawn(
+        &mut self,
+        stage_id: &TGameElementID,
+        core: MapEntityCore,
+    ) -> Option<TGameElementID> {
+        let stage = self.game.get_stage_by_game_el_id_mut(stage_id)?;
+        Some(
+            stage
+                .get_world_mut()
+                .add_map_entity(&mut self.game_el_gen, core),
+        )
+    }
+
+    pub fn map_entity_despawn(&mut self, id: &TGameElementID) -> bool {
+        self.game
+            .get_stages_mut()
+            .iter_mut()
+            .any(|stage| stage.get_world_mut().remove_map_entity(id))
+    }
+
+    /**
+     * The pickups that characters collected since the last call, of all stages
+     */
+    pub fn map_entity_take_collected_pickups(&mut self) -> Vec<CollectedPickup> {
+        self.game
+            .get_stages_mut()
+            .iter_mut()
+            .flat_map(|stage| stage.get_world_mut().take_collected_pickups())
+            .collect()
+    }
+
+    /**
+     * The entity core can be changed directly, e.g. to open a door
+     */
+    pub fn map_entity_mut(&mut self, id: &TGameElementID) -> Option<&mut MapEntityCore> {
+        self.game.get_stages_mut().iter_mut().find_map(|stage| {
+            stage
+                .get_world_mut()
+                .get_map_entities_mut()
+                .iter_mut()
+                .find(|ent| ent.base.game_element_id == *id)
+                .map(|ent| &mut ent.core)
+        })
+    }
+
+    /**
+     * Moves the player into a new stage
+     */
+    pub fn stage_create(
+        &mut self,
+        player_id: &ServerPlayerID,
+    ) -> Result<MsgSvStageEvent, MsgObjStageError> {
+        let from = self
+            .player_stage_index(player_id)
+            .ok_or(MsgObjStageError::UnknownPlayer)?;
+        let to = self.game.add_stage(&mut self.game_el_gen);
+        let stage_id = self.game.get_stage(to).game_element_id;
+        self.move_player_to_stage(player_id, from, to);
+        Ok(MsgSvStageEvent::Joined {
+            player_id: *player_id,
+            stage_id,
+        })
+    }
+
+    pub fn stage_join(
+        &mut self,
+        player_id: &ServerPlayerID,
+        stage_id: &TGameElementID,
+    ) -> Result<MsgSvStageEvent, MsgObjStageError> {
+        let from = self
+            .player_stage_index(player_id)
+            .ok_or(MsgObjStageError::UnknownPlayer)?;
+        let to = self
+            .game
+            .get_stages()
+            .iter()
+            .position(|stage| stage.game_element_id == *stage_id)
+            .ok_or(MsgObjStageError::UnknownStage)?;
+        if from == to {
+            return Err(MsgObjStageError::AlreadyInStage);
+        }
+        let stage = self.game.get_stage(to);
+        if stage.locked {
+            return Err(MsgObjStageError::Locked);
+        }
+        if self.stage_rules.max_players_per_stage != 0
+            && stage.get_world().get_characters().len() >= self.stage_rules.max_players_per_stage
+        {
+            return Err(MsgObjStageError::Full);
+        }
+        self.move_player_to_stage(player_id, from, to);
+        Ok(MsgSvStageEvent::Joined {
+            player_id: *player_id,
+            stage_id: *stage_id,
+        })
+    }
+
+    /**
+     * Locks or unlocks the stage the player is in
+     */
+    pub fn stage_set_locked(
+        &mut self,
+        player_id: &ServerPlayerID,
+        locked: bool,
+    ) -> Result<MsgSvStageEvent, MsgObjStageError> {
+        if !self.stage_rules.allow_locking {
+            return Err(MsgObjStageError::LockingNotAllowed);
+        }
+        let index = self
+            .player_stage_index(player_id)
+            .ok_or(MsgObjStageError::UnknownPlayer)?;
+        let stage = self.game.get_stage_mut(index);
+        stage.locked = locked;
+        Ok(MsgSvStageEvent::LockChanged {
+            stage_id: stage.game_element_id,
+            locked,
+        })
+    }
 }
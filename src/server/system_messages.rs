@@ -0,0 +1,56 @@
+use crate::network::messages::{MsgSvSystemMessage, NetworkStr};
+
+/**
+ * The messages the server sends to the players, the clients translate them by id
+ */
+pub enum ServerSystemMessage<'a> {
+    PlayerJoined { name: &'a str },
+    PlayerKicked { name: &'a str },
+    VotePassed,
+    VoteFailed,
+    MatchEnded,
+}
+
+impl<'a> ServerSystemMessage<'a> {
+    fn id(&self) -> &'static str {
+        match self {
+            Self::PlayerJoined { .. } => "player_joined",
+            Self::PlayerKicked { .. } => "player_kicked",
+            Self::VotePassed => "vote_passed",
+            Self::VoteFailed => "vote_failed",
+            Self::MatchEnded => "match_ended",
+        }
+    }
+
+    fn params(&self) -> Vec<&str> {
+        match self {
+            Self::PlayerJoined { name } | Self::PlayerKicked { name } => vec![*name],
+            Self::VotePassed | Self::VoteFailed | Self::MatchEnded => Vec::new(),
+        }
+    }
+
+    /**
+     * The english text for clients that don't know the id
+     */
+    fn fallback(&self) -> String {
+        match self {
+            Self::PlayerJoined { name } => format!("'{}' entered the game", name),
+            Self::PlayerKicked { name } => format!("'{}' was kicked", name),
+            Self::VotePassed => "Vote passed".to_string(),
+            Self::VoteFailed => "Vote failed".to_string(),
+            Self::MatchEnded => "The match ended".to_string(),
+        }
+    }
+
+    pub fn to_net(&self) -> MsgSvSystemMessage {
+        MsgSvSystemMessage {
+            id: NetworkStr::from_truncated(self.id()),
+            params: self
+                .params()
+                .iter()
+                .map(|param| NetworkStr::from_truncated(param))
+                .collect(),
+            fallback: NetworkStr::from_truncated(&self.fallback()),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::{fmt::Write, time::Duration};
+
+/**
+ * The parts of a server tick that are timed independently
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerTickSection {
+    GameTick = 0,
+    SnapshotBuild,
+    NetworkSend,
+}
+
+impl ServerTickSection {
+    pub const COUNT: usize = 3;
+
+    const ALL: [ServerTickSection; Self::COUNT] = [
+        ServerTickSection::GameTick,
+        ServerTickSection::SnapshotBuild,
+        ServerTickSection::NetworkSend,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            ServerTickSection::GameTick => "game_tick",
+            ServerTickSection::SnapshotBuild => "snapshot_build",
+            ServerTickSection::NetworkSend => "network_send",
+        }
+    }
+}
+
+// the upper bounds of the histogram buckets in microseconds,
+// the last bucket takes everything that is slower
+const BUCKET_BOUNDS_MICROS: [u64; 10] = [50, 100, 250, 500, 1000, 2500, 5000, 10000, 20000, 50000];
+
+#[derive(Debug, Clone, Default)]
+pub struct TickTimeHistogram {
+    buckets: [u64; BUCKET_BOUNDS_MICROS.len() + 1],
+    sum: Duration,
+    count: u64,
+    max: Duration,
+}
+
+impl TickTimeHistogram {
+    pub fn add(&mut self, time: Duration) {
+        let micros = time.as_micros() as u64;
+        let index = BUCKET_BOUNDS_MICROS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_MICROS.len());
+        self.buckets[index] += 1;
+        self.sum += time;
+        self.count += 1;
+        self.max = self.max.max(time);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn avg(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(self.sum.as_secs_f64() / self.count as f64)
+        }
+    }
+
+    fn write_prometheus(&self, res: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bucket, bound) in self.buckets.iter().zip(BUCKET_BOUNDS_MICROS.iter()) {
+            cumulative += bucket;
+            let _ = writeln!(
+                res,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                name,
+                labels,
+                *bound as f64 / 1_000_000.0,
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            res,
+            "{}_bucket{{{}le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        // metrics without labels have no braces
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{}}}", labels),
+        };
+        let _ = writeln!(res, "{}_sum{} {}", name, labels, self.sum.as_secs_f64());
+        let _ = writeln!(res, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/**
+ * Collects how long the parts of the server ticks took,
+ * so admins can see what causes lag spikes
+ */
+pub struct ServerTickProfiler {
+    sections: [TickTimeHistogram; ServerTickSection::COUNT],
+    // the time of the parts of the current tick
+    cur_tick: [Duration; ServerTickSection::COUNT],
+    total: TickTimeHistogram,
+    // ticks that took longer than this are counted as over budget
+    tick_budget: Duration,
+    over_budget_ticks: u64,
+}
+
+impl ServerTickProfiler {
+    pub fn new(tick_budget: Duration) -> Self {
+        Self {
+            sections: Default::default(),
+            cur_tick: Default::default(),
+            total: Default::default(),
+            tick_budget,
+            over_budget_ticks: 0,
+        }
+    }
+
+    /**
+     * Parts can be added multiple times per tick, e.g. once per client
+     */
+    pub fn add(&mut self, section: ServerTickSection, time: Duration) {
+        self.cur_tick[section as usize] += time;
+    }
+
+    /**
+     * Call this after all parts of the tick were added
+     */
+    pub fn finish_tick(&mut self) {
+        let mut total = Duration::ZERO;
+        for (histogram, time) in self.sections.iter_mut().zip(self.cur_tick.iter_mut()) {
+            histogram.add(*time);
+            total += *time;
+            *time = Duration::ZERO;
+        }
+        self.total.add(total);
+        if total > self.tick_budget {
+            self.over_budget_ticks += 1;
+        }
+    }
+
+    pub fn section(&self, section: ServerTickSection) -> &TickTimeHistogram {
+        &self.sections[section as usize]
+    }
+
+    pub fn total(&self) -> &TickTimeHistogram {
+        &self.total
+    }
+
+    pub fn over_budget_ticks(&self) -> u64 {
+        self.over_budget_ticks
+    }
+
+    /**
+     * The statistics in the prometheus text format
+     */
+    pub fn to_prometheus_text(&self) -> String {
+        let mut res = String::new();
+        res.push_str("# TYPE server_tick_section_seconds histogram\n");
+        for section in ServerTickSection::ALL {
+            self.section(section).write_prometheus(
+                &mut res,
+                "server_tick_section_seconds",
+                &format!("section=\"{}\",", section.name()),
+            );
+        }
+        res.push_str("# TYPE server_tick_seconds histogram\n");
+        self.total
+            .write_prometheus(&mut res, "server_tick_seconds", "");
+        res.push_str("# TYPE server_tick_over_budget_total counter\n");
+        let _ = writeln!(
+            res,
+            "server_tick_over_budget_total {}",
+            self.over_budget_ticks
+        );
+        res
+    }
+
+    /**
+     * A short human readable summary, e.g. for the console of admins
+     */
+    pub fn summary(&self) -> String {
+        let mut res = String::new();
+        for section in ServerTickSection::ALL {
+            let histogram = self.section(section);
+            let _ = writeln!(
+                res,
+                "{}: avg {} us, max {} us",
+                section.name(),
+                histogram.avg().as_micros(),
+                histogram.max().as_micros()
+            );
+        }
+        let _ = writeln!(
+            res,
+            "tick: avg {} us, max {} us, {} of {} ticks over budget",
+            self.total.avg().as_micros(),
+            self.total.max().as_micros(),
+            self.over_budget_ticks,
+            self.total.count()
+        );
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, time::Duration};
+
+    use super::{ServerTickProfiler, ServerTickSection};
+
+    /**
+     * Splits a sample line into the metric name, the labels and the value
+     */
+    fn parse_sample(line: &str) -> (&str, Vec<(&str, &str)>, f64) {
+        let (metric, value) = line.rsplit_once(' ').expect("sample without value");
+        let value = value.parse::<f64>().expect("value is not a number");
+        let (name, labels) = match metric.split_once('{') {
+            Some((name, labels)) => {
+                let labels = labels.strip_suffix('}').expect("labels are not closed");
+                assert!(!labels.is_empty(), "empty labels: {}", line);
+                let labels = labels
+                    .split(',')
+                    .map(|label| {
+                        let (key, value) = label.split_once('=').expect("label without value");
+                        let value = value
+                            .strip_prefix('"')
+                            .and_then(|value| value.strip_suffix('"'))
+                            .expect("label value is not quoted");
+                        (key, value)
+                    })
+                    .collect();
+                (name, labels)
+            }
+            None => (metric, Vec::new()),
+        };
+        assert!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':'),
+            "invalid metric name: {}",
+            line
+        );
+        (name, labels, value)
+    }
+
+    #[test]
+    fn prometheus_format() {
+        let mut profiler = ServerTickProfiler::new(Duration::from_millis(20));
+        for tick in 0..10 {
+            profiler.add(ServerTickSection::GameTick, Duration::from_micros(300));
+            profiler.add(ServerTickSection::SnapshotBuild, Duration::from_micros(70));
+            profiler.add(ServerTickSection::SnapshotBuild, Duration::from_micros(70));
+            if tick == 9 {
+                // a lag spike
+                profiler.add(ServerTickSection::NetworkSend, Duration::from_millis(100));
+            }
+            profiler.finish_tick();
+        }
+        assert_eq!(profiler.total().count(), 10);
+        assert_eq!(profiler.over_budget_ticks(), 1);
+        assert_eq!(
+            profiler.section(ServerTickSection::SnapshotBuild).max(),
+            Duration::from_micros(140)
+        );
+
+        let text = profiler.to_prometheus_text();
+        assert!(text.ends_with('\n'));
+        let mut typed = HashSet::new();
+        let mut last_bucket: Option<(String, f64)> = None;
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = comment.split_once(' ').unwrap();
+                assert!(kind == "histogram" || kind == "counter");
+                assert!(typed.insert(name.to_string()), "{} typed twice", name);
+                continue;
+            }
+            let (name, labels, value) = parse_sample(line);
+            let family = name
+                .strip_suffix("_bucket")
+                .or_else(|| name.strip_suffix("_sum"))
+                .or_else(|| name.strip_suffix("_count"))
+                .filter(|family| typed.contains(*family))
+                .unwrap_or(name);
+            assert!(typed.contains(family), "{} has no type", name);
+
+            if name.ends_with("_bucket") {
+                let le = labels
+                    .iter()
+                    .find(|(key, _)| *key == "le")
+                    .expect("bucket without le label")
+                    .1;
+                assert!(le == "+Inf" || le.parse::<f64>().is_ok());
+                // the buckets are cumulative
+                let series = format!("{}{:?}", name, &labels[..labels.len() - 1]);
+                if let Some((last_series, last_value)) = &last_bucket {
+                    if *last_series == series {
+                        assert!(value >= *last_value);
+                    }
+                }
+                last_bucket = Some((series, value));
+            }
+        }
+        assert!(text.contains("server_tick_section_seconds_count{section=\"game_tick\"} 10\n"));
+        assert!(text.contains("server_tick_seconds_bucket{le=\"+Inf\"} 10\n"));
+        assert!(text.contains("server_tick_seconds_count 10\n"));
+        assert!(text.contains("server_tick_over_budget_total 1\n"));
+    }
+}
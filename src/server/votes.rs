@@ -0,0 +1,338 @@
+use std::{collections::HashMap, time::Duration};
+
+use network::network::network::NetworkConnectionID;
+
+use crate::network::messages::{
+    MsgObjVoteKind, MsgObjVoteProgress, MsgObjVoteProposal, MsgObjVoteResult,
+};
+
+/**
+ * Called when a custom vote passed
+ */
+pub type ServerCustomVoteCallback = Box<dyn FnMut(&MsgObjVoteProposal) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerVoteError {
+    // only one vote can run at a time
+    AlreadyRunning,
+    NotRunning,
+    UnknownCustomVote,
+    UnknownMap,
+    // e.g. a spectator or a player that already left
+    InvalidKickTarget,
+}
+
+/**
+ * What the server has to tell the clients (and do) after an update
+ */
+pub enum ServerVoteEvent {
+    Progress(MsgObjVoteProgress),
+    // the kick & map change must be done by the server,
+    // custom votes already called their callback
+    Ended(MsgObjVoteProposal, MsgObjVoteResult),
+}
+
+struct ServerVote {
+    proposal: MsgObjVoteProposal,
+    end_time: Duration,
+    // true for yes
+    votes: HashMap<NetworkConnectionID, bool>,
+    // if the votes changed since the last update
+    changed: bool,
+}
+
+pub struct ServerVotes {
+    cur: Option<ServerVote>,
+    custom_votes: HashMap<String, ServerCustomVoteCallback>,
+    vote_duration: Duration,
+    // kick votes only pass with this many votes, so a single client can't kick others
+    min_kick_voters: usize,
+}
+
+impl ServerVotes {
+    pub fn new(vote_duration: Duration, min_kick_voters: usize) -> Self {
+        Self {
+            cur: None,
+            custom_votes: HashMap::new(),
+            vote_duration,
+            min_kick_voters,
+        }
+    }
+
+    /**
+     * Mods can add own votes that clients can call by name
+     */
+    pub fn register_custom_vote(&mut self, name: &str, callback: ServerCustomVoteCallback) {
+        self.custom_votes.insert(name.to_string(), callback);
+    }
+
+    pub fn call_vote(
+        &mut self,
+        caller: &NetworkConnectionID,
+        proposal: MsgObjVoteProposal,
+        cur_time: Duration,
+    ) -> Result<MsgObjVoteProposal, ServerVoteError> {
+        if self.cur.is_some() {
+            return Err(ServerVoteError::AlreadyRunning);
+        }
+        if let MsgObjVoteKind::Custom(name) = &proposal.kind {
+            if !self.custom_votes.contains_key(name.as_str()) {
+                return Err(ServerVoteError::UnknownCustomVote);
+            }
+        }
+        let mut votes = HashMap::new();
+        // the caller votes yes
+        votes.insert(*caller, true);
+        self.cur = Some(ServerVote {
+            proposal: proposal.clone(),
+            end_time: cur_time + self.vote_duration,
+            votes,
+            changed: true,
+        });
+        Ok(proposal)
+    }
+
+    pub fn vote(&mut self, voter: &NetworkConnectionID, yes: bool) -> Result<(), ServerVoteError> {
+        let vote = self.cur.as_mut().ok_or(ServerVoteError::NotRunning)?;
+        let prev = vote.votes.insert(*voter, yes);
+        vote.changed |= prev != Some(yes);
+        Ok(())
+    }
+
+    /**
+     * The votes of clients that left don't count anymore
+     */
+    pub fn client_left(&mut self, client: &NetworkConnectionID) {
+        if let Some(vote) = &mut self.cur {
+            vote.changed |= vote.votes.remove(client).is_some();
+        }
+    }
+
+    pub fn cancel(&mut self) -> Option<ServerVoteEvent> {
+        self.cur
+            .take()
+            .map(|vote| ServerVoteEvent::Ended(vote.proposal, MsgObjVoteResult::Canceled))
+    }
+
+    pub fn progress(&self, participants: usize, cur_time: Duration) -> Option<MsgObjVoteProgress> {
+        self.cur.as_ref().map(|vote| MsgObjVoteProgress {
+            yes: vote.votes.values().filter(|yes| **yes).count() as u32,
+            no: vote.votes.values().filter(|yes| !**yes).count() as u32,
+            participants: participants as u32,
+            remaining_time_millis: vote.end_time.saturating_sub(cur_time).as_millis() as u64,
+        })
+    }
+
+    /**
+     * A vote passes early if more than half of the participants voted yes,
+     * and fails early if at least half voted no.
+     * Else after the vote time the majority of the votes decides.
+     * Kick votes also need a minimum of voters to pass.
+     */
+    pub fn update(&mut self, participants: usize, cur_time: Duration) -> Option<ServerVoteEvent> {
+        let progress = self.progress(participants, cur_time)?;
+        let min_voters = self.min_kick_voters;
+        let vote = self.cur.as_mut().unwrap();
+        let enough_voters = !matches!(vote.proposal.kind, MsgObjVoteKind::Kick(_))
+            || (progress.yes + progress.no) as usize >= min_voters;
+        let result = if progress.yes * 2 > progress.participants && enough_voters {
+            Some(MsgObjVoteResult::Passed)
+        } else if progress.no * 2 >= progress.participants {
+            Some(MsgObjVoteResult::Failed)
+        } else if cur_time >= vote.end_time {
+            Some(if progress.yes > progress.no && enough_voters {
+                MsgObjVoteResult::Passed
+            } else {
+                MsgObjVoteResult::Failed
+            })
+        } else {
+            None
+        };
+
+        match result {
+            Some(result) => {
+                let vote = self.cur.take().unwrap();
+                if result == MsgObjVoteResult::Passed {
+                    if let MsgObjVoteKind::Custom(name) = &vote.proposal.kind {
+                        if let Some(callback) = self.custom_votes.get_mut(name.as_str()) {
+                            callback(&vote.proposal);
+                        }
+                    }
+                }
+                Some(ServerVoteEvent::Ended(vote.proposal, result))
+            }
+            None => {
+                if vote.changed {
+                    vote.changed = false;
+                    Some(ServerVoteEvent::Progress(progress))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use network::network::network::{NetworkConnectionID, NetworkConnectionIDCounter};
+
+    use crate::{
+        game::INVALID_GAME_ELEMENT_ID,
+        network::messages::{MsgObjVoteKind, MsgObjVoteProposal, MsgObjVoteResult, NetworkStr},
+    };
+
+    use super::{ServerVoteError, ServerVoteEvent, ServerVotes};
+
+    const VOTE_DURATION: Duration = Duration::from_secs(30);
+
+    fn clients(count: usize) -> Vec<NetworkConnectionID> {
+        let counter = NetworkConnectionIDCounter::new();
+        (0..count).map(|_| counter.get_next()).collect()
+    }
+
+    fn proposal(kind: MsgObjVoteKind) -> MsgObjVoteProposal {
+        MsgObjVoteProposal {
+            kind,
+            reason: NetworkStr::from_truncated(""),
+            caller_player_id: INVALID_GAME_ELEMENT_ID,
+        }
+    }
+
+    fn change_map() -> MsgObjVoteProposal {
+        proposal(MsgObjVoteKind::ChangeMap(NetworkStr::from_truncated("dm1")))
+    }
+
+    fn ended_with(event: Option<ServerVoteEvent>, expected: MsgObjVoteResult) -> bool {
+        matches!(event, Some(ServerVoteEvent::Ended(_, result)) if result == expected)
+    }
+
+    #[test]
+    fn vote_quorum() {
+        let ids = clients(4);
+        let start = Duration::from_secs(1);
+        let mut votes = ServerVotes::new(VOTE_DURATION, 3);
+
+        // more than half of the participants must vote yes
+        assert!(votes.call_vote(&ids[0], change_map(), start).is_ok());
+        assert!(matches!(
+            votes.call_vote(&ids[1], change_map(), start),
+            Err(ServerVoteError::AlreadyRunning)
+        ));
+        assert!(matches!(
+            votes.update(ids.len(), start),
+            Some(ServerVoteEvent::Progress(progress)) if progress.yes == 1 && progress.no == 0
+        ));
+        votes.vote(&ids[1], true).unwrap();
+        assert!(matches!(
+            votes.update(ids.len(), start),
+            Some(ServerVoteEvent::Progress(_))
+        ));
+        votes.vote(&ids[2], true).unwrap();
+        assert!(ended_with(
+            votes.update(ids.len(), start),
+            MsgObjVoteResult::Passed
+        ));
+        assert!(votes.update(ids.len(), start).is_none());
+
+        // half of the participants voting no is enough to fail
+        votes.call_vote(&ids[0], change_map(), start).unwrap();
+        votes.vote(&ids[1], false).unwrap();
+        assert!(matches!(
+            votes.update(ids.len(), start),
+            Some(ServerVoteEvent::Progress(_))
+        ));
+        votes.vote(&ids[2], false).unwrap();
+        assert!(ended_with(
+            votes.update(ids.len(), start),
+            MsgObjVoteResult::Failed
+        ));
+
+        // kick votes don't pass with fewer voters than the minimum, even with all yes votes
+        votes
+            .call_vote(
+                &ids[0],
+                proposal(MsgObjVoteKind::Kick(INVALID_GAME_ELEMENT_ID)),
+                start,
+            )
+            .unwrap();
+        votes.vote(&ids[1], true).unwrap();
+        assert!(matches!(
+            votes.update(2, start),
+            Some(ServerVoteEvent::Progress(_))
+        ));
+        assert!(ended_with(
+            votes.update(2, start + VOTE_DURATION),
+            MsgObjVoteResult::Failed
+        ));
+    }
+
+    #[test]
+    fn vote_timeout() {
+        let ids = clients(10);
+        let start = Duration::from_secs(1);
+        let end = start + VOTE_DURATION;
+        let mut votes = ServerVotes::new(VOTE_DURATION, 3);
+
+        votes.call_vote(&ids[0], change_map(), start).unwrap();
+        votes.vote(&ids[1], false).unwrap();
+        assert!(matches!(
+            votes.update(ids.len(), end - Duration::from_millis(1)),
+            Some(ServerVoteEvent::Progress(progress)) if progress.remaining_time_millis == 1
+        ));
+        // nothing changed
+        assert!(votes
+            .update(ids.len(), end - Duration::from_millis(1))
+            .is_none());
+        // a tie fails
+        assert!(ended_with(
+            votes.update(ids.len(), end),
+            MsgObjVoteResult::Failed
+        ));
+
+        // after the vote time the majority of the votes decides
+        votes.call_vote(&ids[0], change_map(), start).unwrap();
+        votes.vote(&ids[1], false).unwrap();
+        votes.vote(&ids[2], true).unwrap();
+        votes.vote(&ids[3], false).unwrap();
+        // the votes of clients that left don't count, else this would be a tie
+        votes.client_left(&ids[3]);
+        assert!(ended_with(
+            votes.update(ids.len(), end),
+            MsgObjVoteResult::Passed
+        ));
+    }
+
+    #[test]
+    fn custom_vote() {
+        let ids = clients(2);
+        let mut votes = ServerVotes::new(VOTE_DURATION, 3);
+        let custom = || proposal(MsgObjVoteKind::Custom(NetworkStr::from_truncated("reset")));
+        assert!(matches!(
+            votes.call_vote(&ids[0], custom(), Duration::ZERO),
+            Err(ServerVoteError::UnknownCustomVote)
+        ));
+
+        let called = Arc::new(AtomicBool::new(false));
+        let called_clone = called.clone();
+        votes.register_custom_vote(
+            "reset",
+            Box::new(move |_| called_clone.store(true, Ordering::Relaxed)),
+        );
+        votes.call_vote(&ids[0], custom(), Duration::ZERO).unwrap();
+        votes.vote(&ids[1], true).unwrap();
+        assert!(ended_with(
+            votes.update(ids.len(), Duration::ZERO),
+            MsgObjVoteResult::Passed
+        ));
+        assert!(called.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,443 @@
+use crate::mapdef::{CTile, MapLayerTile, TileFlag};
+
+const TEST_FLAGS: u8 = TileFlag::XFLIP as u8 | TileFlag::YFLIP as u8 | TileFlag::ROTATE as u8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoMapperIndexInfo {
+    // -1 matches tiles outside of the layer
+    pub index: i32,
+    pub flags: u8,
+    // if false, the flags of the checked tile are ignored
+    pub test_flags: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoMapperPosRuleKind {
+    // the tile must match one of the indices
+    Index,
+    // the tile must not match any of the indices
+    NotIndex,
+}
+
+/**
+ * A condition for the tile at the offset `x`, `y` relative to the current tile.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoMapperPosRule {
+    pub x: i32,
+    pub y: i32,
+    pub kind: AutoMapperPosRuleKind,
+    pub indices: Vec<AutoMapperIndexInfo>,
+}
+
+/**
+ * Sets the tile to `index` and `flags`, if all rules match.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoMapperIndexRule {
+    pub index: u8,
+    pub flags: u8,
+    pub rules: Vec<AutoMapperPosRule>,
+    // chance between 0.0 and 1.0 that the tile is set, if the rules match
+    pub random_probability: f32,
+}
+
+/**
+ * The index rules of a run are applied for every tile, the last matching rule wins.
+ * By default the rules read from a copy of the layer as it was before the run,
+ * so changed tiles don't affect the following tiles of the same run.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoMapperRun {
+    pub index_rules: Vec<AutoMapperIndexRule>,
+    pub use_layer_copy: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoMapperConfig {
+    pub name: String,
+    pub runs: Vec<AutoMapperRun>,
+}
+
+/**
+ * Applies mapping rules to tile layers, e.g. to place the fitting border tiles
+ * around filled areas.
+ * Works without any graphics or editor state, so headless tools can use it too.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AutoMapper {
+    pub configs: Vec<AutoMapperConfig>,
+}
+
+fn parse_flag(word: &str) -> Option<u8> {
+    match word {
+        "XFLIP" => Some(TileFlag::XFLIP as u8),
+        "YFLIP" => Some(TileFlag::YFLIP as u8),
+        "ROTATE" => Some(TileFlag::ROTATE as u8),
+        _ => None,
+    }
+}
+
+/**
+ * Parses the index list of a `Pos` rule, e.g. `1 XFLIP OR 2 NONE OR 3`.
+ */
+fn parse_index_list<'a>(
+    words: impl Iterator<Item = &'a str>,
+    line_index: usize,
+) -> anyhow::Result<Vec<AutoMapperIndexInfo>> {
+    let mut res: Vec<AutoMapperIndexInfo> = Vec::new();
+    let mut expect_index = true;
+    for word in words {
+        if expect_index {
+            let index = word.parse::<i32>().map_err(|_| {
+                anyhow::Error::msg(format!(
+                    "line {}: expected a tile index, found \"{}\"",
+                    line_index + 1,
+                    word
+                ))
+            })?;
+            res.push(AutoMapperIndexInfo {
+                index,
+                flags: 0,
+                test_flags: false,
+            });
+            expect_index = false;
+        } else if word == "OR" {
+            expect_index = true;
+        } else {
+            let info = res.last_mut().unwrap();
+            info.test_flags = true;
+            if word != "NONE" {
+                info.flags |= parse_flag(word).ok_or_else(|| {
+                    anyhow::Error::msg(format!(
+                        "line {}: unknown tile flag \"{}\"",
+                        line_index + 1,
+                        word
+                    ))
+                })?;
+            }
+        }
+    }
+    if res.is_empty() || expect_index {
+        return Err(anyhow::Error::msg(format!(
+            "line {}: the index list is incomplete",
+            line_index + 1
+        )));
+    }
+    Ok(res)
+}
+
+/**
+ * A stable pseudo random value between 0.0 and 1.0,
+ * so applying the same rules with the same seed gives the same result.
+ */
+fn random_value(seed: u32, x: usize, y: usize, rule: usize) -> f32 {
+    let mut hash = (seed as u64)
+        ^ ((x as u64) << 16)
+        ^ ((y as u64) << 40)
+        ^ ((rule as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    // splitmix64 finalizer
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^= hash >> 31;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+impl AutoMapper {
+    /**
+     * Parses the rules file format of DDNet (the `.rules` files in `data/editor`).
+     * Every `[Name]` starts a new config, `NewRun` starts a new run inside a config.
+     */
+    pub fn parse_ddnet_rules(content: &str) -> anyhow::Result<Self> {
+        let mut res = Self::default();
+        // rules without an explicit `NoDefaultRule` require the tile itself to be non empty
+        let mut default_rule = true;
+        let finish_index_rule = |config: Option<&mut AutoMapperConfig>, default_rule: bool| {
+            if let Some(index_rule) = config
+                .and_then(|config| config.runs.last_mut())
+                .and_then(|run| run.index_rules.last_mut())
+            {
+                if default_rule {
+                    index_rule.rules.push(AutoMapperPosRule {
+                        x: 0,
+                        y: 0,
+                        kind: AutoMapperPosRuleKind::NotIndex,
+                        indices: vec![AutoMapperIndexInfo {
+                            index: 0,
+                            flags: 0,
+                            test_flags: false,
+                        }],
+                    });
+                }
+            }
+        };
+
+        for (line_index, line) in content.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                finish_index_rule(res.configs.last_mut(), default_rule);
+                default_rule = true;
+                let name = line.trim_start_matches('[');
+                let name = name.split(']').next().unwrap_or_default();
+                res.configs.push(AutoMapperConfig {
+                    name: name.to_string(),
+                    runs: vec![AutoMapperRun {
+                        index_rules: Vec::new(),
+                        use_layer_copy: true,
+                    }],
+                });
+                continue;
+            }
+
+            let config = res.configs.last_mut().ok_or_else(|| {
+                anyhow::Error::msg(format!(
+                    "line {}: rules must be inside a [config]",
+                    line_index + 1
+                ))
+            })?;
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            match keyword {
+                "NewRun" => {
+                    finish_index_rule(Some(config), default_rule);
+                    default_rule = true;
+                    config.runs.push(AutoMapperRun {
+                        index_rules: Vec::new(),
+                        use_layer_copy: true,
+                    });
+                }
+                "NoLayerCopy" => {
+                    config.runs.last_mut().unwrap().use_layer_copy = false;
+                }
+                "Index" => {
+                    finish_index_rule(Some(config), default_rule);
+                    default_rule = true;
+                    let index = words
+                        .next()
+                        .and_then(|index| index.parse::<u8>().ok())
+                        .ok_or_else(|| {
+                            anyhow::Error::msg(format!(
+                                "line {}: expected a tile index",
+                                line_index + 1
+                            ))
+                        })?;
+                    let mut flags = 0;
+                    for word in words {
+                        flags |= parse_flag(word).ok_or_else(|| {
+                            anyhow::Error::msg(format!(
+                                "line {}: unknown tile flag \"{}\"",
+                                line_index + 1,
+                                word
+                            ))
+                        })?;
+                    }
+                    config
+                        .runs
+                        .last_mut()
+                        .unwrap()
+                        .index_rules
+                        .push(AutoMapperIndexRule {
+                            index,
+                            flags,
+                            rules: Vec::new(),
+                            random_probability: 1.0,
+                        });
+                }
+                _ => {
+                    let index_rule = config
+                        .runs
+                        .last_mut()
+                        .unwrap()
+                        .index_rules
+                        .last_mut()
+                        .ok_or_else(|| {
+                            anyhow::Error::msg(format!(
+                                "line {}: \"{}\" must follow an Index line",
+                                line_index + 1,
+                                keyword
+                            ))
+                        })?;
+                    match keyword {
+                        "Pos" => {
+                            let mut pos = || {
+                                words
+                                    .next()
+                                    .and_then(|pos| pos.parse::<i32>().ok())
+                                    .ok_or_else(|| {
+                                        anyhow::Error::msg(format!(
+                                            "line {}: expected a position",
+                                            line_index + 1
+                                        ))
+                                    })
+                            };
+                            let x = pos()?;
+                            let y = pos()?;
+                            let empty = || {
+                                vec![AutoMapperIndexInfo {
+                                    index: 0,
+                                    flags: 0,
+                                    test_flags: false,
+                                }]
+                            };
+                            let (kind, indices) = match words.next() {
+                                Some("EMPTY") => (AutoMapperPosRuleKind::Index, empty()),
+                                Some("FULL") => (AutoMapperPosRuleKind::NotIndex, empty()),
+                                Some("INDEX") => (
+                                    AutoMapperPosRuleKind::Index,
+                                    parse_index_list(words, line_index)?,
+                                ),
+                                Some("NOTINDEX") => (
+                                    AutoMapperPosRuleKind::NotIndex,
+                                    parse_index_list(words, line_index)?,
+                                ),
+                                // like DDNet, rules with unknown values are ignored
+                                _ => continue,
+                            };
+                            index_rule.rules.push(AutoMapperPosRule {
+                                x,
+                                y,
+                                kind,
+                                indices,
+                            });
+                        }
+                        "Random" => {
+                            // either `Random 50%` or `Random 150` for a chance of 1/150
+                            let value = words.next().unwrap_or_default();
+                            let number_len = value
+                                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                                .unwrap_or(value.len());
+                            let number = value[..number_len].parse::<f32>().map_err(|_| {
+                                anyhow::Error::msg(format!(
+                                    "line {}: invalid random value \"{}\"",
+                                    line_index + 1,
+                                    value
+                                ))
+                            })?;
+                            index_rule.random_probability = if value[number_len..].starts_with('%')
+                            {
+                                (number / 100.0).clamp(0.0, 1.0)
+                            } else if number > 0.0 {
+                                1.0 / number
+                            } else {
+                                1.0
+                            };
+                        }
+                        "NoDefaultRule" => default_rule = false,
+                        _ => {
+                            return Err(anyhow::Error::msg(format!(
+                                "line {}: unknown keyword \"{}\"",
+                                line_index + 1,
+                                keyword
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+        finish_index_rule(res.configs.last_mut(), default_rule);
+        Ok(res)
+    }
+
+    pub fn config_index(&self, name: &str) -> Option<usize> {
+        self.configs.iter().position(|config| config.name == name)
+    }
+
+    /**
+     * Applies the config to the tiles of a layer with the given size.
+     * The `skip` values of the tiles are not updated (see `InitTilemapSkip`).
+     */
+    pub fn apply(
+        &self,
+        config_index: usize,
+        seed: u32,
+        width: usize,
+        height: usize,
+        tiles: &mut [CTile],
+    ) -> anyhow::Result<()> {
+        let config = self
+            .configs
+            .get(config_index)
+            .ok_or_else(|| anyhow::Error::msg("the auto mapper config does not exist"))?;
+        if tiles.len() != width * height {
+            return Err(anyhow::Error::msg(
+                "the tile count does not match the layer size",
+            ));
+        }
+
+        for run in &config.runs {
+            let layer_copy: Option<Vec<CTile>> = if run.use_layer_copy {
+                Some(tiles.to_vec())
+            } else {
+                None
+            };
+            for y in 0..height {
+                for x in 0..width {
+                    for (r, index_rule) in run.index_rules.iter().enumerate() {
+                        let read_tiles = match &layer_copy {
+                            Some(layer_copy) => layer_copy.as_slice(),
+                            None => &*tiles,
+                        };
+                        let respects_rules = index_rule.rules.iter().all(|rule| {
+                            let check_x = x as i64 + rule.x as i64;
+                            let check_y = y as i64 + rule.y as i64;
+                            let (check_index, check_flags) = if check_x >= 0
+                                && check_x < width as i64
+                                && check_y >= 0
+                                && check_y < height as i64
+                            {
+                                let tile = &read_tiles[check_y as usize * width + check_x as usize];
+                                (tile.index as i32, tile.flags & TEST_FLAGS)
+                            } else {
+                                (-1, 0)
+                            };
+                            let matches = rule.indices.iter().any(|info| {
+                                info.index == check_index
+                                    && (!info.test_flags || info.flags == check_flags)
+                            });
+                            match rule.kind {
+                                AutoMapperPosRuleKind::Index => matches,
+                                AutoMapperPosRuleKind::NotIndex => !matches,
+                            }
+                        });
+                        if respects_rules
+                            && (index_rule.random_probability >= 1.0
+                                || random_value(seed, x, y, r) < index_rule.random_probability)
+                        {
+                            let tile = &mut tiles[y * width + x];
+                            tile.index = index_rule.index;
+                            tile.flags = index_rule.flags;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Like `apply`, but for a tile layer of a map.
+     */
+    pub fn apply_to_layer(
+        &self,
+        config_index: usize,
+        seed: u32,
+        layer: &mut MapLayerTile,
+    ) -> anyhow::Result<()> {
+        let MapLayerTile(tile_layer, _, tiles) = layer;
+        self.apply(
+            config_index,
+            seed,
+            tile_layer.width.max(0) as usize,
+            tile_layer.height.max(0) as usize,
+            tiles,
+        )
+    }
+}
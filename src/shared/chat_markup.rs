@@ -0,0 +1,143 @@
+const URL_PREFIXES: [&str; 2] = ["https://", "http://"];
+// emote names longer than this are not searched for the closing ':'
+const MAX_EMOTE_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatSegment {
+    Text(String),
+    // clickable
+    Url(String),
+    // the name of the mentioned player, highlighted
+    Mention(String),
+    // the name of an emote image from the emote container, written as `:name:`
+    Emote(String),
+}
+
+/**
+ * A chat message split into the parts that are rendered differently.
+ * The same model is used for the live chat and the replayed chat of demos.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatMarkup {
+    pub segments: Vec<ChatSegment>,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl ChatMarkup {
+    fn push_text(&mut self, text: &str) {
+        if let Some(ChatSegment::Text(last)) = self.segments.last_mut() {
+            last.push_str(text);
+        } else {
+            self.segments.push(ChatSegment::Text(text.to_string()));
+        }
+    }
+
+    /**
+     * `player_names` are the names that can be mentioned,
+     * `is_emote` decides if an emote with that name exists
+     */
+    pub fn parse(msg: &str, player_names: &[&str], is_emote: impl Fn(&str) -> bool) -> Self {
+        let mut res = Self::default();
+        let mut index = 0;
+        while index < msg.len() {
+            let rest = &msg[index..];
+            let at_word_start = !msg[..index].chars().next_back().map_or(false, is_word_char);
+
+            if at_word_start && URL_PREFIXES.iter().any(|prefix| rest.starts_with(prefix)) {
+                let url_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                res.segments
+                    .push(ChatSegment::Url(rest[..url_len].to_string()));
+                index += url_len;
+                continue;
+            }
+
+            if rest.starts_with(':') {
+                let emote = rest[1..]
+                    .char_indices()
+                    .take(MAX_EMOTE_NAME_LEN + 1)
+                    .find(|(_, c)| *c == ':' || !is_word_char(*c))
+                    .filter(|(name_len, c)| *c == ':' && *name_len > 0)
+                    .map(|(name_len, _)| &rest[1..1 + name_len])
+                    .filter(|name| is_emote(name));
+                if let Some(name) = emote {
+                    res.segments.push(ChatSegment::Emote(name.to_string()));
+                    index += name.len() + 2;
+                    continue;
+                }
+            }
+
+            if at_word_start {
+                // the longest name wins, e.g. "nameless tee" over "nameless"
+                let mention = player_names
+                    .iter()
+                    .filter(|name| !name.is_empty() && rest.starts_with(**name))
+                    .filter(|name| {
+                        !rest[name.len()..]
+                            .chars()
+                            .next()
+                            .map_or(false, is_word_char)
+                    })
+                    .max_by_key(|name| name.len());
+                if let Some(name) = mention {
+                    res.segments.push(ChatSegment::Mention(name.to_string()));
+                    index += name.len();
+                    continue;
+                }
+            }
+
+            let c_len = rest.chars().next().unwrap().len_utf8();
+            res.push_text(&rest[..c_len]);
+            index += c_len;
+        }
+        res
+    }
+
+    /**
+     * E.g. to highlight messages that mention the local player
+     */
+    pub fn mentions(&self, player_name: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, ChatSegment::Mention(name) if name == player_name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChatMarkup, ChatSegment};
+
+    #[test]
+    fn parse_segments() {
+        let markup = ChatMarkup::parse(
+            "hi nameless tee, see https://ddnet.org :happy: :nope:",
+            &["nameless", "nameless tee"],
+            |name| name == "happy",
+        );
+        assert_eq!(
+            markup.segments,
+            vec![
+                ChatSegment::Text("hi ".to_string()),
+                ChatSegment::Mention("nameless tee".to_string()),
+                ChatSegment::Text(", see ".to_string()),
+                ChatSegment::Url("https://ddnet.org".to_string()),
+                ChatSegment::Text(" ".to_string()),
+                ChatSegment::Emote("happy".to_string()),
+                ChatSegment::Text(" :nope:".to_string()),
+            ]
+        );
+        assert!(markup.mentions("nameless tee"));
+        assert!(!markup.mentions("nameless"));
+    }
+
+    #[test]
+    fn mentions_only_whole_words() {
+        let markup = ChatMarkup::parse("brainless", &["brain"], |_| false);
+        assert_eq!(
+            markup.segments,
+            vec![ChatSegment::Text("brainless".to_string())]
+        );
+    }
+}
@@ -1,54 +1,104 @@
-use std::{collections::HashMap, ffi::CStr, io::Read, mem::size_of, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::CStr,
+    io::Read,
+    mem::size_of,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use base::{system::SystemTimeInterface, benchmark};
+use base::{benchmark, system::SystemTimeInterface};
 use flate2::read::ZlibDecoder;
 use rayon::{
-    prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator},
+    prelude::{
+        IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator,
+        ParallelIterator,
+    },
     slice::ParallelSliceMut,
 };
 
 use crate::{
+    datafile_writer::{CDatafileWriter, DatafileWriterDataSource},
+    join_all,
+    map_hash::{MapSectionHashes, MAP_ITEM_TYPE_SECTION_HASHES, MAP_ITEM_UUID_SECTION_HASHES},
+    map_spatial::MapLayerSpatialIndex,
     mapdef::{
-        read_i32_le, read_u32_le, CEnvPoint, CMapItemEnvelope, CMapItemGroup, CMapItemImage,
-        CMapItemInfoSettings, CMapItemLayer, CMapItemLayerQuads, CMapItemLayerSounds,
-        CMapItemLayerTilemap, CMapItemSound, CMapItemVersion, CQuad, MapImage, MapItemTypes,
-        MapLayer, MapLayerQuad, MapLayerTile, MapLayerTypes, MapTileLayerDetail, ReadFromSlice,
-        TilesLayerFlag,
-    }, join_all,
+        read_i32_le, read_u32_le, write_i32_le, CEnvPoint, CMapItemEnvelope, CMapItemGroup,
+        CMapItemImage, CMapItemInfoSettings, CMapItemLayer, CMapItemLayerQuads,
+        CMapItemLayerSounds, CMapItemLayerSoundsVer, CMapItemLayerTilemap, CMapItemSound,
+        CMapItemVersion, CQuad, CSoundSource, CSoundSourceDeprecated, CTile, ItemType, MapImage,
+        MapItemTypes, MapLayer, MapLayerQuad, MapLayerSound, MapLayerTile, MapLayerTypes,
+        MapTileLayerDetail, ReadFromSlice, TilesLayerFlag, WriteToVec,
+    },
 };
 
 const DEBUG: i32 = 0;
 
-enum UUIDOffset {
-    OFFSET_UUID_TYPE = 0x8000,
+pub enum UUIDOffset {
+    // above the 16 bits of the item type in the file,
+    // so the external types never collide with the internal ones
+    OFFSET_UUID_TYPE = 0x10000,
 }
 
+/**
+ * The data of an `ITEMTYPE_EX` item, it maps the uuid of an extended item type
+ * to the internal item type (the id of the item) used in this file
+ */
 #[repr(C)]
-pub struct CUuid {
-    data: [u8; 16],
+pub struct CItemEx {
+    uuid: [i32; 4],
 }
 
-#[repr(C)]
-pub struct CItemEx {
-    uuid: [i32; std::mem::size_of::<CUuid>() / 4],
+impl CItemEx {
+    pub fn from_uuid(uuid: &[u8; 16]) -> Self {
+        let mut res = Self { uuid: [0; 4] };
+        res.uuid.iter_mut().enumerate().for_each(|(i, val)| {
+            *val = i32::from_be_bytes(uuid[i * 4..i * 4 + 4].try_into().unwrap())
+        });
+        res
+    }
+
+    pub fn to_uuid(&self) -> [u8; 16] {
+        let mut res = [0; 16];
+        self.uuid
+            .iter()
+            .enumerate()
+            .for_each(|(i, val)| res[i * 4..i * 4 + 4].copy_from_slice(&val.to_be_bytes()));
+        res
+    }
+
+    pub fn read_from_slice(data: &[u8]) -> Option<Self> {
+        if data.len() < size_of::<CItemEx>() {
+            return None;
+        }
+        let mut res = Self { uuid: [0; 4] };
+        res.uuid
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, val)| *val = read_i32_le(&data[i * 4..]));
+        Some(res)
+    }
 }
-/*
-TODO: impl
-static CItemEx FromUuid(CUuid Uuid)
-{
-    CItemEx Result;
-    for(i = 0; i < (int)sizeof(CUuid) / 4: i32, i++)
-        Result.m_aUuid[i] = bytes_be_to_int(&Uuid.m_aData[i * 4]);
-    return Result;
+
+impl WriteToVec for CItemEx {
+    fn write_to_vec(&self, w: &mut Vec<u8>) {
+        self.uuid.iter().for_each(|val| write_i32_le(w, *val));
+    }
 }
 
-CUuid ToUuid() const
-{
-    CUuid Result;
-    for(i = 0; i < (int)sizeof(CUuid) / 4: i32, i++)
-        int_to_bytes_be(&Result.m_aData[i * 4], m_aUuid[i]);
-    return Result;
-} */
+/**
+ * The uuids of the extended item types that this implementation knows,
+ * by their external item type (at or above `OFFSET_UUID_TYPE`)
+ */
+fn ext_item_type_uuid(ext_type: i32) -> Option<[u8; 16]> {
+    match ext_type {
+        MAP_ITEM_TYPE_SECTION_HASHES => Some(MAP_ITEM_UUID_SECTION_HASHES),
+        _ => None,
+    }
+}
 
 #[derive(Copy, Clone, Default)]
 #[repr(C)]
@@ -203,6 +253,14 @@ pub struct CDatafile {
     unsigned m_Crc;*/
     info: CDatafileInfo,
     header: CDatafileHeader,
+    // (uuid, internal item type) of the `ITEMTYPE_EX` items, see `GetInternalItemType`
+    ext_item_types: Vec<([u8; 16], i32)>,
+    // the datas that were already decompressed, see `use_uncompressed_datas`
+    uncompressed_datas: Arc<Vec<Vec<u8>>>,
+    // the items and the compressed datas as they are stored in the file,
+    // so the map can be exported without the original file, see `export_legacy`
+    raw_items: Arc<Vec<u8>>,
+    raw_datas: Arc<Vec<u8>>,
 }
 
 pub enum ReadFile {
@@ -222,6 +280,8 @@ pub struct CDatafileWrapper {
     pub layers: Vec<MapLayer>,
     env_points: Vec<Vec<CEnvPoint>>,
     sounds: Vec<CMapItemSound>,
+    // `None` if the map was written without them, see `section_hashes_from_header`
+    section_hashes: Option<MapSectionHashes>,
 
     game_layer_index: usize,
     game_group_index: usize,
@@ -232,6 +292,12 @@ pub struct CDatafileWrapper {
     switch_layer_index: usize,
     tune_layer_index: usize,
 
+    // one for every layer, see `build_spatial_indices`
+    layer_spatial_indices: Vec<MapLayerSpatialIndex>,
+
+    // which map items were requested by the open options
+    loaded_map_items: [bool; MapItemTypes::MAPITEMTYPE_COUNT as usize],
+
     // files to read, if the user of this object
     // wants to have support for images etc.
     pub read_files: HashMap<String, ReadFile>,
@@ -243,10 +309,42 @@ pub struct MapFileOpenOptions {
     pub dont_load_map_item: [bool; MapItemTypes::MAPITEMTYPE_COUNT as usize],
 }
 
+/**
+ * Counts the read layers, so e.g. a loading screen can show the progress.
+ * Can be shared across threads.
+ */
+#[derive(Default)]
+pub struct MapLoadProgress {
+    layers_read: AtomicUsize,
+    layers_total: AtomicUsize,
+}
+
+impl MapLoadProgress {
+    fn add_layers_to_read(&self, count: usize) {
+        self.layers_total.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn layer_read(&self) {
+        self.layers_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Value between 0.0 and 1.0
+     */
+    pub fn progress(&self) -> f32 {
+        let total = self.layers_total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.layers_read.load(Ordering::Relaxed) as f32 / total as f32
+    }
+}
+
 #[derive(Default)]
 pub struct MapFileLayersReadOptions {
     pub do_benchmark: bool,
     pub dont_load_design_layers: bool,
+    pub progress: Option<Arc<MapLoadProgress>>,
 }
 
 #[derive(Default)]
@@ -254,6 +352,13 @@ pub struct MapFileImageReadOptions {
     pub do_benchmark: bool,
 }
 
+#[derive(Default)]
+pub struct MapFileExportOptions {
+    // images with the same content (or external images with the same name)
+    // are only written once, the layers are changed to use the remaining image
+    pub dedup_images: bool,
+}
+
 impl CDatafileWrapper {
     pub fn new() -> CDatafileWrapper {
         CDatafileWrapper {
@@ -267,6 +372,7 @@ impl CDatafileWrapper {
             layers: Vec::new(),
             env_points: Vec::new(),
             sounds: Vec::new(),
+            section_hashes: None,
 
             game_layer_index: usize::MAX,
             game_group_index: usize::MAX,
@@ -277,6 +383,10 @@ impl CDatafileWrapper {
             switch_layer_index: usize::MAX,
             tune_layer_index: usize::MAX,
 
+            layer_spatial_indices: Vec::new(),
+
+            loaded_map_items: Default::default(),
+
             read_files: HashMap::default(),
         }
     }
@@ -296,6 +406,10 @@ impl CDatafileWrapper {
     ) -> anyhow::Result<&'a [u8]> {
         let do_benchmark = options.do_benchmark;
         self.name = file_name.to_string();
+        self.loaded_map_items
+            .iter_mut()
+            .zip(options.dont_load_map_item.iter())
+            .for_each(|(loaded, dont_load)| *loaded = !*dont_load);
         //log_trace("datafile", "loading. filename='%s'", pFilename);
 
         // take the CRC of the file and store it
@@ -431,6 +545,7 @@ impl CDatafileWrapper {
                     let (itemsstart, rest) =
                         read_data.split_at(data_file.header.item_size as usize);
                     read_data = rest;
+                    data_file.raw_items = Arc::new(itemsstart.to_vec());
 
                     for i in 0..data_file.header.num_items as usize {
                         let offset = data_file.info.item_offsets[i] as usize;
@@ -448,6 +563,7 @@ impl CDatafileWrapper {
 
                 let (datas, rest) = read_data.split_at(data_file.header.data_size as usize);
                 data_start = datas;
+                data_file.raw_datas = Arc::new(datas.to_vec());
                 read_data = rest;
 
                 return true;
@@ -456,6 +572,38 @@ impl CDatafileWrapper {
             return Err(anyhow::Error::msg("File could not be opened"));
         }
 
+        // the internal item types of the extended (uuid) item types
+        let mut start = i32::default();
+        let mut num = i32::default();
+        Self::GetType(
+            &data_file,
+            ItemType::ITEMTYPE_EX as i32,
+            &mut start,
+            &mut num,
+        );
+        data_file.ext_item_types = items[start as usize..(start + num) as usize]
+            .iter()
+            .filter_map(|item| {
+                CItemEx::read_from_slice(item.data)
+                    .map(|item_ex| (item_ex.to_uuid(), item.header.type_and_id & 0xFFFF))
+            })
+            .collect();
+
+        // the section hashes are tiny, so they are always read
+        let mut start = i32::default();
+        let mut num = i32::default();
+        Self::GetType(
+            &data_file,
+            MAP_ITEM_TYPE_SECTION_HASHES,
+            &mut start,
+            &mut num,
+        );
+        self.section_hashes = if num > 0 {
+            MapSectionHashes::from_item(&items[start as usize].data)
+        } else {
+            None
+        };
+
         // read items
         thread_pool.install(|| {
             join_all!(
@@ -524,8 +672,7 @@ impl CDatafileWrapper {
                                     img.item_data.image_name as usize,
                                     data_start,
                                 );
-                                let name_cstr =
-                                    CStr::from_bytes_with_nul(data_name.as_slice()).unwrap();
+                                let name_cstr = CStr::from_bytes_with_nul(&data_name).unwrap();
                                 img.img_name = name_cstr.to_str().unwrap().to_string();
                             });
                             self.images.iter().enumerate().for_each(|(index, img)| {
@@ -630,9 +777,10 @@ impl CDatafileWrapper {
                                     {
                                         let item_size = size_of::<CMapItemLayerSounds>();
                                         let data = &items[start as usize + i].data[0..item_size];
-                                        *map_layer = MapLayer::Sound(
+                                        *map_layer = MapLayer::Sound(MapLayerSound(
                                             CMapItemLayerSounds::read_from_slice(data),
-                                        );
+                                            Vec::new(),
+                                        ));
                                     } else {
                                         *map_layer = MapLayer::Unknown(layer);
                                     }
@@ -656,8 +804,9 @@ impl CDatafileWrapper {
                             for i in 0..num as usize {
                                 let item_count = items[start as usize + i].data.len() / item_size;
                                 let mut env_points: Vec<CEnvPoint> = Vec::new();
-                                for _n in 0..item_count {
-                                    let data = &items[start as usize + i].data[0..item_size];
+                                for n in 0..item_count {
+                                    let data = &items[start as usize + i].data
+                                        [n * item_size..(n + 1) * item_size];
                                     env_points.push(CEnvPoint::read_from_slice(data));
                                 }
                                 self.env_points.push(env_points);
@@ -693,6 +842,68 @@ impl CDatafileWrapper {
         return Ok(data_start);
     }
 
+    /**
+     * Returns the layer indices in the order they should be read,
+     * if the map should be usable as fast as possible:
+     * - the first stage contains all physics layers
+     * - the second stage contains all layers of groups that are in the
+     *   foreground or move with the camera (parallax >= 100) and the game group
+     * - the last stage contains the far background groups, the closest groups first
+     * Every stage can be read on it's own (see `read_map_layers_staged`), since the
+     * datafile stores the data of every layer as separate compressed chunk.
+     */
+    pub fn layer_load_stages(&self) -> Vec<Vec<usize>> {
+        let physics_flags = TilesLayerFlag::TILESLAYERFLAG_GAME as i32
+            | TilesLayerFlag::TILESLAYERFLAG_TELE as i32
+            | TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32
+            | TilesLayerFlag::TILESLAYERFLAG_FRONT as i32
+            | TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32
+            | TilesLayerFlag::TILESLAYERFLAG_TUNE as i32;
+        let is_physics_layer = |layer: &MapLayer| {
+            if let MapLayer::Tile(tile_layer) = layer {
+                (tile_layer.0.flags & physics_flags) != 0
+            } else {
+                false
+            }
+        };
+
+        let mut physics: Vec<usize> = Vec::new();
+        let mut near: Vec<usize> = Vec::new();
+        let mut far_groups: Vec<(i32, Vec<usize>)> = Vec::new();
+        for g in 0..self.NumGroups() as usize {
+            let group = &self.groups[g];
+            let layer_indices = (group.start_layer.max(0) as usize
+                ..(group.start_layer.max(0) + group.num_layers.max(0)) as usize)
+                .filter(|index| *index < self.layers.len());
+
+            let mut group_layers: Vec<usize> = Vec::new();
+            let mut is_game_group = false;
+            layer_indices.for_each(|index| {
+                if is_physics_layer(&self.layers[index]) {
+                    physics.push(index);
+                    is_game_group = true;
+                } else {
+                    group_layers.push(index);
+                }
+            });
+
+            let parallax = group.parallax_x.min(group.parallax_y);
+            if is_game_group || parallax >= 100 {
+                near.append(&mut group_layers);
+            } else {
+                far_groups.push((parallax, group_layers));
+            }
+        }
+        // the higher the parallax value, the closer the group is to the camera
+        far_groups.sort_by(|(p1, _), (p2, _)| p2.cmp(p1));
+        let far: Vec<usize> = far_groups
+            .drain(..)
+            .flat_map(|(_, layers)| layers.into_iter())
+            .collect();
+
+        vec![physics, near, far]
+    }
+
     pub fn read_map_layers(
         data_file: &CDatafile,
         layers: &mut Vec<MapLayer>,
@@ -700,6 +911,9 @@ impl CDatafileWrapper {
         sys: &Arc<impl SystemTimeInterface + Send + Sync + 'static>,
         options: &MapFileLayersReadOptions,
     ) {
+        if let Some(progress) = &options.progress {
+            progress.add_layers_to_read(layers.len());
+        }
         benchmark!(
             options.do_benchmark,
             &sys,
@@ -709,99 +923,204 @@ impl CDatafileWrapper {
                     .par_iter_mut()
                     .enumerate()
                     .for_each(|(_i, map_layer)| {
-                        if let MapLayer::Tile(tile_layer) = map_layer {
-                            let _item_size = size_of::<CMapItemLayerTilemap>();
-                            let tiles_data_index = tile_layer.0.data;
+                        Self::read_map_layer(data_file, map_layer, data_start, options);
+                    });
+            }
+        );
+    }
+
+    /**
+     * Reads the layers of the given stages stage by stage (see `layer_load_stages`),
+     * the progress of `options` is updated after every layer.
+     * Layers that are not part of `stages` are not read, so the caller can
+     * start with the first stages and read the remaining ones later (see `read_layers`).
+     */
+    pub fn read_map_layers_staged(
+        data_file: &CDatafile,
+        layers: &mut Vec<MapLayer>,
+        stages: &Vec<Vec<usize>>,
+        data_start: &[u8],
+        sys: &Arc<impl SystemTimeInterface + Send + Sync + 'static>,
+        options: &MapFileLayersReadOptions,
+    ) {
+        if let Some(progress) = &options.progress {
+            progress.add_layers_to_read(stages.iter().map(|stage| stage.len()).sum());
+        }
+        let mut stage_of_layer: Vec<usize> = vec![usize::MAX; layers.len()];
+        stages.iter().enumerate().for_each(|(stage_index, stage)| {
+            stage.iter().for_each(|layer_index| {
+                if let Some(stage_of) = stage_of_layer.get_mut(*layer_index) {
+                    *stage_of = stage_index;
+                }
+            });
+        });
+        for stage_index in 0..stages.len() {
+            benchmark!(
+                options.do_benchmark,
+                &sys,
+                "\tloading a stage of the map layers tiles",
+                || {
+                    layers
+                        .par_iter_mut()
+                        .enumerate()
+                        .filter(|(i, _)| stage_of_layer[*i] == stage_index)
+                        .for_each(|(_i, map_layer)| {
+                            Self::read_map_layer(data_file, map_layer, data_start, options);
+                        });
+                }
+            );
+        }
+    }
 
-                            let mut is_entity_layer = false;
+    /**
+     * Reads the given layers (that were not read yet) outside of the wrapper,
+     * so they can be read while the rest of the map is already in use.
+     * `data_start` is the data section of the file (see `data_offset`).
+     */
+    pub fn read_layers(
+        data_file: &CDatafile,
+        layers: &mut Vec<(usize, MapLayer)>,
+        data_start: &[u8],
+        options: &MapFileLayersReadOptions,
+    ) {
+        layers.par_iter_mut().for_each(|(_, map_layer)| {
+            Self::read_map_layer(data_file, map_layer, data_start, options);
+            if let MapLayer::Tile(MapLayerTile(tile_layer, _, tiles)) = map_layer {
+                Self::init_tile_skip(tile_layer, tiles);
+            }
+        });
+    }
 
-                            if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_GAME as i32)
-                                != 0
-                            {
-                                is_entity_layer = true;
-                            }
+    /**
+     * Replaces the given layers with empty layers of the same size,
+     * which can be used everywhere until the real layers are read (see `read_layers`).
+     * Returns the unread layers.
+     */
+    pub fn take_unread_layers(&mut self, layer_indices: &[usize]) -> Vec<(usize, MapLayer)> {
+        layer_indices
+            .iter()
+            .filter_map(|&index| {
+                let map_layer = self.layers.get_mut(index)?;
+                let placeholder = match map_layer {
+                    MapLayer::Tile(MapLayerTile(tile_layer, _, _)) => MapLayer::Tile(MapLayerTile(
+                        tile_layer.clone(),
+                        MapTileLayerDetail::Tile(),
+                        vec![
+                            CTile::default();
+                            tile_layer.width.max(0) as usize * tile_layer.height.max(0) as usize
+                        ],
+                    )),
+                    MapLayer::Quads(MapLayerQuad(quad_layer, _)) => {
+                        let mut quad_layer = quad_layer.clone();
+                        quad_layer.num_quads = 0;
+                        MapLayer::Quads(MapLayerQuad(quad_layer, Vec::new()))
+                    }
+                    _ => map_layer.clone(),
+                };
+                Some((index, std::mem::replace(map_layer, placeholder)))
+            })
+            .collect()
+    }
 
-                            let mut tile_layer_impl = MapTileLayerDetail::Tile();
-                            if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_TELE as i32)
-                                != 0
-                            {
-                                tile_layer_impl = MapTileLayerDetail::Tele(Self::read_tiles(
-                                    data_file,
-                                    tile_layer.0.tele,
-                                    tile_layer.0.width as usize,
-                                    tile_layer.0.height as usize,
-                                    data_start,
-                                ));
-                                is_entity_layer = true;
-                            } else if (tile_layer.0.flags
-                                & TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32)
-                                != 0
-                            {
-                                tile_layer_impl = MapTileLayerDetail::Speedup(Self::read_tiles(
-                                    data_file,
-                                    tile_layer.0.speedup,
-                                    tile_layer.0.width as usize,
-                                    tile_layer.0.height as usize,
-                                    data_start,
-                                ));
-                                is_entity_layer = true;
-                            } else if (tile_layer.0.flags
-                                & TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32)
-                                != 0
-                            {
-                                tile_layer_impl = MapTileLayerDetail::Switch(Self::read_tiles(
-                                    data_file,
-                                    tile_layer.0.switch,
-                                    tile_layer.0.width as usize,
-                                    tile_layer.0.height as usize,
-                                    data_start,
-                                ));
-                                is_entity_layer = true;
-                            } else if (tile_layer.0.flags
-                                & TilesLayerFlag::TILESLAYERFLAG_TUNE as i32)
-                                != 0
-                            {
-                                tile_layer_impl = MapTileLayerDetail::Tune(Self::read_tiles(
-                                    data_file,
-                                    tile_layer.0.tune,
-                                    tile_layer.0.width as usize,
-                                    tile_layer.0.height as usize,
-                                    data_start,
-                                ));
-                                is_entity_layer = true;
-                            }
+    /**
+     * The offset of the data section (the slice that `Open` returns) in the file
+     */
+    pub fn data_offset(&self) -> usize {
+        let header = &self.data_file.header;
+        size_of::<CDatafileHeader>()
+            + header.num_item_types as usize * size_of::<CDatafileItemType>()
+            + (header.num_items as usize + 2 * header.num_raw_data as usize) * size_of::<i32>()
+            + header.item_size as usize
+    }
 
-                            let tiles = if is_entity_layer || !options.dont_load_design_layers {
-                                Self::read_tiles(
-                                    data_file,
-                                    tiles_data_index,
-                                    tile_layer.0.width as usize,
-                                    tile_layer.0.height as usize,
-                                    data_start,
-                                )
-                            } else {
-                                Vec::new()
-                            };
-
-                            *map_layer = MapLayer::Tile(MapLayerTile(
-                                tile_layer.0.clone(),
-                                tile_layer_impl,
-                                tiles,
-                            ));
-                        } else if let MapLayer::Quads(quad_layer) = map_layer {
-                            let quads = Self::read_quads(
-                                data_file,
-                                quad_layer.0.data,
-                                quad_layer.0.num_quads as usize,
-                                data_start,
-                            );
-                            *map_layer = MapLayer::Quads(MapLayerQuad(quad_layer.0.clone(), quads));
-                        } else if let MapLayer::Sound(_sound_layer) = map_layer {
-                            // TODO: nothing to do yet, but actually sounds are loaded
-                        }
-                    });
+    fn read_map_layer(
+        data_file: &CDatafile,
+        map_layer: &mut MapLayer,
+        data_start: &[u8],
+        options: &MapFileLayersReadOptions,
+    ) {
+        if let MapLayer::Tile(tile_layer) = map_layer {
+            let _item_size = size_of::<CMapItemLayerTilemap>();
+            let tiles_data_index = tile_layer.0.data;
+
+            let mut is_entity_layer = false;
+
+            if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_GAME as i32) != 0 {
+                is_entity_layer = true;
             }
-        );
+
+            let mut tile_layer_impl = MapTileLayerDetail::Tile();
+            if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_TELE as i32) != 0 {
+                tile_layer_impl = MapTileLayerDetail::Tele(Self::read_tiles(
+                    data_file,
+                    tile_layer.0.tele,
+                    tile_layer.0.width as usize,
+                    tile_layer.0.height as usize,
+                    data_start,
+                ));
+                is_entity_layer = true;
+            } else if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32) != 0 {
+                tile_layer_impl = MapTileLayerDetail::Speedup(Self::read_tiles(
+                    data_file,
+                    tile_layer.0.speedup,
+                    tile_layer.0.width as usize,
+                    tile_layer.0.height as usize,
+                    data_start,
+                ));
+                is_entity_layer = true;
+            } else if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32) != 0 {
+                tile_layer_impl = MapTileLayerDetail::Switch(Self::read_tiles(
+                    data_file,
+                    tile_layer.0.switch,
+                    tile_layer.0.width as usize,
+                    tile_layer.0.height as usize,
+                    data_start,
+                ));
+                is_entity_layer = true;
+            } else if (tile_layer.0.flags & TilesLayerFlag::TILESLAYERFLAG_TUNE as i32) != 0 {
+                tile_layer_impl = MapTileLayerDetail::Tune(Self::read_tiles(
+                    data_file,
+                    tile_layer.0.tune,
+                    tile_layer.0.width as usize,
+                    tile_layer.0.height as usize,
+                    data_start,
+                ));
+                is_entity_layer = true;
+            }
+
+            let tiles = if is_entity_layer || !options.dont_load_design_layers {
+                Self::read_tiles(
+                    data_file,
+                    tiles_data_index,
+                    tile_layer.0.width as usize,
+                    tile_layer.0.height as usize,
+                    data_start,
+                )
+            } else {
+                Vec::new()
+            };
+
+            *map_layer = MapLayer::Tile(MapLayerTile(tile_layer.0.clone(), tile_layer_impl, tiles));
+        } else if let MapLayer::Quads(quad_layer) = map_layer {
+            let quads = Self::read_quads(
+                data_file,
+                quad_layer.0.data,
+                quad_layer.0.num_quads as usize,
+                data_start,
+            );
+            *map_layer = MapLayer::Quads(MapLayerQuad(quad_layer.0.clone(), quads));
+        } else if let MapLayer::Sound(sound_layer) = map_layer {
+            let mut sound_layer_info = sound_layer.0.clone();
+            let sources = Self::read_sound_sources(data_file, &sound_layer_info, data_start);
+            // the old sources were upgraded, so the layer is now in the current version
+            if !sources.is_empty() {
+                sound_layer_info.version = CMapItemLayerSoundsVer::CURRENT_VERSION as i32;
+            }
+            *map_layer = MapLayer::Sound(MapLayerSound(sound_layer_info, sources));
+        }
+        if let Some(progress) = &options.progress {
+            progress.layer_read();
+        }
     }
 
     pub fn read_image_data(
@@ -829,7 +1148,8 @@ impl CDatafileWrapper {
                                 data_file,
                                 img_data.item_data.image_data as usize,
                                 data_start,
-                            ),
+                            )
+                            .into_owned(),
                         ));
                     }
                 });
@@ -838,6 +1158,261 @@ impl CDatafileWrapper {
         res
     }
 
+    /**
+     * Writes the map back into the legacy datafile format (version 4).
+     * Groups, layers (including their tiles, quads and sound sources if they were read),
+     * envelopes and env points are taken from this wrapper, so changes to them
+     * are part of the result.
+     * Everything else (images, sounds, infos, auto mapper configs, uuid items etc.)
+     * is copied from the original file as is.
+     * If images are deduplicated (see `MapFileExportOptions`), the removed images keep
+     * their data slots, but with empty data.
+     * Serializing the layers and compressing the datas runs on the thread pool,
+     * datas that did not change keep their compressed bytes.
+     */
+    pub fn export_legacy(
+        &self,
+        options: &MapFileExportOptions,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<Vec<u8>> {
+        let header = &self.data_file.header;
+        let info = &self.data_file.info;
+
+        let items_start = self.data_file.raw_items.as_slice();
+        let data_start = self.data_file.raw_datas.as_slice();
+        if items_start.len() != header.item_size as usize
+            || data_start.len() != header.data_size as usize
+        {
+            return Err(anyhow::Error::msg("the opened datafile is incomplete"));
+        }
+
+        let is_valid_data = |index: i32| index >= 0 && (index as u32) < header.num_raw_data;
+        fn serialize<T: WriteToVec>(items: &[T]) -> Vec<u8> {
+            let mut res: Vec<u8> = Vec::with_capacity(items.len() * size_of::<T>());
+            items.iter().for_each(|item| item.write_to_vec(&mut res));
+            res
+        }
+        // data that is replaced by the content of the layers,
+        // every layer is serialized on its own task
+        let mut replaced_data: HashMap<usize, Vec<u8>> = thread_pool.install(|| {
+            self.layers
+                .par_iter()
+                .flat_map_iter(|layer| {
+                    let mut layer_datas: Vec<(i32, Vec<u8>)> = Vec::new();
+                    let mut replace_data =
+                        |index: i32, data: Vec<u8>| layer_datas.push((index, data));
+                    match layer {
+                        MapLayer::Tile(MapLayerTile(tile_layer, detail, tiles)) => {
+                            if !tiles.is_empty() {
+                                // the skip value is only a runtime optimization
+                                let tiles: Vec<CTile> = tiles
+                                    .iter()
+                                    .map(|tile| CTile { skip: 0, ..*tile })
+                                    .collect();
+                                replace_data(tile_layer.data, serialize(&tiles));
+                            }
+                            match detail {
+                                MapTileLayerDetail::Tile() => {}
+                                MapTileLayerDetail::Tele(tiles) if !tiles.is_empty() => {
+                                    replace_data(tile_layer.tele, serialize(tiles))
+                                }
+                                MapTileLayerDetail::Speedup(tiles) if !tiles.is_empty() => {
+                                    replace_data(tile_layer.speedup, serialize(tiles))
+                                }
+                                MapTileLayerDetail::Switch(tiles) if !tiles.is_empty() => {
+                                    replace_data(tile_layer.switch, serialize(tiles))
+                                }
+                                MapTileLayerDetail::Tune(tiles) if !tiles.is_empty() => {
+                                    replace_data(tile_layer.tune, serialize(tiles))
+                                }
+                                _ => {}
+                            }
+                        }
+                        MapLayer::Quads(MapLayerQuad(quad_layer, quads)) => {
+                            if !quads.is_empty() {
+                                replace_data(quad_layer.data, serialize(quads));
+                            }
+                        }
+                        MapLayer::Sound(MapLayerSound(sound_layer, sources)) => {
+                            // the sources are always written in the current version
+                            if !sources.is_empty() {
+                                replace_data(sound_layer.data, serialize(sources));
+                            }
+                        }
+                        _ => {}
+                    }
+                    layer_datas
+                })
+                .filter(|(index, _)| is_valid_data(*index))
+                .map(|(index, data)| (index as usize, data))
+                .collect()
+        });
+        let mut replace_data = |index: i32, data: Vec<u8>| {
+            if is_valid_data(index) {
+                replaced_data.insert(index as usize, data);
+            }
+        };
+
+        // identical images are only written once, `image_remap` contains
+        // the new index of every image (duplicates point to the kept image)
+        let mut image_remap: Vec<i32> = (0..self.images.len() as i32).collect();
+        let mut removed_images: Vec<bool> = vec![false; self.images.len()];
+        if options.dedup_images
+            && self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_IMAGE)
+            && self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_LAYER)
+        {
+            // the original index of the first image with that content
+            let mut known_external: HashMap<&str, usize> = HashMap::new();
+            let mut known_embedded: HashMap<(i32, i32, Vec<u8>), usize> = HashMap::new();
+            let mut next_index = 0;
+            for (i, img) in self.images.iter().enumerate() {
+                let existing = if img.item_data.external != 0 {
+                    *known_external.entry(img.img_name.as_str()).or_insert(i)
+                } else {
+                    let key = (
+                        img.item_data.width,
+                        img.item_data.height,
+                        Self::uncompress_data(
+                            &self.data_file,
+                            img.item_data.image_data as usize,
+                            data_start,
+                        )
+                        .into_owned(),
+                    );
+                    *known_embedded.entry(key).or_insert(i)
+                };
+                if existing == i {
+                    image_remap[i] = next_index;
+                    next_index += 1;
+                } else {
+                    image_remap[i] = image_remap[existing];
+                    removed_images[i] = true;
+                    // the data indices must stay stable, so only the content is dropped.
+                    // the data might be shared with the kept image tho
+                    let kept = &self.images[existing].item_data;
+                    if img.item_data.image_name != kept.image_name {
+                        replace_data(img.item_data.image_name, Vec::new());
+                    }
+                    if img.item_data.external == 0 && img.item_data.image_data != kept.image_data {
+                        replace_data(img.item_data.image_data, Vec::new());
+                    }
+                }
+            }
+        }
+        let remap_image = |image: i32| -> i32 {
+            if image >= 0 && (image as usize) < image_remap.len() {
+                image_remap[image as usize]
+            } else {
+                image
+            }
+        };
+
+        let mut writer = CDatafileWriter::new();
+        thread_pool.install(|| {
+            writer.add_datas(header.num_raw_data as usize, |i| {
+                // unchanged datas are not compressed again,
+                // decompressing them for the comparison is much cheaper
+                match replaced_data.get(&i) {
+                    Some(data)
+                        if Self::uncompress_data(&self.data_file, i, data_start).as_ref()
+                            != data.as_slice() =>
+                    {
+                        DatafileWriterDataSource::Uncompressed(Cow::Borrowed(data))
+                    }
+                    _ => DatafileWriterDataSource::Compressed {
+                        uncompressed_size: info.data_sizes[i] as usize,
+                        compressed: Self::get_data_slice(&self.data_file, i, data_start),
+                    },
+                }
+            })
+        });
+
+        // the written struct might be larger than the item of older map versions
+        let overwrite_item = |item: &mut Vec<u8>, serialized: Vec<u8>| {
+            let len = item.len().min(serialized.len());
+            item[0..len].copy_from_slice(&serialized[0..len]);
+        };
+        let section_hashes_type =
+            Self::GetInternalItemType(&self.data_file, MAP_ITEM_TYPE_SECTION_HASHES);
+        for i in 0..header.num_items as usize {
+            let offset = info.item_offsets[i] as usize;
+            let item = CDatafileItemAndData::read_from_slice(
+                &items_start[offset..],
+                Self::GetItemSize(header, info, i as i32) as usize,
+            );
+            let item_type = (item.header.type_and_id >> 16) & 0xFFFF;
+            let mut id = item.header.type_and_id & 0xFFFF;
+            let mut item_data = item.data.to_vec();
+
+            let mut type_start = i32::default();
+            let mut type_num = i32::default();
+            Self::GetType(&self.data_file, item_type, &mut type_start, &mut type_num);
+            let index = i - type_start as usize;
+
+            let mut serialized: Vec<u8> = Vec::new();
+            if item_type == section_hashes_type
+                || (item_type == ItemType::ITEMTYPE_EX as i32
+                    && CItemEx::read_from_slice(&item_data).map(|item_ex| item_ex.to_uuid())
+                        == Some(MAP_ITEM_UUID_SECTION_HASHES))
+            {
+                // written again below
+                continue;
+            } else if item_type == MapItemTypes::MAPITEMTYPE_IMAGE as i32
+                && index < removed_images.len()
+            {
+                if removed_images[index] {
+                    continue;
+                }
+                id = image_remap[index];
+            } else if item_type == MapItemTypes::MAPITEMTYPE_GROUP as i32 {
+                if let Some(group) = self.groups.get(index) {
+                    group.write_to_vec(&mut serialized);
+                    overwrite_item(&mut item_data, serialized);
+                }
+            } else if item_type == MapItemTypes::MAPITEMTYPE_LAYER as i32 {
+                if let Some(layer) = self.layers.get(index) {
+                    match layer {
+                        MapLayer::Tile(tile_layer) => {
+                            let mut tile_layer = tile_layer.0.clone();
+                            tile_layer.image = remap_image(tile_layer.image);
+                            tile_layer.write_to_vec(&mut serialized)
+                        }
+                        MapLayer::Quads(quad_layer) => {
+                            let mut quad_layer = quad_layer.0.clone();
+                            quad_layer.image = remap_image(quad_layer.image);
+                            quad_layer.write_to_vec(&mut serialized)
+                        }
+                        MapLayer::Sound(sound_layer) => sound_layer.0.write_to_vec(&mut serialized),
+                        MapLayer::Unknown(layer) => layer.write_to_vec(&mut serialized),
+                    }
+                    overwrite_item(&mut item_data, serialized);
+                }
+            } else if item_type == MapItemTypes::MAPITEMTYPE_ENVELOPE as i32 {
+                if let Some(envelope) = self.envelopes.get(index) {
+                    envelope.write_to_vec(&mut serialized);
+                    overwrite_item(&mut item_data, serialized);
+                }
+            } else if item_type == MapItemTypes::MAPITEMTYPE_ENVPOINTS as i32 {
+                if let Some(env_points) = self.env_points.get(index) {
+                    env_points
+                        .iter()
+                        .for_each(|point| point.write_to_vec(&mut serialized));
+                    item_data = serialized;
+                }
+            }
+
+            writer.add_item(item_type, id, item_data);
+        }
+
+        // the hashes change with the layers and images, so they are always written again
+        let section_hashes = self.section_hashes_remapped(data_start, &remap_image, &|image| {
+            !removed_images.get(image).copied().unwrap_or(false)
+        });
+        writer.add_ext_item(MAP_ITEM_UUID_SECTION_HASHES, 0, section_hashes.to_item());
+
+        Ok(writer.finish())
+    }
+
     fn read_tiles<T>(
         data_file: &CDatafile,
         data_index: i32,
@@ -852,7 +1427,7 @@ impl CDatafileWrapper {
             let tile_size = size_of::<T>();
             let uncompressed_data =
                 Self::uncompress_data(data_file, data_index as usize, data_start);
-            let tiles_sliced = uncompressed_data.as_slice();
+            let tiles_sliced = uncompressed_data.as_ref();
             let mut tiles = vec![Default::default(); width * height];
             tiles
                 .par_chunks_exact_mut(width)
@@ -881,7 +1456,7 @@ impl CDatafileWrapper {
             let quad_size = size_of::<CQuad>();
             let uncompressed_data =
                 Self::uncompress_data(data_file, data_index as usize, data_start);
-            let quads_sliced = uncompressed_data.as_slice();
+            let quads_sliced = uncompressed_data.as_ref();
             let mut quads = vec![Default::default(); num_quads];
             quads.par_iter_mut().enumerate().for_each(|(index, quad)| {
                 let quad_sliced = &quads_sliced[index * quad_size..(index * quad_size) + quad_size];
@@ -892,7 +1467,69 @@ impl CDatafileWrapper {
         Vec::new()
     }
 
-    fn uncompress_data(data_file: &CDatafile, index: usize, data_start: &[u8]) -> Vec<u8> {
+    fn read_sound_sources(
+        data_file: &CDatafile,
+        sound_layer: &CMapItemLayerSounds,
+        data_start: &[u8],
+    ) -> Vec<CSoundSource> {
+        if sound_layer.data == -1 || sound_layer.num_sources <= 0 {
+            return Vec::new();
+        }
+        let uncompressed_data =
+            Self::uncompress_data(data_file, sound_layer.data as usize, data_start);
+        if sound_layer.version < CMapItemLayerSoundsVer::CURRENT_VERSION as i32 {
+            uncompressed_data
+                .chunks_exact(size_of::<CSoundSourceDeprecated>())
+                .take(sound_layer.num_sources as usize)
+                .map(|source| CSoundSourceDeprecated::read_from_slice(source).upgrade())
+                .collect()
+        } else {
+            uncompressed_data
+                .chunks_exact(size_of::<CSoundSource>())
+                .take(sound_layer.num_sources as usize)
+                .map(CSoundSource::read_from_slice)
+                .collect()
+        }
+    }
+
+    /**
+     * Reads the uncompressed data at the given data index,
+     * `data_start` is the slice returned by `Open`
+     */
+    pub fn read_raw_data(&self, index: usize, data_start: &[u8]) -> Vec<u8> {
+        Self::uncompress_data(&self.data_file, index, data_start).into_owned()
+    }
+
+    /**
+     * The map settings (server commands) of the map info, one command per entry.
+     * Empty if the map infos were not loaded or the map has no settings.
+     */
+    pub fn read_map_setting_commands(&self, data_start: &[u8]) -> Vec<String> {
+        self.infos
+            .first()
+            .filter(|info| {
+                info.settings() >= 0
+                    && (info.settings() as u32) < self.data_file.header.num_raw_data
+            })
+            .map(|info| {
+                self.read_raw_data(info.settings() as usize, data_start)
+                    .split(|c| *c == 0)
+                    .filter(|command| !command.is_empty())
+                    .map(|command| String::from_utf8_lossy(command).to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn uncompress_data<'a>(
+        data_file: &'a CDatafile,
+        index: usize,
+        data_start: &[u8],
+    ) -> Cow<'a, [u8]> {
+        if let Some(data) = data_file.uncompressed_datas.get(index) {
+            return Cow::Borrowed(data.as_slice());
+        }
+
         // v4 has compressed data
         let UncompressedSize = data_file.info.data_sizes[index];
 
@@ -906,36 +1543,44 @@ impl CDatafileWrapper {
         let mut data = Vec::<u8>::new();
         data.reserve(UncompressedSize as usize);
         d.read_to_end(&mut data).unwrap();
-        data
+        Cow::Owned(data)
     }
 
-    fn GetInternalItemType(ExternalType: i32) -> i32 {
+    /**
+     * Uses the datas that were already decompressed (e.g. by `validate_untrusted_map`)
+     * instead of decompressing the datas of the file again, must be called after `Open`.
+     * The datas stay in memory until `release_uncompressed_datas` is called.
+     */
+    pub fn use_uncompressed_datas(&mut self, datas: Vec<Vec<u8>>) {
+        if datas.len() == self.data_file.header.num_raw_data as usize {
+            self.data_file.uncompressed_datas = Arc::new(datas);
+        }
+    }
+
+    pub fn release_uncompressed_datas(&mut self) {
+        self.data_file.uncompressed_datas = Default::default();
+    }
+
+    fn GetInternalItemType(data_file: &CDatafile, ExternalType: i32) -> i32 {
         if ExternalType < UUIDOffset::OFFSET_UUID_TYPE as i32 {
             return ExternalType;
         }
-        /* TODO! CUuid Uuid = g_UuidManager.GetUuid(ExternalType);
-        Start, Num: i32,
-        GetType(ITEMTYPE_EX, &Start, &Num);
-        for(i = Start; i < Start + Num: i32, i++)
-        {
-            if(GetItemSize(i) < (int)sizeof(CItemEx))
-            {
-                continue;
-            }
-            ID: i32,
-            if(Uuid == ((const CItemEx *)GetItem(i, 0, &ID))->ToUuid())
-            {
-                return ID;
-            }
-        }*/
-        return -1;
+        ext_item_type_uuid(ExternalType)
+            .and_then(|uuid| {
+                data_file
+                    .ext_item_types
+                    .iter()
+                    .find(|(item_uuid, _)| *item_uuid == uuid)
+                    .map(|(_, internal_type)| *internal_type)
+            })
+            .unwrap_or(-1)
     }
 
     fn GetType(data_file: &CDatafile, Type: i32, pStart: &mut i32, pNum: &mut i32) {
         *pStart = 0;
         *pNum = 0;
 
-        let real_type = Self::GetInternalItemType(Type);
+        let real_type = Self::GetInternalItemType(data_file, Type);
         for i in 0..data_file.header.num_item_types as usize {
             if data_file.info.item_types[i].item_type == real_type {
                 *pStart = data_file.info.item_types[i].start;
@@ -976,39 +1621,40 @@ impl CDatafileWrapper {
         return info.data_offsets[Index as usize + 1] - info.data_offsets[Index as usize];
     }
 
-    fn InitTilemapSkip(&mut self, thread_pool: &rayon::ThreadPool) {
+    pub fn InitTilemapSkip(&mut self, thread_pool: &rayon::ThreadPool) {
         for g in 0..self.NumGroups() as usize {
             let pGroup = &self.groups[g];
             for l in 0..pGroup.num_layers as usize {
                 let pLayer = &mut self.layers[pGroup.start_layer as usize + l];
 
                 if let MapLayer::Tile(MapLayerTile(tile_layer, _, tiles)) = pLayer {
-                    let pTiles = tiles;
-                    thread_pool.install(|| {
-                        pTiles
-                            .par_chunks_mut(tile_layer.width as usize)
-                            .for_each(|pTilesChunk| {
-                                let mut x = 0;
-                                while x < tile_layer.width {
-                                    let mut SkippedX: i32 = 1;
-                                    while x + SkippedX < tile_layer.width && SkippedX < 255 {
-                                        if pTilesChunk[x as usize + SkippedX as usize].index > 0 {
-                                            break;
-                                        }
-
-                                        SkippedX += 1;
-                                    }
-
-                                    pTilesChunk[x as usize].skip = (SkippedX - 1) as u8;
-                                    x += SkippedX;
-                                }
-                            });
-                    });
+                    thread_pool.install(|| Self::init_tile_skip(tile_layer, tiles));
                 }
             }
         }
     }
 
+    fn init_tile_skip(tile_layer: &CMapItemLayerTilemap, pTiles: &mut Vec<CTile>) {
+        pTiles
+            .par_chunks_mut(tile_layer.width as usize)
+            .for_each(|pTilesChunk| {
+                let mut x = 0;
+                while x < tile_layer.width {
+                    let mut SkippedX: i32 = 1;
+                    while x + SkippedX < tile_layer.width && SkippedX < 255 {
+                        if pTilesChunk[x as usize + SkippedX as usize].index > 0 {
+                            break;
+                        }
+
+                        SkippedX += 1;
+                    }
+
+                    pTilesChunk[x as usize].skip = (SkippedX - 1) as u8;
+                    x += SkippedX;
+                }
+            });
+    }
+
     pub fn InitLayers(&mut self, thread_pool: &rayon::ThreadPool) {
         for g in 0..self.NumGroups() as usize {
             let pGroup = &mut self.groups[g];
@@ -1065,6 +1711,44 @@ impl CDatafileWrapper {
         self.InitTilemapSkip(thread_pool);
     }
 
+    /**
+     * (Re)builds the spatial indices of all layers, only the client renders the map,
+     * so only it needs them. Must be called after the quads of a layer changed.
+     */
+    pub fn build_spatial_indices(&mut self, thread_pool: &rayon::ThreadPool) {
+        let indices = thread_pool.install(|| {
+            self.layers
+                .par_iter()
+                .map(MapLayerSpatialIndex::new)
+                .collect()
+        });
+        self.layer_spatial_indices = indices;
+    }
+
+    /**
+     * Rebuilds the spatial indices, if the map has them (see `build_spatial_indices`)
+     */
+    pub fn update_spatial_indices(&mut self, thread_pool: &rayon::ThreadPool) {
+        if !self.layer_spatial_indices.is_empty() {
+            self.build_spatial_indices(thread_pool);
+        }
+    }
+
+    /**
+     * Updates the spatial index of a single layer, e.g. after the editor changed it
+     */
+    pub fn update_spatial_index(&mut self, layer_index: usize) {
+        if layer_index < self.layer_spatial_indices.len() {
+            self.layer_spatial_indices[layer_index] =
+                MapLayerSpatialIndex::new(&self.layers[layer_index]);
+        }
+    }
+
+    pub fn get_layer_spatial_index(&self, layer_index: usize) -> &MapLayerSpatialIndex {
+        static NONE: MapLayerSpatialIndex = MapLayerSpatialIndex::None;
+        self.layer_spatial_indices.get(layer_index).unwrap_or(&NONE)
+    }
+
     pub fn is_game_layer(&self, layer_index: usize) -> bool {
         self.game_layer_index == layer_index
     }
@@ -1109,6 +1793,10 @@ impl CDatafileWrapper {
         &self.groups[index]
     }
 
+    pub fn get_group_mut(&mut self, index: usize) -> Option<&mut CMapItemGroup> {
+        self.groups.get_mut(index)
+    }
+
     pub fn env_count(&self) -> usize {
         self.envelopes.len()
     }
@@ -1124,4 +1812,133 @@ impl CDatafileWrapper {
     pub fn get_env_points(&self) -> &[Vec<CEnvPoint>] {
         self.env_points.as_slice()
     }
+
+    /**
+     * The env point at the index that envelopes use (see `CMapItemEnvelope::start_point`),
+     * the points of all env point items are counted in order
+     */
+    pub fn get_env_point(&self, index: usize) -> Option<&CEnvPoint> {
+        self.env_points.iter().flatten().nth(index)
+    }
+
+    pub fn get_env_point_mut(&mut self, index: usize) -> Option<&mut CEnvPoint> {
+        self.env_points.iter_mut().flatten().nth(index)
+    }
+
+    pub fn sound_count(&self) -> usize {
+        self.sounds.len()
+    }
+
+    /**
+     * If the map item type was loaded when the file was opened
+     */
+    pub fn is_map_item_loaded(&self, item_type: MapItemTypes) -> bool {
+        self.loaded_map_items[item_type as usize]
+    }
+
+    pub fn stored_section_hashes(&self) -> Option<&MapSectionHashes> {
+        self.section_hashes.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use base::system::SystemTime;
+
+    use crate::mapdef::{MapLayer, MapLayerTile};
+
+    use super::{CDatafileWrapper, MapFileExportOptions, MapFileLayersReadOptions};
+
+    fn thread_pool() -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap()
+    }
+
+    fn read_map(file: &Vec<u8>, thread_pool: &rayon::ThreadPool) -> CDatafileWrapper {
+        let sys = Arc::new(SystemTime::new());
+        let mut map = CDatafileWrapper::new();
+        let data_start = map
+            .Open(file, "test", thread_pool, &Default::default(), &sys)
+            .unwrap();
+        CDatafileWrapper::read_map_layers(
+            &map.data_file,
+            &mut map.layers,
+            data_start,
+            &sys,
+            &MapFileLayersReadOptions::default(),
+        );
+        map.InitLayers(thread_pool);
+        map
+    }
+
+    fn datas(map: &CDatafileWrapper) -> Vec<Vec<u8>> {
+        (0..map.data_file.header.num_raw_data as usize)
+            .map(|i| map.read_raw_data(i, &map.data_file.raw_datas))
+            .collect()
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let thread_pool = thread_pool();
+        let file = std::fs::read(format!("{}/data/cb2.map", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let mut map = read_map(&file, &thread_pool);
+        // the original file is not needed for the export
+        drop(file);
+
+        let MapLayer::Tile(MapLayerTile(_, _, tiles)) = &mut map.layers[map.game_layer_index]
+        else {
+            panic!("the game layer is no tile layer");
+        };
+        tiles[0].index = tiles[0].index.wrapping_add(1);
+
+        let exported = map
+            .export_legacy(&MapFileExportOptions::default(), &thread_pool)
+            .unwrap();
+        let read_again = read_map(&exported, &thread_pool);
+
+        // only the data of the changed layer differs from the original file
+        let game_tiles_data = map.get_game_layer().0.data as usize;
+        let mut original_datas = datas(&map);
+        let mut read_datas = datas(&read_again);
+        assert_ne!(original_datas[game_tiles_data], read_datas[game_tiles_data]);
+        original_datas.remove(game_tiles_data);
+        read_datas.remove(game_tiles_data);
+        assert_eq!(original_datas, read_datas);
+        // the unchanged datas are copied without compressing them again
+        for i in (0..map.data_file.header.num_raw_data as usize).filter(|i| *i != game_tiles_data) {
+            assert_eq!(
+                CDatafileWrapper::get_data_slice(&map.data_file, i, &map.data_file.raw_datas),
+                CDatafileWrapper::get_data_slice(
+                    &read_again.data_file,
+                    i,
+                    &read_again.data_file.raw_datas
+                )
+            );
+        }
+
+        assert_eq!(map.NumGroups(), read_again.NumGroups());
+        assert_eq!(map.layers.len(), read_again.layers.len());
+        assert_eq!(map.env_count(), read_again.env_count());
+        assert_eq!(map.images.len(), read_again.images.len());
+        assert_eq!(
+            map.get_game_layer().2[0].index,
+            read_again.get_game_layer().2[0].index
+        );
+        assert_eq!(
+            Some(map.section_hashes(&map.data_file.raw_datas).to_item()),
+            read_again
+                .stored_section_hashes()
+                .map(|hashes| hashes.to_item())
+        );
+
+        // nothing changed, so the exported file is exported the same way again
+        let exported_again = read_again
+            .export_legacy(&MapFileExportOptions::default(), &thread_pool)
+            .unwrap();
+        assert_eq!(exported, exported_again);
+    }
 }
@@ -0,0 +1,252 @@
+use std::{borrow::Cow, io::Write};
+
+use flate2::{write::ZlibEncoder, Compression};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    datafile::CItemEx,
+    mapdef::{write_i32_le, write_u32_le, ItemType, WriteToVec},
+};
+
+/**
+ * Size of the datafile header (id + 8 * u32)
+ */
+const DATAFILE_HEADER_SIZE: usize = 4 + 8 * std::mem::size_of::<u32>();
+/**
+ * Size of a single entry in the item type table (type, start, num)
+ */
+const DATAFILE_ITEM_TYPE_SIZE: usize = 3 * std::mem::size_of::<i32>();
+/**
+ * Size of the header in front of every item (type_and_id, size)
+ */
+const DATAFILE_ITEM_HEADER_SIZE: usize = 2 * std::mem::size_of::<i32>();
+
+struct DatafileWriterItem {
+    item_type: i32,
+    id: i32,
+    data: Vec<u8>,
+}
+
+struct DatafileWriterData {
+    uncompressed_size: usize,
+    compressed: Vec<u8>,
+}
+
+/**
+ * A data for [`CDatafileWriter::add_datas`]
+ */
+pub enum DatafileWriterDataSource<'a> {
+    // compressed by the writer
+    Uncompressed(Cow<'a, [u8]>),
+    // already zlib compressed, e.g. an unchanged data of an opened datafile,
+    // it's copied as is
+    Compressed {
+        uncompressed_size: usize,
+        compressed: &'a [u8],
+    },
+}
+
+/**
+ * Writes the legacy (version 4) datafile format, which is used by
+ * the DDNet/Teeworlds 0.6/0.7 `.map` files.
+ * Items can be added in any order, they are grouped by their type when the file is written.
+ * Data is zlib compressed as soon as it is added,
+ * `add_datas` compresses many datas at once on the rayon thread pool
+ * and copies datas that are already compressed.
+ */
+pub struct CDatafileWriter {
+    items: Vec<DatafileWriterItem>,
+    datas: Vec<DatafileWriterData>,
+}
+
+impl CDatafileWriter {
+    pub fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            datas: Vec::new(),
+        }
+    }
+
+    /**
+     * Adds an item, the item data must already be in the
+     * little endian representation of the datafile
+     */
+    pub fn add_item(&mut self, item_type: i32, id: i32, data: Vec<u8>) {
+        self.items.push(DatafileWriterItem {
+            item_type,
+            id,
+            data,
+        });
+    }
+
+    /**
+     * Adds an item of an extended item type, identified by its uuid.
+     * The first item of the type also adds the `ITEMTYPE_EX` item that maps the uuid
+     * to a free internal item type of this file.
+     */
+    pub fn add_ext_item(&mut self, uuid: [u8; 16], id: i32, data: Vec<u8>) {
+        let ext_items = || {
+            self.items
+                .iter()
+                .filter(|item| item.item_type == ItemType::ITEMTYPE_EX as i32)
+        };
+        let existing = ext_items()
+            .find(|item| {
+                CItemEx::read_from_slice(&item.data).map(|item_ex| item_ex.to_uuid()) == Some(uuid)
+            })
+            .map(|item| item.id);
+        let item_type = match existing {
+            Some(item_type) => item_type,
+            None => {
+                // the internal types are counted down from the `ITEMTYPE_EX` type
+                let item_type = (1..)
+                    .map(|index| ItemType::ITEMTYPE_EX as i32 - index)
+                    .find(|item_type| {
+                        !self.items.iter().any(|item| item.item_type == *item_type)
+                            && !ext_items().any(|item| item.id == *item_type)
+                    })
+                    .unwrap();
+                let mut item_ex: Vec<u8> = Vec::new();
+                CItemEx::from_uuid(&uuid).write_to_vec(&mut item_ex);
+                self.add_item(ItemType::ITEMTYPE_EX as i32, item_type, item_ex);
+                item_type
+            }
+        };
+        self.add_item(item_type, id, data);
+    }
+
+    /**
+     * Compresses and adds the data, returns the data index
+     * that items can use to reference the data
+     */
+    pub fn add_data(&mut self, data: &[u8]) -> i32 {
+        self.datas.push(Self::compress(data));
+        (self.datas.len() - 1) as i32
+    }
+
+    /**
+     * Adds `count` datas, the data `i` is created by `get_data(i)`.
+     * Creating and compressing the datas runs in parallel on the current rayon
+     * thread pool, the datas are added in order.
+     * Returns the data index of the first data.
+     */
+    pub fn add_datas<'a>(
+        &mut self,
+        count: usize,
+        get_data: impl Fn(usize) -> DatafileWriterDataSource<'a> + Sync + Send,
+    ) -> i32 {
+        let first = self.datas.len();
+        let datas: Vec<DatafileWriterData> = (0..count)
+            .into_par_iter()
+            .map(|i| match get_data(i) {
+                DatafileWriterDataSource::Uncompressed(data) => Self::compress(&data),
+                DatafileWriterDataSource::Compressed {
+                    uncompressed_size,
+                    compressed,
+                } => DatafileWriterData {
+                    uncompressed_size,
+                    compressed: compressed.to_vec(),
+                },
+            })
+            .collect();
+        self.datas.extend(datas);
+        first as i32
+    }
+
+    fn compress(data: &[u8]) -> DatafileWriterData {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        // writing into a vec cannot fail
+        encoder.write_all(data).unwrap();
+        DatafileWriterData {
+            uncompressed_size: data.len(),
+            compressed: encoder.finish().unwrap(),
+        }
+    }
+
+    pub fn num_datas(&self) -> usize {
+        self.datas.len()
+    }
+
+    /**
+     * Serializes the whole datafile
+     */
+    pub fn finish(mut self) -> Vec<u8> {
+        // items of the same type must be stored next to each other,
+        // the stable sort keeps the order of items of the same type
+        self.items.sort_by_key(|item| item.item_type);
+
+        let mut item_types: Vec<(i32, i32, i32)> = Vec::new();
+        for (index, item) in self.items.iter().enumerate() {
+            match item_types.last_mut() {
+                Some((item_type, _, num)) if *item_type == item.item_type => *num += 1,
+                _ => item_types.push((item.item_type, index as i32, 1)),
+            }
+        }
+
+        let item_size: usize = self
+            .items
+            .iter()
+            .map(|item| DATAFILE_ITEM_HEADER_SIZE + item.data.len())
+            .sum();
+        let data_size: usize = self.datas.iter().map(|data| data.compressed.len()).sum();
+
+        let types_size = item_types.len() * DATAFILE_ITEM_TYPE_SIZE;
+        let offsets_size = (self.items.len() + 2 * self.datas.len()) * std::mem::size_of::<i32>();
+        let file_size = DATAFILE_HEADER_SIZE + types_size + offsets_size + item_size + data_size;
+        let swap_size = file_size - data_size;
+
+        let mut res: Vec<u8> = Vec::with_capacity(file_size);
+
+        // header
+        res.extend_from_slice(b"DATA");
+        write_u32_le(&mut res, 4);
+        // size and swap len do not count the id and the first 3 fields of the header
+        write_u32_le(&mut res, (file_size - 16) as u32);
+        write_u32_le(&mut res, (swap_size - 16) as u32);
+        write_u32_le(&mut res, item_types.len() as u32);
+        write_u32_le(&mut res, self.items.len() as u32);
+        write_u32_le(&mut res, self.datas.len() as u32);
+        write_u32_le(&mut res, item_size as u32);
+        write_u32_le(&mut res, data_size as u32);
+
+        // item types
+        for (item_type, start, num) in &item_types {
+            write_i32_le(&mut res, *item_type);
+            write_i32_le(&mut res, *start);
+            write_i32_le(&mut res, *num);
+        }
+
+        // item offsets
+        let mut offset = 0;
+        for item in &self.items {
+            write_i32_le(&mut res, offset as i32);
+            offset += DATAFILE_ITEM_HEADER_SIZE + item.data.len();
+        }
+
+        // data offsets
+        let mut offset = 0;
+        for data in &self.datas {
+            write_i32_le(&mut res, offset as i32);
+            offset += data.compressed.len();
+        }
+
+        // uncompressed data sizes
+        for data in &self.datas {
+            write_i32_le(&mut res, data.uncompressed_size as i32);
+        }
+
+        // items
+        for item in &self.items {
+            write_i32_le(&mut res, (item.item_type << 16) | (item.id & 0xFFFF));
+            write_i32_le(&mut res, item.data.len() as i32);
+            res.extend_from_slice(&item.data);
+        }
+
+        // data
+        for data in &self.datas {
+            res.extend_from_slice(&data.compressed);
+        }
+
+        res
+    }
+}
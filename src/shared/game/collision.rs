@@ -43,8 +43,15 @@ impl Collision {
     pub fn check_point(&self, x: f32, y: f32) -> bool {
         return self.is_solid(round_to_int(x), round_to_int(y));
     }
+}
+
+/**
+ * The solid parts of the world that characters collide with
+ */
+pub trait CollisionQuery {
+    fn check_point(&self, x: f32, y: f32) -> bool;
 
-    pub fn test_box(&self, pos: &vec2, size_param: &vec2) -> bool {
+    fn test_box(&self, pos: &vec2, size_param: &vec2) -> bool {
         let mut size = *size_param;
         size *= 0.5;
         if self.check_point(pos.x - size.x, pos.y - size.y) {
@@ -62,13 +69,7 @@ impl Collision {
         return false;
     }
 
-    pub fn move_box(
-        &self,
-        in_out_pos: &mut vec2,
-        in_out_vel: &mut vec2,
-        size: &vec2,
-        elasticity: f32,
-    ) {
+    fn move_box(&self, in_out_pos: &mut vec2, in_out_vel: &mut vec2, size: &vec2, elasticity: f32) {
         // do the move
         let mut pos = *in_out_pos;
         let mut vel = *in_out_vel;
@@ -127,3 +128,58 @@ impl Collision {
         *in_out_vel = vel;
     }
 }
+
+impl CollisionQuery for Collision {
+    fn check_point(&self, x: f32, y: f32) -> bool {
+        Collision::check_point(self, x, y)
+    }
+}
+
+/**
+ * A solid box that is not part of the map, e.g. a closed door,
+ * the position is the center of the box
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollisionBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl CollisionBox {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        (x - self.x).abs() <= self.width / 2.0 && (y - self.y).abs() <= self.height / 2.0
+    }
+
+    pub fn intersects(&self, other: &CollisionBox) -> bool {
+        // touching is no intersection, else a character standing on a box can't move
+        (other.x - self.x).abs() < (self.width + other.width) / 2.0
+            && (other.y - self.y).abs() < (self.height + other.height) / 2.0
+    }
+}
+
+/**
+ * The map's collision together with the solid entities of a world
+ */
+pub struct WorldCollision<'a> {
+    pub map: &'a Collision,
+    pub boxes: &'a [CollisionBox],
+}
+
+impl<'a> CollisionQuery for WorldCollision<'a> {
+    fn check_point(&self, x: f32, y: f32) -> bool {
+        self.map.check_point(x, y) || self.boxes.iter().any(|b| b.contains(x, y))
+    }
+
+    // the boxes can be thinner than the tested box, so the corners are not enough
+    fn test_box(&self, pos: &vec2, size: &vec2) -> bool {
+        let tested = CollisionBox {
+            x: pos.x,
+            y: pos.y,
+            width: size.x,
+            height: size.y,
+        };
+        self.map.test_box(pos, size) || self.boxes.iter().any(|b| b.intersects(&tested))
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use math::math::vector::vec2;
+
+use crate::{
+    datafile::CDatafileWrapper,
+    mapdef::{CSpeedupTile, CTile, MapLayer, MapLayerTile, MapTileLayerDetail, TileNum},
+};
+
+// flags of a tile in the compiled collision, the game and the front layer are combined
+pub const COLLISION_FLAG_SOLID: u8 = 1 << 0;
+pub const COLLISION_FLAG_NOHOOK: u8 = 1 << 1;
+pub const COLLISION_FLAG_DEATH: u8 = 1 << 2;
+pub const COLLISION_FLAG_FREEZE: u8 = 1 << 3;
+pub const COLLISION_FLAG_UNFREEZE: u8 = 1 << 4;
+pub const COLLISION_FLAG_NOLASER: u8 = 1 << 5;
+pub const COLLISION_FLAG_TELE: u8 = 1 << 6;
+pub const COLLISION_FLAG_SPEEDUP: u8 = 1 << 7;
+
+// the volume of an occluded sound is multiplied by this for every solid tile in between
+pub const SOUND_OCCLUSION_PER_TILE: f32 = 0.5;
+// at most this many solid tiles are counted, more tiles don't make the sound quieter
+pub const SOUND_OCCLUSION_MAX_TILES: usize = 4;
+
+/**
+ * How a sound source is heard by the listener, see `CollisionShapes::sound_occlusion`
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundOcclusion {
+    // 0.0 - 1.0, multiplied with the volume of the sound
+    pub volume: f32,
+    // 0.0 - 1.0, 1.0 means the sound is not low passed at all
+    pub low_pass: f32,
+}
+
+/**
+ * A rect of solid tiles (in tiles), all tiles of the rect are either hookable or unhookable
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub hookable: bool,
+}
+
+fn tile_flags(index: u8) -> u8 {
+    const SOLID: u8 = TileNum::TILE_SOLID as u8;
+    const NOHOOK: u8 = TileNum::TILE_NOHOOK as u8;
+    const DEATH: u8 = TileNum::TILE_DEATH as u8;
+    const NOLASER: u8 = TileNum::TILE_NOLASER as u8;
+    const FREEZE: u8 = TileNum::TILE_FREEZE as u8;
+    const DFREEZE: u8 = TileNum::TILE_DFREEZE as u8;
+    const LFREEZE: u8 = TileNum::TILE_LFREEZE as u8;
+    const UNFREEZE: u8 = TileNum::TILE_UNFREEZE as u8;
+    const DUNFREEZE: u8 = TileNum::TILE_DUNFREEZE as u8;
+    const LUNFREEZE: u8 = TileNum::TILE_LUNFREEZE as u8;
+    match index {
+        SOLID => COLLISION_FLAG_SOLID,
+        NOHOOK => COLLISION_FLAG_SOLID | COLLISION_FLAG_NOHOOK,
+        DEATH => COLLISION_FLAG_DEATH,
+        NOLASER => COLLISION_FLAG_NOLASER,
+        FREEZE | DFREEZE | LFREEZE => COLLISION_FLAG_FREEZE,
+        UNFREEZE | DUNFREEZE | LUNFREEZE => COLLISION_FLAG_UNFREEZE,
+        _ => 0,
+    }
+}
+
+/**
+ * Runtime collision structure compiled from the physics layers of a map.
+ * Every tile is reduced to a few flags, solid tiles are additionally merged
+ * to rectangles and the tele and speedup tiles are stored in lookup tables,
+ * so huge maps don't need the full physics layers at runtime.
+ */
+#[derive(Default)]
+pub struct CollisionShapes {
+    width: u32,
+    height: u32,
+    // `COLLISION_FLAG_*` per tile
+    flags: Vec<u8>,
+    solid_rects: Vec<CollisionRect>,
+
+    // the tele number of the tele tiles, by tile index (y * width + x)
+    teles: HashMap<u32, (u8, u8)>,
+    // the positions (in tiles) of the tele outs by tele number
+    tele_outs: HashMap<u8, Vec<(u32, u32)>>,
+    tele_check_outs: HashMap<u8, Vec<(u32, u32)>>,
+
+    // sorted by tile index
+    speedups: Vec<(u32, CSpeedupTile)>,
+}
+
+impl CollisionShapes {
+    /**
+     * The layers of the map must be read (see `read_map_layers`)
+     */
+    pub fn compile(map: &CDatafileWrapper) -> Self {
+        let game_layer = map.get_game_layer();
+        let width = game_layer.0.width.max(0) as u32;
+        let height = game_layer.0.height.max(0) as u32;
+        let count = width as usize * height as usize;
+        let mut res = Self {
+            width,
+            height,
+            flags: vec![0; count],
+            ..Default::default()
+        };
+        if game_layer.2.len() != count {
+            return res;
+        }
+
+        let add_tiles = |res: &mut Self, tiles: &[CTile]| {
+            if tiles.len() == count {
+                res.flags
+                    .iter_mut()
+                    .zip(tiles.iter())
+                    .for_each(|(flags, tile)| *flags |= tile_flags(tile.index));
+            }
+        };
+        add_tiles(&mut res, &game_layer.2);
+
+        map.layers
+            .iter()
+            .enumerate()
+            .for_each(|(layer_index, layer)| match layer {
+                MapLayer::Tile(MapLayerTile(tile_layer, detail, tiles))
+                    if tile_layer.width.max(0) as u32 == width
+                        && tile_layer.height.max(0) as u32 == height =>
+                {
+                    if map.is_front_layer(layer_index) {
+                        add_tiles(&mut res, tiles);
+                    }
+                    match detail {
+                        MapTileLayerDetail::Tele(teles) if teles.len() == count => {
+                            res.add_teles(teles.iter().map(|tele| (tele.number, tele.tile_type)))
+                        }
+                        MapTileLayerDetail::Speedup(speedups) if speedups.len() == count => {
+                            speedups
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, speedup)| speedup.force > 0)
+                                .for_each(|(i, speedup)| {
+                                    res.flags[i] |= COLLISION_FLAG_SPEEDUP;
+                                    res.speedups.push((i as u32, speedup.clone()));
+                                });
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            });
+
+        res.solid_rects = res.merge_solid_rects();
+        res
+    }
+
+    fn add_teles(&mut self, teles: impl Iterator<Item = (u8, u8)>) {
+        const TELEOUT: u8 = TileNum::TILE_TELEOUT as u8;
+        const TELECHECKOUT: u8 = TileNum::TILE_TELECHECKOUT as u8;
+        let width = self.width;
+        teles
+            .enumerate()
+            .filter(|(_, (number, tile_type))| *number > 0 && *tile_type > 0)
+            .for_each(|(i, (number, tile_type))| {
+                let pos = (i as u32 % width, i as u32 / width);
+                match tile_type {
+                    TELEOUT => self.tele_outs.entry(number).or_default().push(pos),
+                    TELECHECKOUT => self.tele_check_outs.entry(number).or_default().push(pos),
+                    _ => {
+                        self.flags[i] |= COLLISION_FLAG_TELE;
+                        self.teles.insert(i as u32, (number, tile_type));
+                    }
+                }
+            });
+    }
+
+    /**
+     * Greedy merge: runs of solid tiles per row,
+     * runs with the same x range in consecutive rows become one rect
+     */
+    fn merge_solid_rects(&self) -> Vec<CollisionRect> {
+        let mut res: Vec<CollisionRect> = Vec::new();
+        // the rects that can still grow downwards, by their x start
+        let mut open: HashMap<u32, usize> = HashMap::new();
+        let width = self.width as usize;
+        for y in 0..self.height {
+            let row = &self.flags[y as usize * width..(y as usize + 1) * width];
+            let mut next_open: HashMap<u32, usize> = HashMap::new();
+            let mut x = 0;
+            while x < width {
+                if (row[x] & COLLISION_FLAG_SOLID) == 0 {
+                    x += 1;
+                    continue;
+                }
+                let hookable = (row[x] & COLLISION_FLAG_NOHOOK) == 0;
+                let start = x;
+                while x < width
+                    && (row[x] & COLLISION_FLAG_SOLID) != 0
+                    && ((row[x] & COLLISION_FLAG_NOHOOK) == 0) == hookable
+                {
+                    x += 1;
+                }
+                let run_width = (x - start) as u32;
+                match open.get(&(start as u32)).copied() {
+                    Some(rect)
+                        if res[rect].width == run_width && res[rect].hookable == hookable =>
+                    {
+                        res[rect].height += 1;
+                        next_open.insert(start as u32, rect);
+                    }
+                    _ => {
+                        next_open.insert(start as u32, res.len());
+                        res.push(CollisionRect {
+                            x: start as u32,
+                            y,
+                            width: run_width,
+                            height: 1,
+                            hookable,
+                        });
+                    }
+                }
+            }
+            open = next_open;
+        }
+        res
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /**
+     * The `COLLISION_FLAG_*` of the tile, positions outside of the map are clamped
+     * to the border like in `Collision::get_tile`
+     */
+    pub fn tile_flags(&self, x: i32, y: i32) -> u8 {
+        if self.flags.is_empty() {
+            return 0;
+        }
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.flags[y * self.width as usize + x]
+    }
+
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        (self.tile_flags(x, y) & COLLISION_FLAG_SOLID) != 0
+    }
+
+    /**
+     * The solid tiles the line from `from` to `to` (in world units) passes through,
+     * the tiles of `from` and `to` themself are not counted.
+     * Stops counting at `max`.
+     */
+    pub fn solid_tiles_between(&self, from: &vec2, to: &vec2, max: usize) -> usize {
+        let tile_pos = |pos: f32| (pos / 32.0).floor() as i32;
+        let (mut x, mut y) = (tile_pos(from.x), tile_pos(from.y));
+        let (end_x, end_y) = (tile_pos(to.x), tile_pos(to.y));
+        let (dir_x, dir_y) = (to.x - from.x, to.y - from.y);
+        let step_x = if dir_x > 0.0 { 1 } else { -1 };
+        let step_y = if dir_y > 0.0 { 1 } else { -1 };
+
+        // the line position (0.0 - 1.0) where the next tile border is crossed
+        let next_border = |tile: i32, step: i32, start: f32, dir: f32| {
+            if dir == 0.0 {
+                f32::INFINITY
+            } else {
+                ((tile + step.max(0)) as f32 * 32.0 - start) / dir
+            }
+        };
+        let mut border_x = next_border(x, step_x, from.x, dir_x);
+        let mut border_y = next_border(y, step_y, from.y, dir_y);
+        let delta_x = (32.0 / dir_x).abs();
+        let delta_y = (32.0 / dir_y).abs();
+
+        let mut res = 0;
+        // every step moves one tile closer to the end
+        let steps = (end_x - x).unsigned_abs() + (end_y - y).unsigned_abs();
+        for _ in 1..steps {
+            if border_x < border_y {
+                x += step_x;
+                border_x += delta_x;
+            } else {
+                y += step_y;
+                border_y += delta_y;
+            }
+            if self.is_solid(x, y) {
+                res += 1;
+                if res >= max {
+                    break;
+                }
+            }
+        }
+        res
+    }
+
+    /**
+     * Sounds behind walls are quieter and muffled,
+     * the more solid tiles are between the listener and the sound source the stronger
+     */
+    pub fn sound_occlusion(&self, listener: &vec2, source: &vec2) -> SoundOcclusion {
+        let tiles = self.solid_tiles_between(listener, source, SOUND_OCCLUSION_MAX_TILES);
+        SoundOcclusion {
+            volume: SOUND_OCCLUSION_PER_TILE.powi(tiles as i32),
+            low_pass: 1.0 / (1.0 + tiles as f32),
+        }
+    }
+
+    pub fn solid_rects(&self) -> &[CollisionRect] {
+        &self.solid_rects
+    }
+
+    /**
+     * Number and type of the tele tile, tele outs are not part of this
+     * (see `tele_outs`)
+     */
+    pub fn tele(&self, x: u32, y: u32) -> Option<(u8, u8)> {
+        if x >= self.width {
+            return None;
+        }
+        self.teles.get(&(y * self.width + x)).copied()
+    }
+
+    pub fn tele_outs(&self, number: u8) -> &[(u32, u32)] {
+        self.tele_outs
+            .get(&number)
+            .map(|outs| outs.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn tele_check_outs(&self, number: u8) -> &[(u32, u32)] {
+        self.tele_check_outs
+            .get(&number)
+            .map(|outs| outs.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn speedup(&self, x: u32, y: u32) -> Option<&CSpeedupTile> {
+        if x >= self.width {
+            return None;
+        }
+        let index = y * self.width + x;
+        self.speedups
+            .binary_search_by_key(&index, |(i, _)| *i)
+            .ok()
+            .map(|i| &self.speedups[i].1)
+    }
+}
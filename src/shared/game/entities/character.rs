@@ -115,7 +115,7 @@ impl EntityInterface<CharacterCore> for Character {
             other_chars_before: &mut pipe.other_chars_before,
             other_chars_after: &mut pipe.other_chars_after,
         };
-        Self::physics_move(&mut core.core, &mut core_pipe, &pipe.collision);
+        Self::physics_move(&mut core.core, &mut core_pipe, pipe.collision);
         Self::physics_quantize(&mut core.core);
     }
 
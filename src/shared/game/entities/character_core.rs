@@ -1,6 +1,6 @@
 use std::ops::AddAssign;
 
-use crate::{game::collision::Collision, mapdef::TileNum, types::GameTickType};
+use crate::{game::collision::CollisionQuery, mapdef::TileNum, types::GameTickType};
 
 use math::math::{
     closest_point_on_line, distance, dot, length, mix, normalize, round_to_int, vector::vec2, PI,
@@ -469,7 +469,7 @@ enum CannotMove {
     Down = 1 << 3,
 }
 
-const fn physical_size() -> f32 {
+pub const fn physical_size() -> f32 {
     28.0
 }
 const fn physical_size_vec2() -> vec2 {
@@ -527,7 +527,7 @@ pub trait CorePhysics {
         use_input: bool,
         do_deferred_tick: bool,
         pipe: &mut dyn CorePipe,
-        collision: &Collision,
+        collision: &dyn CollisionQuery,
     ) {
         core.move_restrictions = 0; // TODO core.m_pCollision->GetMoveRestrictions(UseInput ? IsSwitchActiveCb : 0, this, core.m_Pos);
         core.triggered_events = 0;
@@ -942,7 +942,7 @@ pub trait CorePhysics {
         return 1.0 / curvature.powf((value - start) / range);
     }
 
-    fn physics_move(core: &mut Core, pipe: &mut dyn CorePipe, collision: &Collision) {
+    fn physics_move(core: &mut Core, pipe: &mut dyn CorePipe, collision: &dyn CollisionQuery) {
         let ramp_value = Self::velocity_ramp(
             length(&core.vel) * 50.0,
             core.tuning.velramp_start,
@@ -0,0 +1,133 @@
+use bincode::{Decode, Encode};
+
+use crate::{
+    game::{collision::CollisionBox, TGameElementID},
+    types::GameTickType,
+};
+
+use super::Entity;
+
+// the size of a pickup in both directions, like a tile
+pub const MAP_ENTITY_PICKUP_SIZE: f32 = 32.0;
+
+#[derive(Clone, Copy, PartialEq, Encode, Decode)]
+pub enum MapEntityKind {
+    Door {
+        width: f32,
+        height: f32,
+        closed: bool,
+    },
+    // moves from the start position to the target and back
+    MovingPlatform {
+        width: f32,
+        height: f32,
+        target_x: f32,
+        target_y: f32,
+        ticks_per_way: u32,
+    },
+    // the mod decides what the type means,
+    // it's collected by the first character that touches it
+    Pickup {
+        pickup_type: u32,
+    },
+}
+
+/**
+ * The replicated part of an entity that a mod spawned at runtime,
+ * unlike the entities baked into the map's game layer
+ */
+#[derive(Clone, Copy, Encode, Decode)]
+pub struct MapEntityCore {
+    pub kind: MapEntityKind,
+    // the position is the center of the entity
+    pub start_x: f32,
+    pub start_y: f32,
+    pub x: f32,
+    pub y: f32,
+    // 0 means the entity lives until it's despawned
+    pub lifetime_ticks: GameTickType,
+    pub ticks_alive: GameTickType,
+}
+
+impl MapEntityCore {
+    pub fn new(kind: MapEntityKind, x: f32, y: f32, lifetime_ticks: GameTickType) -> Self {
+        Self {
+            kind,
+            start_x: x,
+            start_y: y,
+            x,
+            y,
+            lifetime_ticks,
+            ticks_alive: 0,
+        }
+    }
+
+    /**
+     * The area the entity covers at its current position
+     */
+    pub fn bounds(&self) -> CollisionBox {
+        let (width, height) = match self.kind {
+            MapEntityKind::Door { width, height, .. }
+            | MapEntityKind::MovingPlatform { width, height, .. } => (width, height),
+            MapEntityKind::Pickup { .. } => (MAP_ENTITY_PICKUP_SIZE, MAP_ENTITY_PICKUP_SIZE),
+        };
+        CollisionBox {
+            x: self.x,
+            y: self.y,
+            width,
+            height,
+        }
+    }
+
+    /**
+     * Closed doors and platforms block characters like solid tiles
+     */
+    pub fn is_solid(&self) -> bool {
+        match self.kind {
+            MapEntityKind::Door { closed, .. } => closed,
+            MapEntityKind::MovingPlatform { .. } => true,
+            MapEntityKind::Pickup { .. } => false,
+        }
+    }
+}
+
+pub struct MapEntity {
+    pub base: Entity,
+    pub core: MapEntityCore,
+}
+
+impl MapEntity {
+    pub fn new(game_el_id: &TGameElementID, core: MapEntityCore) -> Self {
+        Self {
+            base: Entity::new(game_el_id),
+            core,
+        }
+    }
+
+    /**
+     * Returns false if the lifetime of the entity is over
+     */
+    pub fn tick(&mut self) -> bool {
+        let core = &mut self.core;
+        core.ticks_alive += 1;
+        if let MapEntityKind::MovingPlatform {
+            target_x,
+            target_y,
+            ticks_per_way,
+            ..
+        } = core.kind
+        {
+            let ticks_per_way = ticks_per_way.max(1) as GameTickType;
+            let way_tick = core.ticks_alive % (ticks_per_way * 2);
+            // forth in the first half, back in the second
+            let progress = if way_tick < ticks_per_way {
+                way_tick as f32 / ticks_per_way as f32
+            } else {
+                (ticks_per_way * 2 - way_tick) as f32 / ticks_per_way as f32
+            };
+            core.x = core.start_x + (target_x - core.start_x) * progress;
+            core.y = core.start_y + (target_y - core.start_y) * progress;
+        }
+        core.lifetime_ticks == 0 || core.ticks_alive < core.lifetime_ticks
+    }
+}
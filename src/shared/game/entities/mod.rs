@@ -29,3 +29,4 @@ impl Entity {
 
 pub mod character;
 pub mod character_core;
+pub mod map_entity;
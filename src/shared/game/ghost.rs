@@ -0,0 +1,151 @@
+use base::filesys::{file_name_from_name, FileSystem};
+use bincode::{Decode, Encode};
+
+use crate::{map_hash::MapSectionHash, types::GameTickType};
+
+use super::{entities::character_core::Core, simulation_pipe::LocalPlayerInput};
+
+const GHOST_DIR: &str = "ghosts";
+
+#[derive(Debug, Clone, Copy, Default, Encode, Decode)]
+pub struct GhostSample {
+    pub x: f32,
+    pub y: f32,
+    pub cursor_x: i32,
+    pub cursor_y: i32,
+    pub dir: i32,
+    pub jump: bool,
+    pub hook: bool,
+}
+
+/**
+ * The path of a player's (best) run, the standard ghost format that
+ * race mods emit and clients render.
+ * The ghost only fits to maps with the same physics.
+ */
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct GhostPath {
+    pub map_physics_hash: MapSectionHash,
+    pub player_name: String,
+    pub finish_ticks: GameTickType,
+    // ticks between two samples
+    pub sample_interval: u32,
+    pub samples: Vec<GhostSample>,
+}
+
+/**
+ * Records the samples of one character while it runs
+ */
+pub struct GhostRecorder {
+    sample_interval: u32,
+    ticks: GameTickType,
+    samples: Vec<GhostSample>,
+}
+
+impl GhostRecorder {
+    pub fn new(sample_interval: u32) -> Self {
+        Self {
+            sample_interval: sample_interval.max(1),
+            ticks: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    /**
+     * Call this once per tick after the character was simulated
+     */
+    pub fn record_tick(&mut self, core: &Core, input: &LocalPlayerInput) {
+        if self.ticks % self.sample_interval as GameTickType == 0 {
+            self.samples.push(GhostSample {
+                x: core.pos.x,
+                y: core.pos.y,
+                cursor_x: input.x,
+                cursor_y: input.y,
+                dir: input.dir,
+                jump: input.jump,
+                hook: input.hook,
+            });
+        }
+        self.ticks += 1;
+    }
+
+    /**
+     * E.g. when the player was killed before the finish
+     */
+    pub fn restart(&mut self) {
+        self.ticks = 0;
+        self.samples.clear();
+    }
+
+    pub fn finish(&mut self, map_physics_hash: &MapSectionHash, player_name: &str) -> GhostPath {
+        let res = GhostPath {
+            map_physics_hash: *map_physics_hash,
+            player_name: player_name.to_string(),
+            finish_ticks: self.ticks,
+            sample_interval: self.sample_interval,
+            samples: std::mem::take(&mut self.samples),
+        };
+        self.ticks = 0;
+        res
+    }
+}
+
+fn ghost_map_dir(map_physics_hash: &MapSectionHash) -> String {
+    let hash_str: String = map_physics_hash
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}/{}/", GHOST_DIR, hash_str)
+}
+
+impl GhostPath {
+    /**
+     * Saves the ghost in the user directory, keyed by the physics hash of the map
+     * and the player name.
+     * Only replaces an existing ghost of the player if this one is faster.
+     */
+    pub async fn save(&self, fs: &FileSystem) -> anyhow::Result<()> {
+        let path = format!(
+            "{}{}.ghost",
+            ghost_map_dir(&self.map_physics_hash),
+            file_name_from_name(&self.player_name)
+        );
+        if let Ok(file) = fs.open_file(&path).await {
+            if let Ok((existing, _)) =
+                bincode::decode_from_slice::<GhostPath, _>(&file, bincode::config::standard())
+            {
+                if existing.finish_ticks <= self.finish_ticks {
+                    return Ok(());
+                }
+            }
+        }
+        fs.write_file(
+            &path,
+            bincode::encode_to_vec(self, bincode::config::standard())?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * All saved ghosts of the map, sorted by the finish time
+     */
+    pub async fn load_for_map(
+        fs: &FileSystem,
+        map_physics_hash: &MapSectionHash,
+    ) -> Vec<GhostPath> {
+        let mut res: Vec<GhostPath> = Vec::new();
+        fs.files_of_dir(&ghost_map_dir(map_physics_hash), &mut |_, file| {
+            if let Ok((ghost, _)) =
+                bincode::decode_from_slice::<GhostPath, _>(&file, bincode::config::standard())
+            {
+                if ghost.map_physics_hash == *map_physics_hash {
+                    res.push(ghost);
+                }
+            }
+        })
+        .await;
+        res.sort_by_key(|ghost| ghost.finish_ticks);
+        res
+    }
+}
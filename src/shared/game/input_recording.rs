@@ -0,0 +1,225 @@
+use bincode::{Decode, Encode};
+
+use super::{
+    collision::Collision,
+    simulation_pipe::{LocalPlayerInput, SimulationPipe, SimulationPlayerInput},
+    snapshot::{
+        Snapshot, SnapshotClientInfo, SnapshotClientRole, SnapshotManager, SnapshotSpectatorCamera,
+    },
+    state::{GameState, GameStateInterface},
+    state_checksum::GameStateChecksum,
+    TGameElementID, INVALID_GAME_ELEMENT_ID,
+};
+
+#[derive(Clone, Encode, Decode)]
+pub struct InputRecordingTick {
+    pub inputs: Vec<(TGameElementID, LocalPlayerInput)>,
+    // the checksum of the state after this tick
+    pub checksum: GameStateChecksum,
+}
+
+impl SimulationPlayerInput for InputRecordingTick {
+    fn get_input(&self, player_id: TGameElementID) -> Option<&LocalPlayerInput> {
+        self.inputs
+            .iter()
+            .find(|(id, _)| *id == player_id)
+            .map(|(_, inp)| inp)
+    }
+}
+
+/**
+ * The exact inputs of all players per tick, starting from a snapshot of the game state.
+ * Replaying it against the start state must give the same checksums,
+ * which is used to verify tool assisted runs and for physics regression tests.
+ */
+#[derive(Encode, Decode)]
+pub struct InputRecording {
+    pub start: Snapshot,
+    pub ticks: Vec<InputRecordingTick>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputPlaybackDesync {
+    // the index of the tick in the recording
+    pub tick_index: usize,
+    pub recorded_checksum: GameStateChecksum,
+    pub replayed_checksum: GameStateChecksum,
+}
+
+impl InputRecording {
+    /**
+     * Starts a recording from the current state of the game
+     */
+    pub fn new(snap_builder: &SnapshotManager, game: &GameState) -> Self {
+        Self {
+            start: snap_builder.build_for(
+                game,
+                &SnapshotClientInfo {
+                    client_player_id: INVALID_GAME_ELEMENT_ID,
+                    role: SnapshotClientRole::Spectator(SnapshotSpectatorCamera::Free),
+                    snap_everything: true,
+                    snap_other_stages: true,
+                    time_since_connect_nanos: 0,
+                    view_area: None,
+                    tracked_players: &[],
+                },
+            ),
+            ticks: Vec::new(),
+        }
+    }
+
+    /**
+     * Ticks the game and records the inputs of all players that have a character
+     */
+    pub fn record_tick(&mut self, game: &mut GameState, pipe: &mut SimulationPipe) {
+        let mut inputs = Vec::new();
+        for stage in game.get_stages() {
+            for char in stage.get_world().get_characters() {
+                let player_id = char.cores[0].player_id;
+                if let Some(inp) = pipe.player_inputs.get_input(player_id) {
+                    inputs.push((player_id, *inp));
+                }
+            }
+        }
+        game.tick(pipe);
+        self.ticks.push(InputRecordingTick {
+            inputs,
+            checksum: game.state_checksum(),
+        });
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(
+            path,
+            bincode::encode_to_vec(self, bincode::config::standard())?,
+        )?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let file = std::fs::read(path)?;
+        let (res, _) = bincode::decode_from_slice(&file, bincode::config::standard())?;
+        Ok(res)
+    }
+
+    /**
+     * Writes the start state of the recording into the game state
+     */
+    pub fn prepare(&self, snap_builder: &mut SnapshotManager, game: &mut GameState) {
+        snap_builder.convert_to_game_state(&self.start, game);
+    }
+
+    /**
+     * Replays a single tick, the game must be prepared (see `prepare`)
+     * and have simulated all ticks before
+     */
+    pub fn replay_tick(
+        &self,
+        tick_index: usize,
+        game: &mut GameState,
+        collision: &Collision,
+    ) -> Result<(), InputPlaybackDesync> {
+        let tick = &self.ticks[tick_index];
+        game.tick(&mut SimulationPipe {
+            player_inputs: tick,
+            collision,
+        });
+        let replayed_checksum = game.state_checksum();
+        if replayed_checksum != tick.checksum {
+            return Err(InputPlaybackDesync {
+                tick_index,
+                recorded_checksum: tick.checksum,
+                replayed_checksum,
+            });
+        }
+        Ok(())
+    }
+
+    /**
+     * Replays the whole recording against a fresh game state,
+     * returns the first tick that did not give the recorded state
+     */
+    pub fn verify(&self, collision: &Collision) -> Result<(), InputPlaybackDesync> {
+        let mut snap_builder = SnapshotManager::new();
+        let mut game = GameState::new();
+        self.prepare(&mut snap_builder, &mut game);
+        (0..self.ticks.len())
+            .try_for_each(|tick_index| self.replay_tick(tick_index, &mut game, collision))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use math::math::vector::vec2;
+
+    use crate::{
+        game::{
+            collision::Collision,
+            simulation_pipe::{LocalPlayerInput, SimulationPipe},
+            snapshot::SnapshotManager,
+            state::GameState,
+            GameElementGenerator,
+        },
+        mapdef::{CTile, TileNum},
+    };
+
+    use super::{InputRecording, InputRecordingTick};
+
+    #[test]
+    fn record_replay() {
+        // a closed box, so the character hits the floor and the walls
+        let (width, height) = (20, 20);
+        let tiles: Vec<CTile> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let solid = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                CTile {
+                    index: if solid {
+                        TileNum::TILE_SOLID as u8
+                    } else {
+                        TileNum::TILE_AIR as u8
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let collision = Collision::new(width, height, &tiles);
+
+        let mut id_gen = GameElementGenerator::default();
+        let mut game = GameState::new();
+        let stage_index = game.add_stage(&mut id_gen);
+        let player_id = id_gen.get_character_id();
+        game.get_stage_mut(stage_index)
+            .get_world_mut()
+            .add_character(&mut id_gen, &player_id)
+            .cores[0]
+            .core
+            .pos = vec2::new(5.0 * 32.0, 10.0 * 32.0);
+
+        let snap_builder = SnapshotManager::new();
+        let mut recording = InputRecording::new(&snap_builder, &game);
+        for tick in 0..200 {
+            let inputs = InputRecordingTick {
+                inputs: vec![(
+                    player_id,
+                    LocalPlayerInput {
+                        x: 100,
+                        y: -50,
+                        dir: if (tick / 50) % 2 == 0 { 1 } else { -1 },
+                        jump: tick % 30 < 5,
+                        hook: tick % 70 > 40,
+                    },
+                )],
+                checksum: Default::default(),
+            };
+            recording.record_tick(&mut game, &mut SimulationPipe::new(&inputs, &collision));
+        }
+        assert!(recording.verify(&collision).is_ok());
+
+        // different inputs must be noticed
+        recording.ticks[100].inputs[0].1.dir *= -1;
+        assert!(recording
+            .verify(&collision)
+            .is_err_and(|desync| desync.tick_index >= 100));
+    }
+}
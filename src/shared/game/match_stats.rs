@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use bincode::{Decode, Encode};
+
+use crate::types::GameTickType;
+
+use super::TGameElementID;
+
+#[derive(Debug, Clone, Copy, Default, Encode, Decode)]
+pub struct MatchPlayerStats {
+    pub player_id: TGameElementID,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    // the best finish time of a race in ticks
+    pub finish_ticks: Option<GameTickType>,
+    pub ticks_alive: GameTickType,
+}
+
+/**
+ * The end of match report, the same schema is sent to the clients (scoreboard)
+ * and used by ranking mods
+ */
+#[derive(Debug, Clone, Default, Encode, Decode)]
+pub struct MatchStatsReport {
+    pub start_tick: GameTickType,
+    pub end_tick: GameTickType,
+    // sorted by the player id
+    pub players: Vec<MatchPlayerStats>,
+}
+
+/**
+ * Collects the statistics of the current match
+ */
+#[derive(Default)]
+pub struct MatchStats {
+    start_tick: GameTickType,
+    players: HashMap<TGameElementID, MatchPlayerStats>,
+}
+
+impl MatchStats {
+    fn player_mut(&mut self, player_id: &TGameElementID) -> &mut MatchPlayerStats {
+        self.players
+            .entry(*player_id)
+            .or_insert_with(|| MatchPlayerStats {
+                player_id: *player_id,
+                ..Default::default()
+            })
+    }
+
+    pub fn reset(&mut self, start_tick: GameTickType) {
+        self.start_tick = start_tick;
+        self.players.clear();
+    }
+
+    pub fn add_tick_alive(&mut self, player_id: &TGameElementID) {
+        self.player_mut(player_id).ticks_alive += 1;
+    }
+
+    pub fn add_damage(&mut self, from: &TGameElementID, to: &TGameElementID, amount: u32) {
+        // self damage is not counted as dealt damage
+        if from != to {
+            self.player_mut(from).damage_dealt += amount;
+        }
+        self.player_mut(to).damage_taken += amount;
+    }
+
+    pub fn add_kill(&mut self, killer: &TGameElementID, victim: &TGameElementID) {
+        if killer != victim {
+            self.player_mut(killer).kills += 1;
+        }
+        self.player_mut(victim).deaths += 1;
+    }
+
+    /**
+     * Only the best finish time is kept
+     */
+    pub fn add_finish(&mut self, player_id: &TGameElementID, ticks: GameTickType) {
+        let player = self.player_mut(player_id);
+        player.finish_ticks = Some(player.finish_ticks.map_or(ticks, |best| best.min(ticks)));
+    }
+
+    pub fn report(&self, end_tick: GameTickType) -> MatchStatsReport {
+        let mut players: Vec<MatchPlayerStats> = self.players.values().copied().collect();
+        players.sort_by_key(|player| player.player_id);
+        MatchStatsReport {
+            start_tick: self.start_tick,
+            end_tick,
+            players,
+        }
+    }
+}
@@ -3,11 +3,17 @@ use crate::id_gen::{
 };
 
 pub mod collision;
+pub mod collision_shapes;
 pub mod entities;
+pub mod ghost;
+pub mod input_recording;
+pub mod match_stats;
 pub mod simulation_pipe;
 pub mod snapshot;
+pub mod snapshot_delta;
 pub mod stage;
 pub mod state;
+pub mod state_checksum;
 pub mod weapons;
 pub mod world;
 
@@ -21,6 +27,7 @@ pub const FIRST_GAME_ELEMENT_ID: IDGeneratorIDType = ID_GENERATOR_ID_FIRST;
 pub struct GameElementGenerator {
     stage_gen: IDGenerator,
     char_gen: IDGenerator,
+    map_entity_gen: IDGenerator,
 }
 
 impl Default for GameElementGenerator {
@@ -28,6 +35,7 @@ impl Default for GameElementGenerator {
         Self {
             stage_gen: IDGenerator::new(),
             char_gen: IDGenerator::new(),
+            map_entity_gen: IDGenerator::new(),
         }
     }
 }
@@ -40,4 +48,8 @@ impl GameElementGenerator {
     pub fn get_character_id(&mut self) -> TGameElementID {
         self.char_gen.get_next()
     }
+
+    pub fn get_map_entity_id(&mut self) -> TGameElementID {
+        self.map_entity_gen.get_next()
+    }
 }
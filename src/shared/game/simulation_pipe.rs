@@ -1,10 +1,14 @@
+use bincode::{Decode, Encode};
+
 use crate::network::messages::{MsgObjPlayerInput, WeaponType};
 
 use super::{
-    collision::Collision, entities::character_core::Core, TGameElementID, INVALID_GAME_ELEMENT_ID,
+    collision::{Collision, CollisionQuery},
+    entities::character_core::Core,
+    TGameElementID, INVALID_GAME_ELEMENT_ID,
 };
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, Encode, Decode)]
 pub struct LocalPlayerInput {
     pub x: i32,
     pub y: i32,
@@ -165,7 +169,7 @@ pub struct SimulationPipeEntities<'a> {
     pub other_chars_before: &'a mut [&'a mut Core],
     pub other_chars_after: &'a mut [&'a mut Core],
 
-    pub collision: &'a Collision,
+    pub collision: &'a dyn CollisionQuery,
 }
 
 impl<'a> SimulationPipeEntities<'a> {
@@ -175,7 +179,7 @@ impl<'a> SimulationPipeEntities<'a> {
         player_inputs: &'a dyn SimulationPlayerInput,
         other_chars_before: &'a mut [&'a mut Core],
         other_chars_after: &'a mut [&'a mut Core],
-        collision: &'a Collision,
+        collision: &'a dyn CollisionQuery,
     ) -> Self {
         Self {
             next_core_index: next_core_index,
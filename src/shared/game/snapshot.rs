@@ -1,18 +1,78 @@
-
-
 use super::{
-    entities::character::{Character, CharacterCore},
+    entities::{
+        character::{Character, CharacterCore},
+        map_entity::{MapEntity, MapEntityCore},
+    },
     stage::GameStage,
-    state::GameState,
+    state::{GameState, GameStateInterface},
+    state_checksum::{state_checksum, GameStateChecksum, StateChecksumStage},
     TGameElementID, INVALID_GAME_ELEMENT_ID,
 };
 use bincode::{Decode, Encode};
 
-pub struct SnapshotClientInfo {
+/**
+ * The camera of a spectator
+ */
+#[derive(Encode, Decode, Default, Clone, Copy, PartialEq)]
+pub enum SnapshotSpectatorCamera {
+    // the client moves the camera on its own
+    #[default]
+    Free,
+    // follows the character of the player
+    Chase(TGameElementID),
+}
+
+#[derive(Encode, Decode, Default, Clone, Copy, PartialEq)]
+pub enum SnapshotClientRole {
+    #[default]
+    Player,
+    // has no character
+    Spectator(SnapshotSpectatorCamera),
+}
+
+// the largest visible area of the client at zoom 1, see `RenderTools::calc_canvas_params`
+const SNAPSHOT_VIEW_WIDTH: f32 = 1500.0;
+const SNAPSHOT_VIEW_HEIGHT: f32 = 1050.0;
+// entities slightly outside of the view are still snapped,
+// so they don't pop in while the camera moves or the view area is on its way
+const SNAPSHOT_VIEW_MARGIN: f32 = 32.0 * 10.0;
+// clients can't request the whole map by sending a huge zoom
+const SNAPSHOT_VIEW_MAX_ZOOM: f32 = 4.0;
+
+/**
+ * The camera of the client, entities outside of it are not snapped
+ */
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotViewArea {
+    pub x: f32,
+    pub y: f32,
+    pub zoom: f32,
+}
+
+impl SnapshotViewArea {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let zoom = if self.zoom.is_finite() {
+            self.zoom.clamp(0.0, SNAPSHOT_VIEW_MAX_ZOOM)
+        } else {
+            SNAPSHOT_VIEW_MAX_ZOOM
+        };
+        let half_width = SNAPSHOT_VIEW_WIDTH / 2.0 * zoom + SNAPSHOT_VIEW_MARGIN;
+        let half_height = SNAPSHOT_VIEW_HEIGHT / 2.0 * zoom + SNAPSHOT_VIEW_MARGIN;
+        (x - self.x).abs() <= half_width && (y - self.y).abs() <= half_height
+    }
+}
+
+pub struct SnapshotClientInfo<'a> {
     pub client_player_id: TGameElementID,
+    pub role: SnapshotClientRole,
     pub snap_everything: bool,
     pub snap_other_stages: bool,
     pub time_since_connect_nanos: u64,
+    // `None` snaps the whole map, e.g. before the client sent its camera
+    pub view_area: Option<SnapshotViewArea>,
+    // players that are snapped even outside of the view area,
+    // e.g. the player whose inputs a spectator watches
+    pub tracked_players: &'a [TGameElementID],
 }
 
 #[derive(Encode, Decode, Default)]
@@ -22,9 +82,17 @@ pub struct SnapshotCharacter {
     pub game_el_id: TGameElementID,
 }
 
+#[derive(Encode, Decode)]
+pub struct SnapshotMapEntity {
+    pub core: MapEntityCore,
+
+    pub game_el_id: TGameElementID,
+}
+
 #[derive(Encode, Decode, Default)]
 pub struct SnapshotWorld {
     pub characters: Vec<SnapshotCharacter>,
+    pub map_entities: Vec<SnapshotMapEntity>,
 }
 
 #[derive(Encode, Decode, Default)]
@@ -32,6 +100,7 @@ pub struct SnapshotStage {
     pub world: SnapshotWorld,
 
     pub game_el_id: TGameElementID,
+    pub locked: bool,
 }
 
 #[derive(Encode, Decode, Default)]
@@ -45,7 +114,41 @@ pub struct Snapshot {
     pub monotonic_tick: u64,
 
     pub recv_player_id: TGameElementID,
+    // the perspective of the snapshot, e.g. for demos of spectators
+    pub recv_role: SnapshotClientRole,
     pub time_since_connect_nanos: u64,
+
+    // clients don't predict while the server paused the simulation
+    pub paused: bool,
+}
+
+impl Snapshot {
+    /**
+     * The same checksum as `GameState::state_checksum` of the game state
+     * this snapshot is converted to, the server sends it for the (filtered) snapshot
+     */
+    pub fn state_checksum(&self) -> GameStateChecksum {
+        state_checksum(
+            self.stages
+                .iter()
+                .map(|stage| StateChecksumStage {
+                    id: stage.game_el_id,
+                    characters: stage
+                        .world
+                        .characters
+                        .iter()
+                        .map(|char| (char.game_el_id, &char.core))
+                        .collect(),
+                    map_entities: stage
+                        .world
+                        .map_entities
+                        .iter()
+                        .map(|ent| (ent.game_el_id, &ent.core))
+                        .collect(),
+                })
+                .collect(),
+        )
+    }
 }
 
 pub struct SnapshotManager {
@@ -59,11 +162,42 @@ impl SnapshotManager {
         }
     }
 
+    /**
+     * Only the own character, the chased character and the tracked players are
+     * snapped independent of the view area of the client
+     */
     pub fn build_for(&self, game: &GameState, client: &SnapshotClientInfo) -> Snapshot {
         let mut res = Snapshot::default();
         res.time_since_connect_nanos = client.time_since_connect_nanos;
         res.monotonic_tick = game.cur_monotonic_tick;
-        res.recv_player_id = client.client_player_id;
+        res.paused = game.is_paused();
+        // the chase target is validated every snapshot, since the player might have left
+        res.recv_role = match client.role {
+            SnapshotClientRole::Spectator(SnapshotSpectatorCamera::Chase(target))
+                if !game.is_valid_spectator_target(&target) =>
+            {
+                SnapshotClientRole::Spectator(SnapshotSpectatorCamera::Free)
+            }
+            role => role,
+        };
+        res.recv_player_id = match client.role {
+            SnapshotClientRole::Player => client.client_player_id,
+            SnapshotClientRole::Spectator(_) => INVALID_GAME_ELEMENT_ID,
+        };
+        let view_area = client.view_area.filter(|_| !client.snap_everything);
+        let in_view = |x: f32, y: f32| view_area.map_or(true, |view| view.contains(x, y));
+        let chase_target = match res.recv_role {
+            SnapshotClientRole::Spectator(SnapshotSpectatorCamera::Chase(target)) => target,
+            _ => INVALID_GAME_ELEMENT_ID,
+        };
+        let recv_player_id = res.recv_player_id;
+        let is_char_snapped = |char: &Character| {
+            let player_id = char.cores[0].player_id;
+            player_id == recv_player_id
+                || player_id == chase_target
+                || client.tracked_players.contains(&player_id)
+                || in_view(char.cores[0].core.pos.x, char.cores[0].core.pos.y)
+        };
         game.get_stages().iter().for_each(|stage| {
             res.stages.push(SnapshotStage {
                 world: SnapshotWorld {
@@ -71,6 +205,7 @@ impl SnapshotManager {
                         .get_world()
                         .get_characters()
                         .iter()
+                        .filter(|char| is_char_snapped(*char))
                         .map(|char| -> SnapshotCharacter {
                             SnapshotCharacter {
                                 core: char.cores[0],
@@ -78,8 +213,19 @@ impl SnapshotManager {
                             }
                         })
                         .collect(),
+                    map_entities: stage
+                        .get_world()
+                        .get_map_entities()
+                        .iter()
+                        .filter(|ent| in_view(ent.core.x, ent.core.y))
+                        .map(|ent| SnapshotMapEntity {
+                            core: ent.core,
+                            game_el_id: ent.base.game_element_id,
+                        })
+                        .collect(),
                 },
                 game_el_id: stage.game_element_id,
+                locked: stage.locked,
             });
         });
         res
@@ -90,6 +236,14 @@ impl SnapshotManager {
      * It uses a mutable reference to reuse vector capacity, heap objects etc.
      */
     pub fn convert_to_game_state(&mut self, snapshot: &Snapshot, write_game_state: &mut GameState) {
+        if snapshot.paused != write_game_state.is_paused() {
+            if snapshot.paused {
+                write_game_state.pause();
+            } else {
+                write_game_state.resume();
+            }
+        }
+
         // clear stages, we want to find stages that are in the snapshot aswell as in the game state
         // so we can reuse them
         self.helper_state.get_stages_mut().clear();
@@ -126,6 +280,7 @@ impl SnapshotManager {
             }
 
             let stage = write_game_state.get_stages_mut().last_mut().unwrap();
+            stage.locked = stage_snap.locked;
             stage.get_world_mut().get_characters_mut().clear();
             // now go through the children of the stage
             stage_snap.world.characters.iter().for_each(|char| {
@@ -133,6 +288,13 @@ impl SnapshotManager {
                 character.cores[0] = char.core;
                 stage.get_world_mut().get_characters_mut().push(character);
             });
+            stage.get_world_mut().get_map_entities_mut().clear();
+            stage_snap.world.map_entities.iter().for_each(|ent| {
+                stage
+                    .get_world_mut()
+                    .get_map_entities_mut()
+                    .push(MapEntity::new(&ent.game_el_id, ent.core));
+            });
         });
     }
 }
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::anyhow;
+
+use crate::network::messages::MsgSvSnapshotDelta;
+
+use super::snapshot::Snapshot;
+
+// how many snapshots both sides keep as possible baselines (~0.6 seconds)
+pub const SNAPSHOT_MAX_BASELINES: usize = 32;
+// the newest acknowledged baselines the server tries for every snapshot
+const SNAPSHOT_BASELINE_CANDIDATES: usize = 3;
+// protects the client against deltas that claim huge snapshots
+const SNAPSHOT_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+fn write_varint(res: &mut Vec<u8>, mut val: usize) {
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            res.push(byte);
+            return;
+        }
+        res.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> anyhow::Result<usize> {
+    let mut res: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| anyhow!("the delta ended too early"))?;
+        *pos += 1;
+        if shift >= usize::BITS {
+            return Err(anyhow!("the delta contains an invalid number"));
+        }
+        res |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(res);
+        }
+        shift += 7;
+    }
+}
+
+/**
+ * The bytes are xor'ed with the baseline (missing baseline bytes count as 0),
+ * the unchanged runs are only stored as their length:
+ * len, then (unchanged run, changed run, changed bytes) until the end
+ */
+fn encode_delta(baseline: &[u8], data: &[u8]) -> Vec<u8> {
+    let xor_at = |i: usize| data[i] ^ baseline.get(i).copied().unwrap_or(0);
+    let mut res = Vec::new();
+    write_varint(&mut res, data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let unchanged_start = i;
+        while i < data.len() && xor_at(i) == 0 {
+            i += 1;
+        }
+        let changed_start = i;
+        while i < data.len() && xor_at(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut res, changed_start - unchanged_start);
+        write_varint(&mut res, i - changed_start);
+        res.extend((changed_start..i).map(xor_at));
+    }
+    res
+}
+
+fn decode_delta(baseline: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos)?;
+    if len > SNAPSHOT_MAX_SIZE {
+        return Err(anyhow!("the delta is too large"));
+    }
+    let mut res: Vec<u8> = (0..len)
+        .map(|i| baseline.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut i = 0;
+    while i < len {
+        i = i.saturating_add(read_varint(delta, &mut pos)?);
+        let changed = read_varint(delta, &mut pos)?;
+        if i.saturating_add(changed) > len || pos.saturating_add(changed) > delta.len() {
+            return Err(anyhow!("the delta does not fit to the baseline"));
+        }
+        res[i..i + changed]
+            .iter_mut()
+            .zip(delta[pos..pos + changed].iter())
+            .for_each(|(byte, xor)| *byte ^= *xor);
+        i += changed;
+        pos += changed;
+    }
+    Ok(res)
+}
+
+/**
+ * The snapshots the server sent to a client, the ones the client acknowledged
+ * are used as baselines for the next snapshots.
+ * With several baselines a lost ack or snapshot does not force a full snapshot.
+ */
+#[derive(Default)]
+pub struct SnapshotBaselines {
+    // monotonic tick -> encoded snapshot
+    sent: BTreeMap<u64, Vec<u8>>,
+    acked: BTreeSet<u64>,
+}
+
+impl SnapshotBaselines {
+    pub fn ack(&mut self, monotonic_tick: u64) {
+        if self.sent.contains_key(&monotonic_tick) {
+            self.acked.insert(monotonic_tick);
+        }
+    }
+
+    /**
+     * Encodes the snapshot against the acknowledged baseline that gives the smallest delta
+     */
+    pub fn encode(&mut self, snapshot: &Snapshot) -> MsgSvSnapshotDelta {
+        let data = bincode::encode_to_vec(snapshot, bincode::config::standard()).unwrap();
+
+        let mut res = MsgSvSnapshotDelta {
+            monotonic_tick: snapshot.monotonic_tick,
+            baseline_tick: None,
+            data: encode_delta(&[], &data),
+        };
+        for baseline_tick in self.acked.iter().rev().take(SNAPSHOT_BASELINE_CANDIDATES) {
+            let delta = encode_delta(&self.sent[baseline_tick], &data);
+            if delta.len() < res.data.len() {
+                res.baseline_tick = Some(*baseline_tick);
+                res.data = delta;
+            }
+        }
+
+        self.sent.insert(snapshot.monotonic_tick, data);
+        while self.sent.len() > SNAPSHOT_MAX_BASELINES {
+            self.sent.pop_first();
+        }
+        let sent = &self.sent;
+        self.acked.retain(|tick| sent.contains_key(tick));
+        res
+    }
+}
+
+/**
+ * The snapshots the client received, the server encodes new snapshots against them
+ */
+#[derive(Default)]
+pub struct SnapshotDeltaReceiver {
+    // monotonic tick -> encoded snapshot
+    received: BTreeMap<u64, Vec<u8>>,
+}
+
+impl SnapshotDeltaReceiver {
+    /**
+     * Returns the snapshot, the client should acknowledge its tick afterwards
+     */
+    pub fn apply(&mut self, delta: &MsgSvSnapshotDelta) -> anyhow::Result<Snapshot> {
+        let baseline: &[u8] = match delta.baseline_tick {
+            Some(tick) => self
+                .received
+                .get(&tick)
+                .ok_or_else(|| anyhow!("the baseline of the snapshot delta is unknown"))?,
+            None => &[],
+        };
+        let data = decode_delta(baseline, &delta.data)?;
+        let (snapshot, _) =
+            bincode::decode_from_slice::<Snapshot, _>(&data, bincode::config::standard())?;
+
+        self.received.insert(delta.monotonic_tick, data);
+        while self.received.len() > SNAPSHOT_MAX_BASELINES {
+            self.received.pop_first();
+        }
+        Ok(snapshot)
+    }
+
+    /**
+     * E.g. after a map change, the server starts without baselines too
+     */
+    pub fn clear(&mut self) {
+        self.received.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_delta, encode_delta};
+
+    #[test]
+    fn delta_roundtrip() {
+        let baseline: Vec<u8> = (0..200).map(|i| (i % 7) as u8).collect();
+        let mut data = baseline.clone();
+        data[3] = 100;
+        data[150..160].iter_mut().for_each(|byte| *byte = 0xFF);
+        data.extend([1, 2, 3]);
+
+        let delta = encode_delta(&baseline, &data);
+        assert!(delta.len() < data.len());
+        assert_eq!(decode_delta(&baseline, &delta).unwrap(), data);
+
+        // shorter than the baseline and without baseline
+        assert_eq!(
+            decode_delta(&baseline, &encode_delta(&baseline, &data[..50])).unwrap(),
+            &data[..50]
+        );
+        assert_eq!(decode_delta(&[], &encode_delta(&[], &data)).unwrap(), data);
+    }
+}
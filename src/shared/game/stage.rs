@@ -10,6 +10,8 @@ pub struct GameStage {
     stage_index: u32,
 
     pub game_element_id: TGameElementID,
+    // other players can't join
+    pub locked: bool,
 }
 
 impl GameStage {
@@ -19,6 +21,7 @@ impl GameStage {
             stage_index: stage_index,
 
             game_element_id,
+            locked: false,
         }
     }
 
@@ -5,8 +5,10 @@ use base::system::SystemInterface;
 use crate::types::GameTickType;
 
 use super::{
+    match_stats::{MatchStats, MatchStatsReport},
     simulation_pipe::{SimulationPipe, SimulationPipeStage},
     stage::GameStage,
+    state_checksum::{state_checksum, GameStateChecksum, StateChecksumStage},
     GameElementGenerator, TGameElementID,
 };
 
@@ -16,6 +18,38 @@ pub trait GameStateInterface {
     fn prev_game_tick(&self) -> GameTickType;
     fn game_start_tick(&self) -> GameTickType;
     fn intra_tick(&self, system: &dyn SystemInterface) -> f64;
+    /**
+     * If spectators can chase the character of this player
+     */
+    fn is_valid_spectator_target(&self, player_id: &TGameElementID) -> bool;
+
+    /**
+     * Freezes the simulation, e.g. for local practice or the editor's test play
+     */
+    fn pause(&mut self);
+    fn resume(&mut self);
+    fn is_paused(&self) -> bool;
+    /**
+     * Simulates the next `n_ticks` ticks while paused, one per tick call
+     */
+    fn step(&mut self, n_ticks: GameTickType);
+
+    /**
+     * Damage, kills & finishes are recorded here by the game logic (or mods)
+     */
+    fn match_stats_mut(&mut self) -> &mut MatchStats;
+    fn match_stats_report(&self) -> MatchStatsReport;
+    /**
+     * Returns the report of the match and starts collecting for the next one
+     */
+    fn end_match(&mut self) -> MatchStatsReport;
+
+    /**
+     * Checksum over the canonical world state of the current tick
+     * (stages and characters ordered by their ids), the same state
+     * always gives the same checksum on every platform
+     */
+    fn state_checksum(&self) -> GameStateChecksum;
 }
 
 /**
@@ -34,6 +68,12 @@ pub struct GameState {
 
     start_tick: GameTickType,
     start_tick_time: Duration,
+
+    paused: bool,
+    // ticks that are still simulated while paused
+    pending_steps: GameTickType,
+
+    match_stats: MatchStats,
 }
 
 impl GameState {
@@ -45,6 +85,11 @@ impl GameState {
             start_tick: 1,
             start_tick_time: Duration::from_nanos(0),
             stages: Vec::new(),
+
+            paused: false,
+            pending_steps: 0,
+
+            match_stats: MatchStats::default(),
         }
     }
 
@@ -83,9 +128,14 @@ impl GameState {
         self.stages.len()
     }
 
-    fn tick_impl(&mut self, is_prediction: bool, pipe: &mut SimulationPipe) {
+    fn tick_impl(
+        &mut self,
+        prev_core_index: usize,
+        is_prediction: bool,
+        pipe: &mut SimulationPipe,
+    ) {
         let mut sim_pipe = SimulationPipeStage::new(
-            0,
+            prev_core_index,
             if is_prediction { 1 } else { 0 },
             pipe.player_inputs,
             is_prediction,
@@ -97,17 +147,37 @@ impl GameState {
 
         if !is_prediction {
             self.cur_tick += 1;
+
+            for stage in &self.stages {
+                for char in stage.get_world().get_characters() {
+                    self.match_stats.add_tick_alive(&char.cores[0].player_id);
+                }
+            }
         }
     }
 
     pub fn tick(&mut self, pipe: &mut SimulationPipe) {
-        self.tick_impl(false, pipe);
+        if !self.paused || self.pending_steps > 0 {
+            self.pending_steps = self.pending_steps.saturating_sub(1);
+            self.tick_impl(0, false, pipe);
+        }
 
+        // the monotonic tick also increases while paused
         self.cur_monotonic_tick += 1;
     }
 
-    pub fn pred_tick(&mut self, pipe: &mut SimulationPipe) {
-        self.tick_impl(true, pipe);
+    /**
+     * Simulates the second core of the characters `ticks` ticks ahead of the first one
+     */
+    pub fn pred_tick(&mut self, ticks: u64, pipe: &mut SimulationPipe) {
+        if self.paused {
+            return;
+        }
+        for tick in 0..ticks {
+            // the first tick starts from the confirmed state, the others continue the prediction
+            let prev_core_index = if tick == 0 { 0 } else { 1 };
+            self.tick_impl(prev_core_index, true, pipe);
+        }
     }
 }
 
@@ -133,8 +203,77 @@ impl GameStateInterface for GameState {
         // the total passed time since the start - the time passed by amount of ticks gives the current time in the tick
         // now use this time and devide it be the amount of time that is passed per tick
         let time_per_tick = Duration::from_secs(1).as_nanos() as u64 / self.game_tick_speed();
-        ((system.time_get_nanoseconds().as_nanos() - self.start_tick_time.as_nanos()) as f64
+        // the time keeps running while the game tick is paused
+        (((system.time_get_nanoseconds().as_nanos() - self.start_tick_time.as_nanos()) as f64
             - ((self.game_tick() - self.game_start_tick()) * time_per_tick) as f64)
-            / time_per_tick as f64
+            / time_per_tick as f64)
+            .clamp(0.0, 1.0)
+    }
+
+    fn is_valid_spectator_target(&self, player_id: &TGameElementID) -> bool {
+        self.stages.iter().any(|stage| {
+            stage
+                .get_world()
+                .get_characters()
+                .iter()
+                .any(|char| char.cores[0].player_id == *player_id)
+        })
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+        self.pending_steps = 0;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.pending_steps = 0;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn step(&mut self, n_ticks: GameTickType) {
+        if self.paused {
+            self.pending_steps = self.pending_steps.saturating_add(n_ticks);
+        }
+    }
+
+    fn match_stats_mut(&mut self) -> &mut MatchStats {
+        &mut self.match_stats
+    }
+
+    fn match_stats_report(&self) -> MatchStatsReport {
+        self.match_stats.report(self.cur_tick)
+    }
+
+    fn end_match(&mut self) -> MatchStatsReport {
+        let report = self.match_stats_report();
+        self.match_stats.reset(self.cur_tick);
+        report
+    }
+
+    fn state_checksum(&self) -> GameStateChecksum {
+        state_checksum(
+            self.stages
+                .iter()
+                .map(|stage| StateChecksumStage {
+                    id: stage.game_element_id,
+                    characters: stage
+                        .get_world()
+                        .get_characters()
+                        .iter()
+                        .map(|char| (char.base.game_element_id, &char.cores[0]))
+                        .collect(),
+                    map_entities: stage
+                        .get_world()
+                        .get_map_entities()
+                        .iter()
+                        .map(|ent| (ent.base.game_element_id, &ent.core))
+                        .collect(),
+                })
+                .collect(),
+        )
     }
 }
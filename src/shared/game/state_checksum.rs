@@ -0,0 +1,82 @@
+use sha2::{Digest, Sha256};
+
+use super::{
+    collision::Collision,
+    entities::{character::CharacterCore, map_entity::MapEntityCore},
+    simulation_pipe::{LocalPlayerInput, SimulationPipe, SimulationPlayerInput},
+    state::{GameState, GameStateInterface},
+    TGameElementID,
+};
+
+pub type GameStateChecksum = u64;
+
+// the server sends the checksum of every this many monotonic ticks (once per second),
+// for the snapshot of the tick before
+pub const STATE_CHECKSUM_INTERVAL_TICKS: u64 = 50;
+
+/**
+ * The parts of a stage that go into the checksum, in any order
+ */
+pub struct StateChecksumStage<'a> {
+    pub id: TGameElementID,
+    pub characters: Vec<(TGameElementID, &'a CharacterCore)>,
+    pub map_entities: Vec<(TGameElementID, &'a MapEntityCore)>,
+}
+
+/**
+ * Checksum over the canonical world state (stages, characters and map entities
+ * ordered by their ids), used by the game state and by the snapshots,
+ * so both give the same checksum for the same state on every platform
+ */
+pub fn state_checksum(mut stages: Vec<StateChecksumStage>) -> GameStateChecksum {
+    let mut hasher = Sha256::new();
+    stages.sort_by_key(|stage| stage.id);
+    for mut stage in stages {
+        hasher.update(stage.id.0.to_le_bytes());
+        stage.characters.sort_by_key(|(id, _)| *id);
+        for (id, core) in stage.characters {
+            hasher.update(id.0.to_le_bytes());
+            hasher.update(bincode::encode_to_vec(core, bincode::config::standard()).unwrap());
+        }
+        stage.map_entities.sort_by_key(|(id, _)| *id);
+        for (id, core) in stage.map_entities {
+            hasher.update(id.0.to_le_bytes());
+            hasher.update(bincode::encode_to_vec(core, bincode::config::standard()).unwrap());
+        }
+    }
+    let hash: [u8; 32] = hasher.finalize().into();
+    GameStateChecksum::from_le_bytes(hash[0..8].try_into().unwrap())
+}
+
+/**
+ * The inputs that the server applied in a tick, by player
+ */
+pub struct StateChecksumInputs<'a>(pub &'a [(TGameElementID, LocalPlayerInput)]);
+
+impl<'a> SimulationPlayerInput for StateChecksumInputs<'a> {
+    fn get_input(&self, player_id: TGameElementID) -> Option<&LocalPlayerInput> {
+        self.0
+            .iter()
+            .find(|(id, _)| *id == player_id)
+            .map(|(_, input)| input)
+    }
+}
+
+/**
+ * Simulates one tick from the confirmed state of a snapshot with the inputs
+ * the server applied in that tick and returns the checksum of the result.
+ * The server and the client both do this for the same snapshot,
+ * so the own unconfirmed inputs and the guessed inputs of other players
+ * of the client's prediction don't cause false desyncs.
+ */
+pub fn resimulated_state_checksum(
+    base: &mut GameState,
+    inputs: &[(TGameElementID, LocalPlayerInput)],
+    collision: &Collision,
+) -> GameStateChecksum {
+    base.tick(&mut SimulationPipe::new(
+        &StateChecksumInputs(inputs),
+        collision,
+    ));
+    base.state_checksum()
+}
@@ -1 +1 @@
-pub mod definitions;
\ No newline at end of file
+pub mod definitions;
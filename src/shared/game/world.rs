@@ -1,14 +1,32 @@
 use super::{
+    collision::{CollisionBox, WorldCollision},
     entities::{
         character::{Character, CharacterCore},
+        character_core::physical_size,
+        map_entity::{MapEntity, MapEntityCore, MapEntityKind},
         Entity, EntityInterface,
     },
     simulation_pipe::{SimulationPipeEntities, SimulationPipeStage},
     GameElementGenerator, TGameElementID,
 };
 
+/**
+ * A pickup that a character touched, it's removed from the world
+ * and the mod decides what it does
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollectedPickup {
+    pub entity_id: TGameElementID,
+    pub player_id: TGameElementID,
+    pub pickup_type: u32,
+}
+
 pub struct GameWorld {
     characters: Vec<Character>,
+    // spawned by mods at runtime
+    map_entities: Vec<MapEntity>,
+    // collected since the last `take_collected_pickups`
+    collected_pickups: Vec<CollectedPickup>,
 }
 
 impl GameWorld {
@@ -41,7 +59,86 @@ impl GameWorld {
         self.characters.last_mut().unwrap()
     }
 
+    pub fn get_map_entities(&self) -> &Vec<MapEntity> {
+        &self.map_entities
+    }
+
+    pub fn get_map_entities_mut(&mut self) -> &mut Vec<MapEntity> {
+        &mut self.map_entities
+    }
+
+    pub fn add_map_entity(
+        &mut self,
+        game_el_gen: &mut GameElementGenerator,
+        core: MapEntityCore,
+    ) -> TGameElementID {
+        let id = game_el_gen.get_map_entity_id();
+        self.map_entities.push(MapEntity::new(&id, core));
+        id
+    }
+
+    pub fn remove_map_entity(&mut self, id: &TGameElementID) -> bool {
+        let len = self.map_entities.len();
+        self.map_entities
+            .retain(|ent| ent.base.game_element_id != *id);
+        len != self.map_entities.len()
+    }
+
+    pub fn take_collected_pickups(&mut self) -> Vec<CollectedPickup> {
+        std::mem::take(&mut self.collected_pickups)
+    }
+
+    /**
+     * Removes the pickups that overlap a character
+     */
+    fn collect_pickups(&mut self, core_index: usize) {
+        let characters = &self.characters;
+        let collected_pickups = &mut self.collected_pickups;
+        self.map_entities.retain(|ent| {
+            let MapEntityKind::Pickup { pickup_type } = ent.core.kind else {
+                return true;
+            };
+            let bounds = ent.core.bounds();
+            let collector = characters.iter().find(|char| {
+                let core = &char.cores[core_index];
+                bounds.intersects(&CollisionBox {
+                    x: core.core.pos.x,
+                    y: core.core.pos.y,
+                    width: physical_size(),
+                    height: physical_size(),
+                })
+            });
+            match collector {
+                Some(char) => {
+                    collected_pickups.push(CollectedPickup {
+                        entity_id: ent.base.game_element_id,
+                        player_id: char.cores[core_index].player_id,
+                        pickup_type,
+                    });
+                    false
+                }
+                None => true,
+            }
+        });
+    }
+
     pub fn tick(&mut self, pipe: &mut SimulationPipeStage) {
+        if !pipe.is_prediction {
+            self.map_entities.retain_mut(|ent| ent.tick());
+        }
+
+        // closed doors and platforms are solid for the characters
+        let solid_boxes: Vec<CollisionBox> = self
+            .map_entities
+            .iter()
+            .filter(|ent| ent.core.is_solid())
+            .map(|ent| ent.core.bounds())
+            .collect();
+        let collision = WorldCollision {
+            map: pipe.collision,
+            boxes: &solid_boxes,
+        };
+
         // todo move vector somewhere to decrease heap allocations
         let mut character_parts = Vec::<(&Entity, &mut CharacterCore)>::new();
 
@@ -62,7 +159,7 @@ impl GameWorld {
                     prev_core_index: pipe.prev_core_index,
                     other_chars_after: &mut [],
                     other_chars_before: &mut [],
-                    collision: pipe.collision,
+                    collision: &collision,
                 },
             );
         });
@@ -76,15 +173,104 @@ impl GameWorld {
                     prev_core_index: pipe.prev_core_index,
                     other_chars_after: &mut [],
                     other_chars_before: &mut [],
-                    collision: pipe.collision,
+                    collision: &collision,
                 },
             );
         });
+
+        // pickups are only collected by the server's simulation
+        if !pipe.is_prediction {
+            self.collect_pickups(pipe.next_core_index);
+        }
     }
 }
 
 impl Default for GameWorld {
     fn default() -> GameWorld {
-        GameWorld { characters: vec![] }
+        GameWorld {
+            characters: vec![],
+            map_entities: vec![],
+            collected_pickups: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use math::math::vector::vec2;
+
+    use crate::{
+        game::{
+            collision::Collision,
+            entities::map_entity::{MapEntityCore, MapEntityKind},
+            simulation_pipe::{LocalPlayerInput, SimulationPipe},
+            state::GameState,
+            state_checksum::StateChecksumInputs,
+            GameElementGenerator,
+        },
+        mapdef::{CTile, TileNum},
+    };
+
+    #[test]
+    fn map_entities_collide() {
+        // an empty map, only the entities are solid
+        let (width, height) = (20, 20);
+        let tiles = vec![
+            CTile {
+                index: TileNum::TILE_AIR as u8,
+                ..Default::default()
+            };
+            (width * height) as usize
+        ];
+        let collision = Collision::new(width, height, &tiles);
+
+        let mut id_gen = GameElementGenerator::default();
+        let mut game = GameState::new();
+        let stage_index = game.add_stage(&mut id_gen);
+        let player_id = id_gen.get_character_id();
+        let world = game.get_stage_mut(stage_index).get_world_mut();
+        world.add_character(&mut id_gen, &player_id).cores[0]
+            .core
+            .pos = vec2::new(5.0 * 32.0, 5.0 * 32.0);
+        // a thin closed door as floor below the character
+        world.add_map_entity(
+            &mut id_gen,
+            MapEntityCore::new(
+                MapEntityKind::Door {
+                    width: 128.0,
+                    height: 4.0,
+                    closed: true,
+                },
+                5.0 * 32.0,
+                8.0 * 32.0,
+                0,
+            ),
+        );
+        // a pickup on the way to the floor
+        world.add_map_entity(
+            &mut id_gen,
+            MapEntityCore::new(
+                MapEntityKind::Pickup { pickup_type: 3 },
+                5.0 * 32.0,
+                7.0 * 32.0,
+                0,
+            ),
+        );
+
+        let inputs = [(player_id, LocalPlayerInput::default())];
+        let inputs = StateChecksumInputs(&inputs);
+        for _ in 0..100 {
+            game.tick(&mut SimulationPipe::new(&inputs, &collision));
+        }
+
+        let world = game.get_stage_mut(stage_index).get_world_mut();
+        let pos = world.get_characters()[0].cores[0].core.pos;
+        assert!(pos.y < 8.0 * 32.0, "the character fell through the door");
+        assert!(pos.y > 7.0 * 32.0);
+        let pickups = world.take_collected_pickups();
+        assert_eq!(pickups.len(), 1);
+        assert_eq!(pickups[0].player_id, player_id);
+        assert_eq!(pickups[0].pickup_type, 3);
+        assert_eq!(world.get_map_entities().len(), 1);
     }
 }
@@ -80,13 +80,13 @@ impl<K: Eq + Hash + Clone, V> HashQueue<K, V> {
 
     pub fn for_each_in_queue_order<T>(&self, mut predicate: T)
     where
-        T: FnMut(&V),
+        T: FnMut(&K, &V),
     {
         let mut begin = self.queue.front().clone();
         while begin.is_some() {
-            let item_res = self.map.get(&begin.as_ref().unwrap().borrow().data);
-            if let Some(item) = item_res {
-                predicate(&item.1);
+            let key = begin.as_ref().unwrap().borrow().data.clone();
+            if let Some(item) = self.map.get(&key) {
+                predicate(&key, &item.1);
             }
             let next = begin.as_ref().unwrap().borrow().next.clone();
             begin = next;
@@ -1,4 +1,6 @@
-#[derive(PartialEq, Eq, Copy, Clone, Hash, bincode::Encode, bincode::Decode)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash, bincode::Encode, bincode::Decode,
+)]
 pub struct IDGeneratorIDType(pub u64); // TODO! change visibility to private again
 
 pub const ID_GENERATOR_ID_INVALID: IDGeneratorIDType = IDGeneratorIDType(0);
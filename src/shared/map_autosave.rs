@@ -0,0 +1,320 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use arrayvec::ArrayString;
+use base::{
+    filesys::{file_name_from_name, FileSystem},
+    io_batcher::{IOBatcher, IOBatcherTask},
+    log_println,
+};
+use bincode::{Decode, Encode};
+
+use crate::{datafile::CDatafileWrapper, map_journal::MapJournal};
+
+const AUTOSAVE_DIR: &str = "cache/editor_autosave";
+
+/**
+ * The unsaved changes of a map: the map file the journal was started with
+ * and the journal itself. Replaying the journal on the loaded base map
+ * gives the state of the map at the time of the autosave.
+ */
+#[derive(Encode, Decode)]
+pub struct MapAutosave {
+    pub map_name: String,
+    pub base_map: Vec<u8>,
+    pub journal: MapJournal,
+}
+
+fn autosave_path(map_name: &str) -> String {
+    format!(
+        "{}/{}.autosave",
+        AUTOSAVE_DIR,
+        file_name_from_name(map_name)
+    )
+}
+
+impl MapAutosave {
+    /**
+     * All autosaves that were not discarded, e.g. because the editor crashed.
+     * Broken files are ignored.
+     */
+    pub async fn load_all(fs: &FileSystem) -> Vec<MapAutosave> {
+        let mut res: Vec<MapAutosave> = Vec::new();
+        fs.files_of_write_dir_recursive(AUTOSAVE_DIR, &mut |name, file| {
+            if !name.ends_with(".autosave") {
+                return;
+            }
+            if let Ok((autosave, _)) =
+                bincode::decode_from_slice::<MapAutosave, _>(&file, bincode::config::standard())
+            {
+                res.push(autosave);
+            }
+        })
+        .await;
+        res.sort_by(|a, b| a.map_name.cmp(&b.map_name));
+        res
+    }
+
+    /**
+     * Applies the unsaved changes to the map, which must be loaded from `base_map`.
+     * Returns the journal, so the recovered changes can still be undone.
+     */
+    pub fn recover(
+        self,
+        map: &mut CDatafileWrapper,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<MapJournal> {
+        self.journal.replay(map, thread_pool)?;
+        Ok(self.journal)
+    }
+
+    /**
+     * Removes the autosave of the map, after it was saved regularly
+     * or the user did not want to recover it
+     */
+    pub async fn discard(fs: &FileSystem, map_name: &str) {
+        let _ = fs.remove_file(&autosave_path(map_name)).await;
+    }
+
+    async fn save(&self, fs: &FileSystem) -> anyhow::Result<()> {
+        let path = autosave_path(&self.map_name);
+        // write to a temporary file first, a crash while writing
+        // must not destroy the last autosave
+        let tmp_path = format!("{}.tmp", path);
+        fs.write_file(
+            &tmp_path,
+            bincode::encode_to_vec(self, bincode::config::standard())?,
+        )
+        .await?;
+        fs.rename_file(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
+
+/**
+ * Periodically persists the journal of the map that is currently edited.
+ * The autosaves are written on the io batcher, at most one at a time.
+ */
+pub struct MapAutosaver {
+    map_name: String,
+    base_map: Vec<u8>,
+    interval: Duration,
+    last_save: Duration,
+    // the journal of the last autosave, to skip saves without changes
+    last_saved_journal: MapJournal,
+
+    fs: Arc<FileSystem>,
+    io_batcher: Arc<Mutex<IOBatcher>>,
+    write_task: Option<IOBatcherTask<()>>,
+    // a write that finishes after a newer write or discard never brings back an old autosave
+    write_generation: u64,
+    written_generation: Arc<tokio::sync::Mutex<u64>>,
+}
+
+impl MapAutosaver {
+    /**
+     * `base_map` is the map file as it was loaded or last saved
+     */
+    pub fn new(
+        map_name: &str,
+        base_map: Vec<u8>,
+        interval: Duration,
+        cur_time: Duration,
+        fs: &Arc<FileSystem>,
+        io_batcher: &Arc<Mutex<IOBatcher>>,
+    ) -> Self {
+        Self {
+            map_name: map_name.to_string(),
+            base_map,
+            interval,
+            last_save: cur_time,
+            last_saved_journal: MapJournal::default(),
+
+            fs: fs.clone(),
+            io_batcher: io_batcher.clone(),
+            write_task: None,
+            write_generation: 0,
+            written_generation: Default::default(),
+        }
+    }
+
+    /**
+     * Runs `autosave` (`None` discards the autosave) after all writes that were started before
+     */
+    fn spawn_write(&mut self, autosave: Option<MapAutosave>) -> IOBatcherTask<()> {
+        self.write_generation += 1;
+        let generation = self.write_generation;
+        let written_generation = self.written_generation.clone();
+        let fs = self.fs.clone();
+        let map_name = self.map_name.clone();
+        self.io_batcher.lock().unwrap().spawn(async move {
+            let mut written_generation = written_generation.lock().await;
+            if *written_generation < generation {
+                match autosave {
+                    Some(autosave) => autosave.save(&fs).await.map_err(|err| {
+                        ArrayString::from(&format!(
+                            "could not write the autosave of {}: {}",
+                            map_name, err
+                        ))
+                        .unwrap_or_default()
+                    })?,
+                    None => MapAutosave::discard(&fs, &map_name).await,
+                }
+                *written_generation = generation;
+            }
+            Ok(())
+        })
+    }
+
+    /**
+     * Call this regularly, saves the journal if the interval passed and there are changes.
+     * Returns true if an autosave was started.
+     */
+    pub fn update(&mut self, cur_time: Duration, journal: &MapJournal) -> bool {
+        if let Some(task) = &mut self.write_task {
+            if !task.is_finished() {
+                return false;
+            }
+            if let Err(err) = task.get_storage() {
+                log_println!("map autosave: {}", err);
+                // try again in the next interval
+                self.last_saved_journal = MapJournal::default();
+            }
+            self.write_task = None;
+        }
+        if cur_time < self.last_save + self.interval || *journal == self.last_saved_journal {
+            return false;
+        }
+        self.last_save = cur_time;
+        self.last_saved_journal = journal.clone();
+        let autosave = MapAutosave {
+            map_name: self.map_name.clone(),
+            base_map: self.base_map.clone(),
+            journal: journal.clone(),
+        };
+        self.write_task = Some(self.spawn_write(Some(autosave)));
+        true
+    }
+
+    /**
+     * After the map was saved regularly the saved file is the new base,
+     * the journal must be started from scratch too
+     */
+    pub fn map_saved(&mut self, new_base_map: Vec<u8>) {
+        self.base_map = new_base_map;
+        self.last_saved_journal = MapJournal::default();
+        self.write_task = Some(self.spawn_write(None));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use base::{filesys::FileSystem, io_batcher::IOBatcher, system::SystemTime};
+
+    use crate::{
+        datafile::CDatafileWrapper,
+        map_journal::{MapAction, MapJournal},
+        mapdef::MapLayer,
+    };
+
+    use super::{MapAutosave, MapAutosaver};
+
+    fn thread_pool() -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap()
+    }
+
+    fn read_map(file: &Vec<u8>, thread_pool: &rayon::ThreadPool) -> CDatafileWrapper {
+        let mut map = CDatafileWrapper::new();
+        map.Open(
+            file,
+            "test",
+            thread_pool,
+            &Default::default(),
+            &Arc::new(SystemTime::new()),
+        )
+        .unwrap();
+        map
+    }
+
+    fn layer_images(map: &CDatafileWrapper) -> Vec<i32> {
+        map.layers
+            .iter()
+            .map(|layer| match layer {
+                MapLayer::Tile(layer) => layer.0.image,
+                MapLayer::Quads(layer) => layer.0.image,
+                _ => -1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn autosave_and_recover() {
+        let thread_pool = thread_pool();
+        let write_dir = std::env::temp_dir().join(format!("map_autosave_{}", std::process::id()));
+        let fs = Arc::new(FileSystem::with_write_dir(write_dir.to_str().unwrap()));
+        let io_batcher = Arc::new(Mutex::new(IOBatcher::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap(),
+        )));
+        let load_all = || {
+            let fs = fs.clone();
+            let mut task = io_batcher
+                .lock()
+                .unwrap()
+                .spawn(async move { Ok(MapAutosave::load_all(&fs).await) });
+            io_batcher.lock().unwrap().wait_finished(&mut task);
+            task.get_storage().unwrap()
+        };
+
+        let base_map =
+            std::fs::read(format!("{}/data/cb2.map", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let mut map = read_map(&base_map, &thread_pool);
+        let interval = Duration::from_secs(60);
+        let mut autosaver = MapAutosaver::new(
+            "cb2",
+            base_map.clone(),
+            interval,
+            Duration::ZERO,
+            &fs,
+            &io_batcher,
+        );
+
+        let mut journal = MapJournal::default();
+        // nothing changed yet
+        assert!(!autosaver.update(interval, &journal));
+        let step = vec![MapAction::set_layer_image(&map, 0, 1).unwrap()];
+        journal.push(&mut map, step, &thread_pool).unwrap();
+        // the interval did not pass yet
+        assert!(!autosaver.update(interval / 2, &journal));
+        assert!(autosaver.update(interval, &journal));
+        io_batcher.lock().unwrap().finish_all();
+
+        let autosaves = load_all();
+        assert_eq!(autosaves.len(), 1);
+        let autosave = autosaves.into_iter().next().unwrap();
+        assert_eq!(autosave.map_name, "cb2");
+        let mut recovered = read_map(&autosave.base_map, &thread_pool);
+        let recovered_journal = autosave.recover(&mut recovered, &thread_pool).unwrap();
+        assert!(recovered_journal == journal);
+        assert_eq!(layer_images(&recovered), layer_images(&map));
+
+        // a regular save makes the autosave obsolete
+        autosaver.map_saved(base_map);
+        io_batcher.lock().unwrap().finish_all();
+        assert!(load_all().is_empty());
+
+        let _ = std::fs::remove_dir_all(write_dir);
+    }
+}
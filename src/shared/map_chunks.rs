@@ -0,0 +1,201 @@
+use crate::mapdef::{read_u32_le, write_u32_le};
+
+const MAP_CHUNKS_MAGIC: [u8; 8] = *b"DDPGMAP\0";
+pub const MAP_CHUNKS_VERSION: u32 = 1;
+
+pub type MapChunkId = [u8; 4];
+
+// the legacy datafile (version 4) of the map
+pub const MAP_CHUNK_LEGACY_DATAFILE: MapChunkId = *b"DF04";
+
+// a reader that does not know this chunk must not load the map,
+// because the map can't be used correctly without it.
+// chunks without this flag are optional (e.g. metadata or editor only data)
+pub const MAP_CHUNK_FLAG_REQUIRED: u32 = 1 << 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapChunk {
+    pub id: MapChunkId,
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+/**
+ * Extensible container for map files.
+ * Every section of the map is a chunk with an id, readers only interpret the chunks they know,
+ * but keep all others, so saving the map again does not strip data
+ * that was added by newer versions.
+ *
+ * Layout (little endian):
+ * - magic (8 bytes), version (u32), chunk count (u32)
+ * - per chunk: id (4 bytes), flags (u32), size (u32), data
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapChunkContainer {
+    pub version: u32,
+    pub chunks: Vec<MapChunk>,
+}
+
+impl Default for MapChunkContainer {
+    fn default() -> Self {
+        Self {
+            version: MAP_CHUNKS_VERSION,
+            chunks: Vec::new(),
+        }
+    }
+}
+
+impl MapChunkContainer {
+    pub fn is_chunked_map(file: &[u8]) -> bool {
+        file.starts_with(&MAP_CHUNKS_MAGIC)
+    }
+
+    /**
+     * Wraps a legacy map into a container
+     */
+    pub fn from_legacy_map(file: &[u8]) -> Self {
+        let mut res = Self::default();
+        res.set(
+            MAP_CHUNK_LEGACY_DATAFILE,
+            MAP_CHUNK_FLAG_REQUIRED,
+            file.to_vec(),
+        );
+        res
+    }
+
+    /**
+     * Newer container versions only add information,
+     * so reading them is fine as long as all required chunks are known (see `check_required`).
+     */
+    pub fn read(file: &[u8]) -> anyhow::Result<Self> {
+        if !Self::is_chunked_map(file) {
+            return Err(anyhow::Error::msg("not a chunked map file"));
+        }
+        let mut offset = MAP_CHUNKS_MAGIC.len();
+        let read_u32 = |offset: &mut usize| -> anyhow::Result<u32> {
+            if *offset + 4 > file.len() {
+                return Err(anyhow::Error::msg("the map file is truncated"));
+            }
+            let res = read_u32_le(&file[*offset..]);
+            *offset += 4;
+            Ok(res)
+        };
+        let version = read_u32(&mut offset)?;
+        let chunk_count = read_u32(&mut offset)?;
+        let mut chunks: Vec<MapChunk> = Vec::new();
+        for _ in 0..chunk_count {
+            if offset + 4 > file.len() {
+                return Err(anyhow::Error::msg("the map file is truncated"));
+            }
+            let id: MapChunkId = file[offset..offset + 4].try_into()?;
+            offset += 4;
+            let flags = read_u32(&mut offset)?;
+            let size = read_u32(&mut offset)? as usize;
+            if offset + size > file.len() {
+                return Err(anyhow::Error::msg("the map file is truncated"));
+            }
+            chunks.push(MapChunk {
+                id,
+                flags,
+                data: file[offset..offset + size].to_vec(),
+            });
+            offset += size;
+        }
+        Ok(Self { version, chunks })
+    }
+
+    /**
+     * Writes all chunks in their current order, including the ones this version does not know.
+     * The version is never lowered, so newer readers still see the version they wrote.
+     */
+    pub fn write(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = Vec::with_capacity(
+            MAP_CHUNKS_MAGIC.len()
+                + 8
+                + self
+                    .chunks
+                    .iter()
+                    .map(|chunk| 12 + chunk.data.len())
+                    .sum::<usize>(),
+        );
+        res.extend_from_slice(&MAP_CHUNKS_MAGIC);
+        write_u32_le(&mut res, self.version.max(MAP_CHUNKS_VERSION));
+        write_u32_le(&mut res, self.chunks.len() as u32);
+        self.chunks.iter().for_each(|chunk| {
+            res.extend_from_slice(&chunk.id);
+            write_u32_le(&mut res, chunk.flags);
+            write_u32_le(&mut res, chunk.data.len() as u32);
+            res.extend_from_slice(&chunk.data);
+        });
+        res
+    }
+
+    pub fn get(&self, id: &MapChunkId) -> Option<&MapChunk> {
+        self.chunks.iter().find(|chunk| chunk.id == *id)
+    }
+
+    /**
+     * Replaces the chunk with the same id, or appends it.
+     * Replacing keeps the position, so unknown chunks stay where they were.
+     */
+    pub fn set(&mut self, id: MapChunkId, flags: u32, data: Vec<u8>) {
+        match self.chunks.iter_mut().find(|chunk| chunk.id == id) {
+            Some(chunk) => {
+                chunk.flags = flags;
+                chunk.data = data;
+            }
+            None => self.chunks.push(MapChunk { id, flags, data }),
+        }
+    }
+
+    pub fn remove(&mut self, id: &MapChunkId) -> Option<MapChunk> {
+        let index = self.chunks.iter().position(|chunk| chunk.id == *id)?;
+        Some(self.chunks.remove(index))
+    }
+
+    pub fn unknown_chunks<'a>(
+        &'a self,
+        known: &'a [MapChunkId],
+    ) -> impl Iterator<Item = &'a MapChunk> + 'a {
+        self.chunks
+            .iter()
+            .filter(move |chunk| !known.contains(&chunk.id))
+    }
+
+    /**
+     * Fails if the container has required chunks that are not in `known`
+     */
+    pub fn check_required(&self, known: &[MapChunkId]) -> anyhow::Result<()> {
+        match self
+            .unknown_chunks(known)
+            .find(|chunk| (chunk.flags & MAP_CHUNK_FLAG_REQUIRED) != 0)
+        {
+            Some(chunk) => Err(anyhow::Error::msg(format!(
+                "the map requires the unknown chunk \"{}\", it was created by a newer version",
+                String::from_utf8_lossy(&chunk.id)
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn legacy_map(&self) -> Option<&[u8]> {
+        self.get(&MAP_CHUNK_LEGACY_DATAFILE)
+            .map(|chunk| chunk.data.as_slice())
+    }
+}
+
+/**
+ * Returns the legacy datafile of a map file, no matter if the file is a chunked map
+ * or a plain legacy map.
+ */
+pub fn legacy_map_data(file: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if !MapChunkContainer::is_chunked_map(&file) {
+        return Ok(file);
+    }
+    let container = MapChunkContainer::read(&file)?;
+    container.check_required(&[MAP_CHUNK_LEGACY_DATAFILE])?;
+    container
+        .legacy_map()
+        .map(|data| data.to_vec())
+        .ok_or_else(|| anyhow::Error::msg("the map contains no datafile"))
+}
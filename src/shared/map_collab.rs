@@ -0,0 +1,432 @@
+use bincode::{Decode, Encode};
+
+use crate::{
+    datafile::CDatafileWrapper,
+    map_diff::MapPatchTile,
+    map_journal::MapAction,
+    mapdef::{MapLayer, MapLayerQuad},
+};
+
+/**
+ * Unique id of an edit operation, `seq` counts the operations of one client
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode)]
+pub struct MapEditOpId {
+    pub client: u32,
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum MapEditNewLayer {
+    Tiles { width: u32, height: u32 },
+    Quads,
+    Sounds,
+}
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum MapEditOpKind {
+    // a new layer at the index `layer` of all layers, the layer is part of `group`
+    InsertLayer {
+        group: u32,
+        layer: u32,
+        new_layer: MapEditNewLayer,
+    },
+    // consecutive tiles in a row of a tile layer
+    SetTileRun {
+        layer: u32,
+        x: u32,
+        y: u32,
+        tiles: Vec<MapPatchTile>,
+    },
+    // `point` 0 - 3 are the corners, 4 is the pivot
+    MoveQuadPoint {
+        layer: u32,
+        quad: u32,
+        point: u8,
+        x: i32,
+        y: i32,
+    },
+}
+
+/**
+ * A single edit of a map by one client of a collaborative editing session.
+ * The server orders all operations, clients apply the operations of others
+ * and transform their own pending operations against them (see `transform`).
+ * Concurrent changes of the same tiles or quad points are resolved by
+ * last writer wins, using the lamport timestamp and then the client id.
+ */
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct MapEditOp {
+    pub id: MapEditOpId,
+    // see `MapEditClock`
+    pub lamport: u64,
+    pub kind: MapEditOpKind,
+}
+
+/**
+ * Lamport clock of a client, every created operation gets the next timestamp
+ * and every received operation moves the clock forward
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MapEditClock {
+    time: u64,
+}
+
+impl MapEditClock {
+    pub fn next(&mut self) -> u64 {
+        self.time += 1;
+        self.time
+    }
+
+    pub fn observe(&mut self, lamport: u64) {
+        self.time = self.time.max(lamport);
+    }
+}
+
+impl MapEditOp {
+    pub fn layer(&self) -> u32 {
+        match &self.kind {
+            MapEditOpKind::InsertLayer { layer, .. }
+            | MapEditOpKind::SetTileRun { layer, .. }
+            | MapEditOpKind::MoveQuadPoint { layer, .. } => *layer,
+        }
+    }
+
+    fn layer_mut(&mut self) -> &mut u32 {
+        match &mut self.kind {
+            MapEditOpKind::InsertLayer { layer, .. }
+            | MapEditOpKind::SetTileRun { layer, .. }
+            | MapEditOpKind::MoveQuadPoint { layer, .. } => layer,
+        }
+    }
+
+    /**
+     * If this operation overwrites `other` when both change the same thing
+     */
+    pub fn wins_over(&self, other: &MapEditOp) -> bool {
+        (self.lamport, self.id.client) > (other.lamport, other.id.client)
+    }
+
+    /**
+     * Changes this operation, so it can be applied after `applied`,
+     * which was created concurrently, but was applied first.
+     */
+    pub fn transform(&mut self, applied: &MapEditOp) {
+        if let MapEditOpKind::InsertLayer {
+            layer: inserted, ..
+        } = &applied.kind
+        {
+            // two layers inserted at the same index are ordered by their op id
+            let is_after_insert = match self.kind {
+                MapEditOpKind::InsertLayer { .. } => applied.id < self.id,
+                _ => true,
+            };
+            let layer = self.layer_mut();
+            if *layer > *inserted || (*layer == *inserted && is_after_insert) {
+                *layer += 1;
+            }
+            return;
+        }
+
+        let self_wins = self.wins_over(applied);
+        match (&mut self.kind, &applied.kind) {
+            (
+                MapEditOpKind::SetTileRun { layer, x, y, tiles },
+                MapEditOpKind::SetTileRun {
+                    layer: applied_layer,
+                    x: applied_x,
+                    y: applied_y,
+                    tiles: applied_tiles,
+                },
+            ) if !self_wins && layer == applied_layer && y == applied_y => {
+                // the tiles of the winner stay, this op sets them to the same values
+                let start = (*x).max(*applied_x);
+                let end = (*x + tiles.len() as u32).min(*applied_x + applied_tiles.len() as u32);
+                for i in start..end {
+                    tiles[(i - *x) as usize] = applied_tiles[(i - *applied_x) as usize].clone();
+                }
+            }
+            (
+                MapEditOpKind::MoveQuadPoint {
+                    layer,
+                    quad,
+                    point,
+                    x,
+                    y,
+                },
+                MapEditOpKind::MoveQuadPoint {
+                    layer: applied_layer,
+                    quad: applied_quad,
+                    point: applied_point,
+                    x: applied_x,
+                    y: applied_y,
+                },
+            ) if !self_wins
+                && layer == applied_layer
+                && quad == applied_quad
+                && point == applied_point =>
+            {
+                *x = *applied_x;
+                *y = *applied_y;
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     * The journal action for this operation, so it can be applied and undone
+     * like a local change (see `MapJournal::push`).
+     * Inserting layers changes the structure of the map, which the map does not support yet.
+     */
+    pub fn to_action(&self, map: &CDatafileWrapper) -> anyhow::Result<MapAction> {
+        match &self.kind {
+            MapEditOpKind::InsertLayer { .. } => Err(anyhow::Error::msg(
+                "inserting layers is not supported by the map yet",
+            )),
+            MapEditOpKind::SetTileRun { layer, x, y, tiles } => MapAction::set_tiles(
+                map,
+                *layer as usize,
+                *x as usize,
+                *y as usize,
+                tiles.len().max(1),
+                tiles.clone(),
+                None,
+            ),
+            MapEditOpKind::MoveQuadPoint {
+                layer,
+                quad,
+                point,
+                x,
+                y,
+            } => {
+                let quad_index = *quad as usize;
+                let Some(MapLayer::Quads(MapLayerQuad(_, quads))) = map.layers.get(*layer as usize)
+                else {
+                    return Err(anyhow::Error::msg("the layer is not a quad layer"));
+                };
+                let Some(quad) = quads.get(quad_index) else {
+                    return Err(anyhow::Error::msg("the quad does not exist"));
+                };
+                let mut new_quad = quad.clone();
+                let Some(new_point) = new_quad.points.get_mut(*point as usize) else {
+                    return Err(anyhow::Error::msg("the quad point does not exist"));
+                };
+                new_point.x = *x;
+                new_point.y = *y;
+                MapAction::set_quad(map, *layer as usize, quad_index, &new_quad)
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard()).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let (op, _) = bincode::decode_from_slice::<Self, _>(data, bincode::config::standard())?;
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        datafile::CDatafileWrapper,
+        map_diff::MapPatchTile,
+        mapdef::{
+            CMapItemLayerQuads, CMapItemLayerTilemap, CQuad, CTile, MapLayer, MapLayerQuad,
+            MapLayerTile, MapTileLayerDetail,
+        },
+    };
+
+    use super::{MapEditClock, MapEditNewLayer, MapEditOp, MapEditOpId, MapEditOpKind};
+
+    const WIDTH: i32 = 8;
+    const HEIGHT: i32 = 4;
+
+    fn map() -> CDatafileWrapper {
+        let mut info = CMapItemLayerTilemap::default();
+        info.width = WIDTH;
+        info.height = HEIGHT;
+        let mut map = CDatafileWrapper::new();
+        map.layers = vec![
+            MapLayer::Tile(MapLayerTile(
+                info,
+                MapTileLayerDetail::Tile(),
+                vec![CTile::default(); (WIDTH * HEIGHT) as usize],
+            )),
+            MapLayer::Quads(MapLayerQuad(
+                CMapItemLayerQuads::default(),
+                vec![CQuad::default(); 2],
+            )),
+        ];
+        map
+    }
+
+    fn tiles(count: usize, index: u8) -> Vec<MapPatchTile> {
+        vec![MapPatchTile { index, flags: 0 }; count]
+    }
+
+    fn op(client: u32, clock: &mut MapEditClock, kind: MapEditOpKind) -> MapEditOp {
+        MapEditOp {
+            id: MapEditOpId { client, seq: 0 },
+            lamport: clock.next(),
+            kind,
+        }
+    }
+
+    /**
+     * The tiles and quad points of the map, to compare two maps
+     */
+    fn map_state(map: &CDatafileWrapper) -> (Vec<u8>, Vec<(i32, i32)>) {
+        let MapLayer::Tile(MapLayerTile(_, _, tiles)) = &map.layers[0] else {
+            panic!("the first layer is no tile layer");
+        };
+        let MapLayer::Quads(MapLayerQuad(_, quads)) = &map.layers[1] else {
+            panic!("the second layer is no quad layer");
+        };
+        (
+            tiles.iter().map(|tile| tile.index).collect(),
+            quads
+                .iter()
+                .flat_map(|quad| quad.points.iter().map(|point| (point.x, point.y)))
+                .collect(),
+        )
+    }
+
+    /**
+     * Applies `first` and then `second`, transformed like a client does,
+     * that receives `first` after it created `second`
+     */
+    fn merge(first: &MapEditOp, second: &MapEditOp) -> CDatafileWrapper {
+        let mut map = map();
+        first.to_action(&map).unwrap().apply(&mut map).unwrap();
+        let mut second = second.clone();
+        second.transform(first);
+        second.to_action(&map).unwrap().apply(&mut map).unwrap();
+        map
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        // both clients edit the same tiles and the same quad point without knowing of each other
+        let mut clock1 = MapEditClock::default();
+        let mut clock2 = MapEditClock::default();
+        let tiles1 = op(
+            1,
+            &mut clock1,
+            MapEditOpKind::SetTileRun {
+                layer: 0,
+                x: 1,
+                y: 2,
+                tiles: tiles(4, 1),
+            },
+        );
+        let tiles2 = op(
+            2,
+            &mut clock2,
+            MapEditOpKind::SetTileRun {
+                layer: 0,
+                x: 3,
+                y: 2,
+                tiles: tiles(4, 2),
+            },
+        );
+        let point1 = op(
+            1,
+            &mut clock1,
+            MapEditOpKind::MoveQuadPoint {
+                layer: 1,
+                quad: 1,
+                point: 4,
+                x: 10,
+                y: 20,
+            },
+        );
+        // client 2 saw the tiles of client 1 before, so its change wins
+        clock2.observe(tiles1.lamport);
+        clock2.observe(point1.lamport);
+        let point2 = op(
+            2,
+            &mut clock2,
+            MapEditOpKind::MoveQuadPoint {
+                layer: 1,
+                quad: 1,
+                point: 4,
+                x: 30,
+                y: 40,
+            },
+        );
+
+        for (op1, op2) in [(&tiles1, &tiles2), (&point1, &point2)] {
+            assert!(op2.wins_over(op1));
+            let state = map_state(&merge(op1, op2));
+            assert_eq!(state, map_state(&merge(op2, op1)));
+        }
+
+        // on equal timestamps the higher client id wins the overlapping tiles
+        let (tiles_state, _) = map_state(&merge(&tiles2, &tiles1));
+        let row = (2 * WIDTH) as usize;
+        assert_eq!(
+            tiles_state[row..row + WIDTH as usize],
+            [0, 1, 1, 2, 2, 2, 2, 0]
+        );
+        let (_, points_state) = map_state(&merge(&point2, &point1));
+        assert_eq!(points_state[5 + 4], (30, 40));
+    }
+
+    #[test]
+    fn inserted_layers_move_other_ops() {
+        let mut clock = MapEditClock::default();
+        let insert = |client: u32, clock: &mut MapEditClock| {
+            op(
+                client,
+                clock,
+                MapEditOpKind::InsertLayer {
+                    group: 0,
+                    layer: 0,
+                    new_layer: MapEditNewLayer::Quads,
+                },
+            )
+        };
+        let insert1 = insert(1, &mut clock);
+        let insert2 = insert(2, &mut clock);
+        let mut tiles = op(
+            3,
+            &mut clock,
+            MapEditOpKind::SetTileRun {
+                layer: 0,
+                x: 0,
+                y: 0,
+                tiles: tiles(1, 1),
+            },
+        );
+
+        tiles.transform(&insert1);
+        tiles.transform(&insert2);
+        assert_eq!(tiles.layer(), 2);
+
+        // two layers at the same index are ordered the same way on both clients
+        let mut transformed1 = insert1.clone();
+        transformed1.transform(&insert2);
+        let mut transformed2 = insert2.clone();
+        transformed2.transform(&insert1);
+        assert_eq!(transformed1.layer(), 0);
+        assert_eq!(transformed2.layer(), 1);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let op = op(
+            1,
+            &mut MapEditClock::default(),
+            MapEditOpKind::SetTileRun {
+                layer: 3,
+                x: 1,
+                y: 2,
+                tiles: tiles(3, 7),
+            },
+        );
+        assert_eq!(MapEditOp::from_bytes(&op.to_bytes()).unwrap(), op);
+    }
+}
@@ -0,0 +1,636 @@
+use bincode::{Decode, Encode};
+
+use crate::{
+    datafile::CDatafileWrapper,
+    mapdef::{
+        CDoorTile, CQuad, CSpeedupTile, CSwitchTile, CTeleTile, CTile, CTuneTile, MapLayer,
+        MapLayerQuad, MapLayerTile, MapTileLayerDetail, ReadFromSlice, WriteToVec,
+    },
+};
+
+/**
+ * Tiles that are this close to each other are merged into one run,
+ * since a new run costs more than a few unchanged tiles
+ */
+const TILE_RUN_MERGE_GAP: usize = 4;
+
+// patches come from the network, bigger patches are rejected before they are decoded
+const MAX_PATCH_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct MapPatchTile {
+    pub index: u8,
+    pub flags: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum MapPatchOp {
+    // consecutive tiles of a tile layer, starting at `start` (y * width + x)
+    TileRun {
+        layer: u32,
+        start: u32,
+        tiles: Vec<MapPatchTile>,
+    },
+    // consecutive tiles of the tele, speedup, switch, door or tune data of a tile layer,
+    // starting at `start`, the tiles in the datafile representation
+    DetailRun {
+        layer: u32,
+        start: u32,
+        data: Vec<u8>,
+    },
+    // a single quad changed (e.g. it was moved), the quad in the datafile representation
+    QuadChanged {
+        layer: u32,
+        quad: u32,
+        data: Vec<u8>,
+    },
+    // quads were added or removed, all quads of the layer in the datafile representation
+    QuadsReplaced {
+        layer: u32,
+        data: Vec<u8>,
+    },
+    // an external image that the new map version uses additionally,
+    // the receiver has to download it from the resource server
+    ImageAdded {
+        name: String,
+    },
+}
+
+/**
+ * Compact binary patch between two versions of a map.
+ * The patch can only be applied to a map with the same layer structure
+ * (the same layers and tile layer sizes), structural changes require
+ * a full map download.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub struct MapPatch {
+    // the layer count of the map this patch can be applied to
+    pub layer_count: u32,
+    pub ops: Vec<MapPatchOp>,
+}
+
+impl MapPatch {
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard()).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let (patch, _) = bincode::decode_from_slice::<Self, _>(
+            data,
+            bincode::config::standard().with_limit::<MAX_PATCH_SIZE>(),
+        )?;
+        Ok(patch)
+    }
+}
+
+fn serialize<T: WriteToVec>(items: &[T]) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::new();
+    items.iter().for_each(|item| item.write_to_vec(&mut res));
+    res
+}
+
+/**
+ * The size of the datafile representation of the type
+ */
+fn item_size<T: WriteToVec + Default>() -> usize {
+    let mut res: Vec<u8> = Vec::new();
+    T::default().write_to_vec(&mut res);
+    res.len()
+}
+
+fn quads_equal(q1: &CQuad, q2: &CQuad) -> bool {
+    let mut d1: Vec<u8> = Vec::new();
+    let mut d2: Vec<u8> = Vec::new();
+    q1.write_to_vec(&mut d1);
+    q2.write_to_vec(&mut d2);
+    d1 == d2
+}
+
+fn tiles_equal(t1: &CTile, t2: &CTile) -> bool {
+    t1.index == t2.index && t1.flags == t2.flags
+}
+
+/**
+ * The ranges of changed items, small gaps of unchanged items are included
+ */
+fn changed_runs(len: usize, equal: impl Fn(usize) -> bool) -> Vec<std::ops::Range<usize>> {
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < len {
+        if equal(i) {
+            i += 1;
+            continue;
+        }
+        // find the end of the run
+        let start = i;
+        let mut end = i + 1;
+        let mut unchanged = 0;
+        while end < len && unchanged <= TILE_RUN_MERGE_GAP {
+            if equal(end) {
+                unchanged += 1;
+            } else {
+                unchanged = 0;
+            }
+            end += 1;
+        }
+        let end = end - unchanged;
+        res.push(start..end);
+        i = end;
+    }
+    res
+}
+
+fn diff_tiles(layer: u32, old: &[CTile], new: &[CTile], ops: &mut Vec<MapPatchOp>) {
+    for run in changed_runs(new.len(), |i| tiles_equal(&old[i], &new[i])) {
+        ops.push(MapPatchOp::TileRun {
+            layer,
+            start: run.start as u32,
+            tiles: new[run]
+                .iter()
+                .map(|tile| MapPatchTile {
+                    index: tile.index,
+                    flags: tile.flags,
+                })
+                .collect(),
+        });
+    }
+}
+
+fn diff_detail_tiles<T: WriteToVec + Default>(
+    layer: u32,
+    old: &[T],
+    new: &[T],
+    ops: &mut Vec<MapPatchOp>,
+) -> anyhow::Result<()> {
+    if old.len() != new.len() {
+        return Err(anyhow::Error::msg(format!(
+            "the size of the detail tiles of layer {} differs, a patch is not possible",
+            layer
+        )));
+    }
+    let size = item_size::<T>();
+    let (old, new) = (serialize(old), serialize(new));
+    let tile = |data: &[u8], i: usize| data[i * size..(i + 1) * size].to_vec();
+    for run in changed_runs(new.len() / size, |i| tile(&old, i) == tile(&new, i)) {
+        ops.push(MapPatchOp::DetailRun {
+            layer,
+            start: run.start as u32,
+            data: new[run.start * size..run.end * size].to_vec(),
+        });
+    }
+    Ok(())
+}
+
+fn diff_detail(
+    layer: u32,
+    old: &MapTileLayerDetail,
+    new: &MapTileLayerDetail,
+    ops: &mut Vec<MapPatchOp>,
+) -> anyhow::Result<()> {
+    match (old, new) {
+        (MapTileLayerDetail::Tile(), MapTileLayerDetail::Tile()) => Ok(()),
+        (MapTileLayerDetail::Tele(old), MapTileLayerDetail::Tele(new)) => {
+            diff_detail_tiles(layer, old, new, ops)
+        }
+        (MapTileLayerDetail::Speedup(old), MapTileLayerDetail::Speedup(new)) => {
+            diff_detail_tiles(layer, old, new, ops)
+        }
+        (MapTileLayerDetail::Switch(old), MapTileLayerDetail::Switch(new)) => {
+            diff_detail_tiles(layer, old, new, ops)
+        }
+        (MapTileLayerDetail::Door(old), MapTileLayerDetail::Door(new)) => {
+            diff_detail_tiles(layer, old, new, ops)
+        }
+        (MapTileLayerDetail::Tune(old), MapTileLayerDetail::Tune(new)) => {
+            diff_detail_tiles(layer, old, new, ops)
+        }
+        _ => Err(anyhow::Error::msg(format!(
+            "the type of the tile layer {} differs, a patch is not possible",
+            layer
+        ))),
+    }
+}
+
+/**
+ * The tile count and the tile size in the datafile representation
+ * of the detail tiles, `None` for layers without detail tiles
+ */
+pub fn detail_layout(detail: &MapTileLayerDetail) -> Option<(usize, usize)> {
+    match detail {
+        MapTileLayerDetail::Tile() => None,
+        MapTileLayerDetail::Tele(tiles) => Some((tiles.len(), item_size::<CTeleTile>())),
+        MapTileLayerDetail::Speedup(tiles) => Some((tiles.len(), item_size::<CSpeedupTile>())),
+        MapTileLayerDetail::Switch(tiles) => Some((tiles.len(), item_size::<CSwitchTile>())),
+        MapTileLayerDetail::Door(tiles) => Some((tiles.len(), item_size::<CDoorTile>())),
+        MapTileLayerDetail::Tune(tiles) => Some((tiles.len(), item_size::<CTuneTile>())),
+    }
+}
+
+/**
+ * `count` detail tiles from `start` on in the datafile representation,
+ * empty for layers without detail tiles
+ */
+pub fn detail_tiles_data(detail: &MapTileLayerDetail, start: usize, count: usize) -> Vec<u8> {
+    let range = start..start + count;
+    match detail {
+        MapTileLayerDetail::Tile() => Vec::new(),
+        MapTileLayerDetail::Tele(tiles) => serialize(&tiles[range]),
+        MapTileLayerDetail::Speedup(tiles) => serialize(&tiles[range]),
+        MapTileLayerDetail::Switch(tiles) => serialize(&tiles[range]),
+        MapTileLayerDetail::Door(tiles) => serialize(&tiles[range]),
+        MapTileLayerDetail::Tune(tiles) => serialize(&tiles[range]),
+    }
+}
+
+fn apply_detail_tiles<T: ReadFromSlice>(tiles: &mut [T], start: usize, data: &[u8], size: usize) {
+    tiles[start..]
+        .iter_mut()
+        .zip(data.chunks_exact(size))
+        .for_each(|(tile, tile_data)| *tile = T::read_from_slice(tile_data));
+}
+
+pub fn apply_detail(detail: &mut MapTileLayerDetail, start: usize, data: &[u8]) {
+    let Some((_, size)) = detail_layout(detail) else {
+        return;
+    };
+    match detail {
+        MapTileLayerDetail::Tile() => {}
+        MapTileLayerDetail::Tele(tiles) => apply_detail_tiles(tiles, start, data, size),
+        MapTileLayerDetail::Speedup(tiles) => apply_detail_tiles(tiles, start, data, size),
+        MapTileLayerDetail::Switch(tiles) => apply_detail_tiles(tiles, start, data, size),
+        MapTileLayerDetail::Door(tiles) => apply_detail_tiles(tiles, start, data, size),
+        MapTileLayerDetail::Tune(tiles) => apply_detail_tiles(tiles, start, data, size),
+    }
+}
+
+/**
+ * Creates a patch that transforms `old` into `new`.
+ * Both maps must have their layers read (see `read_map_layers`).
+ */
+pub fn diff(old: &CDatafileWrapper, new: &CDatafileWrapper) -> anyhow::Result<MapPatch> {
+    if old.layers.len() != new.layers.len() {
+        return Err(anyhow::Error::msg(
+            "the layer count differs, a patch is not possible",
+        ));
+    }
+
+    let mut patch = MapPatch {
+        layer_count: new.layers.len() as u32,
+        ops: Vec::new(),
+    };
+    for (l, (old_layer, new_layer)) in old.layers.iter().zip(new.layers.iter()).enumerate() {
+        match (old_layer, new_layer) {
+            (
+                MapLayer::Tile(MapLayerTile(old_info, old_detail, old_tiles)),
+                MapLayer::Tile(MapLayerTile(new_info, new_detail, new_tiles)),
+            ) => {
+                if old_info.width != new_info.width
+                    || old_info.height != new_info.height
+                    || old_tiles.len() != new_tiles.len()
+                {
+                    return Err(anyhow::Error::msg(format!(
+                        "the size of the tile layer {} differs, a patch is not possible",
+                        l
+                    )));
+                }
+                diff_tiles(l as u32, old_tiles, new_tiles, &mut patch.ops);
+                diff_detail(l as u32, old_detail, new_detail, &mut patch.ops)?;
+            }
+            (
+                MapLayer::Quads(MapLayerQuad(_, old_quads)),
+                MapLayer::Quads(MapLayerQuad(_, new_quads)),
+            ) => {
+                if old_quads.len() != new_quads.len() {
+                    patch.ops.push(MapPatchOp::QuadsReplaced {
+                        layer: l as u32,
+                        data: serialize(new_quads),
+                    });
+                } else {
+                    old_quads
+                        .iter()
+                        .zip(new_quads.iter())
+                        .enumerate()
+                        .filter(|(_, (old_quad, new_quad))| !quads_equal(old_quad, new_quad))
+                        .for_each(|(q, (_, new_quad))| {
+                            let mut data: Vec<u8> = Vec::new();
+                            new_quad.write_to_vec(&mut data);
+                            patch.ops.push(MapPatchOp::QuadChanged {
+                                layer: l as u32,
+                                quad: q as u32,
+                                data,
+                            });
+                        });
+                }
+            }
+            (MapLayer::Sound(_), MapLayer::Sound(_))
+            | (MapLayer::Unknown(_), MapLayer::Unknown(_)) => {}
+            _ => {
+                return Err(anyhow::Error::msg(format!(
+                    "the type of the layer {} differs, a patch is not possible",
+                    l
+                )));
+            }
+        }
+    }
+
+    // embedded images are part of the map file, the patch can't transport them
+    for img in new.images.iter().filter(|img| img.item_data.external == 0) {
+        let unchanged = old.images.iter().any(|old_img| {
+            old_img.item_data.external == 0
+                && old_img.img_name == img.img_name
+                && old_img.item_data.width == img.item_data.width
+                && old_img.item_data.height == img.item_data.height
+        });
+        if !unchanged {
+            return Err(anyhow::Error::msg(format!(
+                "the embedded image {} was added or changed, a patch is not possible",
+                img.img_name
+            )));
+        }
+    }
+
+    new.images
+        .iter()
+        .filter(|img| img.item_data.external != 0)
+        .filter(|img| {
+            !old.images
+                .iter()
+                .any(|old_img| old_img.img_name == img.img_name)
+        })
+        .for_each(|img| {
+            patch.ops.push(MapPatchOp::ImageAdded {
+                name: img.img_name.clone(),
+            })
+        });
+
+    Ok(patch)
+}
+
+/**
+ * Applies the patch to the map.
+ * Returns the names of the external images that were added,
+ * the caller has to make sure they are downloaded.
+ * The map is not modified, if the patch does not fit to the map.
+ */
+pub fn apply(
+    map: &mut CDatafileWrapper,
+    patch: &MapPatch,
+    thread_pool: &rayon::ThreadPool,
+) -> anyhow::Result<Vec<String>> {
+    if map.layers.len() != patch.layer_count as usize {
+        return Err(anyhow::Error::msg(
+            "the patch was created for a different map",
+        ));
+    }
+
+    // check all ops first, so a broken patch does not leave a half patched map
+    let quad_size = item_size::<CQuad>();
+    for op in &patch.ops {
+        let valid = match op {
+            MapPatchOp::TileRun {
+                layer,
+                start,
+                tiles,
+            } => match map.layers.get(*layer as usize) {
+                Some(MapLayer::Tile(MapLayerTile(_, _, layer_tiles))) => {
+                    *start as usize + tiles.len() <= layer_tiles.len()
+                }
+                _ => false,
+            },
+            MapPatchOp::DetailRun { layer, start, data } => match map.layers.get(*layer as usize) {
+                Some(MapLayer::Tile(MapLayerTile(_, detail, _))) => {
+                    detail_layout(detail).map_or(false, |(len, size)| {
+                        data.len() % size == 0 && *start as usize + data.len() / size <= len
+                    })
+                }
+                _ => false,
+            },
+            MapPatchOp::QuadChanged { layer, quad, data } => {
+                match map.layers.get(*layer as usize) {
+                    Some(MapLayer::Quads(MapLayerQuad(_, quads))) => {
+                        (*quad as usize) < quads.len() && data.len() == quad_size
+                    }
+                    _ => false,
+                }
+            }
+            MapPatchOp::QuadsReplaced { layer, data } => {
+                matches!(map.layers.get(*layer as usize), Some(MapLayer::Quads(_)))
+                    && data.len() % quad_size == 0
+            }
+            MapPatchOp::ImageAdded { .. } => true,
+        };
+        if !valid {
+            return Err(anyhow::Error::msg("the patch does not fit to the map"));
+        }
+    }
+
+    let mut added_images: Vec<String> = Vec::new();
+    for op in &patch.ops {
+        match op {
+            MapPatchOp::TileRun {
+                layer,
+                start,
+                tiles,
+            } => {
+                if let MapLayer::Tile(MapLayerTile(_, _, layer_tiles)) =
+                    &mut map.layers[*layer as usize]
+                {
+                    layer_tiles[*start as usize..*start as usize + tiles.len()]
+                        .iter_mut()
+                        .zip(tiles.iter())
+                        .for_each(|(tile, patch_tile)| {
+                            tile.index = patch_tile.index;
+                            tile.flags = patch_tile.flags;
+                        });
+                }
+            }
+            MapPatchOp::DetailRun { layer, start, data } => {
+                if let MapLayer::Tile(MapLayerTile(_, detail, _)) = &mut map.layers[*layer as usize]
+                {
+                    apply_detail(detail, *start as usize, data);
+                }
+            }
+            MapPatchOp::QuadChanged { layer, quad, data } => {
+                if let MapLayer::Quads(MapLayerQuad(_, quads)) = &mut map.layers[*layer as usize] {
+                    quads[*quad as usize] = CQuad::read_from_slice(data);
+                }
+            }
+            MapPatchOp::QuadsReplaced { layer, data } => {
+                if let MapLayer::Quads(MapLayerQuad(quad_layer, quads)) =
+                    &mut map.layers[*layer as usize]
+                {
+                    *quads = data
+                        .chunks_exact(quad_size)
+                        .map(|quad| CQuad::read_from_slice(quad))
+                        .collect();
+                    quad_layer.num_quads = quads.len() as i32;
+                }
+            }
+            MapPatchOp::ImageAdded { name } => added_images.push(name.clone()),
+        }
+    }
+
+    // the tile skip values and spatial indices are outdated now
+    if patch
+        .ops
+        .iter()
+        .any(|op| matches!(op, MapPatchOp::TileRun { .. }))
+    {
+        map.InitTilemapSkip(thread_pool);
+    }
+    if !patch.is_empty() {
+        map.update_spatial_indices(thread_pool);
+    }
+
+    Ok(added_images)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        datafile::CDatafileWrapper,
+        mapdef::{
+            CMapItemLayerQuads, CMapItemLayerTilemap, CQuad, CSpeedupTile, CTeleTile, CTile,
+            MapLayer, MapLayerQuad, MapLayerTile, MapTileLayerDetail,
+        },
+    };
+
+    use super::{apply, diff, serialize, MapPatch, MapPatchOp};
+
+    const WIDTH: i32 = 16;
+    const HEIGHT: i32 = 8;
+
+    fn tile_layer(detail: MapTileLayerDetail) -> MapLayer {
+        let mut info = CMapItemLayerTilemap::default();
+        info.width = WIDTH;
+        info.height = HEIGHT;
+        MapLayer::Tile(MapLayerTile(
+            info,
+            detail,
+            vec![CTile::default(); (WIDTH * HEIGHT) as usize],
+        ))
+    }
+
+    fn map() -> CDatafileWrapper {
+        let tiles = (WIDTH * HEIGHT) as usize;
+        let mut map = CDatafileWrapper::new();
+        map.layers = vec![
+            tile_layer(MapTileLayerDetail::Tile()),
+            tile_layer(MapTileLayerDetail::Tele(vec![CTeleTile::default(); tiles])),
+            tile_layer(MapTileLayerDetail::Speedup(vec![
+                CSpeedupTile::default();
+                tiles
+            ])),
+            MapLayer::Quads(MapLayerQuad(
+                CMapItemLayerQuads::default(),
+                vec![CQuad::default(); 2],
+            )),
+        ];
+        map
+    }
+
+    /**
+     * All layers in the datafile representation, to compare two maps
+     */
+    fn layers_data(map: &CDatafileWrapper) -> Vec<Vec<u8>> {
+        map.layers
+            .iter()
+            .map(|layer| match layer {
+                MapLayer::Tile(MapLayerTile(_, detail, tiles)) => {
+                    let mut data = serialize(tiles);
+                    match detail {
+                        MapTileLayerDetail::Tele(tiles) => data.extend(serialize(tiles)),
+                        MapTileLayerDetail::Speedup(tiles) => data.extend(serialize(tiles)),
+                        _ => {}
+                    }
+                    data
+                }
+                MapLayer::Quads(MapLayerQuad(_, quads)) => serialize(quads),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_apply_round_trip() {
+        let old = map();
+        let mut new = map();
+        if let MapLayer::Tile(MapLayerTile(_, _, tiles)) = &mut new.layers[0] {
+            tiles[3].index = 1;
+            tiles[100].index = 2;
+        }
+        if let MapLayer::Tile(MapLayerTile(_, MapTileLayerDetail::Tele(tele), tiles)) =
+            &mut new.layers[1]
+        {
+            tiles[5].index = 26;
+            tele[5].number = 7;
+            tele[5].tile_type = 26;
+        }
+        if let MapLayer::Tile(MapLayerTile(_, MapTileLayerDetail::Speedup(speedup), _)) =
+            &mut new.layers[2]
+        {
+            speedup[20].force = 10;
+            speedup[20].angle = -90;
+            speedup[21].max_speed = 3;
+        }
+        if let MapLayer::Quads(MapLayerQuad(_, quads)) = &mut new.layers[3] {
+            quads[1].pos_env = 2;
+        }
+
+        let patch = diff(&old, &new).unwrap();
+        assert!(patch
+            .ops
+            .iter()
+            .any(|op| matches!(op, MapPatchOp::DetailRun { layer: 1, .. })));
+        assert!(patch
+            .ops
+            .iter()
+            .any(|op| matches!(op, MapPatchOp::DetailRun { layer: 2, .. })));
+        // over the network
+        let patch = MapPatch::from_bytes(&patch.to_bytes()).unwrap();
+
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let mut patched = map();
+        assert!(apply(&mut patched, &patch, &thread_pool)
+            .unwrap()
+            .is_empty());
+        assert!(layers_data(&patched) == layers_data(&new));
+        assert!(diff(&patched, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn patch_does_not_fit() {
+        let mut other = map();
+        if let MapLayer::Tile(MapLayerTile(_, MapTileLayerDetail::Tele(tele), _)) =
+            &mut other.layers[1]
+        {
+            tele.truncate(10);
+        }
+        let patch = MapPatch {
+            layer_count: 4,
+            ops: vec![MapPatchOp::DetailRun {
+                layer: 1,
+                start: 8,
+                data: vec![1; 2 * 4],
+            }],
+        };
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        assert!(apply(&mut other, &patch, &thread_pool).is_err());
+        // tele tiles have a different size than speedup tiles
+        let mut speedup_patch = patch.clone();
+        if let MapPatchOp::DetailRun { layer, .. } = &mut speedup_patch.ops[0] {
+            *layer = 2;
+        }
+        assert!(apply(&mut map(), &speedup_patch, &thread_pool).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use bincode::{Decode, Encode};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    datafile::{CDatafileWrapper, UUIDOffset},
+    mapdef::{
+        MapItemTypes, MapLayer, MapLayerQuad, MapLayerSound, MapLayerTile, MapTileLayerDetail,
+        TilesLayerFlag, WriteToVec,
+    },
+};
+
+pub type MapSectionHash = [u8; 32];
+
+/**
+ * The extended map item that stores the `MapSectionHashes` in the map file, so they are
+ * known without reading the layers. Other clients ignore unknown extended items.
+ * The stored hashes are only a hint (e.g. which resources can be reused),
+ * the physics are always verified with `physics_hash`.
+ */
+pub const MAP_ITEM_TYPE_SECTION_HASHES: i32 = UUIDOffset::OFFSET_UUID_TYPE as i32;
+pub const MAP_ITEM_UUID_SECTION_HASHES: [u8; 16] = [
+    0x5e, 0x3a, 0x8c, 0x41, 0x9d, 0x27, 0x3f, 0x0b, 0xa6, 0x14, 0x72, 0xd8, 0xc3, 0x5f, 0x90, 0x1e,
+];
+
+/**
+ * Hashes of the logical sections of a map.
+ * Unlike a hash over the whole file, cosmetic changes only change the hash of the
+ * affected group, so e.g. unchanged images can be reused and the physics
+ * can be compared independent of the design.
+ * The hashes only depend on the content, not on the order of the data in the file.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct MapSectionHashes {
+    // all physics layers (game, front, tele, speedup, switch, tune)
+    pub physics: MapSectionHash,
+    // the design layers of each group
+    pub groups: Vec<MapSectionHash>,
+    // each image, with it's name and for embedded images also it's data
+    pub images: Vec<MapSectionHash>,
+}
+
+impl MapSectionHashes {
+    pub fn from_item(data: &[u8]) -> Option<Self> {
+        bincode::decode_from_slice(data, bincode::config::standard())
+            .ok()
+            .map(|(hashes, _)| hashes)
+    }
+
+    pub fn to_item(&self) -> Vec<u8> {
+        let mut res = bincode::encode_to_vec(self, bincode::config::standard()).unwrap();
+        // items are 4 byte aligned
+        res.resize((res.len() + 3) & !3, 0);
+        res
+    }
+}
+
+const PHYSICS_FLAGS: i32 = TilesLayerFlag::TILESLAYERFLAG_GAME as i32
+    | TilesLayerFlag::TILESLAYERFLAG_TELE as i32
+    | TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32
+    | TilesLayerFlag::TILESLAYERFLAG_FRONT as i32
+    | TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32
+    | TilesLayerFlag::TILESLAYERFLAG_TUNE as i32;
+
+fn is_physics_layer(layer: &MapLayer) -> bool {
+    match layer {
+        MapLayer::Tile(tile_layer) => (tile_layer.0.flags & PHYSICS_FLAGS) != 0,
+        _ => false,
+    }
+}
+
+fn hash_items<T: WriteToVec>(hasher: &mut Sha256, items: &[T]) {
+    let mut data: Vec<u8> = Vec::new();
+    items.iter().for_each(|item| item.write_to_vec(&mut data));
+    hasher.update((items.len() as u64).to_le_bytes());
+    hasher.update(&data);
+}
+
+/**
+ * Only hashes the content of the layer, data indices are ignored,
+ * since they change if other layers change.
+ * `remap_image` gives the image index the layer has in the written file.
+ */
+fn hash_layer(hasher: &mut Sha256, layer: &MapLayer, remap_image: &dyn Fn(i32) -> i32) {
+    match layer {
+        MapLayer::Tile(MapLayerTile(tile_layer, detail, tiles)) => {
+            hasher.update(b"tiles");
+            hasher.update(tile_layer.width.to_le_bytes());
+            hasher.update(tile_layer.height.to_le_bytes());
+            hasher.update(tile_layer.flags.to_le_bytes());
+            if (tile_layer.flags & PHYSICS_FLAGS) == 0 {
+                hasher.update(tile_layer.color.x.to_le_bytes());
+                hasher.update(tile_layer.color.y.to_le_bytes());
+                hasher.update(tile_layer.color.z.to_le_bytes());
+                hasher.update(tile_layer.color.w.to_le_bytes());
+                hasher.update(tile_layer.color_env.to_le_bytes());
+                hasher.update(tile_layer.color_env_offset.to_le_bytes());
+                hasher.update(remap_image(tile_layer.image).to_le_bytes());
+            }
+            // the skip value is only a runtime optimization
+            hasher.update((tiles.len() as u64).to_le_bytes());
+            tiles.iter().for_each(|tile| {
+                hasher.update([tile.index, tile.flags, tile.reserved]);
+            });
+            match detail {
+                MapTileLayerDetail::Tile() => {}
+                MapTileLayerDetail::Tele(tiles) => hash_items(hasher, tiles),
+                MapTileLayerDetail::Speedup(tiles) => hash_items(hasher, tiles),
+                MapTileLayerDetail::Switch(tiles) => hash_items(hasher, tiles),
+                MapTileLayerDetail::Door(tiles) => hash_items(hasher, tiles),
+                MapTileLayerDetail::Tune(tiles) => hash_items(hasher, tiles),
+            }
+        }
+        MapLayer::Quads(MapLayerQuad(quad_layer, quads)) => {
+            hasher.update(b"quads");
+            hasher.update(remap_image(quad_layer.image).to_le_bytes());
+            hash_items(hasher, quads);
+        }
+        MapLayer::Sound(MapLayerSound(sound_layer, sources)) => {
+            hasher.update(b"sounds");
+            hasher.update(sound_layer.sound.to_le_bytes());
+            hasher.update(sound_layer.num_sources.to_le_bytes());
+            hash_items(hasher, sources);
+        }
+        MapLayer::Unknown(layer) => {
+            hasher.update(b"unknown");
+            hasher.update(layer.item_layer.to_le_bytes());
+            hasher.update(layer.flags.to_le_bytes());
+        }
+    }
+}
+
+impl CDatafileWrapper {
+    /**
+     * Hash over all physics layers, the layers must be read (see `read_map_layers`).
+     */
+    pub fn physics_hash(&self) -> MapSectionHash {
+        let mut hasher = Sha256::new();
+        self.layers
+            .iter()
+            .filter(|layer| is_physics_layer(layer))
+            .for_each(|layer| hash_layer(&mut hasher, layer, &|image| image));
+        hasher.finalize().into()
+    }
+
+    /**
+     * The hashes stored in the map file, calculated if the map was written without them
+     * (e.g. by other editors). The layers must be read in that case.
+     * The stored hashes might be stale (e.g. after an edit by a tool that keeps unknown items)
+     * or spoofed, so they must not be used to verify the map, see `physics_hash`.
+     */
+    pub fn section_hashes_from_header(&self, data_start: &[u8]) -> MapSectionHashes {
+        match self.stored_section_hashes() {
+            Some(hashes) => hashes.clone(),
+            None => self.section_hashes(data_start),
+        }
+    }
+
+    /**
+     * Calculates the hashes of all sections.
+     * `data_start` is the slice returned by `Open`, it's used to hash the embedded images.
+     * Design layers that were not read (see `MapFileLayersReadOptions`) only contribute
+     * their layer information.
+     */
+    pub fn section_hashes(&self, data_start: &[u8]) -> MapSectionHashes {
+        self.section_hashes_remapped(data_start, &|image| image, &|_| true)
+    }
+
+    /**
+     * The hashes of the map after the images were remapped by the export
+     * (see `MapFileExportOptions::dedup_images`), `keep_image` filters the removed images
+     */
+    pub(crate) fn section_hashes_remapped(
+        &self,
+        data_start: &[u8],
+        remap_image: &dyn Fn(i32) -> i32,
+        keep_image: &dyn Fn(usize) -> bool,
+    ) -> MapSectionHashes {
+        let groups = (0..self.NumGroups() as usize)
+            .map(|g| {
+                let group = self.get_group(g);
+                let mut hasher = Sha256::new();
+                hasher.update(group.offset_x.to_le_bytes());
+                hasher.update(group.offset_y.to_le_bytes());
+                hasher.update(group.parallax_x.to_le_bytes());
+                hasher.update(group.parallax_y.to_le_bytes());
+                hasher.update(group.use_clipping.to_le_bytes());
+                hasher.update(group.clip_x.to_le_bytes());
+                hasher.update(group.clip_y.to_le_bytes());
+                hasher.update(group.clip_w.to_le_bytes());
+                hasher.update(group.clip_h.to_le_bytes());
+                let start = group.start_layer.max(0) as usize;
+                let end = (start + group.num_layers.max(0) as usize).min(self.layers.len());
+                self.layers[start.min(end)..end]
+                    .iter()
+                    .filter(|layer| !is_physics_layer(layer))
+                    .for_each(|layer| hash_layer(&mut hasher, layer, remap_image));
+                hasher.finalize().into()
+            })
+            .collect();
+
+        let images = if self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_IMAGE) {
+            self.images
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| keep_image(*index))
+                .map(|(_, img)| {
+                    let mut hasher = Sha256::new();
+                    hasher.update(img.img_name.as_bytes());
+                    hasher.update(img.item_data.external.to_le_bytes());
+                    if img.item_data.external == 0 {
+                        hasher.update(img.item_data.width.to_le_bytes());
+                        hasher.update(img.item_data.height.to_le_bytes());
+                        hasher.update(
+                            self.read_raw_data(img.item_data.image_data as usize, data_start),
+                        );
+                    }
+                    hasher.finalize().into()
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        MapSectionHashes {
+            physics: self.physics_hash(),
+            groups,
+            images,
+        }
+    }
+}
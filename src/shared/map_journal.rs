@@ -0,0 +1,817 @@
+use bincode::{Decode, Encode};
+
+use crate::{
+    datafile::CDatafileWrapper,
+    map_diff::{apply_detail, detail_layout, detail_tiles_data, MapPatchTile},
+    mapdef::{
+        CEnvPoint, CMapItemGroup, CQuad, CSoundSource, CTile, MapLayer, MapLayerQuad,
+        MapLayerSound, MapLayerTile, MapTileLayerDetail, ReadFromSlice, WriteToVec,
+    },
+};
+
+/**
+ * A single change of the map that knows the old and the new state,
+ * so it can be applied and reverted.
+ * Items are stored in their datafile representation.
+ */
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum MapAction {
+    // a rect of tiles of a tile layer, `old` and `new` are stored row by row.
+    // the detail tiles (tele, speedup etc.) of the rect are in the datafile representation,
+    // both are empty if the detail tiles don't change
+    Tiles {
+        layer: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        old: Vec<MapPatchTile>,
+        new: Vec<MapPatchTile>,
+        old_detail: Vec<u8>,
+        new_detail: Vec<u8>,
+    },
+    Quad {
+        layer: u32,
+        quad: u32,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    // quads were added or removed, all quads of the layer
+    Quads {
+        layer: u32,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    // all sound sources of a sound layer
+    SoundSources {
+        layer: u32,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    // the properties of a group (offset, parallax, clipping),
+    // the layers of the group never change
+    Group {
+        group: u32,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    // the image of a tile or quad layer
+    LayerImage {
+        layer: u32,
+        old: i32,
+        new: i32,
+    },
+    // `point` is the index the envelopes use (see `CMapItemEnvelope::start_point`)
+    EnvPoint {
+        point: u32,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+}
+
+fn serialize<T: WriteToVec>(items: &[T]) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::new();
+    items.iter().for_each(|item| item.write_to_vec(&mut res));
+    res
+}
+
+fn item_size<T: WriteToVec + Default>() -> usize {
+    serialize(&[T::default()]).len()
+}
+
+fn serialize_group(group: &CMapItemGroup) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::new();
+    group.write_to_vec(&mut res);
+    res
+}
+
+fn serialize_env_point(point: &CEnvPoint) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::new();
+    point.write_to_vec(&mut res);
+    res
+}
+
+/**
+ * If the detail tiles of a rect with `tile_count` tiles fit to the layer
+ * with `layer_tile_count` tiles
+ */
+fn detail_fits(
+    detail: &MapTileLayerDetail,
+    layer_tile_count: usize,
+    tile_count: usize,
+    detail_data: &[u8],
+) -> bool {
+    detail_layout(detail).is_some_and(|(len, size)| {
+        len == layer_tile_count && detail_data.len() == tile_count * size
+    })
+}
+
+fn to_patch_tiles<'a>(tiles: impl Iterator<Item = &'a CTile>) -> Vec<MapPatchTile> {
+    tiles
+        .map(|tile| MapPatchTile {
+            index: tile.index,
+            flags: tile.flags,
+        })
+        .collect()
+}
+
+impl MapAction {
+    /**
+     * Sets a rect of tiles, the current tiles of the map are the old state.
+     * `new_detail` are the detail tiles of the rect in the datafile representation,
+     * `None` keeps the detail tiles of the map
+     */
+    pub fn set_tiles(
+        map: &CDatafileWrapper,
+        layer: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        new: Vec<MapPatchTile>,
+        new_detail: Option<Vec<u8>>,
+    ) -> anyhow::Result<Self> {
+        let Some(MapLayer::Tile(MapLayerTile(tile_layer, detail, tiles))) = map.layers.get(layer)
+        else {
+            return Err(anyhow::Error::msg("the layer is not a tile layer"));
+        };
+        let layer_width = tile_layer.width.max(0) as usize;
+        let layer_height = tile_layer.height.max(0) as usize;
+        if width == 0
+            || new.len() % width != 0
+            || x + width > layer_width
+            || y + new.len() / width > layer_height
+            || tiles.len() != layer_width * layer_height
+        {
+            return Err(anyhow::Error::msg("the tiles are outside of the layer"));
+        }
+        let rows = y..y + new.len() / width;
+        let (old_detail, new_detail) = match new_detail {
+            Some(new_detail) => {
+                if !detail_fits(detail, layer_width * layer_height, new.len(), &new_detail) {
+                    return Err(anyhow::Error::msg(
+                        "the detail tiles don't fit to the tiles or the layer",
+                    ));
+                }
+                let old_detail = rows
+                    .clone()
+                    .flat_map(|row| detail_tiles_data(detail, row * layer_width + x, width))
+                    .collect();
+                (old_detail, new_detail)
+            }
+            None => Default::default(),
+        };
+        let old = rows
+            .flat_map(|row| to_patch_tiles(tiles[row * layer_width + x..][..width].iter()))
+            .collect();
+        Ok(Self::Tiles {
+            layer: layer as u32,
+            x: x as u32,
+            y: y as u32,
+            width: width as u32,
+            old,
+            new,
+            old_detail,
+            new_detail,
+        })
+    }
+
+    pub fn set_quad(
+        map: &CDatafileWrapper,
+        layer: usize,
+        quad: usize,
+        new: &CQuad,
+    ) -> anyhow::Result<Self> {
+        match map.layers.get(layer) {
+            Some(MapLayer::Quads(MapLayerQuad(_, quads))) if quad < quads.len() => Ok(Self::Quad {
+                layer: layer as u32,
+                quad: quad as u32,
+                old: serialize(&quads[quad..quad + 1]),
+                new: serialize(std::slice::from_ref(new)),
+            }),
+            _ => Err(anyhow::Error::msg("the quad does not exist")),
+        }
+    }
+
+    pub fn set_quads(map: &CDatafileWrapper, layer: usize, new: &[CQuad]) -> anyhow::Result<Self> {
+        match map.layers.get(layer) {
+            Some(MapLayer::Quads(MapLayerQuad(_, quads))) => Ok(Self::Quads {
+                layer: layer as u32,
+                old: serialize(quads),
+                new: serialize(new),
+            }),
+            _ => Err(anyhow::Error::msg("the layer is not a quad layer")),
+        }
+    }
+
+    pub fn set_sound_sources(
+        map: &CDatafileWrapper,
+        layer: usize,
+        new: &[CSoundSource],
+    ) -> anyhow::Result<Self> {
+        match map.layers.get(layer) {
+            Some(MapLayer::Sound(MapLayerSound(_, sources))) => Ok(Self::SoundSources {
+                layer: layer as u32,
+                old: serialize(sources),
+                new: serialize(new),
+            }),
+            _ => Err(anyhow::Error::msg("the layer is not a sound layer")),
+        }
+    }
+
+    pub fn set_group(
+        map: &CDatafileWrapper,
+        group: usize,
+        new: &CMapItemGroup,
+    ) -> anyhow::Result<Self> {
+        if group >= map.NumGroups() as usize {
+            return Err(anyhow::Error::msg("the group does not exist"));
+        }
+        Ok(Self::Group {
+            group: group as u32,
+            old: serialize_group(map.get_group(group)),
+            new: serialize_group(new),
+        })
+    }
+
+    pub fn set_layer_image(map: &CDatafileWrapper, layer: usize, new: i32) -> anyhow::Result<Self> {
+        let old = match map.layers.get(layer) {
+            Some(MapLayer::Tile(MapLayerTile(tile_layer, _, _))) => tile_layer.image,
+            Some(MapLayer::Quads(MapLayerQuad(quad_layer, _))) => quad_layer.image,
+            _ => return Err(anyhow::Error::msg("the layer can't have an image")),
+        };
+        Ok(Self::LayerImage {
+            layer: layer as u32,
+            old,
+            new,
+        })
+    }
+
+    pub fn set_env_point(
+        map: &CDatafileWrapper,
+        point: usize,
+        new: &CEnvPoint,
+    ) -> anyhow::Result<Self> {
+        let old = map
+            .get_env_point(point)
+            .map(serialize_env_point)
+            .ok_or_else(|| anyhow::Error::msg("the env point does not exist"))?;
+        Ok(Self::EnvPoint {
+            point: point as u32,
+            old,
+            new: serialize_env_point(new),
+        })
+    }
+
+    /**
+     * The same action in the other direction
+     */
+    pub fn inverted(&self) -> Self {
+        match self.clone() {
+            Self::Tiles {
+                layer,
+                x,
+                y,
+                width,
+                old,
+                new,
+                old_detail,
+                new_detail,
+            } => Self::Tiles {
+                layer,
+                x,
+                y,
+                width,
+                old: new,
+                new: old,
+                old_detail: new_detail,
+                new_detail: old_detail,
+            },
+            Self::Quad {
+                layer,
+                quad,
+                old,
+                new,
+            } => Self::Quad {
+                layer,
+                quad,
+                old: new,
+                new: old,
+            },
+            Self::Quads { layer, old, new } => Self::Quads {
+                layer,
+                old: new,
+                new: old,
+            },
+            Self::SoundSources { layer, old, new } => Self::SoundSources {
+                layer,
+                old: new,
+                new: old,
+            },
+            Self::Group { group, old, new } => Self::Group {
+                group,
+                old: new,
+                new: old,
+            },
+            Self::LayerImage { layer, old, new } => Self::LayerImage {
+                layer,
+                old: new,
+                new: old,
+            },
+            Self::EnvPoint { point, old, new } => Self::EnvPoint {
+                point,
+                old: new,
+                new: old,
+            },
+        }
+    }
+
+    /**
+     * Sets the new state of the action, the map is not modified
+     * if the action does not fit to the map.
+     * The tile skip values and spatial indices are not updated, see `MapJournal`.
+     */
+    pub fn apply(&self, map: &mut CDatafileWrapper) -> anyhow::Result<()> {
+        let does_not_fit = || anyhow::Error::msg("the action does not fit to the map");
+        match self {
+            Self::Tiles {
+                layer,
+                x,
+                y,
+                width,
+                old,
+                new,
+                old_detail,
+                new_detail,
+            } => {
+                let Some(MapLayer::Tile(MapLayerTile(tile_layer, detail, tiles))) =
+                    map.layers.get_mut(*layer as usize)
+                else {
+                    return Err(does_not_fit());
+                };
+                let layer_width = tile_layer.width.max(0) as usize;
+                let layer_height = tile_layer.height.max(0) as usize;
+                let (x, y, width) = (*x as usize, *y as usize, *width as usize);
+                if width == 0
+                    || old.len() != new.len()
+                    || new.len() % width != 0
+                    || x + width > layer_width
+                    || y + new.len() / width > layer_height
+                    || tiles.len() != layer_width * layer_height
+                    || old_detail.len() != new_detail.len()
+                    || (!new_detail.is_empty()
+                        && !detail_fits(detail, tiles.len(), new.len(), new_detail))
+                {
+                    return Err(does_not_fit());
+                }
+                if !new_detail.is_empty() {
+                    new_detail
+                        .chunks_exact(new_detail.len() / (new.len() / width))
+                        .enumerate()
+                        .for_each(|(row, new_detail)| {
+                            apply_detail(detail, (y + row) * layer_width + x, new_detail)
+                        });
+                }
+                new.chunks_exact(width).enumerate().for_each(|(row, new)| {
+                    tiles[(y + row) * layer_width + x..][..width]
+                        .iter_mut()
+                        .zip(new.iter())
+                        .for_each(|(tile, new)| {
+                            tile.index = new.index;
+                            tile.flags = new.flags;
+                        })
+                });
+            }
+            Self::Quad {
+                layer, quad, new, ..
+            } => match map.layers.get_mut(*layer as usize) {
+                Some(MapLayer::Quads(MapLayerQuad(_, quads)))
+                    if (*quad as usize) < quads.len() && new.len() == item_size::<CQuad>() =>
+                {
+                    quads[*quad as usize] = CQuad::read_from_slice(new);
+                }
+                _ => return Err(does_not_fit()),
+            },
+            Self::Quads { layer, new, .. } => match map.layers.get_mut(*layer as usize) {
+                Some(MapLayer::Quads(MapLayerQuad(quad_layer, quads)))
+                    if new.len() % item_size::<CQuad>() == 0 =>
+                {
+                    *quads = new
+                        .chunks_exact(item_size::<CQuad>())
+                        .map(CQuad::read_from_slice)
+                        .collect();
+                    quad_layer.num_quads = quads.len() as i32;
+                }
+                _ => return Err(does_not_fit()),
+            },
+            Self::SoundSources { layer, new, .. } => match map.layers.get_mut(*layer as usize) {
+                Some(MapLayer::Sound(MapLayerSound(sound_layer, sources)))
+                    if new.len() % item_size::<CSoundSource>() == 0 =>
+                {
+                    *sources = new
+                        .chunks_exact(item_size::<CSoundSource>())
+                        .map(CSoundSource::read_from_slice)
+                        .collect();
+                    sound_layer.num_sources = sources.len() as i32;
+                }
+                _ => return Err(does_not_fit()),
+            },
+            Self::Group { group, new, .. } => {
+                if new.len() != serialize_group(&CMapItemGroup::default()).len() {
+                    return Err(does_not_fit());
+                }
+                let Some(map_group) = map.get_group_mut(*group as usize) else {
+                    return Err(does_not_fit());
+                };
+                let mut new_group = CMapItemGroup::read_from_slice(new);
+                new_group.start_layer = map_group.start_layer;
+                new_group.num_layers = map_group.num_layers;
+                *map_group = new_group;
+            }
+            Self::LayerImage { layer, new, .. } => match map.layers.get_mut(*layer as usize) {
+                Some(MapLayer::Tile(MapLayerTile(tile_layer, _, _))) => tile_layer.image = *new,
+                Some(MapLayer::Quads(MapLayerQuad(quad_layer, _))) => quad_layer.image = *new,
+                _ => return Err(does_not_fit()),
+            },
+            Self::EnvPoint { point, new, .. } => {
+                if new.len() != serialize_env_point(&CEnvPoint::default()).len() {
+                    return Err(does_not_fit());
+                }
+                let Some(map_point) = map.get_env_point_mut(*point as usize) else {
+                    return Err(does_not_fit());
+                };
+                *map_point = CEnvPoint::read_from_slice(new);
+            }
+        }
+        Ok(())
+    }
+
+    // the layer whose spatial index is outdated after the action
+    pub fn changed_layer(&self) -> Option<usize> {
+        match self {
+            Self::Tiles { layer, .. }
+            | Self::Quad { layer, .. }
+            | Self::Quads { layer, .. }
+            | Self::SoundSources { layer, .. } => Some(*layer as usize),
+            Self::Group { .. } | Self::LayerImage { .. } | Self::EnvPoint { .. } => None,
+        }
+    }
+}
+
+/**
+ * Undo/redo history of the map as a list of steps, every step is a list of actions.
+ * The journal can be serialized, so the same history format can be used
+ * to send changes to other editors or to restore the map after a crash
+ * (see `replay`).
+ */
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub struct MapJournal {
+    steps: Vec<Vec<MapAction>>,
+    // the steps before this index are applied to the map
+    position: usize,
+}
+
+impl MapJournal {
+    /**
+     * Applies the actions in order, if one action fails,
+     * the already applied ones are reverted
+     */
+    fn apply_actions(
+        map: &mut CDatafileWrapper,
+        actions: impl Iterator<Item = MapAction>,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<()> {
+        let mut applied: Vec<MapAction> = Vec::new();
+        let mut res = Ok(());
+        for action in actions {
+            if let Err(err) = action.apply(map) {
+                res = Err(err);
+                break;
+            }
+            applied.push(action);
+        }
+        if res.is_err() {
+            applied.iter().rev().for_each(|action| {
+                // it was applied just now, so reverting it can't fail
+                let _ = action.inverted().apply(map);
+            });
+        }
+
+        if applied
+            .iter()
+            .any(|action| matches!(action, MapAction::Tiles { .. }))
+        {
+            map.InitTilemapSkip(thread_pool);
+        }
+        applied
+            .iter()
+            .filter_map(|action| action.changed_layer())
+            .for_each(|layer| map.update_spatial_index(layer));
+        res
+    }
+
+    /**
+     * Applies the actions as a new step, all steps that were undone are dropped
+     */
+    pub fn push(
+        &mut self,
+        map: &mut CDatafileWrapper,
+        actions: Vec<MapAction>,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<()> {
+        Self::apply_actions(map, actions.iter().cloned(), thread_pool)?;
+        self.record(actions);
+        Ok(())
+    }
+
+    /**
+     * Applies the actions as part of the last step, e.g. for every tile a brush stroke paints,
+     * so the whole stroke is undone at once.
+     * If there is no step or the last step was undone, the actions are a new step.
+     */
+    pub fn push_merged(
+        &mut self,
+        map: &mut CDatafileWrapper,
+        actions: Vec<MapAction>,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<()> {
+        if self.position == 0 || self.can_redo() {
+            return self.push(map, actions, thread_pool);
+        }
+        Self::apply_actions(map, actions.iter().cloned(), thread_pool)?;
+        self.steps[self.position - 1].extend(actions);
+        Ok(())
+    }
+
+    /**
+     * Adds a step whose actions were already applied to the map
+     * (e.g. while the user was dragging a quad)
+     */
+    pub fn record(&mut self, actions: Vec<MapAction>) {
+        if actions.is_empty() {
+            return;
+        }
+        self.steps.truncate(self.position);
+        self.steps.push(actions);
+        self.position = self.steps.len();
+    }
+
+    /**
+     * Returns `false` if there is nothing to undo
+     */
+    pub fn undo(
+        &mut self,
+        map: &mut CDatafileWrapper,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<bool> {
+        if !self.can_undo() {
+            return Ok(false);
+        }
+        let step = &self.steps[self.position - 1];
+        Self::apply_actions(
+            map,
+            step.iter().rev().map(|action| action.inverted()),
+            thread_pool,
+        )?;
+        self.position -= 1;
+        Ok(true)
+    }
+
+    /**
+     * Returns `false` if there is nothing to redo
+     */
+    pub fn redo(
+        &mut self,
+        map: &mut CDatafileWrapper,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<bool> {
+        if !self.can_redo() {
+            return Ok(false);
+        }
+        let step = &self.steps[self.position];
+        Self::apply_actions(map, step.iter().cloned(), thread_pool)?;
+        self.position += 1;
+        Ok(true)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position < self.steps.len()
+    }
+
+    pub fn steps(&self) -> &[Vec<MapAction>] {
+        &self.steps
+    }
+
+    /**
+     * Applies all steps that were not undone to a map in the state
+     * the journal was started with, e.g. the last saved version of the map.
+     */
+    pub fn replay(
+        &self,
+        map: &mut CDatafileWrapper,
+        thread_pool: &rayon::ThreadPool,
+    ) -> anyhow::Result<()> {
+        self.steps[0..self.position]
+            .iter()
+            .try_for_each(|step| Self::apply_actions(map, step.iter().cloned(), thread_pool))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard()).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let (journal, _) =
+            bincode::decode_from_slice::<Self, _>(data, bincode::config::standard())?;
+        if journal.position > journal.steps.len() {
+            return Err(anyhow::Error::msg("the journal is corrupted"));
+        }
+        Ok(journal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        datafile::CDatafileWrapper,
+        map_diff::MapPatchTile,
+        mapdef::{
+            CMapItemLayerQuads, CMapItemLayerTilemap, CQuad, CTeleTile, CTile, MapLayer,
+            MapLayerQuad, MapLayerTile, MapTileLayerDetail,
+        },
+    };
+
+    use super::{serialize, MapAction, MapJournal};
+
+    const WIDTH: i32 = 8;
+    const HEIGHT: i32 = 4;
+
+    fn map() -> CDatafileWrapper {
+        let tiles = (WIDTH * HEIGHT) as usize;
+        let tile_layer = |detail: MapTileLayerDetail| {
+            let mut info = CMapItemLayerTilemap::default();
+            info.width = WIDTH;
+            info.height = HEIGHT;
+            MapLayer::Tile(MapLayerTile(info, detail, vec![CTile::default(); tiles]))
+        };
+        let mut map = CDatafileWrapper::new();
+        map.layers = vec![
+            tile_layer(MapTileLayerDetail::Tile()),
+            tile_layer(MapTileLayerDetail::Tele(vec![CTeleTile::default(); tiles])),
+            MapLayer::Quads(MapLayerQuad(
+                CMapItemLayerQuads::default(),
+                vec![CQuad::default(); 2],
+            )),
+        ];
+        map
+    }
+
+    /**
+     * All layers in the datafile representation, to compare two maps
+     */
+    fn layers_data(map: &CDatafileWrapper) -> Vec<Vec<u8>> {
+        map.layers
+            .iter()
+            .map(|layer| match layer {
+                MapLayer::Tile(MapLayerTile(_, detail, tiles)) => {
+                    let mut data = serialize(tiles);
+                    if let MapTileLayerDetail::Tele(tele) = detail {
+                        data.extend(serialize(tele));
+                    }
+                    data
+                }
+                MapLayer::Quads(MapLayerQuad(_, quads)) => serialize(quads),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn tiles(count: usize, index: u8) -> Vec<MapPatchTile> {
+        vec![MapPatchTile { index, flags: 0 }; count]
+    }
+
+    fn thread_pool() -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn undo_redo() {
+        let thread_pool = thread_pool();
+        let mut map = map();
+        let mut journal = MapJournal::default();
+        let state0 = layers_data(&map);
+
+        // a 2x2 rect of tele tiles and a moved quad in one step
+        let tele = serialize(&vec![
+            CTeleTile {
+                number: 7,
+                tile_type: 26,
+            };
+            4
+        ]);
+        let mut quad = CQuad::default();
+        quad.pos_env = 2;
+        let step1 = vec![
+            MapAction::set_tiles(&map, 1, 3, 1, 2, tiles(4, 26), Some(tele)).unwrap(),
+            MapAction::set_quad(&map, 2, 1, &quad).unwrap(),
+        ];
+        journal.push(&mut map, step1, &thread_pool).unwrap();
+        let state1 = layers_data(&map);
+        assert!(state1 != state0);
+
+        let step2 = vec![MapAction::set_tiles(&map, 0, 0, 0, 8, tiles(16, 1), None).unwrap()];
+        journal.push(&mut map, step2, &thread_pool).unwrap();
+        let state2 = layers_data(&map);
+
+        assert!(journal.undo(&mut map, &thread_pool).unwrap());
+        assert!(layers_data(&map) == state1);
+        assert!(journal.undo(&mut map, &thread_pool).unwrap());
+        assert!(layers_data(&map) == state0);
+        assert!(!journal.undo(&mut map, &thread_pool).unwrap());
+
+        assert!(journal.redo(&mut map, &thread_pool).unwrap());
+        assert!(layers_data(&map) == state1);
+        assert!(journal.redo(&mut map, &thread_pool).unwrap());
+        assert!(layers_data(&map) == state2);
+        assert!(!journal.redo(&mut map, &thread_pool).unwrap());
+
+        // the serialized journal restores the map from its start state
+        let mut replayed = self::map();
+        MapJournal::from_bytes(&journal.to_bytes())
+            .unwrap()
+            .replay(&mut replayed, &thread_pool)
+            .unwrap();
+        assert!(layers_data(&replayed) == state2);
+
+        // a new step drops the undone steps
+        journal.undo(&mut map, &thread_pool).unwrap();
+        let step3 = vec![MapAction::set_layer_image(&map, 0, 3).unwrap()];
+        journal.push(&mut map, step3, &thread_pool).unwrap();
+        assert_eq!(journal.steps().len(), 2);
+        assert!(!journal.can_redo());
+    }
+
+    #[test]
+    fn merged_steps() {
+        let thread_pool = thread_pool();
+        let mut map = map();
+        let mut journal = MapJournal::default();
+        let state0 = layers_data(&map);
+
+        // a brush stroke that paints over its own tiles
+        for (x, index) in [(0, 1), (1, 2), (0, 3)] {
+            let tele = serialize(&[CTeleTile {
+                number: index,
+                tile_type: 26,
+            }]);
+            let action = MapAction::set_tiles(&map, 1, x, 0, 1, tiles(1, index), Some(tele));
+            journal
+                .push_merged(&mut map, vec![action.unwrap()], &thread_pool)
+                .unwrap();
+        }
+        let stroke = layers_data(&map);
+        assert_eq!(journal.steps().len(), 1);
+        assert_eq!(journal.steps()[0].len(), 3);
+
+        // the whole stroke is undone at once, also the overwritten tile
+        assert!(journal.undo(&mut map, &thread_pool).unwrap());
+        assert!(layers_data(&map) == state0);
+        assert!(journal.redo(&mut map, &thread_pool).unwrap());
+        assert!(layers_data(&map) == stroke);
+
+        // after an undo, merged actions are a new step
+        journal.undo(&mut map, &thread_pool).unwrap();
+        let action = MapAction::set_tiles(&map, 0, 0, 0, 1, tiles(1, 1), None).unwrap();
+        journal
+            .push_merged(&mut map, vec![action], &thread_pool)
+            .unwrap();
+        assert_eq!(journal.steps().len(), 1);
+        assert_eq!(journal.steps()[0].len(), 1);
+    }
+
+    #[test]
+    fn failed_step_is_reverted() {
+        let thread_pool = thread_pool();
+        let mut map = map();
+        let mut journal = MapJournal::default();
+        let state0 = layers_data(&map);
+
+        let valid = MapAction::set_tiles(&map, 0, 0, 0, 2, tiles(2, 1), None).unwrap();
+        // the detail tiles of a layer without detail tiles
+        assert!(MapAction::set_tiles(&map, 0, 0, 0, 1, tiles(1, 1), Some(vec![0; 2])).is_err());
+        let mut invalid = valid.clone();
+        if let MapAction::Tiles { layer, .. } = &mut invalid {
+            *layer = 2;
+        }
+        assert!(journal
+            .push(&mut map, vec![valid, invalid], &thread_pool)
+            .is_err());
+        assert!(layers_data(&map) == state0);
+        assert!(journal.steps().is_empty());
+    }
+}
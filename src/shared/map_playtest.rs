@@ -0,0 +1,139 @@
+use crate::{
+    datafile::CDatafileWrapper,
+    game::{
+        collision::Collision,
+        simulation_pipe::{LocalPlayerInput, SimulationPipe, SimulationPlayerInput},
+        state::GameState,
+        GameElementGenerator, TGameElementID,
+    },
+    id_gen::IDGenerator,
+    map_journal::MapAction,
+    mapdef::EEntityTiles,
+};
+
+struct PlaytestInput<'a> {
+    player_id: TGameElementID,
+    input: &'a LocalPlayerInput,
+}
+
+impl<'a> SimulationPlayerInput for PlaytestInput<'a> {
+    fn get_input(&self, player_id: TGameElementID) -> Option<&LocalPlayerInput> {
+        (player_id == self.player_id).then_some(self.input)
+    }
+}
+
+/**
+ * Plays the map that is currently edited in-process, without a server.
+ * Changes of the editor are synced into the running game (see `sync`),
+ * so the map can be tested while it is edited.
+ * Dropping the playtest returns to the editor, the map itself is never modified.
+ */
+pub struct MapPlaytest {
+    game: GameState,
+    game_el_gen: GameElementGenerator,
+    collision: Collision,
+    player_id: TGameElementID,
+    stage_index: usize,
+}
+
+fn collision_of_map(map: &CDatafileWrapper) -> Collision {
+    let game_layer = map.get_game_layer();
+    Collision::new(
+        game_layer.0.width as u32,
+        game_layer.0.height as u32,
+        game_layer.2.as_slice(),
+    )
+}
+
+// the position of the first spawn tile, or the top left of the map
+fn spawn_pos_of_map(map: &CDatafileWrapper) -> (f32, f32) {
+    let game_layer = map.get_game_layer();
+    let w = game_layer.0.width as usize;
+    game_layer
+        .2
+        .iter()
+        .position(|tile| tile.index == EEntityTiles::ENTITY_SPAWN as u8)
+        .map(|index| {
+            (
+                (index % w) as f32 * 32.0 + 1.0,
+                (index / w) as f32 * 32.0 + 1.0,
+            )
+        })
+        .unwrap_or((1.0, 1.0))
+}
+
+impl MapPlaytest {
+    pub fn new(map: &CDatafileWrapper) -> Self {
+        let mut game = GameState::new();
+        let mut game_el_gen = GameElementGenerator::default();
+        let player_id = IDGenerator::new().get_next();
+        let stage_index = game.add_stage(&mut game_el_gen);
+
+        // the character is added on the first respawn
+        let mut res = Self {
+            game,
+            game_el_gen,
+            collision: collision_of_map(map),
+            player_id,
+            stage_index,
+        };
+        res.respawn(map);
+        res
+    }
+
+    /**
+     * Puts the character back to the spawn of the map
+     */
+    pub fn respawn(&mut self, map: &CDatafileWrapper) {
+        let (x, y) = spawn_pos_of_map(map);
+        let player_id = self.player_id;
+        let world = self.game.get_stage_mut(self.stage_index).get_world_mut();
+        if world
+            .get_characters()
+            .iter()
+            .all(|char| char.cores[0].player_id != player_id)
+        {
+            world.add_character(&mut self.game_el_gen, &player_id);
+        }
+        if let Some(char) = world
+            .get_characters_mut()
+            .iter_mut()
+            .find(|char| char.cores[0].player_id == player_id)
+        {
+            char.cores[0].core.pos.x = x;
+            char.cores[0].core.pos.y = y;
+        }
+    }
+
+    /**
+     * Call this with the actions the editor applied to the map since the last sync.
+     * Only changes of the game layer affect the running game, the character keeps its state.
+     */
+    pub fn sync(&mut self, map: &CDatafileWrapper, actions: &[MapAction]) {
+        if actions
+            .iter()
+            .filter_map(|action| action.changed_layer())
+            .any(|layer| map.is_game_layer(layer))
+        {
+            self.collision = collision_of_map(map);
+        }
+    }
+
+    pub fn tick(&mut self, input: &LocalPlayerInput) {
+        self.game.tick(&mut SimulationPipe {
+            player_inputs: &PlaytestInput {
+                player_id: self.player_id,
+                input,
+            },
+            collision: &self.collision,
+        });
+    }
+
+    pub fn player_id(&self) -> TGameElementID {
+        self.player_id
+    }
+
+    pub fn game(&self) -> &GameState {
+        &self.game
+    }
+}
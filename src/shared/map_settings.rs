@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+
+use base::console::{quote_arg, split_command};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapSettingKind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Float { min: f32, max: f32 },
+    String { max_len: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapSettingSchema {
+    pub name: &'static str,
+    pub kind: MapSettingKind,
+}
+
+/**
+ * Server settings a map is allowed to change.
+ * Everything else is rejected, so a map can't e.g. change the rcon password.
+ */
+pub const MAP_SERVER_SETTINGS: &[MapSettingSchema] = &[
+    MapSettingSchema {
+        name: "sv_team",
+        kind: MapSettingKind::Int { min: 0, max: 3 },
+    },
+    MapSettingSchema {
+        name: "sv_max_team_size",
+        kind: MapSettingKind::Int { min: 1, max: 64 },
+    },
+    MapSettingSchema {
+        name: "sv_solo_server",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_deepfly",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_endless_drag",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_hit",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_old_laser",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_teleport_hold_hook",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_teleport_lose_weapons",
+        kind: MapSettingKind::Bool,
+    },
+    MapSettingSchema {
+        name: "sv_door_delay",
+        kind: MapSettingKind::Int { min: 0, max: 60 },
+    },
+    MapSettingSchema {
+        name: "sv_plasma_range",
+        kind: MapSettingKind::Int { min: 1, max: 99999 },
+    },
+    MapSettingSchema {
+        name: "sv_plasma_per_sec",
+        kind: MapSettingKind::Int { min: 0, max: 50 },
+    },
+    MapSettingSchema {
+        name: "sv_dragger_range",
+        kind: MapSettingKind::Int { min: 1, max: 99999 },
+    },
+];
+
+/**
+ * The tune params a map can set with `tune` and `tune_zone`
+ */
+pub const MAP_TUNE_PARAMS: &[&str] = &[
+    "ground_control_speed",
+    "ground_control_accel",
+    "ground_friction",
+    "ground_jump_impulse",
+    "air_jump_impulse",
+    "air_control_speed",
+    "air_control_accel",
+    "air_friction",
+    "hook_length",
+    "hook_fire_speed",
+    "hook_drag_accel",
+    "hook_drag_speed",
+    "gravity",
+    "velramp_start",
+    "velramp_range",
+    "velramp_curvature",
+    "gun_curvature",
+    "gun_speed",
+    "gun_lifetime",
+    "shotgun_curvature",
+    "shotgun_speed",
+    "shotgun_speeddiff",
+    "shotgun_lifetime",
+    "grenade_curvature",
+    "grenade_speed",
+    "grenade_lifetime",
+    "laser_reach",
+    "laser_bounce_delay",
+    "laser_bounce_num",
+    "laser_bounce_cost",
+    "laser_damage",
+    "player_collision",
+    "player_hooking",
+    "jetpack_strength",
+    "shotgun_strength",
+    "explosion_strength",
+    "hammer_strength",
+    "hook_duration",
+    "hammer_fire_delay",
+    "gun_fire_delay",
+    "shotgun_fire_delay",
+    "grenade_fire_delay",
+    "laser_fire_delay",
+    "ninja_fire_delay",
+    "hammer_hit_fire_delay",
+];
+
+// the longest game type name a map can hint
+const MAX_GAME_TYPE_LEN: usize = 32;
+// the longest message that is shown when entering or leaving a tune zone
+const MAX_ZONE_MESSAGE_LEN: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapSettingValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    String(String),
+}
+
+impl MapSettingValue {
+    fn parse(value: &str, kind: &MapSettingKind) -> Result<Self, String> {
+        match kind {
+            MapSettingKind::Bool => match value {
+                "0" => Ok(Self::Bool(false)),
+                "1" => Ok(Self::Bool(true)),
+                _ => Err(format!("expected 0 or 1, found \"{}\"", value)),
+            },
+            MapSettingKind::Int { min, max } => {
+                let val = value
+                    .parse::<i64>()
+                    .map_err(|_| format!("expected an integer, found \"{}\"", value))?;
+                if val < *min || val > *max {
+                    return Err(format!("{} is not between {} and {}", val, min, max));
+                }
+                Ok(Self::Int(val))
+            }
+            MapSettingKind::Float { min, max } => {
+                let val = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|val| val.is_finite())
+                    .ok_or_else(|| format!("expected a number, found \"{}\"", value))?;
+                if val < *min || val > *max {
+                    return Err(format!("{} is not between {} and {}", val, min, max));
+                }
+                Ok(Self::Float(val))
+            }
+            MapSettingKind::String { max_len } => {
+                if value.len() > *max_len {
+                    return Err(format!("longer than {} characters", max_len));
+                }
+                Ok(Self::String(value.to_string()))
+            }
+        }
+    }
+
+    fn to_command_arg(&self) -> String {
+        match self {
+            MapSettingValue::Bool(val) => (*val as i32).to_string(),
+            MapSettingValue::Int(val) => val.to_string(),
+            MapSettingValue::Float(val) => val.to_string(),
+            MapSettingValue::String(val) => quote_arg(val),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MapModVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl MapModVersion {
+    /**
+     * Parses `major[.minor[.patch]]`
+     */
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.split('.');
+        let mut next = |optional: bool| match parts.next() {
+            Some(part) => part.parse::<u32>().ok(),
+            None if optional => Some(0),
+            None => None,
+        };
+        let res = Self {
+            major: next(false)?,
+            minor: next(true)?,
+            patch: next(true)?,
+        };
+        parts.next().is_none().then_some(res)
+    }
+
+    /**
+     * Same major version and at least the required minor and patch version
+     */
+    pub fn is_compatible_with(&self, required: &MapModVersion) -> bool {
+        self.major == required.major && self >= required
+    }
+}
+
+impl std::fmt::Display for MapModVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapRequiredMod {
+    pub name: String,
+    pub version: MapModVersion,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapSettingError {
+    UnknownCommand { command: String },
+    InvalidArguments { command: String, reason: String },
+}
+
+/**
+ * The typed and validated map settings.
+ * The settings are stored as server commands in the map,
+ * but only the commands of the schema are accepted:
+ * - `game_type <name>`: the game type the map was made for (only a hint)
+ * - `mod_required <name> <version>`: a mod the map needs
+ * - `tune <param> <value>`, `tune_zone <zone> <param> <value>`: see `MAP_TUNE_PARAMS`
+ * - `tune_zone_enter <zone> <message>`, `tune_zone_leave <zone> <message>`
+ * - `<setting> <value>`: see `MAP_SERVER_SETTINGS`
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapSettings {
+    pub game_type: Option<String>,
+    pub required_mods: Vec<MapRequiredMod>,
+    pub tunes: BTreeMap<String, f32>,
+    pub zone_tunes: BTreeMap<(u8, String), f32>,
+    pub zone_enter_messages: BTreeMap<u8, String>,
+    pub zone_leave_messages: BTreeMap<u8, String>,
+    pub server_settings: BTreeMap<String, MapSettingValue>,
+}
+
+fn parse_tune_value(param: &str, value: &str) -> Result<f32, String> {
+    if !MAP_TUNE_PARAMS.contains(&param) {
+        return Err(format!("unknown tune param \"{}\"", param));
+    }
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|val| val.is_finite())
+        .ok_or_else(|| format!("expected a number, found \"{}\"", value))
+}
+
+impl MapSettings {
+    /**
+     * Parses the setting commands of a map (see `read_map_setting_commands`).
+     * Invalid or unknown commands are not part of the result, but are reported as errors.
+     * Later commands overwrite earlier ones.
+     */
+    pub fn parse(commands: &[String]) -> (Self, Vec<MapSettingError>) {
+        let mut res = Self::default();
+        let mut errors: Vec<MapSettingError> = Vec::new();
+        for command in commands {
+            let args = split_command(command);
+            let Some(name) = args.first() else {
+                continue;
+            };
+            let args = &args[1..];
+            let invalid = |reason: String| MapSettingError::InvalidArguments {
+                command: command.clone(),
+                reason,
+            };
+            let expect_args = |count: usize| {
+                if args.len() != count {
+                    Err(invalid(format!(
+                        "expected {} arguments, found {}",
+                        count,
+                        args.len()
+                    )))
+                } else {
+                    Ok(())
+                }
+            };
+
+            let parsed: Result<(), MapSettingError> = match name.as_str() {
+                "game_type" => expect_args(1).and_then(|_| {
+                    if args[0].is_empty() || args[0].len() > MAX_GAME_TYPE_LEN {
+                        return Err(invalid("invalid game type name".to_string()));
+                    }
+                    res.game_type = Some(args[0].clone());
+                    Ok(())
+                }),
+                "mod_required" => expect_args(2).and_then(|_| {
+                    let version = MapModVersion::parse(&args[1])
+                        .ok_or_else(|| invalid(format!("invalid version \"{}\"", args[1])))?;
+                    match res
+                        .required_mods
+                        .iter_mut()
+                        .find(|required| required.name == args[0])
+                    {
+                        Some(required) => required.version = version,
+                        None => res.required_mods.push(MapRequiredMod {
+                            name: args[0].clone(),
+                            version,
+                        }),
+                    }
+                    Ok(())
+                }),
+                "tune" => expect_args(2).and_then(|_| {
+                    let value = parse_tune_value(&args[0], &args[1]).map_err(invalid)?;
+                    res.tunes.insert(args[0].clone(), value);
+                    Ok(())
+                }),
+                "tune_zone" => expect_args(3).and_then(|_| {
+                    let zone = args[0]
+                        .parse::<u8>()
+                        .map_err(|_| invalid(format!("invalid tune zone \"{}\"", args[0])))?;
+                    let value = parse_tune_value(&args[1], &args[2]).map_err(invalid)?;
+                    res.zone_tunes.insert((zone, args[1].clone()), value);
+                    Ok(())
+                }),
+                "tune_zone_enter" | "tune_zone_leave" => expect_args(2).and_then(|_| {
+                    let zone = args[0]
+                        .parse::<u8>()
+                        .map_err(|_| invalid(format!("invalid tune zone \"{}\"", args[0])))?;
+                    if args[1].len() > MAX_ZONE_MESSAGE_LEN {
+                        return Err(invalid(format!(
+                            "the message is longer than {} bytes",
+                            MAX_ZONE_MESSAGE_LEN
+                        )));
+                    }
+                    let messages = if name == "tune_zone_enter" {
+                        &mut res.zone_enter_messages
+                    } else {
+                        &mut res.zone_leave_messages
+                    };
+                    messages.insert(zone, args[1].clone());
+                    Ok(())
+                }),
+                _ => match MAP_SERVER_SETTINGS
+                    .iter()
+                    .find(|schema| schema.name == name.as_str())
+                {
+                    Some(schema) => expect_args(1).and_then(|_| {
+                        let value =
+                            MapSettingValue::parse(&args[0], &schema.kind).map_err(invalid)?;
+                        res.server_settings.insert(schema.name.to_string(), value);
+                        Ok(())
+                    }),
+                    None => Err(MapSettingError::UnknownCommand {
+                        command: command.clone(),
+                    }),
+                },
+            };
+            if let Err(err) = parsed {
+                errors.push(err);
+            }
+        }
+        (res, errors)
+    }
+
+    pub fn tune(&self, param: &str) -> Option<f32> {
+        self.tunes.get(param).copied()
+    }
+
+    pub fn zone_tune(&self, zone: u8, param: &str) -> Option<f32> {
+        self.zone_tunes.get(&(zone, param.to_string())).copied()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.server_settings.get(name) {
+            Some(MapSettingValue::Bool(val)) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.server_settings.get(name) {
+            Some(MapSettingValue::Int(val)) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f32> {
+        match self.server_settings.get(name) {
+            Some(MapSettingValue::Float(val)) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.server_settings.get(name) {
+            Some(MapSettingValue::String(val)) => Some(val.as_str()),
+            _ => None,
+        }
+    }
+
+    /**
+     * The settings as commands again, e.g. to store them in a map.
+     * Only contains validated settings, so parsing the result gives no errors.
+     */
+    pub fn to_commands(&self) -> Vec<String> {
+        let mut res: Vec<String> = Vec::new();
+        if let Some(game_type) = &self.game_type {
+            res.push(format!("game_type {}", quote_arg(game_type)));
+        }
+        self.required_mods.iter().for_each(|required| {
+            res.push(format!(
+                "mod_required {} {}",
+                quote_arg(&required.name),
+                required.version
+            ))
+        });
+        self.tunes
+            .iter()
+            .for_each(|(param, value)| res.push(format!("tune {} {}", param, value)));
+        self.zone_tunes.iter().for_each(|((zone, param), value)| {
+            res.push(format!("tune_zone {} {} {}", zone, param, value))
+        });
+        self.zone_enter_messages.iter().for_each(|(zone, message)| {
+            res.push(format!("tune_zone_enter {} {}", zone, quote_arg(message)))
+        });
+        self.zone_leave_messages.iter().for_each(|(zone, message)| {
+            res.push(format!("tune_zone_leave {} {}", zone, quote_arg(message)))
+        });
+        self.server_settings
+            .iter()
+            .for_each(|(name, value)| res.push(format!("{} {}", name, value.to_command_arg())));
+        res
+    }
+}
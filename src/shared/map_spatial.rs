@@ -0,0 +1,215 @@
+use crate::mapdef::{CQuad, MapLayer, MapLayerQuad};
+
+// amount of quads a leaf of the quad bvh contains at most
+const QUAD_BVH_LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct QuadBvhNode {
+    min: [i32; 2],
+    max: [i32; 2],
+    // leaf: the range in `quad_indices`, inner node: `count` is 0,
+    // the left child is the next node and `first` is the right child
+    first: u32,
+    count: u32,
+}
+
+/**
+ * Bounding volume hierarchy over the quads of a quad layer.
+ * The coordinates are the fixed point coordinates of the quad points.
+ * Quads that are moved by an envelope can be anywhere, they are always part of the result.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct QuadBvh {
+    nodes: Vec<QuadBvhNode>,
+    quad_indices: Vec<u32>,
+    animated_quads: Vec<u32>,
+    // (min, max) of every quad
+    bounds: Vec<([i32; 2], [i32; 2])>,
+}
+
+fn intersects(min1: [i32; 2], max1: [i32; 2], min2: [i32; 2], max2: [i32; 2]) -> bool {
+    max1[0] >= min2[0] && min1[0] <= max2[0] && max1[1] >= min2[1] && min1[1] <= max2[1]
+}
+
+fn quad_bounds(quad: &CQuad) -> ([i32; 2], [i32; 2]) {
+    // the fifth point is the pivot, not a corner
+    let mut min = [i32::MAX; 2];
+    let mut max = [i32::MIN; 2];
+    quad.points[0..4].iter().for_each(|point| {
+        min[0] = min[0].min(point.x);
+        min[1] = min[1].min(point.y);
+        max[0] = max[0].max(point.x);
+        max[1] = max[1].max(point.y);
+    });
+    (min, max)
+}
+
+impl QuadBvh {
+    pub fn new(quads: &[CQuad]) -> Self {
+        let mut res = Self::default();
+        let mut bounds: Vec<([i32; 2], [i32; 2])> = Vec::with_capacity(quads.len());
+        quads.iter().enumerate().for_each(|(q, quad)| {
+            bounds.push(quad_bounds(quad));
+            if quad.pos_env >= 0 {
+                res.animated_quads.push(q as u32);
+            } else {
+                res.quad_indices.push(q as u32);
+            }
+        });
+        if !res.quad_indices.is_empty() {
+            let count = res.quad_indices.len();
+            res.build_node(&bounds, 0, count);
+        }
+        res.bounds = bounds;
+        res
+    }
+
+    fn build_node(&mut self, bounds: &[([i32; 2], [i32; 2])], first: usize, count: usize) {
+        let mut node = QuadBvhNode {
+            min: [i32::MAX; 2],
+            max: [i32::MIN; 2],
+            first: first as u32,
+            count: count as u32,
+        };
+        self.quad_indices[first..first + count]
+            .iter()
+            .for_each(|q| {
+                let (min, max) = bounds[*q as usize];
+                node.min = [node.min[0].min(min[0]), node.min[1].min(min[1])];
+                node.max = [node.max[0].max(max[0]), node.max[1].max(max[1])];
+            });
+        let node_index = self.nodes.len();
+        self.nodes.push(node);
+        if count <= QUAD_BVH_LEAF_SIZE {
+            return;
+        }
+
+        // split at the median of the longer axis
+        let axis =
+            if node.max[0] as i64 - node.min[0] as i64 >= node.max[1] as i64 - node.min[1] as i64 {
+                0
+            } else {
+                1
+            };
+        let center = |q: &u32| {
+            let (min, max) = bounds[*q as usize];
+            min[axis] as i64 + max[axis] as i64
+        };
+        let half = count / 2;
+        self.quad_indices[first..first + count].select_nth_unstable_by_key(half, |q| center(q));
+
+        self.nodes[node_index].count = 0;
+        self.build_node(bounds, first, half);
+        self.nodes[node_index].first = self.nodes.len() as u32;
+        self.build_node(bounds, first + half, count - half);
+    }
+
+    /**
+     * Pushes the indices of all quads whose bounding box intersects the rect
+     * (in fixed point coordinates, inclusive), including all animated quads.
+     * The order of the indices is unspecified.
+     */
+    pub fn query_rect(&self, min: [i32; 2], max: [i32; 2], res: &mut Vec<usize>) {
+        res.extend(self.animated_quads.iter().map(|q| *q as usize));
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !intersects(node.min, node.max, min, max) {
+                continue;
+            }
+            if node.count > 0 {
+                let first = node.first as usize;
+                res.extend(
+                    self.quad_indices[first..first + node.count as usize]
+                        .iter()
+                        .map(|q| *q as usize)
+                        .filter(|q| {
+                            let (quad_min, quad_max) = self.bounds[*q];
+                            intersects(quad_min, quad_max, min, max)
+                        }),
+                );
+            } else {
+                stack.push(node_index + 1);
+                stack.push(node.first as usize);
+            }
+        }
+    }
+
+    /**
+     * Like `query_rect`, but only for a single point, e.g. for selecting quads
+     */
+    pub fn query_point(&self, point: [i32; 2], res: &mut Vec<usize>) {
+        self.query_rect(point, point, res)
+    }
+}
+
+/**
+ * The spatial index of a layer, used by the client to cull what is outside of the screen.
+ * Tile layers don't need one, their buffered visuals are already split by rows.
+ */
+#[derive(Debug, Clone, Default)]
+pub enum MapLayerSpatialIndex {
+    Quads(QuadBvh),
+    // tile and sound layers and layers that were not read
+    #[default]
+    None,
+}
+
+impl MapLayerSpatialIndex {
+    pub fn new(layer: &MapLayer) -> Self {
+        match layer {
+            MapLayer::Quads(MapLayerQuad(_, quads)) if !quads.is_empty() => {
+                Self::Quads(QuadBvh::new(quads))
+            }
+            _ => Self::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapdef::CQuad;
+
+    use super::QuadBvh;
+
+    fn quad(x: i32, y: i32, size: i32) -> CQuad {
+        let mut quad = CQuad::default();
+        quad.pos_env = -1;
+        for (i, point) in quad.points[0..4].iter_mut().enumerate() {
+            point.x = x + (i as i32 % 2) * size;
+            point.y = y + (i as i32 / 2) * size;
+        }
+        quad
+    }
+
+    #[test]
+    fn query_rect() {
+        let mut quads: Vec<CQuad> = (0..100)
+            .map(|i| quad((i % 10) * 1024, (i / 10) * 1024, 512))
+            .collect();
+        // moved by an envelope, so it is always part of the result
+        quads[55].pos_env = 0;
+        let bvh = QuadBvh::new(&quads);
+
+        let (min, max) = ([1000, 2000], [3000, 3100]);
+        let mut res = Vec::new();
+        bvh.query_rect(min, max, &mut res);
+        res.sort_unstable();
+
+        let expected: Vec<usize> = (0..quads.len())
+            .filter(|&i| {
+                let (x, y) = ((i as i32 % 10) * 1024, (i as i32 / 10) * 1024);
+                i == 55 || (x + 512 >= min[0] && x <= max[0] && y + 512 >= min[1] && y <= max[1])
+            })
+            .collect();
+        assert_eq!(res, expected);
+
+        res.clear();
+        bvh.query_point([2048 + 100, 100], &mut res);
+        res.sort_unstable();
+        assert_eq!(res, vec![2, 55]);
+    }
+}
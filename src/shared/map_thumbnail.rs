@@ -0,0 +1,218 @@
+use crate::{
+    datafile::CDatafileWrapper,
+    mapdef::{CTile, MapLayer, MapLayerTile, TileNum, TilesLayerFlag},
+};
+
+/**
+ * A small RGBA8 image of a map, e.g. for the server browser or the demo list.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct MapThumbnail {
+    pub width: u32,
+    pub height: u32,
+    // RGBA8, row by row
+    pub data: Vec<u8>,
+}
+
+pub struct MapThumbnailOptions {
+    // the thumbnail keeps the aspect ratio of the game layer
+    // and is at most this large
+    pub max_width: u32,
+    pub max_height: u32,
+    // also draws the design tile layers of the game group below the physics
+    pub draw_design_layers: bool,
+}
+
+impl Default for MapThumbnailOptions {
+    fn default() -> Self {
+        Self {
+            max_width: 256,
+            max_height: 256,
+            draw_design_layers: true,
+        }
+    }
+}
+
+// design tiles have no texture here, so they use one color tinted by the layer color
+const DESIGN_TILE_COLOR: [f32; 4] = [0.55, 0.5, 0.45, 0.6];
+
+fn physics_tile_color(index: u8) -> Option<[f32; 4]> {
+    const SOLID: u8 = TileNum::TILE_SOLID as u8;
+    const DEATH: u8 = TileNum::TILE_DEATH as u8;
+    const NOHOOK: u8 = TileNum::TILE_NOHOOK as u8;
+    const FREEZE: u8 = TileNum::TILE_FREEZE as u8;
+    const DFREEZE: u8 = TileNum::TILE_DFREEZE as u8;
+    const LFREEZE: u8 = TileNum::TILE_LFREEZE as u8;
+    const UNFREEZE: u8 = TileNum::TILE_UNFREEZE as u8;
+    const DUNFREEZE: u8 = TileNum::TILE_DUNFREEZE as u8;
+    const LUNFREEZE: u8 = TileNum::TILE_LUNFREEZE as u8;
+    const START: u8 = TileNum::TILE_START as u8;
+    const FINISH: u8 = TileNum::TILE_FINISH as u8;
+    match index {
+        0 => None,
+        SOLID => Some([0.35, 0.3, 0.25, 1.0]),
+        NOHOOK => Some([0.6, 0.6, 0.65, 1.0]),
+        DEATH => Some([0.8, 0.1, 0.1, 1.0]),
+        FREEZE | DFREEZE | LFREEZE => Some([0.1, 0.1, 0.3, 0.8]),
+        UNFREEZE | DUNFREEZE | LUNFREEZE => Some([0.6, 0.8, 1.0, 0.6]),
+        START => Some([0.2, 0.8, 0.2, 1.0]),
+        FINISH => Some([0.9, 0.8, 0.1, 1.0]),
+        // entities and other special tiles are too small to be visible anyway
+        _ => None,
+    }
+}
+
+/**
+ * Blends the tiles of one layer into the thumbnail.
+ * Every pixel covers `scale` x `scale` tiles, the colors of the covered tiles are averaged.
+ */
+fn blend_layer(
+    pixels: &mut [[f32; 4]],
+    thumbnail_width: usize,
+    scale: usize,
+    layer_width: usize,
+    layer_height: usize,
+    tiles: &[CTile],
+    tile_color: impl Fn(u8) -> Option<[f32; 4]>,
+) {
+    if tiles.len() != layer_width * layer_height {
+        return;
+    }
+    pixels.iter_mut().enumerate().for_each(|(i, pixel)| {
+        let start_x = (i % thumbnail_width) * scale;
+        let start_y = (i / thumbnail_width) * scale;
+        let mut sum = [0.0f32; 4];
+        let mut count = 0;
+        for y in start_y..(start_y + scale).min(layer_height) {
+            for x in start_x..(start_x + scale).min(layer_width) {
+                count += 1;
+                if let Some(color) = tile_color(tiles[y * layer_width + x].index) {
+                    // premultiplied, so empty tiles make the pixel more transparent
+                    sum[0] += color[0] * color[3];
+                    sum[1] += color[1] * color[3];
+                    sum[2] += color[2] * color[3];
+                    sum[3] += color[3];
+                }
+            }
+        }
+        if count == 0 || sum[3] == 0.0 {
+            return;
+        }
+        let alpha = sum[3] / count as f32;
+        for c in 0..3 {
+            pixel[c] = sum[c] / count as f32 + pixel[c] * (1.0 - alpha);
+        }
+        pixel[3] = alpha + pixel[3] * (1.0 - alpha);
+    });
+}
+
+impl CDatafileWrapper {
+    /**
+     * Rasterizes the game group into a small image without any graphics backend.
+     * The physics layers (game and front) are drawn over the design tile layers
+     * of the game group. Quads and other groups are ignored.
+     * The layers must be read and initialized (see `read_map_layers` and `InitLayers`),
+     * design layers that were not read are skipped.
+     */
+    pub fn thumbnail(&self, options: &MapThumbnailOptions) -> MapThumbnail {
+        if self.layers.is_empty() || options.max_width == 0 || options.max_height == 0 {
+            return MapThumbnail::default();
+        }
+        let game_layer = self.get_game_layer();
+        let layer_width = game_layer.0.width.max(0) as usize;
+        let layer_height = game_layer.0.height.max(0) as usize;
+        if layer_width == 0 || layer_height == 0 {
+            return MapThumbnail::default();
+        }
+
+        let scale = layer_width
+            .div_ceil(options.max_width as usize)
+            .max(layer_height.div_ceil(options.max_height as usize))
+            .max(1);
+        let width = layer_width.div_ceil(scale);
+        let height = layer_height.div_ceil(scale);
+        let mut pixels: Vec<[f32; 4]> = vec![[0.0; 4]; width * height];
+
+        let group = self.get_game_group();
+        let start = group.start_layer.max(0) as usize;
+        let end = (start + group.num_layers.max(0) as usize).min(self.layers.len());
+        let physics_flags = TilesLayerFlag::TILESLAYERFLAG_GAME as i32
+            | TilesLayerFlag::TILESLAYERFLAG_FRONT as i32
+            | TilesLayerFlag::TILESLAYERFLAG_TELE as i32
+            | TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32
+            | TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32
+            | TilesLayerFlag::TILESLAYERFLAG_TUNE as i32;
+
+        if options.draw_design_layers {
+            self.layers[start.min(end)..end]
+                .iter()
+                .for_each(|layer| match layer {
+                    MapLayer::Tile(MapLayerTile(tile_layer, _, tiles))
+                        if (tile_layer.flags & physics_flags) == 0 =>
+                    {
+                        let tint = &tile_layer.color;
+                        let color = [
+                            DESIGN_TILE_COLOR[0] * tint.x as f32 / 255.0,
+                            DESIGN_TILE_COLOR[1] * tint.y as f32 / 255.0,
+                            DESIGN_TILE_COLOR[2] * tint.z as f32 / 255.0,
+                            DESIGN_TILE_COLOR[3] * tint.w as f32 / 255.0,
+                        ];
+                        blend_layer(
+                            &mut pixels,
+                            width,
+                            scale,
+                            tile_layer.width.max(0) as usize,
+                            tile_layer.height.max(0) as usize,
+                            tiles,
+                            |index| if index != 0 { Some(color) } else { None },
+                        );
+                    }
+                    _ => {}
+                });
+        }
+
+        let game_flags = TilesLayerFlag::TILESLAYERFLAG_GAME as i32
+            | TilesLayerFlag::TILESLAYERFLAG_FRONT as i32;
+        self.layers.iter().for_each(|layer| match layer {
+            MapLayer::Tile(MapLayerTile(tile_layer, _, tiles))
+                if (tile_layer.flags & game_flags) != 0 =>
+            {
+                blend_layer(
+                    &mut pixels,
+                    width,
+                    scale,
+                    tile_layer.width.max(0) as usize,
+                    tile_layer.height.max(0) as usize,
+                    tiles,
+                    physics_tile_color,
+                );
+            }
+            _ => {}
+        });
+
+        MapThumbnail {
+            width: width as u32,
+            height: height as u32,
+            data: pixels
+                .iter()
+                .flat_map(|pixel| {
+                    // the pixels are premultiplied, the image is not
+                    let alpha = pixel[3];
+                    let unpremultiply = |c: f32| {
+                        if alpha > 0.0 {
+                            ((c / alpha).clamp(0.0, 1.0) * 255.0) as u8
+                        } else {
+                            0
+                        }
+                    };
+                    [
+                        unpremultiply(pixel[0]),
+                        unpremultiply(pixel[1]),
+                        unpremultiply(pixel[2]),
+                        (alpha.clamp(0.0, 1.0) * 255.0) as u8,
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,879 @@
+use std::{io::Read, mem::size_of, sync::Arc};
+
+use base::system::SystemTimeInterface;
+use flate2::read::ZlibDecoder;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use thiserror::Error;
+
+use crate::{
+    datafile::{CDatafileWrapper, MapFileOpenOptions},
+    mapdef::{
+        read_i32_le, read_u32_le, CMapItemEnvelope, CMapItemGroup, CMapItemImage,
+        CMapItemInfoSettings, CMapItemLayer, CMapItemLayerQuads, CMapItemLayerSounds,
+        CMapItemLayerSoundsVer, CMapItemLayerTilemap, CMapItemSound, CMapItemVersion, CQuad,
+        CSoundSource, CSoundSourceDeprecated, CSpeedupTile, CSwitchTile, CTeleTile, CTile,
+        CTuneTile, MapItemTypes, MapLayerTypes, TilesLayerFlag,
+    },
+};
+
+// id + 8 * u32
+const HEADER_SIZE: usize = 4 + 8 * size_of::<u32>();
+// type_and_id + size
+const ITEM_HEADER_SIZE: usize = 2 * size_of::<i32>();
+
+/**
+ * Limits for maps from untrusted sources, e.g. maps that are downloaded from a server
+ */
+#[derive(Debug, Clone)]
+pub struct MapReadLimits {
+    pub max_file_size: usize,
+    pub max_items: usize,
+    pub max_groups: usize,
+    pub max_layers: usize,
+    // width and height of a tile layer
+    pub max_layer_size: usize,
+    // width * height of a tile layer
+    pub max_layer_tiles: usize,
+    pub max_quads: usize,
+    // uncompressed size of a single data
+    pub max_data_size: usize,
+    // uncompressed size of all datas
+    pub max_total_data_size: usize,
+}
+
+impl Default for MapReadLimits {
+    fn default() -> Self {
+        Self {
+            max_file_size: 64 * 1024 * 1024,
+            max_items: 64 * 1024,
+            max_groups: 1024,
+            max_layers: 1024,
+            max_layer_size: 16 * 1024,
+            max_layer_tiles: 16 * 1024 * 1024,
+            max_quads: 100_000,
+            // a full tile layer of the largest tiles (speedup) still fits
+            max_data_size: 128 * 1024 * 1024,
+            // all datas are in memory at once after the validation,
+            // large maps (like data/_cb2.map) need almost 500 MiB
+            max_total_data_size: 512 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MapReadError {
+    #[error("the map file is truncated")]
+    Truncated,
+    #[error("the map file has a wrong signature")]
+    WrongSignature,
+    #[error("the map file version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("the map exceeds a limit: {what} is {value}, but at most {max} is allowed")]
+    LimitExceeded {
+        what: &'static str,
+        value: u64,
+        max: u64,
+    },
+    #[error("the map item {index} of type {item_type} is invalid: {reason}")]
+    InvalidItem {
+        item_type: i32,
+        index: usize,
+        reason: String,
+    },
+    #[error("the map data {index} is invalid: {reason}")]
+    InvalidData { index: usize, reason: String },
+    #[error("the map could not be opened: {0}")]
+    Open(anyhow::Error),
+}
+
+fn check_limit(what: &'static str, value: u64, max: usize) -> Result<(), MapReadError> {
+    if value > max as u64 {
+        return Err(MapReadError::LimitExceeded {
+            what,
+            value,
+            max: max as u64,
+        });
+    }
+    Ok(())
+}
+
+struct UntrustedDatafile<'a> {
+    // (type, start, num)
+    item_types: Vec<(i32, usize, usize)>,
+    // the item data without the item header
+    items: Vec<&'a [u8]>,
+    compressed_datas: Vec<&'a [u8]>,
+    data_sizes: Vec<usize>,
+}
+
+impl<'a> UntrustedDatafile<'a> {
+    fn parse(file: &'a [u8], limits: &MapReadLimits) -> Result<Self, MapReadError> {
+        check_limit("the file size", file.len() as u64, limits.max_file_size)?;
+        if file.len() < HEADER_SIZE {
+            return Err(MapReadError::Truncated);
+        }
+        if &file[0..4] != b"DATA" && &file[0..4] != b"ATAD" {
+            return Err(MapReadError::WrongSignature);
+        }
+        let header_field = |index: usize| read_u32_le(&file[4 + index * 4..]);
+        let version = header_field(0);
+        if version != 4 {
+            return Err(MapReadError::UnsupportedVersion(version));
+        }
+        let num_item_types = header_field(3) as usize;
+        let num_items = header_field(4) as usize;
+        let num_raw_data = header_field(5) as usize;
+        let item_size = header_field(6) as usize;
+        let data_size = header_field(7) as usize;
+        check_limit("the item count", num_items as u64, limits.max_items)?;
+        check_limit(
+            "the item type count",
+            num_item_types as u64,
+            limits.max_items,
+        )?;
+        check_limit("the data count", num_raw_data as u64, limits.max_items)?;
+
+        // all sizes are at most u32::MAX * 4, so they don't overflow in u64
+        let items_offset = HEADER_SIZE as u64
+            + num_item_types as u64 * 12
+            + (num_items as u64 + 2 * num_raw_data as u64) * 4;
+        let data_offset = items_offset + item_size as u64;
+        if data_offset + data_size as u64 > file.len() as u64 {
+            return Err(MapReadError::Truncated);
+        }
+        let read_table =
+            |offset: u64, index: usize| read_i32_le(&file[offset as usize + index * 4..]);
+        let items_start = &file[items_offset as usize..data_offset as usize];
+        let data_start = &file[data_offset as usize..data_offset as usize + data_size];
+
+        let mut item_types: Vec<(i32, usize, usize)> = Vec::with_capacity(num_item_types);
+        for i in 0..num_item_types {
+            let item_type = read_table(HEADER_SIZE as u64, i * 3);
+            let start = read_table(HEADER_SIZE as u64, i * 3 + 1);
+            let num = read_table(HEADER_SIZE as u64, i * 3 + 2);
+            if start < 0 || num < 0 || start as usize + num as usize > num_items {
+                return Err(MapReadError::InvalidItem {
+                    item_type,
+                    index: start.max(0) as usize,
+                    reason: "the item type points outside of the items".to_string(),
+                });
+            }
+            item_types.push((item_type, start as usize, num as usize));
+        }
+
+        let offsets_start = HEADER_SIZE as u64 + num_item_types as u64 * 12;
+        let mut items: Vec<&[u8]> = Vec::with_capacity(num_items);
+        for i in 0..num_items {
+            let offset = read_table(offsets_start, i);
+            let end = if i + 1 < num_items {
+                read_table(offsets_start, i + 1)
+            } else {
+                item_size as i32
+            };
+            // the offsets come from the file, so compare them as usize to not overflow
+            if offset < 0
+                || end < 0
+                || offset as usize + ITEM_HEADER_SIZE > end as usize
+                || end as usize > item_size
+            {
+                return Err(MapReadError::InvalidItem {
+                    item_type: -1,
+                    index: i,
+                    reason: "the item offset is invalid".to_string(),
+                });
+            }
+            items.push(&items_start[offset as usize + ITEM_HEADER_SIZE..end as usize]);
+        }
+
+        let data_offsets_start = offsets_start + num_items as u64 * 4;
+        let data_sizes_start = data_offsets_start + num_raw_data as u64 * 4;
+        let mut compressed_datas: Vec<&[u8]> = Vec::with_capacity(num_raw_data);
+        let mut data_sizes: Vec<usize> = Vec::with_capacity(num_raw_data);
+        let mut total_data_size: u64 = 0;
+        for i in 0..num_raw_data {
+            let offset = read_table(data_offsets_start, i);
+            let end = if i + 1 < num_raw_data {
+                read_table(data_offsets_start, i + 1)
+            } else {
+                data_size as i32
+            };
+            let uncompressed_size = read_table(data_sizes_start, i);
+            if offset < 0 || end < offset || end as usize > data_size || uncompressed_size < 0 {
+                return Err(MapReadError::InvalidData {
+                    index: i,
+                    reason: "the data offset or size is invalid".to_string(),
+                });
+            }
+            check_limit(
+                "the uncompressed data size",
+                uncompressed_size as u64,
+                limits.max_data_size,
+            )?;
+            total_data_size += uncompressed_size as u64;
+            compressed_datas.push(&data_start[offset as usize..end as usize]);
+            data_sizes.push(uncompressed_size as usize);
+        }
+        check_limit(
+            "the uncompressed size of all datas",
+            total_data_size,
+            limits.max_total_data_size,
+        )?;
+
+        Ok(Self {
+            item_types,
+            items,
+            compressed_datas,
+            data_sizes,
+        })
+    }
+
+    /**
+     * The items of the type, like the reader finds them
+     */
+    fn items_of_type(&self, item_type: i32) -> (usize, &[&'a [u8]]) {
+        match self.item_types.iter().find(|(t, _, _)| *t == item_type) {
+            Some((_, start, num)) => (*start, &self.items[*start..*start + *num]),
+            None => (0, &[]),
+        }
+    }
+
+    /**
+     * Checks that every item of the type is at least as large as the struct the reader reads
+     */
+    fn check_item_sizes(
+        &self,
+        item_type: MapItemTypes,
+        min_size: usize,
+    ) -> Result<&[&'a [u8]], MapReadError> {
+        let item_type = item_type as i32;
+        let (start, items) = self.items_of_type(item_type);
+        match items.iter().position(|item| item.len() < min_size) {
+            Some(i) => Err(MapReadError::InvalidItem {
+                item_type,
+                index: start + i,
+                reason: format!("the item is smaller than {} bytes", min_size),
+            }),
+            None => Ok(items),
+        }
+    }
+
+    /**
+     * The data must exist and have at least `min_size` bytes uncompressed
+     */
+    fn check_data(&self, index: i32, min_size: usize) -> Result<(), MapReadError> {
+        if index < 0 || index as usize >= self.data_sizes.len() {
+            return Err(MapReadError::InvalidData {
+                index: index.max(0) as usize,
+                reason: "the data does not exist".to_string(),
+            });
+        }
+        if self.data_sizes[index as usize] < min_size {
+            return Err(MapReadError::InvalidData {
+                index: index as usize,
+                reason: format!("the data is smaller than {} bytes", min_size),
+            });
+        }
+        Ok(())
+    }
+
+    fn uncompress(&self, index: usize) -> Result<Vec<u8>, MapReadError> {
+        let size = self.data_sizes[index];
+        let mut data: Vec<u8> = Vec::with_capacity(size);
+        // never read more than the data claims to have, this protects against zip bombs
+        ZlibDecoder::new(self.compressed_datas[index])
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|err| MapReadError::InvalidData {
+                index,
+                reason: err.to_string(),
+            })?;
+        if data.len() != size {
+            return Err(MapReadError::InvalidData {
+                index,
+                reason: format!("the uncompressed size is not {} bytes", size),
+            });
+        }
+        Ok(data)
+    }
+}
+
+fn invalid_item(item_type: MapItemTypes, index: usize, reason: &str) -> MapReadError {
+    MapReadError::InvalidItem {
+        item_type: item_type as i32,
+        index,
+        reason: reason.to_string(),
+    }
+}
+
+/**
+ * Checks everything the reader (`CDatafileWrapper::Open` and `read_map_layers`) relies on,
+ * so reading the map afterwards can not panic or allocate more than the limits allow.
+ * All datas are decompressed once, in parallel on the thread pool,
+ * and returned by their data index, so the reader doesn't have to decompress them again.
+ */
+pub fn validate_untrusted_map(
+    file: &[u8],
+    limits: &MapReadLimits,
+    thread_pool: &rayon::ThreadPool,
+) -> Result<Vec<Vec<u8>>, MapReadError> {
+    let datafile = UntrustedDatafile::parse(file, limits)?;
+
+    datafile.check_item_sizes(
+        MapItemTypes::MAPITEMTYPE_VERSION,
+        size_of::<CMapItemVersion>(),
+    )?;
+    for item in datafile.check_item_sizes(
+        MapItemTypes::MAPITEMTYPE_INFO,
+        size_of::<CMapItemInfoSettings>(),
+    )? {
+        let settings = CMapItemInfoSettings::read_from_slice(item).settings();
+        if settings >= 0 {
+            datafile.check_data(settings, 0)?;
+        }
+    }
+    datafile.check_item_sizes(
+        MapItemTypes::MAPITEMTYPE_ENVELOPE,
+        size_of::<CMapItemEnvelope>(),
+    )?;
+    for item in
+        datafile.check_item_sizes(MapItemTypes::MAPITEMTYPE_SOUND, size_of::<CMapItemSound>())?
+    {
+        // name and data are at the same position in every version
+        let sound_name = read_i32_le(&item[8..]);
+        let sound_data = read_i32_le(&item[12..]);
+        datafile.check_data(sound_name, 0)?;
+        datafile.check_data(sound_data, 0)?;
+    }
+
+    // the image names are read when the map is opened
+    let (images_start, _) = datafile.items_of_type(MapItemTypes::MAPITEMTYPE_IMAGE as i32);
+    let images =
+        datafile.check_item_sizes(MapItemTypes::MAPITEMTYPE_IMAGE, size_of::<CMapItemImage>())?;
+    let mut image_names: Vec<(usize, i32)> = Vec::new();
+    for (i, item) in images.iter().enumerate() {
+        let image = CMapItemImage::read_from_slice(item);
+        datafile.check_data(image.image_name, 1)?;
+        image_names.push((images_start + i, image.image_name));
+        if image.external == 0 {
+            if image.width <= 0
+                || image.height <= 0
+                || image.width as usize > limits.max_layer_size
+                || image.height as usize > limits.max_layer_size
+            {
+                return Err(invalid_item(
+                    MapItemTypes::MAPITEMTYPE_IMAGE,
+                    images_start + i,
+                    "the image size is invalid",
+                ));
+            }
+            datafile.check_data(
+                image.image_data,
+                image.width as usize * image.height as usize * 4,
+            )?;
+        }
+    }
+
+    let (layers_start, _) = datafile.items_of_type(MapItemTypes::MAPITEMTYPE_LAYER as i32);
+    let layers =
+        datafile.check_item_sizes(MapItemTypes::MAPITEMTYPE_LAYER, size_of::<CMapItemLayer>())?;
+    check_limit("the layer count", layers.len() as u64, limits.max_layers)?;
+    for (i, item) in layers.iter().enumerate() {
+        let index = layers_start + i;
+        let layer_error =
+            |reason: &str| invalid_item(MapItemTypes::MAPITEMTYPE_LAYER, index, reason);
+        let layer = CMapItemLayer::read_from_slice(item);
+        if layer.item_layer == MapLayerTypes::LAYERTYPE_TILES as i32 {
+            if item.len() < size_of::<CMapItemLayerTilemap>() {
+                return Err(layer_error("the tile layer item is too small"));
+            }
+            let tile_layer = CMapItemLayerTilemap::read_from_slice(item);
+            if tile_layer.width <= 0 || tile_layer.height <= 0 {
+                return Err(layer_error("the tile layer size is invalid"));
+            }
+            check_limit(
+                "the tile layer width",
+                tile_layer.width as u64,
+                limits.max_layer_size,
+            )?;
+            check_limit(
+                "the tile layer height",
+                tile_layer.height as u64,
+                limits.max_layer_size,
+            )?;
+            let tile_count = tile_layer.width as usize * tile_layer.height as usize;
+            check_limit(
+                "the tile count of a layer",
+                tile_count as u64,
+                limits.max_layer_tiles,
+            )?;
+            datafile.check_data(tile_layer.data, tile_count * size_of::<CTile>())?;
+            let flags = tile_layer.flags;
+            // the reader only reads one of them, in this order
+            if (flags & TilesLayerFlag::TILESLAYERFLAG_TELE as i32) != 0 {
+                datafile.check_data(tile_layer.tele, tile_count * size_of::<CTeleTile>())?;
+            } else if (flags & TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32) != 0 {
+                datafile.check_data(tile_layer.speedup, tile_count * size_of::<CSpeedupTile>())?;
+            } else if (flags & TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32) != 0 {
+                datafile.check_data(tile_layer.switch, tile_count * size_of::<CSwitchTile>())?;
+            } else if (flags & TilesLayerFlag::TILESLAYERFLAG_TUNE as i32) != 0 {
+                datafile.check_data(tile_layer.tune, tile_count * size_of::<CTuneTile>())?;
+            }
+        } else if layer.item_layer == MapLayerTypes::LAYERTYPE_QUADS as i32 {
+            if item.len() < size_of::<CMapItemLayerQuads>() {
+                return Err(layer_error("the quad layer item is too small"));
+            }
+            let quad_layer = CMapItemLayerQuads::read_from_slice(item);
+            if quad_layer.num_quads < 0 {
+                return Err(layer_error("the quad count is negative"));
+            }
+            check_limit(
+                "the quad count of a layer",
+                quad_layer.num_quads as u64,
+                limits.max_quads,
+            )?;
+            if quad_layer.data != -1 {
+                datafile.check_data(
+                    quad_layer.data,
+                    quad_layer.num_quads as usize * size_of::<CQuad>(),
+                )?;
+            }
+        } else if layer.item_layer == MapLayerTypes::LAYERTYPE_SOUNDS as i32 {
+            if item.len() < size_of::<CMapItemLayerSounds>() {
+                return Err(layer_error("the sound layer item is too small"));
+            }
+            let sound_layer = CMapItemLayerSounds::read_from_slice(item);
+            if sound_layer.data != -1 && sound_layer.num_sources > 0 {
+                let source_size =
+                    if sound_layer.version < CMapItemLayerSoundsVer::CURRENT_VERSION as i32 {
+                        size_of::<CSoundSourceDeprecated>()
+                    } else {
+                        size_of::<CSoundSource>()
+                    };
+                datafile.check_data(sound_layer.data, 0)?;
+                check_limit(
+                    "the sound source count of a layer",
+                    sound_layer.num_sources as u64,
+                    datafile.data_sizes[sound_layer.data as usize] / source_size,
+                )?;
+            }
+        }
+    }
+
+    let (groups_start, _) = datafile.items_of_type(MapItemTypes::MAPITEMTYPE_GROUP as i32);
+    let groups =
+        datafile.check_item_sizes(MapItemTypes::MAPITEMTYPE_GROUP, size_of::<CMapItemGroup>())?;
+    check_limit("the group count", groups.len() as u64, limits.max_groups)?;
+    for (i, item) in groups.iter().enumerate() {
+        let group = CMapItemGroup::read_from_slice(item);
+        if group.start_layer < 0
+            || group.num_layers < 0
+            || group.start_layer as usize + group.num_layers as usize > layers.len()
+        {
+            return Err(invalid_item(
+                MapItemTypes::MAPITEMTYPE_GROUP,
+                groups_start + i,
+                "the layers of the group do not exist",
+            ));
+        }
+    }
+
+    // decompress everything once, this catches broken zlib streams
+    // and datas that are smaller or larger than they claim to be
+    thread_pool.install(|| {
+        (0..datafile.data_sizes.len())
+            .into_par_iter()
+            .map(|index| {
+                let data = datafile.uncompress(index)?;
+                match image_names.iter().find(|(_, name)| *name as usize == index) {
+                    Some((item_index, _)) => {
+                        match std::ffi::CStr::from_bytes_with_nul(&data)
+                            .ok()
+                            .and_then(|name| name.to_str().ok())
+                        {
+                            Some(_) => Ok(data),
+                            None => Err(invalid_item(
+                                MapItemTypes::MAPITEMTYPE_IMAGE,
+                                *item_index,
+                                "the image name is not a valid string",
+                            )),
+                        }
+                    }
+                    None => Ok(data),
+                }
+            })
+            .collect()
+    })
+}
+
+impl CDatafileWrapper {
+    /**
+     * Like `Open`, but for maps from untrusted sources:
+     * the map is checked against the limits first (see `validate_untrusted_map`),
+     * broken maps result in an error instead of a panic.
+     * The datas that were decompressed by the validation are used by the reader,
+     * `release_uncompressed_datas` frees them after the map was read.
+     */
+    pub fn read_untrusted<'a>(
+        &mut self,
+        data_param: &'a Vec<u8>,
+        file_name: &str,
+        thread_pool: &rayon::ThreadPool,
+        options: &MapFileOpenOptions,
+        limits: &MapReadLimits,
+        sys: &Arc<impl SystemTimeInterface + Send + Sync + 'static>,
+    ) -> Result<&'a [u8], MapReadError> {
+        let datas = validate_untrusted_map(data_param, limits, thread_pool)?;
+        let data_start = self
+            .Open(data_param, file_name, thread_pool, options, sys)
+            .map_err(MapReadError::Open)?;
+        self.use_uncompressed_datas(datas);
+        Ok(data_start)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{panic::AssertUnwindSafe, sync::Arc, time::Instant};
+
+    use base::system::SystemTime;
+
+    use crate::{
+        datafile::{CDatafileWrapper, MapFileImageReadOptions, MapFileLayersReadOptions},
+        mapdef::{read_u32_le, MapLayerTile},
+    };
+
+    use super::{
+        validate_untrusted_map, MapReadError, MapReadLimits, UntrustedDatafile, HEADER_SIZE,
+    };
+
+    fn read_test_map(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/data/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    fn thread_pool() -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap()
+    }
+
+    /**
+     * The offset of the uncompressed size of the data in the file
+     */
+    fn data_size_offset(file: &[u8], index: usize) -> usize {
+        let header_field = |index: usize| read_u32_le(&file[4 + index * 4..]) as usize;
+        let (num_item_types, num_items, num_raw_data) =
+            (header_field(3), header_field(4), header_field(5));
+        HEADER_SIZE + num_item_types * 12 + (num_items + num_raw_data + index) * 4
+    }
+
+    /**
+     * Reads the map like the client does (see `ClientMapFile`),
+     * this must never panic, no matter what the file contains
+     */
+    fn read_like_client(
+        file: &Vec<u8>,
+        limits: &MapReadLimits,
+        thread_pool: &rayon::ThreadPool,
+    ) -> Result<CDatafileWrapper, MapReadError> {
+        let sys = Arc::new(SystemTime::new());
+        let mut map = CDatafileWrapper::new();
+        let data_start =
+            map.read_untrusted(file, "test", thread_pool, &Default::default(), limits, &sys)?;
+        CDatafileWrapper::read_map_layers(
+            &map.data_file,
+            &mut map.layers,
+            data_start,
+            &sys,
+            &MapFileLayersReadOptions::default(),
+        );
+        CDatafileWrapper::read_image_data(
+            &map.data_file,
+            &map.images,
+            data_start,
+            &sys,
+            &MapFileImageReadOptions::default(),
+        );
+        map.InitLayers(thread_pool);
+        Ok(map)
+    }
+
+    fn game_tiles(map: &CDatafileWrapper) -> Vec<(u8, u8)> {
+        let MapLayerTile(_, _, tiles) = map.get_game_layer();
+        tiles.iter().map(|tile| (tile.index, tile.flags)).collect()
+    }
+
+    #[test]
+    fn huge_item_offset() {
+        let mut file = b"DATA".to_vec();
+        // version, size, swaplen, item types, items, datas, item size, data size
+        for field in [4u32, 0, 0, 0, 1, 0, 8, 0] {
+            file.extend_from_slice(&field.to_le_bytes());
+        }
+        // the offset of the only item
+        file.extend_from_slice(&i32::MAX.to_le_bytes());
+        file.extend_from_slice(&[0; 8]);
+
+        assert!(matches!(
+            UntrustedDatafile::parse(&file, &MapReadLimits::default()),
+            Err(MapReadError::InvalidItem { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn valid_map() {
+        let file = read_test_map("cb2.map");
+        let thread_pool = thread_pool();
+        let untrusted = read_like_client(&file, &MapReadLimits::default(), &thread_pool).unwrap();
+
+        // the validated datas are used by the reader, the result must not differ
+        let sys = Arc::new(SystemTime::new());
+        let mut trusted = CDatafileWrapper::new();
+        let data_start = trusted
+            .Open(&file, "test", &thread_pool, &Default::default(), &sys)
+            .unwrap();
+        CDatafileWrapper::read_map_layers(
+            &trusted.data_file,
+            &mut trusted.layers,
+            data_start,
+            &sys,
+            &MapFileLayersReadOptions::default(),
+        );
+        trusted.InitLayers(&thread_pool);
+        assert!(!game_tiles(&trusted).is_empty());
+        assert!(game_tiles(&untrusted) == game_tiles(&trusted));
+        assert_eq!(untrusted.physics_hash(), trusted.physics_hash());
+    }
+
+    #[test]
+    fn broken_header() {
+        let file = read_test_map("cb2.map");
+        let limits = MapReadLimits::default();
+
+        let mut wrong_signature = file.clone();
+        wrong_signature[0] = b'X';
+        assert!(matches!(
+            UntrustedDatafile::parse(&wrong_signature, &limits),
+            Err(MapReadError::WrongSignature)
+        ));
+
+        let mut wrong_version = file.clone();
+        wrong_version[4] = 3;
+        assert!(matches!(
+            UntrustedDatafile::parse(&wrong_version, &limits),
+            Err(MapReadError::UnsupportedVersion(3))
+        ));
+
+        assert!(matches!(
+            UntrustedDatafile::parse(&file[..file.len() - 1], &limits),
+            Err(MapReadError::Truncated)
+        ));
+        assert!(matches!(
+            UntrustedDatafile::parse(&file[..HEADER_SIZE - 1], &limits),
+            Err(MapReadError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn wrong_data_size() {
+        let file = read_test_map("cb2.map");
+        let thread_pool = thread_pool();
+        let offset = data_size_offset(&file, 0);
+        let size = i32::from_le_bytes(file[offset..offset + 4].try_into().unwrap());
+
+        // a zip bomb claims to be smaller than it is
+        let mut smaller = file.clone();
+        smaller[offset..offset + 4].copy_from_slice(&(size - 1).to_le_bytes());
+        assert!(matches!(
+            validate_untrusted_map(&smaller, &MapReadLimits::default(), &thread_pool),
+            Err(MapReadError::InvalidData { index: 0, .. })
+        ));
+
+        let mut larger = file.clone();
+        larger[offset..offset + 4].copy_from_slice(&(size + 1).to_le_bytes());
+        assert!(matches!(
+            validate_untrusted_map(&larger, &MapReadLimits::default(), &thread_pool),
+            Err(MapReadError::InvalidData { index: 0, .. })
+        ));
+
+        let mut negative = file.clone();
+        negative[offset..offset + 4].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(matches!(
+            validate_untrusted_map(&negative, &MapReadLimits::default(), &thread_pool),
+            Err(MapReadError::InvalidData { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        let file = read_test_map("cb2.map");
+        let thread_pool = thread_pool();
+        let exceeds = |limits: MapReadLimits, limit: &str| match validate_untrusted_map(
+            &file,
+            &limits,
+            &thread_pool,
+        ) {
+            Err(MapReadError::LimitExceeded { what, .. }) => assert_eq!(what, limit),
+            _ => panic!("the limit {} was not checked", limit),
+        };
+        exceeds(
+            MapReadLimits {
+                max_file_size: file.len() - 1,
+                ..Default::default()
+            },
+            "the file size",
+        );
+        exceeds(
+            MapReadLimits {
+                max_layers: 1,
+                ..Default::default()
+            },
+            "the layer count",
+        );
+        exceeds(
+            MapReadLimits {
+                max_layer_tiles: 16,
+                ..Default::default()
+            },
+            "the tile count of a layer",
+        );
+        exceeds(
+            MapReadLimits {
+                max_total_data_size: 1024,
+                ..Default::default()
+            },
+            "the uncompressed size of all datas",
+        );
+    }
+
+    /**
+     * A small mutation based fuzzer: every iteration changes a few bytes of a real map,
+     * mostly in the header and the items, because the datas are checked by zlib anyway.
+     * Reading the mutated map must result in a map or an error, but never in a panic.
+     * Run longer with e.g. `MAP_FUZZ_ITERATIONS=100000 cargo test --release fuzz_`.
+     */
+    #[test]
+    fn fuzz_mutated_maps() {
+        let file = read_test_map("cb2.map");
+        let thread_pool = thread_pool();
+        let limits = MapReadLimits::default();
+        let iterations: usize = std::env::var("MAP_FUZZ_ITERATIONS")
+            .ok()
+            .and_then(|iterations| iterations.parse().ok())
+            .unwrap_or(32);
+        let items_end = data_size_offset(&file, 0)
+            + read_u32_le(&file[4 + 5 * 4..]) as usize * 4
+            + read_u32_le(&file[4 + 6 * 4..]) as usize;
+
+        // xorshift, so every run tests the same maps
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |max: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as usize
+        };
+        const INTERESTING: [i32; 8] = [0, 1, -1, 255, 0x10000, i32::MAX, i32::MIN, -0x10000];
+
+        for iteration in 0..iterations {
+            let mut mutated = file.clone();
+            for _ in 0..1 + next(4) {
+                let region = if next(8) == 0 {
+                    mutated.len()
+                } else {
+                    items_end.min(mutated.len())
+                };
+                let offset = next(region - 3) & !3;
+                match next(8) {
+                    0 => mutated.truncate(offset.max(HEADER_SIZE)),
+                    1..=3 => mutated[offset] = next(256) as u8,
+                    _ => mutated[offset..offset + 4]
+                        .copy_from_slice(&INTERESTING[next(INTERESTING.len())].to_le_bytes()),
+                }
+            }
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                let _ = read_like_client(&mutated, &limits, &thread_pool);
+            }));
+            assert!(
+                res.is_ok(),
+                "the mutated map of iteration {} panicked",
+                iteration
+            );
+        }
+    }
+
+    /**
+     * Compares reading the layers and images of a map as trusted and as untrusted map,
+     * run with `cargo test --release benchmark_ -- --ignored --nocapture`
+     */
+    #[test]
+    #[ignore]
+    fn benchmark_untrusted_read() {
+        let thread_pool = thread_pool();
+        let sys = Arc::new(SystemTime::new());
+        for name in ["cb2.map", "_cb2.map"] {
+            let file = read_test_map(name);
+            const RUNS: u32 = 10;
+
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                let mut map = CDatafileWrapper::new();
+                let data_start = map
+                    .Open(&file, name, &thread_pool, &Default::default(), &sys)
+                    .unwrap();
+                CDatafileWrapper::read_map_layers(
+                    &map.data_file,
+                    &mut map.layers,
+                    data_start,
+                    &sys,
+                    &MapFileLayersReadOptions::default(),
+                );
+                CDatafileWrapper::read_image_data(
+                    &map.data_file,
+                    &map.images,
+                    data_start,
+                    &sys,
+                    &MapFileImageReadOptions::default(),
+                );
+            }
+            let trusted = start.elapsed() / RUNS;
+
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                let mut map = CDatafileWrapper::new();
+                let data_start = map
+                    .read_untrusted(
+                        &file,
+                        name,
+                        &thread_pool,
+                        &Default::default(),
+                        &MapReadLimits::default(),
+                        &sys,
+                    )
+                    .unwrap();
+                CDatafileWrapper::read_map_layers(
+                    &map.data_file,
+                    &mut map.layers,
+                    data_start,
+                    &sys,
+                    &MapFileLayersReadOptions::default(),
+                );
+                CDatafileWrapper::read_image_data(
+                    &map.data_file,
+                    &map.images,
+                    data_start,
+                    &sys,
+                    &MapFileImageReadOptions::default(),
+                );
+            }
+            let untrusted = start.elapsed() / RUNS;
+
+            println!(
+                "{}: trusted {:.2}ms, untrusted {:.2}ms",
+                name,
+                trusted.as_secs_f64() * 1000.0,
+                untrusted.as_secs_f64() * 1000.0
+            );
+        }
+    }
+}
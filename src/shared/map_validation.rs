@@ -0,0 +1,315 @@
+use crate::{
+    datafile::CDatafileWrapper,
+    mapdef::{
+        EEntityTiles, MapItemTypes, MapLayer, MapLayerQuad, MapLayerSound, MapLayerTile,
+        TilesLayerFlag, ENTITY_OFFSET, NUM_ENTITIES,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapDiagnosticSeverity {
+    // the map can be loaded, but might behave unexpected
+    Warning,
+    // the map should not be loaded
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapDiagnosticKind {
+    NoGameLayer,
+    MultipleGameLayers {
+        layers: Vec<usize>,
+    },
+    NoSpawnPoint,
+    GroupLayersOutOfRange {
+        group: usize,
+        start_layer: i32,
+        num_layers: i32,
+    },
+    // the amount of tiles does not match width * height of the layer
+    TileCountMismatch {
+        layer: usize,
+        expected: usize,
+        found: usize,
+    },
+    // a physics layer contains tiles that are not known.
+    // only the first tile is reported, together with the count of all invalid tiles
+    TileIndexOutOfRange {
+        layer: usize,
+        x: usize,
+        y: usize,
+        index: u8,
+        count: usize,
+    },
+    ImageOutOfRange {
+        layer: usize,
+        image: i32,
+    },
+    SoundOutOfRange {
+        layer: usize,
+        sound: i32,
+    },
+    // `quad` is `None` for tile layers, for sound layers it is the sound source
+    EnvelopeOutOfRange {
+        layer: usize,
+        quad: Option<usize>,
+        envelope: i32,
+    },
+    EnvelopePointsOutOfRange {
+        envelope: usize,
+        start_point: i32,
+        num_points: i32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapDiagnostic {
+    pub severity: MapDiagnosticSeverity,
+    pub kind: MapDiagnosticKind,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MapDiagnostics {
+    pub diagnostics: Vec<MapDiagnostic>,
+}
+
+impl MapDiagnostics {
+    fn push(&mut self, severity: MapDiagnosticSeverity, kind: MapDiagnosticKind) {
+        self.diagnostics.push(MapDiagnostic { severity, kind });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == MapDiagnosticSeverity::Error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &MapDiagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == MapDiagnosticSeverity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &MapDiagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == MapDiagnosticSeverity::Warning)
+    }
+}
+
+impl CDatafileWrapper {
+    /**
+     * Checks the map for broken references and physics layer inconsistencies.
+     * Only map items that were loaded by `Open` are checked, e.g. the image references
+     * are ignored if the images were not loaded.
+     * Tiles are only checked if the layers were read (see `read_map_layers`).
+     */
+    pub fn validate(&self) -> MapDiagnostics {
+        let mut res = MapDiagnostics::default();
+
+        let layers_loaded = self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_LAYER);
+        let groups_loaded = self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_GROUP);
+        let images_loaded = self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_IMAGE);
+        let sounds_loaded = self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_SOUND);
+        let envelopes_loaded = self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_ENVELOPE);
+        let env_points_loaded = self.is_map_item_loaded(MapItemTypes::MAPITEMTYPE_ENVPOINTS);
+
+        if groups_loaded {
+            for g in 0..self.NumGroups() as usize {
+                let group = self.get_group(g);
+                if group.start_layer < 0
+                    || group.num_layers < 0
+                    || (layers_loaded
+                        && group.start_layer as usize + group.num_layers as usize
+                            > self.layers.len())
+                {
+                    res.push(
+                        MapDiagnosticSeverity::Error,
+                        MapDiagnosticKind::GroupLayersOutOfRange {
+                            group: g,
+                            start_layer: group.start_layer,
+                            num_layers: group.num_layers,
+                        },
+                    );
+                }
+            }
+        }
+
+        if !layers_loaded {
+            return res;
+        }
+
+        let check_envelope = |res: &mut MapDiagnostics,
+                              layer: usize,
+                              quad: Option<usize>,
+                              envelope: i32| {
+            if envelopes_loaded && envelope >= 0 && envelope as usize >= self.env_count() {
+                res.push(
+                    MapDiagnosticSeverity::Error,
+                    MapDiagnosticKind::EnvelopeOutOfRange {
+                        layer,
+                        quad,
+                        envelope,
+                    },
+                );
+            }
+        };
+        let check_image = |res: &mut MapDiagnostics, layer: usize, image: i32| {
+            if images_loaded && image >= 0 && image as usize >= self.images.len() {
+                res.push(
+                    MapDiagnosticSeverity::Error,
+                    MapDiagnosticKind::ImageOutOfRange { layer, image },
+                );
+            }
+        };
+
+        let mut game_layers: Vec<usize> = Vec::new();
+        let mut has_spawn = false;
+        let mut game_tiles_read = false;
+        for (l, layer) in self.layers.iter().enumerate() {
+            match layer {
+                MapLayer::Tile(MapLayerTile(tile_layer, _, tiles)) => {
+                    let is_game = (tile_layer.flags & TilesLayerFlag::TILESLAYERFLAG_GAME as i32)
+                        != 0;
+                    let is_front = (tile_layer.flags
+                        & TilesLayerFlag::TILESLAYERFLAG_FRONT as i32)
+                        != 0;
+                    let is_physics = is_game
+                        || is_front
+                        || (tile_layer.flags
+                            & (TilesLayerFlag::TILESLAYERFLAG_TELE as i32
+                                | TilesLayerFlag::TILESLAYERFLAG_SPEEDUP as i32
+                                | TilesLayerFlag::TILESLAYERFLAG_SWITCH as i32
+                                | TilesLayerFlag::TILESLAYERFLAG_TUNE as i32))
+                            != 0;
+                    if is_game {
+                        game_layers.push(l);
+                    }
+
+                    if !is_physics {
+                        check_image(&mut res, l, tile_layer.image);
+                        check_envelope(&mut res, l, None, tile_layer.color_env);
+                    }
+
+                    // design layers are not always read
+                    if tiles.is_empty() {
+                        continue;
+                    }
+                    game_tiles_read |= is_game;
+
+                    let expected = tile_layer.width.max(0) as usize
+                        * tile_layer.height.max(0) as usize;
+                    if tiles.len() != expected {
+                        res.push(
+                            MapDiagnosticSeverity::Error,
+                            MapDiagnosticKind::TileCountMismatch {
+                                layer: l,
+                                expected,
+                                found: tiles.len(),
+                            },
+                        );
+                        continue;
+                    }
+
+                    if is_game || is_front {
+                        let mut invalid_count = 0;
+                        let mut first_invalid: Option<(usize, u8)> = None;
+                        tiles.iter().enumerate().for_each(|(index, tile)| {
+                            if tile.index > ENTITY_OFFSET + NUM_ENTITIES {
+                                invalid_count += 1;
+                                first_invalid.get_or_insert((index, tile.index));
+                            }
+                            if is_game
+                                && tile.index >= EEntityTiles::ENTITY_SPAWN as u8
+                                && tile.index <= EEntityTiles::ENTITY_SPAWN_BLUE as u8
+                            {
+                                has_spawn = true;
+                            }
+                        });
+                        if let Some((index, tile_index)) = first_invalid {
+                            let w = tile_layer.width as usize;
+                            res.push(
+                                MapDiagnosticSeverity::Warning,
+                                MapDiagnosticKind::TileIndexOutOfRange {
+                                    layer: l,
+                                    x: index % w,
+                                    y: index / w,
+                                    index: tile_index,
+                                    count: invalid_count,
+                                },
+                            );
+                        }
+                    }
+                }
+                MapLayer::Quads(MapLayerQuad(quad_layer, quads)) => {
+                    check_image(&mut res, l, quad_layer.image);
+                    quads.iter().enumerate().for_each(|(q, quad)| {
+                        check_envelope(&mut res, l, Some(q), quad.pos_env);
+                        check_envelope(&mut res, l, Some(q), quad.color_env);
+                    });
+                }
+                MapLayer::Sound(MapLayerSound(sound_layer, sources)) => {
+                    sources.iter().enumerate().for_each(|(s, source)| {
+                        check_envelope(&mut res, l, Some(s), source.pos_env);
+                        check_envelope(&mut res, l, Some(s), source.sound_env);
+                    });
+                    if sounds_loaded
+                        && sound_layer.sound >= 0
+                        && sound_layer.sound as usize >= self.sound_count()
+                    {
+                        res.push(
+                            MapDiagnosticSeverity::Error,
+                            MapDiagnosticKind::SoundOutOfRange {
+                                layer: l,
+                                sound: sound_layer.sound,
+                            },
+                        );
+                    }
+                }
+                MapLayer::Unknown(_) => {}
+            }
+        }
+
+        if game_layers.is_empty() {
+            res.push(MapDiagnosticSeverity::Error, MapDiagnosticKind::NoGameLayer);
+        } else {
+            if game_layers.len() > 1 {
+                res.push(
+                    MapDiagnosticSeverity::Warning,
+                    MapDiagnosticKind::MultipleGameLayers {
+                        layers: game_layers,
+                    },
+                );
+            }
+            if game_tiles_read && !has_spawn {
+                res.push(MapDiagnosticSeverity::Error, MapDiagnosticKind::NoSpawnPoint);
+            }
+        }
+
+        if envelopes_loaded && env_points_loaded {
+            let env_point_count: usize = self
+                .get_env_points()
+                .iter()
+                .map(|points| points.len())
+                .sum();
+            for e in 0..self.env_count() {
+                let env = self.get_env(e);
+                if env.start_point < 0
+                    || env.num_points < 0
+                    || env.start_point as usize + env.num_points as usize > env_point_count
+                {
+                    res.push(
+                        MapDiagnosticSeverity::Error,
+                        MapDiagnosticKind::EnvelopePointsOutOfRange {
+                            envelope: e,
+                            start_point: env.start_point,
+                            num_points: env.num_points,
+                        },
+                    );
+                }
+            }
+        }
+
+        res
+    }
+}
@@ -7,6 +7,14 @@ pub trait ReadFromSlice {
     fn read_from_slice(data: &[u8]) -> Self;
 }
 
+/**
+ * The counter part of [`ReadFromSlice`], appends the little endian
+ * representation of the type (as stored in the datafile) to `out`
+ */
+pub trait WriteToVec {
+    fn write_to_vec(&self, out: &mut Vec<u8>);
+}
+
 // layer types
 #[derive(PartialEq, PartialOrd)]
 pub enum MapLayerTypes {
@@ -56,6 +64,11 @@ pub enum EEntityTiles {
     ENTITY_SPAWN_RED,
     ENTITY_SPAWN_BLUE,
 }
+
+// all entities start after this offset in the game layer
+pub const ENTITY_OFFSET: u8 = 255 - 16 * 4;
+// the count of known entities (see the list below)
+pub const NUM_ENTITIES: u8 = 50;
 /*
 ENTITY_FLAGSTAND_RED,
 ENTITY_FLAGSTAND_BLUE,
@@ -123,6 +136,25 @@ pub fn read_i16_le(data: &[u8]) -> i16 {
     i16::from_le_bytes([data[0], data[1]])
 }
 
+pub fn write_u32_le(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+pub fn write_i32_le(out: &mut Vec<u8>, val: i32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+pub fn write_i16_le(out: &mut Vec<u8>, val: i16) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn write_color(out: &mut Vec<u8>, color: &CColor) {
+    write_i32_le(out, color.x);
+    write_i32_le(out, color.y);
+    write_i32_le(out, color.z);
+    write_i32_le(out, color.w);
+}
+
 #[repr(C)]
 pub struct CMapItemVersion {
     version: i32,
@@ -136,6 +168,12 @@ impl CMapItemVersion {
     }
 }
 
+impl WriteToVec for CMapItemVersion {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+    }
+}
+
 #[repr(C)]
 pub struct CMapItemInfo {
     version: i32,
@@ -172,6 +210,16 @@ impl CMapItemInfo {
     }
 }
 
+impl WriteToVec for CMapItemInfo {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+        write_i32_le(out, self.author);
+        write_i32_le(out, self.map_version);
+        write_i32_le(out, self.credits);
+        write_i32_le(out, self.license);
+    }
+}
+
 #[repr(C)]
 pub struct CMapItemInfoSettings {
     info: CMapItemInfo,
@@ -191,6 +239,20 @@ impl CMapItemInfoSettings {
             settings,
         }
     }
+
+    /**
+     * The data index of the map settings, -1 if the map has none
+     */
+    pub fn settings(&self) -> i32 {
+        self.settings
+    }
+}
+
+impl WriteToVec for CMapItemInfoSettings {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.info.write_to_vec(out);
+        write_i32_le(out, self.settings);
+    }
 }
 
 #[derive(Copy, Clone, Default)]
@@ -235,6 +297,17 @@ impl CMapItemImage {
     }
 }
 
+impl WriteToVec for CMapItemImage {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+        write_i32_le(out, self.width);
+        write_i32_le(out, self.height);
+        write_i32_le(out, self.external);
+        write_i32_le(out, self.image_name);
+        write_i32_le(out, self.image_data);
+    }
+}
+
 #[derive(Default)]
 pub struct MapImage {
     pub item_data: CMapItemImage,
@@ -484,6 +557,27 @@ impl CMapItemGroup {
     }
 }
 
+impl WriteToVec for CMapItemGroup {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+        write_i32_le(out, self.offset_x);
+        write_i32_le(out, self.offset_y);
+        write_i32_le(out, self.parallax_x);
+        write_i32_le(out, self.parallax_y);
+
+        write_i32_le(out, self.start_layer);
+        write_i32_le(out, self.num_layers);
+
+        write_i32_le(out, self.use_clipping);
+        write_i32_le(out, self.clip_x);
+        write_i32_le(out, self.clip_y);
+        write_i32_le(out, self.clip_w);
+        write_i32_le(out, self.clip_h);
+
+        self.name.iter().for_each(|c| write_i32_le(out, *c));
+    }
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CMapItemLayer {
@@ -511,6 +605,14 @@ impl CMapItemLayer {
     }
 }
 
+impl WriteToVec for CMapItemLayer {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+        write_i32_le(out, self.item_layer);
+        write_i32_le(out, self.flags);
+    }
+}
+
 type CColor = ivec4;
 
 #[derive(Default, Clone)]
@@ -641,6 +743,33 @@ impl CMapItemLayerTilemap {
     }
 }
 
+impl WriteToVec for CMapItemLayerTilemap {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.layer.write_to_vec(out);
+        write_i32_le(out, self.version);
+
+        write_i32_le(out, self.width);
+        write_i32_le(out, self.height);
+        write_i32_le(out, self.flags);
+
+        write_color(out, &self.color);
+        write_i32_le(out, self.color_env);
+        write_i32_le(out, self.color_env_offset);
+
+        write_i32_le(out, self.image);
+        write_i32_le(out, self.data);
+
+        self.name.iter().for_each(|c| write_i32_le(out, *c));
+
+        // DDRace
+        write_i32_le(out, self.tele);
+        write_i32_le(out, self.speedup);
+        write_i32_le(out, self.front);
+        write_i32_le(out, self.switch);
+        write_i32_le(out, self.tune);
+    }
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CMapItemLayerQuads {
@@ -692,19 +821,34 @@ impl CMapItemLayerQuads {
     }
 }
 
-enum CMapItemLayerSoundsVer {
+impl WriteToVec for CMapItemLayerQuads {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.layer.write_to_vec(out);
+        write_i32_le(out, self.version);
+
+        write_i32_le(out, self.num_quads);
+        write_i32_le(out, self.data);
+        write_i32_le(out, self.image);
+
+        self.name.iter().for_each(|c| write_i32_le(out, *c));
+    }
+}
+
+pub enum CMapItemLayerSoundsVer {
+    // the sources are stored as `CSoundSourceDeprecated`
+    VERSION_DEPRECATED_SOURCES = 1,
     CURRENT_VERSION = 2,
 }
 
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CMapItemLayerSounds {
-    layer: CMapItemLayer,
-    version: i32,
+    pub layer: CMapItemLayer,
+    pub version: i32,
 
-    num_sources: i32,
-    data: i32,
-    sound: i32,
+    pub num_sources: i32,
+    pub data: i32,
+    pub sound: i32,
 
     name: [i32; 3],
 }
@@ -747,6 +891,221 @@ impl CMapItemLayerSounds {
     }
 }
 
+impl WriteToVec for CMapItemLayerSounds {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.layer.write_to_vec(out);
+        write_i32_le(out, self.version);
+
+        write_i32_le(out, self.num_sources);
+        write_i32_le(out, self.data);
+        write_i32_le(out, self.sound);
+
+        self.name.iter().for_each(|c| write_i32_le(out, *c));
+    }
+}
+
+pub enum SoundShapeType {
+    SHAPE_RECTANGLE = 0,
+    SHAPE_CIRCLE,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct CSoundShape {
+    pub shape_type: i32,
+    // rectangles: fixed point (22.10) size
+    // circles: `width` is the radius in world units (not fixed point), `height` is unused
+    pub width: i32,
+    pub height: i32,
+}
+
+impl ReadFromSlice for CSoundShape {
+    fn read_from_slice(data: &[u8]) -> Self {
+        let (shape_type, rest) = data.split_at(size_of::<i32>());
+        let shape_type = read_i32_le(shape_type);
+
+        let (width, rest) = rest.split_at(size_of::<i32>());
+        let width = read_i32_le(width);
+
+        let (height, _rest) = rest.split_at(size_of::<i32>());
+        let height = read_i32_le(height);
+
+        Self {
+            shape_type,
+            width,
+            height,
+        }
+    }
+}
+
+impl WriteToVec for CSoundShape {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.shape_type);
+        write_i32_le(out, self.width);
+        write_i32_le(out, self.height);
+    }
+}
+
+/**
+ * A positioned emitter of the sound of a sound layer
+ */
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+pub struct CSoundSource {
+    pub position: CPoint,
+    pub looped: i32,
+    // if the sound is panned by the position of the listener
+    pub pan: i32,
+    // in seconds
+    pub time_delay: i32,
+    // 0 - 255, the part of the shape (from the center) that plays at full volume
+    pub falloff: i32,
+
+    pub pos_env: i32,
+    pub pos_env_offset: i32,
+    pub sound_env: i32,
+    pub sound_env_offset: i32,
+
+    pub shape: CSoundShape,
+}
+
+impl ReadFromSlice for CSoundSource {
+    fn read_from_slice(data: &[u8]) -> Self {
+        let (position, mut rest) = data.split_at(size_of::<CPoint>());
+        let position = CPoint::read_from_slice(position);
+
+        let mut values: [i32; 8] = Default::default();
+        values.iter_mut().for_each(|value| {
+            let (c, rest2) = rest.split_at(size_of::<i32>());
+            *value = read_i32_le(c);
+            rest = rest2;
+        });
+
+        let shape = CSoundShape::read_from_slice(rest);
+
+        Self {
+            position,
+            looped: values[0],
+            pan: values[1],
+            time_delay: values[2],
+            falloff: values[3],
+            pos_env: values[4],
+            pos_env_offset: values[5],
+            sound_env: values[6],
+            sound_env_offset: values[7],
+            shape,
+        }
+    }
+}
+
+impl WriteToVec for CSoundSource {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.position.write_to_vec(out);
+        write_i32_le(out, self.looped);
+        write_i32_le(out, self.pan);
+        write_i32_le(out, self.time_delay);
+        write_i32_le(out, self.falloff);
+        write_i32_le(out, self.pos_env);
+        write_i32_le(out, self.pos_env_offset);
+        write_i32_le(out, self.sound_env);
+        write_i32_le(out, self.sound_env_offset);
+        self.shape.write_to_vec(out);
+    }
+}
+
+impl CSoundSource {
+    /**
+     * The volume (0 - 1) of the source for a listener at `listener`
+     * (in world coordinates, relative to the already animated source position).
+     * Inside the falloff part of the shape the volume is 1,
+     * from there it falls linearly to 0 at the border of the shape.
+     */
+    pub fn volume_at(&self, listener_x: f32, listener_y: f32) -> f32 {
+        let falloff = self.falloff.clamp(0, 255) as f32 / 255.0;
+        let dx = (listener_x - self.position.x as f32 / 1024.0).abs();
+        let dy = (listener_y - self.position.y as f32 / 1024.0).abs();
+        // the volume of a single axis, `half_size` is the distance from the center to the border
+        let axis_volume = |dist: f32, half_size: f32| {
+            let full = half_size * falloff;
+            if dist >= half_size {
+                0.0
+            } else if dist <= full {
+                1.0
+            } else {
+                1.0 - (dist - full) / (half_size - full)
+            }
+        };
+        if self.shape.shape_type == SoundShapeType::SHAPE_CIRCLE as i32 {
+            axis_volume((dx * dx + dy * dy).sqrt(), self.shape.width as f32)
+        } else {
+            axis_volume(dx, self.shape.width as f32 / 1024.0 / 2.0)
+                * axis_volume(dy, self.shape.height as f32 / 1024.0 / 2.0)
+        }
+    }
+}
+
+/**
+ * The sound source of sound layers before version 2, they are always circles
+ */
+#[repr(C)]
+pub struct CSoundSourceDeprecated {
+    pub position: CPoint,
+    pub looped: i32,
+    pub time_delay: i32,
+    pub falloff_distance: i32,
+
+    pub pos_env: i32,
+    pub pos_env_offset: i32,
+    pub sound_env: i32,
+    pub sound_env_offset: i32,
+}
+
+impl ReadFromSlice for CSoundSourceDeprecated {
+    fn read_from_slice(data: &[u8]) -> Self {
+        let (position, mut rest) = data.split_at(size_of::<CPoint>());
+        let position = CPoint::read_from_slice(position);
+
+        let mut values: [i32; 7] = Default::default();
+        values.iter_mut().for_each(|value| {
+            let (c, rest2) = rest.split_at(size_of::<i32>());
+            *value = read_i32_le(c);
+            rest = rest2;
+        });
+
+        Self {
+            position,
+            looped: values[0],
+            time_delay: values[1],
+            falloff_distance: values[2],
+            pos_env: values[3],
+            pos_env_offset: values[4],
+            sound_env: values[5],
+            sound_env_offset: values[6],
+        }
+    }
+}
+
+impl CSoundSourceDeprecated {
+    pub fn upgrade(&self) -> CSoundSource {
+        CSoundSource {
+            position: self.position,
+            looped: self.looped,
+            pan: 1,
+            time_delay: self.time_delay,
+            falloff: 0,
+            pos_env: self.pos_env,
+            pos_env_offset: self.pos_env_offset,
+            sound_env: self.sound_env,
+            sound_env_offset: self.sound_env_offset,
+            shape: CSoundShape {
+                shape_type: SoundShapeType::SHAPE_CIRCLE as i32,
+                width: self.falloff_distance,
+                height: 0,
+            },
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default)]
 #[repr(C)]
 pub struct CTile {
@@ -779,6 +1138,12 @@ impl ReadFromSlice for CTile {
     }
 }
 
+impl WriteToVec for CTile {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.index, self.flags, self.skip, self.reserved]);
+    }
+}
+
 // ddrace
 #[derive(Default, Clone)]
 #[repr(C)]
@@ -802,6 +1167,12 @@ impl ReadFromSlice for CTeleTile {
     }
 }
 
+impl WriteToVec for CTeleTile {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.number, self.tile_type]);
+    }
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CSpeedupTile {
@@ -822,6 +1193,9 @@ impl ReadFromSlice for CSpeedupTile {
         let (ttype, rest) = rest.split_at(size_of::<u8>());
         let ttype = ttype[0];
 
+        // skip the padding byte of the C layout
+        let (_, rest) = rest.split_at(size_of::<u8>());
+
         let (angle, _rest) = rest.split_at(size_of::<i16>());
         let angle = read_i16_le(angle);
 
@@ -834,6 +1208,14 @@ impl ReadFromSlice for CSpeedupTile {
     }
 }
 
+impl WriteToVec for CSpeedupTile {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        // the padding byte of the C layout
+        out.extend_from_slice(&[self.force, self.max_speed, self.tile_type, 0]);
+        write_i16_le(out, self.angle);
+    }
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CSwitchTile {
@@ -866,6 +1248,12 @@ impl ReadFromSlice for CSwitchTile {
     }
 }
 
+impl WriteToVec for CSwitchTile {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.number, self.tile_type, self.flags, self.delay]);
+    }
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CDoorTile {
@@ -882,6 +1270,9 @@ impl ReadFromSlice for CDoorTile {
         let (flags, rest) = rest.split_at(size_of::<u8>());
         let flags = flags[0];
 
+        // skip the padding bytes of the C layout
+        let (_, rest) = rest.split_at(size_of::<u8>() * 2);
+
         let (number, _rest) = rest.split_at(size_of::<i32>());
         let number = read_i32_le(number);
 
@@ -893,6 +1284,14 @@ impl ReadFromSlice for CDoorTile {
     }
 }
 
+impl WriteToVec for CDoorTile {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        // the padding bytes of the C layout
+        out.extend_from_slice(&[self.index, self.flags, 0, 0]);
+        write_i32_le(out, self.number);
+    }
+}
+
 #[derive(Default, Clone)]
 #[repr(C)]
 pub struct CTuneTile {
@@ -903,18 +1302,24 @@ pub struct CTuneTile {
 impl ReadFromSlice for CTuneTile {
     fn read_from_slice(data: &[u8]) -> Self {
         let (number, rest) = data.split_at(size_of::<u8>());
-        let _number = number[0];
+        let number = number[0];
 
         let (ttype, _rest) = rest.split_at(size_of::<u8>());
         let ttype = ttype[0];
 
         Self {
-            number: ttype,
+            number,
             tile_type: ttype,
         }
     }
 }
 
+impl WriteToVec for CTuneTile {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.number, self.tile_type]);
+    }
+}
+
 pub type CPoint = vec2_base<i32>;
 
 impl ReadFromSlice for CPoint {
@@ -929,6 +1334,13 @@ impl ReadFromSlice for CPoint {
     }
 }
 
+impl WriteToVec for CPoint {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.x);
+        write_i32_le(out, self.y);
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct CQuad {
     pub points: [CPoint; 5],
@@ -991,6 +1403,22 @@ impl ReadFromSlice for CQuad {
     }
 }
 
+impl WriteToVec for CQuad {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        self.points.iter().for_each(|point| point.write_to_vec(out));
+        self.colors.iter().for_each(|color| write_color(out, color));
+        self.tex_coords
+            .iter()
+            .for_each(|tex_coord| tex_coord.write_to_vec(out));
+
+        write_i32_le(out, self.pos_env);
+        write_i32_le(out, self.pos_env_offset);
+
+        write_i32_le(out, self.color_env);
+        write_i32_le(out, self.color_env_offset);
+    }
+}
+
 #[derive(Clone)]
 pub enum MapTileLayerDetail {
     Tile(),
@@ -1011,11 +1439,14 @@ pub struct MapLayerTile(
 #[derive(Clone)]
 pub struct MapLayerQuad(pub CMapItemLayerQuads, pub Vec<CQuad>);
 
+#[derive(Clone)]
+pub struct MapLayerSound(pub CMapItemLayerSounds, pub Vec<CSoundSource>);
+
 #[derive(Clone)]
 pub enum MapLayer {
     Tile(MapLayerTile),
     Quads(MapLayerQuad),
-    Sound(CMapItemLayerSounds),
+    Sound(MapLayerSound),
     Unknown(CMapItemLayer),
 }
 
@@ -1024,12 +1455,13 @@ impl MapLayer {
         match self {
             MapLayer::Tile(layer) => &layer.0.layer,
             MapLayer::Quads(layer) => &layer.0.layer,
-            MapLayer::Sound(layer) => &layer.layer,
+            MapLayer::Sound(layer) => &layer.0.layer,
             MapLayer::Unknown(layer) => &layer,
         }
     }
 }
 
+#[derive(Default)]
 #[repr(C)]
 pub struct CEnvPoint {
     pub time: i32, // in ms
@@ -1060,6 +1492,14 @@ impl CEnvPoint {
     }
 }
 
+impl WriteToVec for CEnvPoint {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.time);
+        write_i32_le(out, self.curve_type);
+        self.values.iter().for_each(|c| write_i32_le(out, *c));
+    }
+}
+
 enum CMapItemEnvelopeVer {
     CURRENT_VERSION = 2,
 }
@@ -1110,6 +1550,17 @@ impl CMapItemEnvelope {
     }
 }
 
+impl WriteToVec for CMapItemEnvelope {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+        write_i32_le(out, self.channels);
+        write_i32_le(out, self.start_point);
+        write_i32_le(out, self.num_points);
+        self.name.iter().for_each(|c| write_i32_le(out, *c));
+        write_i32_le(out, self.synchronized);
+    }
+}
+
 #[repr(C)]
 pub struct CMapItemSound {
     version: i32,
@@ -1149,3 +1600,15 @@ impl CMapItemSound {
         }
     }
 }
+
+impl WriteToVec for CMapItemSound {
+    fn write_to_vec(&self, out: &mut Vec<u8>) {
+        write_i32_le(out, self.version);
+
+        write_i32_le(out, self.external);
+
+        write_i32_le(out, self.sound_name);
+        write_i32_le(out, self.sound_data);
+        write_i32_le(out, self.sound_data_size);
+    }
+}
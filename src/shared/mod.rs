@@ -1,10 +1,26 @@
+pub mod auto_mapper;
+pub mod chat_markup;
 pub mod datafile;
+pub mod datafile_writer;
 pub mod game;
 pub mod hash_queue;
 pub mod id_gen;
 pub mod linked_list;
+pub mod map_autosave;
+pub mod map_chunks;
+pub mod map_collab;
+pub mod map_diff;
+pub mod map_hash;
+pub mod map_journal;
+pub mod map_playtest;
+pub mod map_settings;
+pub mod map_spatial;
+pub mod map_thumbnail;
+pub mod map_untrusted;
+pub mod map_validation;
 pub mod mapdef;
 pub mod network;
 pub mod rayon_helper;
+pub mod tile_region;
 pub mod types;
 pub mod worker;
@@ -0,0 +1,125 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+};
+
+use bincode::{Decode, Encode};
+
+use super::messages::NetworkStr;
+
+// servers announce themselves to this port, clients listen on it
+pub const LAN_DISCOVERY_PORT: u16 = 8303;
+// so random broadcasts on the same port are ignored
+const LAN_DISCOVERY_MAGIC: [u8; 4] = *b"ddlp";
+
+/**
+ * What a server broadcasts into the local network (about once per second)
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct LanServerAnnouncement {
+    magic: [u8; 4],
+    // the port of the game server, the ip is the one the announcement came from
+    pub port: u16,
+    pub name: NetworkStr<64>,
+    pub game_type: NetworkStr<32>,
+    pub map: NetworkStr<64>,
+    pub players: u32,
+    pub max_players: u32,
+    pub passworded: bool,
+}
+
+impl LanServerAnnouncement {
+    pub fn new(
+        port: u16,
+        name: NetworkStr<64>,
+        game_type: NetworkStr<32>,
+        map: NetworkStr<64>,
+        players: u32,
+        max_players: u32,
+        passworded: bool,
+    ) -> Self {
+        Self {
+            magic: LAN_DISCOVERY_MAGIC,
+            port,
+            name,
+            game_type,
+            map,
+            players,
+            max_players,
+            passworded,
+        }
+    }
+}
+
+/**
+ * Server side, broadcasts the server info over udp
+ */
+pub struct LanDiscoveryAnnouncer {
+    socket: UdpSocket,
+}
+
+impl LanDiscoveryAnnouncer {
+    pub fn new() -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    pub fn announce(&self, announcement: &LanServerAnnouncement) -> anyhow::Result<()> {
+        let packet = bincode::encode_to_vec(announcement, bincode::config::standard())?;
+        self.socket.send_to(
+            &packet,
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, LAN_DISCOVERY_PORT),
+        )?;
+        Ok(())
+    }
+}
+
+/**
+ * Client side, collects the announcements of the servers in the local network.
+ * Only one listener per machine can bind the discovery port.
+ */
+pub struct LanDiscoveryListener {
+    socket: UdpSocket,
+}
+
+impl LanDiscoveryListener {
+    pub fn new() -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, LAN_DISCOVERY_PORT))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    /**
+     * Never blocks, returns the game server addresses with their announcements
+     * that arrived since the last call
+     */
+    pub fn poll(&self) -> Vec<(SocketAddr, LanServerAnnouncement)> {
+        let mut res = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((size, sender)) => {
+                    let announcement = bincode::decode_from_slice::<LanServerAnnouncement, _>(
+                        &buf[..size],
+                        bincode::config::standard(),
+                    );
+                    if let Ok((announcement, _)) = announcement {
+                        if announcement.magic == LAN_DISCOVERY_MAGIC {
+                            res.push((
+                                SocketAddr::new(sender.ip(), announcement.port),
+                                announcement,
+                            ));
+                        }
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                // e.g. a connection reset from an earlier send, just try the next one
+                Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+                Err(_) => break,
+            }
+        }
+        res
+    }
+}
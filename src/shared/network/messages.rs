@@ -2,7 +2,17 @@ use arrayvec::{ArrayString, CapacityError};
 
 use math::math::vector::vec4_base;
 
-use crate::{game::snapshot::Snapshot, types::NetFloatIntegerRepType};
+use crate::{
+    game::{
+        ghost::GhostPath,
+        match_stats::MatchStatsReport,
+        snapshot::{Snapshot, SnapshotSpectatorCamera, SnapshotViewArea},
+        state_checksum::GameStateChecksum,
+        TGameElementID,
+    },
+    map_hash::MapSectionHash,
+    types::{GameTickType, NetFloatIntegerRepType},
+};
 
 use bincode::{Decode, Encode};
 
@@ -18,6 +28,19 @@ impl<const CAP: usize> NetworkStr<CAP> {
         let arrstr = ArrayString::from(s)?;
         Ok(NetworkStr(arrstr))
     }
+
+    /**
+     * Cuts the string at the last character that fits
+     */
+    pub fn from_truncated(s: &str) -> Self {
+        let mut arrstr = ArrayString::new();
+        for c in s.chars() {
+            if arrstr.try_push(c).is_err() {
+                break;
+            }
+        }
+        NetworkStr(arrstr)
+    }
 }
 
 impl<const CAP: usize> Encode for NetworkStr<CAP> {
@@ -100,19 +123,170 @@ const MAX_MAP_NAME_LEN: usize = 64;
 #[derive(Decode, Encode)]
 pub struct MsgSvServerInfo {
     pub map: NetworkStr<MAX_MAP_NAME_LEN>,
+    // the hash over the physics layers of the map,
+    // the client can verify that it uses the same physics as the server
+    pub map_physics_hash: MapSectionHash,
     pub game_type: NetworkStr<32>,
 }
 
 #[derive(Decode, Encode)]
 pub struct MsgSvPlayerInfo {
+    pub player_id: TGameElementID,
     pub info: MsgObjPlayerInfo,
+    // e.g. to mark bots in the scoreboard
+    pub is_bot: bool,
+}
+
+#[derive(Clone, Decode, Encode)]
+pub enum MsgObjVoteKind {
+    Kick(TGameElementID),
+    ChangeMap(NetworkStr<MAX_MAP_NAME_LEN>),
+    // votes of mods, registered on the server by name
+    Custom(NetworkStr<64>),
+}
+
+#[derive(Clone, Decode, Encode)]
+pub struct MsgObjVoteProposal {
+    pub kind: MsgObjVoteKind,
+    pub reason: NetworkStr<64>,
+    // invalid if the server started the vote
+    pub caller_player_id: TGameElementID,
+}
+
+#[derive(Clone, Copy, Default, Decode, Encode)]
+pub struct MsgObjVoteProgress {
+    pub yes: u32,
+    pub no: u32,
+    // clients that are allowed to vote
+    pub participants: u32,
+    pub remaining_time_millis: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Decode, Encode)]
+pub enum MsgObjVoteResult {
+    Passed,
+    Failed,
+    Canceled,
+}
+
+#[derive(Clone, Decode, Encode)]
+pub struct MsgSvVoteStarted {
+    pub proposal: MsgObjVoteProposal,
+    pub progress: MsgObjVoteProgress,
+}
+
+/**
+ * A system message (e.g. a player joined) as id with parameters,
+ * so the client can show it in the language of the player.
+ * Clients that don't know the id show the english fallback text.
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct MsgSvSystemMessage {
+    pub id: NetworkStr<64>,
+    pub params: Vec<NetworkStr<{ 64 * 4 }>>,
+    pub fallback: NetworkStr<{ 256 * 4 }>,
+}
+
+#[derive(Clone, Copy, PartialEq, Decode, Encode)]
+pub enum MsgObjResourceKind {
+    Map,
+    WasmModule,
+    Image,
+    Sound,
+}
+
+/**
+ * An asset the client probably needs soon (e.g. the next map),
+ * so it can be prefetched while idle instead of pausing at the map change
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct MsgObjResourceHint {
+    pub kind: MsgObjResourceKind,
+    pub name: NetworkStr<MAX_MAP_NAME_LEN>,
+    // `None` if the server doesn't know the file yet
+    pub hash: Option<MapSectionHash>,
+}
+
+/**
+ * A named moment of the match (round start, flag capture, personal best),
+ * emitted by the mod and shown as marker on the demo timeline
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct MsgSvBookmark {
+    pub name: NetworkStr<64>,
+    // the player the bookmark is about, if any
+    pub player_id: Option<TGameElementID>,
+    pub monotonic_tick: u64,
+}
+
+#[derive(Clone, Copy, Decode, Encode)]
+pub struct MsgSvPlayerInput {
+    pub player_id: TGameElementID,
+    pub input: MsgObjPlayerInput,
+}
+
+#[derive(Clone, Decode, Encode)]
+pub struct MsgSvGhost {
+    pub player_id: TGameElementID,
+    pub ghost: GhostPath,
+}
+
+/**
+ * The checksum of the snapshot of the tick before `monotonic_tick`
+ * simulated one tick with the inputs the server applied, see `resimulated_state_checksum`
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct MsgObjStateChecksum {
+    pub monotonic_tick: u64,
+    pub checksum: GameStateChecksum,
+    // by player, only of the characters in the snapshot
+    pub inputs: Vec<(TGameElementID, MsgObjPlayerInput)>,
+}
+
+/**
+ * A snapshot encoded as difference to an older snapshot that the client acknowledged,
+ * see `SnapshotBaselines`
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct MsgSvSnapshotDelta {
+    pub monotonic_tick: u64,
+    // `None` if the snapshot is encoded without a baseline
+    pub baseline_tick: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+/**
+ * A chat message of a player, team messages only reach the players of the sender's stage
+ */
+#[derive(Clone, Decode, Encode)]
+pub struct MsgSvChat {
+    pub player_id: TGameElementID,
+    pub msg: NetworkStr<{ 256 * 4 }>,
+    pub team: bool,
 }
 
 #[derive(Decode, Encode)]
 pub enum ServerToClientMessage {
     ServerInfo(MsgSvServerInfo),
+    // a full snapshot, e.g. when joining or after a desync
     Snapshot(Snapshot),
+    SnapshotDelta(MsgSvSnapshotDelta),
     PlayerInfo(MsgSvPlayerInfo),
+    VoteStarted(MsgSvVoteStarted),
+    VoteProgress(MsgObjVoteProgress),
+    VoteEnded(MsgObjVoteResult),
+    StageEvent(MsgSvStageEvent),
+    MatchStats(MatchStatsReport),
+    // the authoritative checksum, sent periodically
+    StateChecksum(MsgObjStateChecksum),
+    // only sent to clients that subscribed to ghosts
+    Ghost(MsgSvGhost),
+    SystemMessage(MsgSvSystemMessage),
+    // the raw input of the watched player, only for allowed spectators
+    PlayerInput(MsgSvPlayerInput),
+    ResourceHints(Vec<MsgObjResourceHint>),
+    Bookmark(MsgSvBookmark),
+    Chat(MsgSvChat),
 }
 
 // # client message parts
@@ -168,14 +342,92 @@ pub struct MsgObjPlayerInput {
 #[derive(Decode, Encode)]
 pub struct MsgClReady {
     pub player_info: MsgObjPlayerInfo,
+    // join without a character
+    pub as_spectator: bool,
 }
 
 pub type MsgClInput = MsgObjPlayerInput;
 
+#[derive(Clone, Copy, PartialEq, Decode, Encode)]
+pub enum MsgObjStageError {
+    // e.g. spectators
+    UnknownPlayer,
+    UnknownStage,
+    AlreadyInStage,
+    Locked,
+    Full,
+    LockingNotAllowed,
+}
+
+#[derive(Clone, Copy, Decode, Encode)]
+pub enum MsgSvStageEvent {
+    Joined {
+        player_id: TGameElementID,
+        stage_id: TGameElementID,
+    },
+    LockChanged {
+        stage_id: TGameElementID,
+        locked: bool,
+    },
+    // only sent to the client that caused it
+    Failed(MsgObjStageError),
+}
+
+#[derive(Decode, Encode)]
+pub struct MsgClCallVote {
+    pub kind: MsgObjVoteKind,
+    pub reason: NetworkStr<64>,
+}
+
+#[derive(Decode, Encode)]
+pub struct MsgClChat {
+    pub msg: NetworkStr<{ 256 * 4 }>,
+    // only to the players of the own stage
+    pub team: bool,
+}
+
+/**
+ * Only accepted by servers that allow it, e.g. local practice servers
+ */
+#[derive(Decode, Encode, Clone, Copy)]
+pub enum MsgClSimulation {
+    Pause,
+    Resume,
+    // simulates the next ticks while paused
+    Step(GameTickType),
+}
+
 #[derive(Decode, Encode)]
 pub enum ClientToServerMessage {
     Ready(MsgClReady),
     Input(MsgClInput),
+    // only for spectators, the server falls back to a free camera if the target is invalid
+    SpectatorCamera(SnapshotSpectatorCamera),
+    CallVote(MsgClCallVote),
+    // true for yes
+    Vote(bool),
+    // moves the player into a new stage
+    StageCreate,
+    StageJoin(TGameElementID),
+    StageLock(bool),
+    // if the client wants to receive the ghosts of race mods
+    GhostSubscribe(bool),
+    // spectators can watch the raw inputs of a player, `None` to stop
+    WatchPlayerInput(Option<TGameElementID>),
+    // the predicted state of the client did not match the server's checksum
+    Desync {
+        monotonic_tick: u64,
+        server_checksum: GameStateChecksum,
+        client_checksum: GameStateChecksum,
+    },
+    // the monotonic tick of a received snapshot delta, it can be used as baseline
+    SnapshotAck(u64),
+    // the camera of the client, only entities close to it are snapped
+    ViewArea(SnapshotViewArea),
+    Simulation(MsgClSimulation),
+    Chat(MsgClChat),
+    // the server answers with its `ServerInfo` again
+    InfoRequest,
 }
 
 #[derive(Decode, Encode)]
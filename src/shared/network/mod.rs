@@ -1,2 +1,3 @@
 pub mod game_event_generator;
+pub mod lan_discovery;
 pub mod messages;
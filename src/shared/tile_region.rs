@@ -0,0 +1,518 @@
+use std::collections::VecDeque;
+
+use crate::mapdef::{
+    CDoorTile, CSpeedupTile, CSwitchTile, CTeleTile, CTile, CTuneTile, MapLayerTile,
+    MapTileLayerDetail, TileFlag, TileNum, ENTITY_OFFSET,
+};
+
+// entity indices (relative to `ENTITY_OFFSET`) that have a direction
+const ENTITY_CRAZY_SHOTGUN_EX: u8 = 33;
+const ENTITY_CRAZY_SHOTGUN: u8 = 34;
+const ENTITY_ARMOR_SHOTGUN: u8 = 35;
+const ENTITY_ARMOR_LASER: u8 = 38;
+
+/**
+ * Physics tiles that have a direction, only these keep their flags
+ * when a physics region is mirrored or rotated.
+ */
+pub fn is_rotatable_tile(index: u8) -> bool {
+    index == TileNum::TILE_STOP as u8
+        || index == TileNum::TILE_STOPS as u8
+        || index == TileNum::TILE_CP as u8
+        || index == TileNum::TILE_CP_F as u8
+        || index == TileNum::TILE_THROUGH_DIR as u8
+        || index == TileNum::TILE_ENTITIES_OFF_1 as u8
+        || index == TileNum::TILE_ENTITIES_OFF_2 as u8
+        || (index > ENTITY_OFFSET && {
+            let entity = index - ENTITY_OFFSET;
+            entity == ENTITY_CRAZY_SHOTGUN_EX
+                || entity == ENTITY_CRAZY_SHOTGUN
+                || (ENTITY_ARMOR_SHOTGUN..=ENTITY_ARMOR_LASER).contains(&entity)
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileTransform {
+    MirrorX,
+    MirrorY,
+    // 90 degrees clockwise
+    Rotate,
+}
+
+fn transform_flags(flags: u8, transform: TileTransform) -> u8 {
+    let xflip = TileFlag::XFLIP as u8;
+    let yflip = TileFlag::YFLIP as u8;
+    let rotate = TileFlag::ROTATE as u8;
+    // a rotated tile has its axes swapped
+    match transform {
+        TileTransform::MirrorX if (flags & rotate) != 0 => flags ^ yflip,
+        TileTransform::MirrorX => flags ^ xflip,
+        TileTransform::MirrorY if (flags & rotate) != 0 => flags ^ xflip,
+        TileTransform::MirrorY => flags ^ yflip,
+        TileTransform::Rotate if (flags & rotate) != 0 => flags ^ (xflip | yflip | rotate),
+        TileTransform::Rotate => flags ^ rotate,
+    }
+}
+
+/**
+ * A tile type of a tile layer that can be part of a region.
+ */
+pub trait RegionTile: Clone + Default {
+    fn is_empty(&self) -> bool;
+
+    /**
+     * Fixes the orientation of the tile itself, after its position was mirrored or rotated.
+     * `physics` is true for tiles of physics layers, where only some tiles have a direction.
+     */
+    fn transform(&mut self, transform: TileTransform, physics: bool);
+}
+
+impl RegionTile for CTile {
+    fn is_empty(&self) -> bool {
+        self.index == 0
+    }
+
+    fn transform(&mut self, transform: TileTransform, physics: bool) {
+        if !physics || is_rotatable_tile(self.index) {
+            self.flags = transform_flags(self.flags, transform);
+        }
+    }
+}
+
+impl RegionTile for CTeleTile {
+    fn is_empty(&self) -> bool {
+        self.tile_type == 0
+    }
+
+    fn transform(&mut self, _transform: TileTransform, _physics: bool) {}
+}
+
+impl RegionTile for CSpeedupTile {
+    fn is_empty(&self) -> bool {
+        self.tile_type == 0
+    }
+
+    fn transform(&mut self, transform: TileTransform, _physics: bool) {
+        // the angle is in degrees, 0 points to the right, positive angles go clockwise
+        let angle = self.angle as i32;
+        let angle = match transform {
+            TileTransform::MirrorX => 180 - angle,
+            TileTransform::MirrorY => -angle,
+            TileTransform::Rotate => angle + 90,
+        };
+        self.angle = angle.rem_euclid(360) as i16;
+    }
+}
+
+impl RegionTile for CSwitchTile {
+    fn is_empty(&self) -> bool {
+        self.tile_type == 0
+    }
+
+    fn transform(&mut self, transform: TileTransform, _physics: bool) {
+        if is_rotatable_tile(self.tile_type) {
+            self.flags = transform_flags(self.flags, transform);
+        }
+    }
+}
+
+impl RegionTile for CDoorTile {
+    fn is_empty(&self) -> bool {
+        self.index == 0
+    }
+
+    fn transform(&mut self, transform: TileTransform, _physics: bool) {
+        if is_rotatable_tile(self.index) {
+            self.flags = transform_flags(self.flags, transform);
+        }
+    }
+}
+
+impl RegionTile for CTuneTile {
+    fn is_empty(&self) -> bool {
+        self.tile_type == 0
+    }
+
+    fn transform(&mut self, _transform: TileTransform, _physics: bool) {}
+}
+
+/**
+ * A rectangular copy of tiles, e.g. the brush of the editor
+ */
+#[derive(Clone, Default)]
+pub struct TileRegion<T> {
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<T>,
+}
+
+impl<T: RegionTile> TileRegion<T> {
+    /**
+     * Copies the rect from the tiles of a layer.
+     * Parts of the rect that are outside of the layer are empty tiles.
+     */
+    pub fn copy_from(
+        layer_width: usize,
+        layer_height: usize,
+        tiles: &[T],
+        x: i32,
+        y: i32,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let mut res = Self {
+            width,
+            height,
+            tiles: vec![T::default(); width * height],
+        };
+        if tiles.len() != layer_width * layer_height {
+            return res;
+        }
+        for ry in 0..height {
+            let ly = y as i64 + ry as i64;
+            if ly < 0 || ly >= layer_height as i64 {
+                continue;
+            }
+            for rx in 0..width {
+                let lx = x as i64 + rx as i64;
+                if lx < 0 || lx >= layer_width as i64 {
+                    continue;
+                }
+                res.tiles[ry * width + rx] = tiles[ly as usize * layer_width + lx as usize].clone();
+            }
+        }
+        res
+    }
+
+    /**
+     * Pastes the region at `x`, `y` into the tiles of a layer, parts outside of the layer are
+     * ignored. If `skip_empty` is true, empty tiles of the region don't overwrite the layer.
+     * Returns the count of changed tiles.
+     */
+    pub fn paste_into(
+        &self,
+        layer_width: usize,
+        layer_height: usize,
+        tiles: &mut [T],
+        x: i32,
+        y: i32,
+        skip_empty: bool,
+    ) -> usize {
+        if tiles.len() != layer_width * layer_height {
+            return 0;
+        }
+        let mut count = 0;
+        for ry in 0..self.height {
+            let ly = y as i64 + ry as i64;
+            if ly < 0 || ly >= layer_height as i64 {
+                continue;
+            }
+            for rx in 0..self.width {
+                let lx = x as i64 + rx as i64;
+                if lx < 0 || lx >= layer_width as i64 {
+                    continue;
+                }
+                let tile = &self.tiles[ry * self.width + rx];
+                if skip_empty && tile.is_empty() {
+                    continue;
+                }
+                tiles[ly as usize * layer_width + lx as usize] = tile.clone();
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn mirror_x(&mut self, physics: bool) {
+        self.tiles
+            .chunks_exact_mut(self.width.max(1))
+            .for_each(|row| {
+                row.reverse();
+            });
+        self.tiles
+            .iter_mut()
+            .for_each(|tile| tile.transform(TileTransform::MirrorX, physics));
+    }
+
+    pub fn mirror_y(&mut self, physics: bool) {
+        for y in 0..self.height / 2 {
+            for x in 0..self.width {
+                self.tiles
+                    .swap(y * self.width + x, (self.height - 1 - y) * self.width + x);
+            }
+        }
+        self.tiles
+            .iter_mut()
+            .for_each(|tile| tile.transform(TileTransform::MirrorY, physics));
+    }
+
+    /**
+     * Rotates the region by 90 degrees clockwise, width and height are swapped
+     */
+    pub fn rotate(&mut self, physics: bool) {
+        let mut tiles = vec![T::default(); self.tiles.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                // the new width is the old height
+                tiles[x * self.height + (self.height - 1 - y)] =
+                    self.tiles[y * self.width + x].clone();
+            }
+        }
+        self.tiles = tiles;
+        std::mem::swap(&mut self.width, &mut self.height);
+        self.tiles
+            .iter_mut()
+            .for_each(|tile| tile.transform(TileTransform::Rotate, physics));
+    }
+}
+
+/**
+ * Replaces the area of tiles that are connected (4-neighborhood) to the tile at `x`, `y`
+ * and have the same index with `tile`.
+ * Returns the count of changed tiles.
+ */
+pub fn flood_fill(
+    layer_width: usize,
+    layer_height: usize,
+    tiles: &mut [CTile],
+    x: usize,
+    y: usize,
+    tile: CTile,
+) -> usize {
+    if x >= layer_width || y >= layer_height || tiles.len() != layer_width * layer_height {
+        return 0;
+    }
+    let target = tiles[y * layer_width + x].index;
+    if target == tile.index && tiles[y * layer_width + x].flags == tile.flags {
+        return 0;
+    }
+
+    let mut visited = vec![false; tiles.len()];
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    queue.push_back((x, y));
+    visited[y * layer_width + x] = true;
+    let mut count = 0;
+    while let Some((x, y)) = queue.pop_front() {
+        tiles[y * layer_width + x] = CTile {
+            index: tile.index,
+            flags: tile.flags,
+            ..tiles[y * layer_width + x]
+        };
+        count += 1;
+        let mut check = |x: usize, y: usize| {
+            let index = y * layer_width + x;
+            if !visited[index] && tiles[index].index == target {
+                visited[index] = true;
+                queue.push_back((x, y));
+            }
+        };
+        if x > 0 {
+            check(x - 1, y);
+        }
+        if x + 1 < layer_width {
+            check(x + 1, y);
+        }
+        if y > 0 {
+            check(x, y - 1);
+        }
+        if y + 1 < layer_height {
+            check(x, y + 1);
+        }
+    }
+    count
+}
+
+/**
+ * The region of the special tiles of a physics layer (tele, speedup etc.)
+ */
+#[derive(Clone)]
+pub enum MapTileRegionDetail {
+    None,
+    Tele(TileRegion<CTeleTile>),
+    Speedup(TileRegion<CSpeedupTile>),
+    Switch(TileRegion<CSwitchTile>),
+    Door(TileRegion<CDoorTile>),
+    Tune(TileRegion<CTuneTile>),
+}
+
+/**
+ * A region of a map tile layer, including its special tiles
+ */
+#[derive(Clone)]
+pub struct MapTileLayerRegion {
+    pub tiles: TileRegion<CTile>,
+    pub detail: MapTileRegionDetail,
+    // the region was copied from a physics layer
+    pub physics: bool,
+}
+
+impl MapTileLayerRegion {
+    pub fn copy_from(
+        layer: &MapLayerTile,
+        physics: bool,
+        x: i32,
+        y: i32,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        let MapLayerTile(tile_layer, detail, tiles) = layer;
+        let w = tile_layer.width.max(0) as usize;
+        let h = tile_layer.height.max(0) as usize;
+        let detail = match detail {
+            MapTileLayerDetail::Tile() => MapTileRegionDetail::None,
+            MapTileLayerDetail::Tele(tiles) => {
+                MapTileRegionDetail::Tele(TileRegion::copy_from(w, h, tiles, x, y, width, height))
+            }
+            MapTileLayerDetail::Speedup(tiles) => MapTileRegionDetail::Speedup(
+                TileRegion::copy_from(w, h, tiles, x, y, width, height),
+            ),
+            MapTileLayerDetail::Switch(tiles) => {
+                MapTileRegionDetail::Switch(TileRegion::copy_from(w, h, tiles, x, y, width, height))
+            }
+            MapTileLayerDetail::Door(tiles) => {
+                MapTileRegionDetail::Door(TileRegion::copy_from(w, h, tiles, x, y, width, height))
+            }
+            MapTileLayerDetail::Tune(tiles) => {
+                MapTileRegionDetail::Tune(TileRegion::copy_from(w, h, tiles, x, y, width, height))
+            }
+        };
+        Self {
+            tiles: TileRegion::copy_from(w, h, tiles, x, y, width, height),
+            detail,
+            physics,
+        }
+    }
+
+    /**
+     * Pastes the region into the layer, the special tiles are only pasted
+     * if the layer has the same kind of special tiles.
+     * The `skip` values of the layer are not updated (see `InitTilemapSkip`).
+     */
+    pub fn paste_into(&self, layer: &mut MapLayerTile, x: i32, y: i32, skip_empty: bool) -> usize {
+        let MapLayerTile(tile_layer, detail, tiles) = layer;
+        let w = tile_layer.width.max(0) as usize;
+        let h = tile_layer.height.max(0) as usize;
+        match (&self.detail, detail) {
+            (MapTileRegionDetail::Tele(region), MapTileLayerDetail::Tele(tiles)) => {
+                region.paste_into(w, h, tiles, x, y, skip_empty);
+            }
+            (MapTileRegionDetail::Speedup(region), MapTileLayerDetail::Speedup(tiles)) => {
+                region.paste_into(w, h, tiles, x, y, skip_empty);
+            }
+            (MapTileRegionDetail::Switch(region), MapTileLayerDetail::Switch(tiles)) => {
+                region.paste_into(w, h, tiles, x, y, skip_empty);
+            }
+            (MapTileRegionDetail::Door(region), MapTileLayerDetail::Door(tiles)) => {
+                region.paste_into(w, h, tiles, x, y, skip_empty);
+            }
+            (MapTileRegionDetail::Tune(region), MapTileLayerDetail::Tune(tiles)) => {
+                region.paste_into(w, h, tiles, x, y, skip_empty);
+            }
+            _ => {}
+        }
+        self.tiles.paste_into(w, h, tiles, x, y, skip_empty)
+    }
+
+    pub fn transform(&mut self, transform: TileTransform) {
+        fn apply<T: RegionTile>(
+            region: &mut TileRegion<T>,
+            transform: TileTransform,
+            physics: bool,
+        ) {
+            match transform {
+                TileTransform::MirrorX => region.mirror_x(physics),
+                TileTransform::MirrorY => region.mirror_y(physics),
+                TileTransform::Rotate => region.rotate(physics),
+            }
+        }
+        apply(&mut self.tiles, transform, self.physics);
+        match &mut self.detail {
+            MapTileRegionDetail::None => {}
+            MapTileRegionDetail::Tele(region) => apply(region, transform, true),
+            MapTileRegionDetail::Speedup(region) => apply(region, transform, true),
+            MapTileRegionDetail::Switch(region) => apply(region, transform, true),
+            MapTileRegionDetail::Door(region) => apply(region, transform, true),
+            MapTileRegionDetail::Tune(region) => apply(region, transform, true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapdef::{CTile, TileFlag};
+
+    use super::{flood_fill, TileRegion};
+
+    fn region() -> TileRegion<CTile> {
+        TileRegion {
+            width: 3,
+            height: 2,
+            tiles: (0..6)
+                .map(|i| CTile {
+                    index: i + 1,
+                    flags: if i == 0 { TileFlag::XFLIP as u8 } else { 0 },
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    fn indices(region: &TileRegion<CTile>) -> Vec<(u8, u8)> {
+        region
+            .tiles
+            .iter()
+            .map(|tile| (tile.index, tile.flags))
+            .collect()
+    }
+
+    #[test]
+    fn rotate_four_times_is_identity() {
+        let mut r = region();
+        r.rotate(false);
+        assert_eq!((r.width, r.height), (2, 3));
+        // the bottom left tile is now the top left tile
+        assert_eq!(r.tiles[0].index, 4);
+        r.rotate(false);
+        r.rotate(false);
+        r.rotate(false);
+        assert_eq!(indices(&r), indices(&region()));
+    }
+
+    #[test]
+    fn mirror_twice_is_identity() {
+        let mut r = region();
+        r.mirror_x(false);
+        assert_eq!(r.tiles[0].index, 3);
+        assert_eq!(r.tiles[2].flags, 0);
+        r.mirror_x(false);
+        r.mirror_y(false);
+        r.mirror_y(false);
+        assert_eq!(indices(&r), indices(&region()));
+    }
+
+    #[test]
+    fn physics_tiles_keep_flags() {
+        let mut r = region();
+        r.rotate(true);
+        let solid = r.tiles.iter().find(|tile| tile.index == 1).unwrap();
+        assert_eq!(solid.flags, TileFlag::XFLIP as u8);
+    }
+
+    #[test]
+    fn copy_paste_and_flood_fill() {
+        let mut tiles = vec![CTile::default(); 16];
+        let r = region();
+        assert_eq!(r.paste_into(4, 4, &mut tiles, 2, 1, false), 4);
+        let copy = TileRegion::copy_from(4, 4, &tiles, 2, 1, 3, 2);
+        assert_eq!(copy.tiles[0].index, 1);
+        assert_eq!(copy.tiles[2].index, 0);
+
+        let fill = CTile {
+            index: 9,
+            ..Default::default()
+        };
+        // all empty tiles are connected
+        assert_eq!(flood_fill(4, 4, &mut tiles, 0, 0, fill), 12);
+        assert_eq!(flood_fill(4, 4, &mut tiles, 0, 0, fill), 0);
+    }
+}